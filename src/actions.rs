@@ -0,0 +1,121 @@
+use crate::money::Money;
+use crate::{ClientAccount, DisputeOutcome, DisputePolicy};
+
+/// everything an action knows about the event besides the account it is applied to.
+/// for the dispute family the amount is the one of the referenced transaction
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TxContext {
+    pub transaction_id: i32,
+    pub amount: Money,
+    pub dispute_policy: DisputePolicy,
+    // deposits are credited to locked accounts as well
+    pub locked_deposits: bool,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Outcome {
+    Applied,
+    Rejected,
+    // a dispute can be partially applied, the outcome tells what was actually held
+    Disputed(DisputeOutcome),
+}
+
+impl Outcome {
+    pub fn is_applied(&self) -> bool {
+        match self {
+            Outcome::Applied => true,
+            Outcome::Rejected => false,
+            Outcome::Disputed(DisputeOutcome::Held(_)) => true,
+            Outcome::Disputed(DisputeOutcome::Exceeded(_, held)) => !held.is_zero(),
+        }
+    }
+}
+
+impl From<bool> for Outcome {
+    fn from(applied: bool) -> Self {
+        if applied {
+            Outcome::Applied
+        } else {
+            Outcome::Rejected
+        }
+    }
+}
+
+/// the five built-in actions implement this, additional ones (fees, bonuses, ...) can be
+/// registered on the processing without touching the built-ins
+pub trait AccountAction {
+    fn apply(&self, account: &mut ClientAccount, ctx: &TxContext) -> Outcome;
+}
+
+pub struct Deposit;
+pub struct Withdrawal;
+pub struct Dispute;
+pub struct Resolve;
+pub struct ChargeBack;
+
+impl AccountAction for Deposit {
+    fn apply(&self, account: &mut ClientAccount, ctx: &TxContext) -> Outcome {
+        match ctx.locked_deposits {
+            true => account.credit(ctx.amount).into(),
+            false => account.deposit(ctx.amount).into(),
+        }
+    }
+}
+
+impl AccountAction for Withdrawal {
+    fn apply(&self, account: &mut ClientAccount, ctx: &TxContext) -> Outcome {
+        account.withdraw(ctx.amount).into()
+    }
+}
+
+impl AccountAction for Dispute {
+    fn apply(&self, account: &mut ClientAccount, ctx: &TxContext) -> Outcome {
+        Outcome::Disputed(account.dispute_with(ctx.amount, ctx.dispute_policy))
+    }
+}
+
+impl AccountAction for Resolve {
+    fn apply(&self, account: &mut ClientAccount, ctx: &TxContext) -> Outcome {
+        account.resolve(ctx.amount).into()
+    }
+}
+
+impl AccountAction for ChargeBack {
+    fn apply(&self, account: &mut ClientAccount, ctx: &TxContext) -> Outcome {
+        account.charge_back(ctx.amount).into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::actions::{AccountAction, Dispute, Outcome, TxContext};
+    use crate::money::{Money, SignedMoney};
+    use crate::{ClientAccount, DisputeOutcome, DisputePolicy};
+
+    fn context(amount: u64, dispute_policy: DisputePolicy) -> TxContext {
+        TxContext {
+            transaction_id: 1,
+            amount: Money::from_minor_units(amount),
+            dispute_policy,
+            locked_deposits: false,
+        }
+    }
+
+    #[test]
+    fn dispute_uses_the_policy_of_the_context() {
+        let mut account = ClientAccount::new(1, SignedMoney::from_minor_units(10));
+        let outcome = Dispute.apply(&mut account, &context(20, DisputePolicy::PartialHold));
+
+        assert_eq!(
+            outcome,
+            Outcome::Disputed(DisputeOutcome::Exceeded(
+                DisputePolicy::PartialHold,
+                Money::from_minor_units(10)
+            ))
+        );
+        assert!(outcome.is_applied());
+
+        let outcome = Dispute.apply(&mut account, &context(20, DisputePolicy::RejectAndReport));
+        assert!(!outcome.is_applied());
+    }
+}
@@ -0,0 +1,403 @@
+//! mass corrections of the accounts without touching the state by hand. a batch is a csv
+//!
+//! ```csv
+//! op,client,amount,note
+//! unlock,1,,chargeback was reversed by the bank
+//! adjust,2,-1.5,fee charged twice
+//! close,3,,customer request
+//! annotate,4,,under review
+//! ```
+//!
+//! a batch is only applied with the hmac-sha256 of the file under a shared key
+//! (`openssl dgst -sha256 -hmac "$(cat batch.key)" corrections.csv`). it is an integrity check,
+//! the batch that is applied is byte for byte the one the hmac was made for. it is not an
+//! approval, whoever can run the batch is given the key file and can make the hmac of any file
+//! themselves. every row is checked before the first one is applied, a batch is applied
+//! completely or not at all.
+//! the note of an annotate row is kept with the account, with the author of the batch and the
+//! time it was applied
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::audit::AuditValue;
+use crate::hashing::{hmac_sha256, Sha256};
+use crate::money::{RoundingMode, SignedMoney};
+use crate::{AccountProcessing, ClientAccount, Note};
+
+pub const COLUMNS: [&str; 4] = ["op", "client", "amount", "note"];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AdminOperation {
+    // a locked account can move money again
+    Unlock,
+    // signed correction of available in minor units
+    Adjust(SignedMoney),
+    // the account is removed, it cannot have a balance
+    Close,
+    // a note against the account, nothing else changes
+    Annotate,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AdminRow {
+    pub line: u64,
+    pub operation: AdminOperation,
+    pub client_id: u16,
+    // why, it ends up in the audit line
+    pub note: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AdminBatch {
+    pub rows: Vec<AdminRow>,
+    // sha-256 of the file as it was read
+    pub digest: String,
+    // the file as it was read, the hmac is checked against it
+    content: Vec<u8>,
+    // who ran the batch, the author of its notes
+    pub author: String,
+}
+
+impl AdminBatch {
+    pub fn parse(content: &[u8], rounding: RoundingMode) -> Result<Self, String> {
+        let mut digest = Sha256::default();
+        digest.update(content);
+
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(content);
+        let headers = rdr.headers().map_err(|e| e.to_string())?;
+        if !headers.iter().eq(COLUMNS) {
+            return Err(format!(
+                "an admin batch needs the columns {}",
+                COLUMNS.join(",")
+            ));
+        }
+
+        let mut rows = vec![];
+        for row in rdr.records() {
+            let row = row.map_err(|e| e.to_string())?;
+            let line = row.position().map_or(0, |position| position.line());
+            let invalid = |what: &str| format!("invalid {} in line {}", what, line);
+            let client_id = row
+                .get(1)
+                .and_then(|client| client.parse().ok())
+                .ok_or_else(|| invalid("client"))?;
+            let amount = row.get(2).unwrap_or_default();
+            let note = row.get(3).unwrap_or_default().to_string();
+
+            let operation = match row.get(0).unwrap_or_default() {
+                "unlock" => AdminOperation::Unlock,
+                "adjust" => match SignedMoney::parse(amount, rounding) {
+                    Ok(SignedMoney::ZERO) | Err(_) => return Err(invalid("amount")),
+                    Ok(amount) => AdminOperation::Adjust(amount),
+                },
+                "close" => AdminOperation::Close,
+                "annotate" if note.is_empty() => return Err(invalid("annotation, it has no note")),
+                "annotate" => AdminOperation::Annotate,
+                _ => return Err(invalid("op")),
+            };
+            if !amount.is_empty() && !matches!(operation, AdminOperation::Adjust(_)) {
+                return Err(invalid("row, only adjust has an amount"));
+            }
+
+            rows.push(AdminRow {
+                line,
+                operation,
+                client_id,
+                note,
+            });
+        }
+
+        Ok(AdminBatch {
+            rows,
+            digest: digest.finish(),
+            content: content.to_vec(),
+            author: String::new(),
+        })
+    }
+
+    pub fn load(path: &str, rounding: RoundingMode) -> Result<Self, String> {
+        let content =
+            fs::read(path).map_err(|e| format!("cannot read admin batch {}: {}", path, e))?;
+        Self::parse(&content, rounding).map_err(|e| format!("invalid admin batch {}: {}", path, e))
+    }
+
+    /// the hmac was made for exactly this file with the key. the expected hmac is never part of
+    /// the error, it would be the hmac of the file
+    pub fn check_hmac(&self, hmac: &str, key: &[u8]) -> Result<(), String> {
+        if key.is_empty() {
+            return Err("the hmac key is empty".to_string());
+        }
+        let expected = hmac_sha256(key, &self.content);
+        let hmac = hmac.trim().to_ascii_lowercase();
+        // compared in full so the time does not tell how much of it was right
+        let matches = hmac.len() == expected.len()
+            && hmac
+                .bytes()
+                .zip(expected.bytes())
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0;
+        match matches {
+            true => Ok(()),
+            false => Err(format!(
+                "the hmac does not match the admin batch with the sha-256 {}",
+                self.digest
+            )),
+        }
+    }
+
+    /// the accounts after every row, in order. the first row that cannot be applied is the error
+    fn validate(
+        &self,
+        processing: &AccountProcessing,
+    ) -> Result<Vec<Option<ClientAccount>>, String> {
+        let mut accounts: BTreeMap<u16, Option<ClientAccount>> = BTreeMap::new();
+        let mut after = vec![];
+        for row in &self.rows {
+            let failed = |why: &str| format!("line {}: client {} {}", row.line, row.client_id, why);
+            let current = accounts
+                .entry(row.client_id)
+                .or_insert_with(|| processing.account(row.client_id).copied());
+            let Some(mut account) = *current else {
+                return Err(failed("has no account"));
+            };
+
+            match row.operation {
+                AdminOperation::Unlock if !account.locked => return Err(failed("is not locked")),
+                AdminOperation::Unlock => account.locked = false,
+                AdminOperation::Adjust(amount) => {
+                    // a negative balance of the dispute policy can still be corrected upwards
+                    let available = account
+                        .available
+                        .checked_add_signed(amount)
+                        .filter(|available| !available.is_negative() || amount > SignedMoney::ZERO)
+                        .ok_or_else(|| failed("cannot be adjusted below zero"))?;
+                    account.available = available;
+                    if account.total().is_none() {
+                        return Err(failed("would overflow"));
+                    }
+                }
+                AdminOperation::Close
+                    if !account.available.is_zero() || !account.held.is_zero() =>
+                {
+                    return Err(failed("cannot be closed with a balance"))
+                }
+                AdminOperation::Close => {}
+                AdminOperation::Annotate => {}
+            }
+
+            *current = match row.operation {
+                AdminOperation::Close => None,
+                _ => Some(account),
+            };
+            after.push(*current);
+        }
+
+        Ok(after)
+    }
+
+    /// checked completely before anything changes, with an audit log it gets its own segment
+    pub fn apply(&self, processing: &mut AccountProcessing) -> Result<(), String> {
+        let after = self.validate(processing)?;
+        let rounding = processing.config.rounding;
+        let at = processing.clock.unix_seconds();
+
+        if let Some(audit) = processing.audit_mut() {
+            audit.record(&[
+                ("event", AuditValue::Str("admin_batch")),
+                ("digest", AuditValue::Str(&self.digest)),
+                ("author", AuditValue::Str(&self.author)),
+                ("rows", AuditValue::Int(self.rows.len() as i128)),
+            ]);
+        }
+        for (row, account) in self.rows.iter().zip(after) {
+            match account {
+                Some(account) => processing.accounts.insert(row.client_id, account),
+                None => processing.accounts.remove(&row.client_id),
+            };
+            if row.operation == AdminOperation::Annotate {
+                let note = Note {
+                    author: self.author.clone(),
+                    at,
+                    text: row.note.clone(),
+                };
+                processing
+                    .notes
+                    .entry(row.client_id)
+                    .or_default()
+                    .push(note);
+            }
+
+            let Some(audit) = processing.audit_mut() else {
+                continue;
+            };
+            let available = account.map_or(SignedMoney::ZERO, |account| account.available);
+            let available = available.format(rounding);
+            let (event, amount) = match row.operation {
+                AdminOperation::Unlock => ("admin_unlock", None),
+                AdminOperation::Adjust(amount) => ("admin_adjust", Some(amount.format(rounding))),
+                AdminOperation::Close => ("admin_close", None),
+                AdminOperation::Annotate => ("admin_annotate", None),
+            };
+            let mut fields = vec![
+                ("event", AuditValue::Str(event)),
+                ("client", AuditValue::Int(row.client_id as i128)),
+            ];
+            if let Some(amount) = &amount {
+                fields.push(("amount", AuditValue::Amount(amount)));
+                fields.push(("available", AuditValue::Amount(&available)));
+            }
+            fields.push(("note", AuditValue::Str(&row.note)));
+            audit.record(&fields);
+        }
+        if let Some(audit) = processing.audit_mut() {
+            audit.record(&[
+                ("event", AuditValue::Str("admin_batch_end")),
+                ("digest", AuditValue::Str(&self.digest)),
+            ]);
+        }
+        info!(
+            "admin batch {} applied, {} rows",
+            self.digest,
+            self.rows.len()
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::admin::{AdminBatch, AdminOperation};
+    use crate::audit::test::SharedBuffer;
+    use crate::audit::AuditLog;
+    use crate::clock::ManualClock;
+    use crate::hashing::hmac_sha256;
+    use crate::money::{RoundingMode, SignedMoney};
+    use crate::{AccountProcessing, Config, Note};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn batch(rows: &str) -> Result<AdminBatch, String> {
+        let content = format!("op,client,amount,note\n{}", rows);
+        AdminBatch::parse(content.as_bytes(), RoundingMode::default())
+    }
+
+    #[test]
+    fn parse_rows() {
+        let parsed =
+            batch("unlock,1,,reversed\nadjust,2,-1.5,twice\nannotate,3,,review\n").unwrap();
+        let operations: Vec<_> = parsed.rows.iter().map(|row| row.operation).collect();
+        assert_eq!(
+            operations,
+            [
+                AdminOperation::Unlock,
+                AdminOperation::Adjust(SignedMoney::from_minor_units(-15000)),
+                AdminOperation::Annotate
+            ]
+        );
+        assert_eq!(parsed.rows[1].line, 3);
+        assert_eq!(parsed.digest.len(), 64);
+
+        let content =
+            "op,client,amount,note\nunlock,1,,reversed\nadjust,2,-1.5,twice\nannotate,3,,review\n";
+        let hmac = hmac_sha256(b"key", content.as_bytes());
+        assert!(parsed.check_hmac(&hmac.to_uppercase(), b"key").is_ok());
+        // the digest needs no key at all
+        assert!(parsed.check_hmac(&parsed.digest, b"key").is_err());
+        assert!(parsed.check_hmac(&hmac, b"other").is_err());
+        assert!(parsed.check_hmac(&hmac, b"").is_err());
+        assert!(parsed.check_hmac("00", b"key").is_err());
+
+        for invalid in [
+            "refund,1,,",
+            "adjust,1,,",
+            "adjust,1,0,",
+            "unlock,1,1.0,",
+            "annotate,1,,",
+            "close,x,,",
+        ] {
+            assert!(batch(invalid).is_err(), "{}", invalid);
+        }
+        assert!(AdminBatch::parse(b"op,client\n", RoundingMode::default()).is_err());
+    }
+
+    #[test]
+    fn batches_are_applied_completely_or_not_at_all() {
+        let buffer = SharedBuffer::default();
+        let mut app = AccountProcessing::new(Config::default());
+        app.subsystems_mut().audit = Some(AuditLog::new(Box::new(buffer.clone())));
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,2.0\n\
+             deposit,1,2,1.0\n\
+             dispute,1,2,\n\
+             chargeback,1,2,\n\
+             deposit,2,3,1.0\n\
+             deposit,3,4,1.0\n"
+                .as_bytes(),
+        );
+
+        // 3 cannot be closed with a balance, nothing of the batch is applied
+        let rejected = batch("unlock,1,,reversed\nclose,3,,request\n").unwrap();
+        assert!(rejected
+            .apply(&mut app)
+            .unwrap_err()
+            .starts_with("line 3: client 3"));
+        assert!(app.accounts[&1].locked);
+        assert!(buffer.0.lock().unwrap().is_empty());
+
+        let mut corrections = batch(
+            "unlock,1,,reversed\n\
+             adjust,2,-1.0,fee\n\
+             close,2,,request\n\
+             annotate,3,,review\n",
+        )
+        .unwrap();
+        corrections.author = "ops".to_string();
+        let clock = Arc::new(ManualClock::default());
+        clock.advance(Duration::from_secs(1_700_000_000));
+        app.clock = clock;
+        corrections.apply(&mut app).unwrap();
+        assert!(!app.accounts[&1].locked);
+        assert!(!app.accounts.contains_key(&2));
+        assert_eq!(app.accounts[&3].available.minor_units(), 10000);
+        assert_eq!(
+            app.notes(3),
+            [Note {
+                author: "ops".to_string(),
+                at: 1_700_000_000,
+                text: "review".to_string(),
+            }]
+        );
+        assert!(app.notes(1).is_empty());
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                format!(
+                    "{{\"event\":\"admin_batch\",\"digest\":\"{}\",\"author\":\"ops\",\"rows\":4}}",
+                    corrections.digest
+                )
+                .as_str(),
+                "{\"event\":\"admin_unlock\",\"client\":1,\"note\":\"reversed\"}",
+                "{\"event\":\"admin_adjust\",\"client\":2,\"amount\":\"-1.0000\",\"available\":\"0.0000\",\"note\":\"fee\"}",
+                "{\"event\":\"admin_close\",\"client\":2,\"note\":\"request\"}",
+                "{\"event\":\"admin_annotate\",\"client\":3,\"note\":\"review\"}",
+                format!(
+                    "{{\"event\":\"admin_batch_end\",\"digest\":\"{}\"}}",
+                    corrections.digest
+                )
+                .as_str(),
+            ]
+        );
+
+        assert!(batch("adjust,3,-2.0,\n").unwrap().apply(&mut app).is_err());
+        assert!(batch("unlock,4,,\n").unwrap().apply(&mut app).is_err());
+    }
+}
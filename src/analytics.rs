@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::money::{Money, RoundingMode};
+use crate::{AccountActions, AccountEvent};
+
+/// lifetime numbers of a client, they are part of the state so a run with --state adds to the
+/// numbers of the runs before it instead of starting from zero
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct ClientStatistics {
+    pub deposits: u64,
+    // saturates instead of failing the run, it is only reported
+    pub deposit_volume: Money,
+    pub disputes: u64,
+    pub chargebacks: u64,
+    // applied events, an export with the same version has the same account
+    pub version: u64,
+    // the tx of the last applied event, None while the version is 0
+    pub last_tx: Option<i32>,
+}
+
+impl ClientStatistics {
+    /// only applied events count
+    pub fn observe(&mut self, event: &AccountEvent) {
+        self.version += 1;
+        self.last_tx = Some(event.transaction_id);
+        match event.action_type {
+            AccountActions::Deposit => {
+                self.deposits += 1;
+                self.deposit_volume = self
+                    .deposit_volume
+                    .checked_add(event.amount.unwrap_or_default())
+                    .unwrap_or(Money::MAX);
+            }
+            AccountActions::Dispute => self.disputes += 1,
+            AccountActions::ChargeBack => self.chargebacks += 1,
+            _ => {}
+        }
+    }
+}
+
+pub fn write_statistics_report(
+    path: &str,
+    statistics: &BTreeMap<u16, ClientStatistics>,
+    rounding: RoundingMode,
+) -> std::io::Result<()> {
+    let mut writer = csv::Writer::from_writer(BufWriter::new(File::create(path)?));
+    writer.write_record([
+        "client",
+        "deposits",
+        "deposit_volume",
+        "disputes",
+        "chargebacks",
+    ])?;
+    for (client_id, client) in statistics {
+        writer.write_record([
+            client_id.to_string(),
+            client.deposits.to_string(),
+            client.deposit_volume.format(rounding),
+            client.disputes.to_string(),
+            client.chargebacks.to_string(),
+        ])?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analytics::ClientStatistics;
+    use crate::money::Money;
+    use crate::{AccountActions, AccountEvent};
+
+    #[test]
+    fn lifetime_numbers() {
+        let event = |action_type, amount| AccountEvent {
+            transaction_id: 1,
+            action_type,
+            client_id: 1,
+            amount,
+        };
+        let mut statistics = ClientStatistics::default();
+        statistics.observe(&event(
+            AccountActions::Deposit,
+            Some(Money::from_minor_units(5)),
+        ));
+        statistics.observe(&event(AccountActions::Deposit, Some(Money::MAX)));
+        statistics.observe(&event(
+            AccountActions::Withdrawal,
+            Some(Money::from_minor_units(5)),
+        ));
+        statistics.observe(&event(AccountActions::Dispute, None));
+
+        assert_eq!(
+            statistics,
+            ClientStatistics {
+                deposits: 2,
+                deposit_volume: Money::MAX,
+                disputes: 1,
+                chargebacks: 0,
+                version: 4,
+                last_tx: Some(1),
+            }
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::redact::MASK;
+
+/// append only JSON lines stream of decisions the engine made that are not visible in the
+/// final balances. Every line is one flat object, values are either strings or numbers.
+///
+/// we only need to write flat objects so there is no json dependency for it
+pub struct AuditLog {
+    writer: Box<dyn Write>,
+    // amounts are written as the mask, for logs that end up in lower trust systems
+    pub redact_amounts: bool,
+}
+
+/// a value in an audit line
+pub enum AuditValue<'a> {
+    Str(&'a str),
+    // a formatted amount, the only values that are redacted
+    Amount(&'a str),
+    Int(i128),
+    Bool(bool),
+}
+
+/// minimal json string escaping, quotes, backslashes and control characters
+pub fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+impl AuditLog {
+    pub fn new(writer: Box<dyn Write>) -> Self {
+        AuditLog {
+            writer,
+            redact_amounts: false,
+        }
+    }
+
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        Ok(Self::new(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    pub fn record(&mut self, fields: &[(&str, AuditValue)]) {
+        let line = fields
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    AuditValue::Str(value) => json_string(value),
+                    AuditValue::Amount(_) if self.redact_amounts => json_string(MASK),
+                    AuditValue::Amount(value) => json_string(value),
+                    AuditValue::Int(value) => value.to_string(),
+                    AuditValue::Bool(value) => value.to_string(),
+                };
+                format!("{}:{}", json_string(key), value)
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+
+        // losing the audit trail should not stop the processing, but it has to be visible
+        if let Err(e) = writeln!(self.writer, "{{{}}}", line) {
+            error!("cannot write audit line: {}", e);
+        }
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::audit::{json_string, AuditLog, AuditValue};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// shared buffer so the test can look at what was written
+    #[derive(Clone, Default)]
+    pub struct SharedBuffer(pub Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn escape_strings() {
+        assert_eq!(json_string("abc"), "\"abc\"");
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+        assert_eq!(json_string("\u{1}"), "\"\\u0001\"");
+    }
+
+    #[test]
+    fn flat_json_lines() {
+        let buffer = SharedBuffer::default();
+        let mut audit = AuditLog::new(Box::new(buffer.clone()));
+        audit.record(&[
+            ("event", AuditValue::Str("dispute")),
+            ("client", AuditValue::Int(1)),
+            ("applied", AuditValue::Bool(true)),
+        ]);
+        audit.record(&[("amount", AuditValue::Int(-5))]);
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output,
+            "{\"event\":\"dispute\",\"client\":1,\"applied\":true}\n{\"amount\":-5}\n"
+        );
+    }
+
+    #[test]
+    fn amounts_can_be_redacted() {
+        let buffer = SharedBuffer::default();
+        let mut audit = AuditLog::new(Box::new(buffer.clone()));
+        audit.redact_amounts = true;
+        audit.record(&[
+            ("client", AuditValue::Int(1)),
+            ("held", AuditValue::Amount("1.5000")),
+        ]);
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(output, "{\"client\":1,\"held\":\"***\"}\n");
+    }
+}
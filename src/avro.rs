@@ -0,0 +1,574 @@
+//! avro object container files for the events and the accounts report, the schemas are the
+//! ones of `schema --format avro` so they can be registered as they are.
+//!
+//! only the null codec is written and read. the sync marker is derived from the schema so the
+//! same input always gives the same file
+
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use crate::binary::{read_csv_events, write_csv_events};
+use crate::hashing::mix;
+use crate::money::{Money, RoundingMode};
+use crate::proto::{read_varint, unzigzag, write_varint, zigzag};
+use crate::schema::{avro_schema, schemas, Field, FieldType, RecordSchema};
+use crate::{AccountEvent, AccountProcessing, ClientAccount, Config};
+
+const MAGIC: &[u8; 4] = b"Obj\x01";
+const BLOCK_RECORDS: u64 = 1000;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Avro,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "avro" => Ok(OutputFormat::Avro),
+            _ => Err(format!("unknown output format: {} (csv, avro)", s)),
+        }
+    }
+}
+
+/// a field of a record, decimals are the unscaled minor units
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Value {
+    Null,
+    Text(String),
+    Integer(i64),
+    Decimal(i128),
+    Bool(bool),
+}
+
+pub fn record_schema(name: &str) -> RecordSchema {
+    schemas()
+        .into_iter()
+        .find(|schema| schema.name == name)
+        .expect("unknown record schema")
+}
+
+fn write_long(buffer: &mut Vec<u8>, value: i64) {
+    write_varint(buffer, zigzag(value));
+}
+
+fn read_long(bytes: &mut &[u8]) -> Result<i64, String> {
+    read_varint(bytes).map(unzigzag)
+}
+
+fn write_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    write_long(buffer, bytes.len() as i64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn read_bytes<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], String> {
+    let length = usize::try_from(read_long(bytes)?).map_err(|_| "negative length")?;
+    if bytes.len() < length {
+        return Err("truncated bytes".to_string());
+    }
+    let (value, rest) = bytes.split_at(length);
+    *bytes = rest;
+
+    Ok(value)
+}
+
+fn write_value(buffer: &mut Vec<u8>, field: &Field, value: &Value) -> Result<(), String> {
+    // a nullable field is the union ["null", type]
+    if field.nullable {
+        write_long(buffer, (*value != Value::Null) as i64);
+    }
+    match (field.field_type, value) {
+        (_, Value::Null) if field.nullable => {}
+        (FieldType::Text, Value::Text(text)) => write_bytes(buffer, text.as_bytes()),
+        (FieldType::Integer { .. }, Value::Integer(integer)) => write_long(buffer, *integer),
+        (FieldType::Decimal, Value::Decimal(decimal)) => {
+            let bytes = decimal.to_be_bytes();
+            // the shortest two's complement that keeps the sign
+            let start = (0..15)
+                .find(|&i| {
+                    !(bytes[i] == 0 && bytes[i + 1] < 0x80
+                        || bytes[i] == 0xff && bytes[i + 1] >= 0x80)
+                })
+                .unwrap_or(15);
+            write_bytes(buffer, &bytes[start..]);
+        }
+        (FieldType::Bool, Value::Bool(bool)) => buffer.push(*bool as u8),
+        _ => return Err(format!("invalid value for {}: {:?}", field.name, value)),
+    }
+
+    Ok(())
+}
+
+fn read_value(bytes: &mut &[u8], field: &Field) -> Result<Value, String> {
+    if field.nullable {
+        match read_long(bytes)? {
+            0 => return Ok(Value::Null),
+            1 => {}
+            branch => return Err(format!("invalid union branch {} of {}", branch, field.name)),
+        }
+    }
+
+    match field.field_type {
+        FieldType::Text => String::from_utf8(read_bytes(bytes)?.to_vec())
+            .map(Value::Text)
+            .map_err(|_| format!("{} is not utf-8", field.name)),
+        FieldType::Integer { .. } => read_long(bytes).map(Value::Integer),
+        FieldType::Decimal => {
+            let decimal = read_bytes(bytes)?;
+            if decimal.is_empty() || decimal.len() > 16 {
+                return Err(format!("invalid decimal for {}", field.name));
+            }
+            let fill = if decimal[0] >= 0x80 { 0xff } else { 0 };
+            let mut unscaled = [fill; 16];
+            unscaled[16 - decimal.len()..].copy_from_slice(decimal);
+            Ok(Value::Decimal(i128::from_be_bytes(unscaled)))
+        }
+        FieldType::Bool => {
+            let (&bool, rest) = bytes.split_first().ok_or("truncated boolean")?;
+            *bytes = rest;
+            Ok(Value::Bool(bool != 0))
+        }
+    }
+}
+
+pub fn write_record(
+    buffer: &mut Vec<u8>,
+    schema: &RecordSchema,
+    values: &[Value],
+) -> Result<(), String> {
+    if values.len() != schema.fields.len() {
+        return Err(format!(
+            "{} values for the {} fields of {}",
+            values.len(),
+            schema.fields.len(),
+            schema.name
+        ));
+    }
+
+    schema
+        .fields
+        .iter()
+        .zip(values)
+        .try_for_each(|(field, value)| write_value(buffer, field, value))
+}
+
+pub fn read_record(bytes: &mut &[u8], schema: &RecordSchema) -> Result<Vec<Value>, String> {
+    schema
+        .fields
+        .iter()
+        .map(|field| read_value(bytes, field))
+        .collect()
+}
+
+fn sync_marker(schema: &str) -> [u8; 16] {
+    let seed = schema
+        .bytes()
+        .fold(0u64, |hash, byte| mix(hash ^ byte as u64));
+    let mut sync = [0u8; 16];
+    sync[..8].copy_from_slice(&mix(seed).to_le_bytes());
+    sync[8..].copy_from_slice(&mix(seed ^ 1).to_le_bytes());
+
+    sync
+}
+
+/// the records are collected into blocks of up to BLOCK_RECORDS
+pub struct ContainerWriter<W: Write> {
+    writer: W,
+    schema: RecordSchema,
+    sync: [u8; 16],
+    block: Vec<u8>,
+    records: u64,
+}
+
+impl<W: Write> ContainerWriter<W> {
+    pub fn new(mut writer: W, schema: RecordSchema) -> Result<Self, String> {
+        let json = avro_schema(&schema);
+        let sync = sync_marker(&json);
+        let mut header = MAGIC.to_vec();
+        write_long(&mut header, 2);
+        write_bytes(&mut header, b"avro.schema");
+        write_bytes(&mut header, json.as_bytes());
+        write_bytes(&mut header, b"avro.codec");
+        write_bytes(&mut header, b"null");
+        write_long(&mut header, 0);
+        header.extend_from_slice(&sync);
+        writer.write_all(&header).map_err(io)?;
+
+        Ok(ContainerWriter {
+            writer,
+            schema,
+            sync,
+            block: vec![],
+            records: 0,
+        })
+    }
+
+    pub fn append(&mut self, values: &[Value]) -> Result<(), String> {
+        write_record(&mut self.block, &self.schema, values)?;
+        self.records += 1;
+        if self.records == BLOCK_RECORDS {
+            self.write_block()?;
+        }
+
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> Result<(), String> {
+        let mut header = vec![];
+        write_long(&mut header, self.records as i64);
+        write_long(&mut header, self.block.len() as i64);
+        self.writer.write_all(&header).map_err(io)?;
+        self.writer.write_all(&self.block).map_err(io)?;
+        self.writer.write_all(&self.sync).map_err(io)?;
+        self.block.clear();
+        self.records = 0;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, String> {
+        if self.records > 0 {
+            self.write_block()?;
+        }
+        self.writer.flush().map_err(io)?;
+
+        Ok(self.writer)
+    }
+}
+
+fn io(e: std::io::Error) -> String {
+    format!("cannot write avro: {}", e)
+}
+
+/// a long straight from the reader, None if the input ends before it
+fn read_long_from<R: Read>(reader: &mut R) -> Result<Option<i64>, String> {
+    let mut bytes = vec![];
+    loop {
+        let mut byte = [0u8; 1];
+        match reader.read(&mut byte) {
+            Ok(0) if bytes.is_empty() => return Ok(None),
+            Ok(0) => return Err("truncated long".to_string()),
+            Ok(_) => bytes.push(byte[0]),
+            Err(e) => return Err(format!("cannot read avro: {}", e)),
+        }
+        if byte[0] & 0x80 == 0 {
+            return read_long(&mut bytes.as_slice()).map(Some);
+        }
+    }
+}
+
+fn read_exact<R: Read>(reader: &mut R, length: i64) -> Result<Vec<u8>, String> {
+    let length = usize::try_from(length).map_err(|_| "negative length")?;
+    let mut bytes = vec![0u8; length];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(|e| format!("truncated avro: {}", e))?;
+
+    Ok(bytes)
+}
+
+/// the records of a file that was written with exactly our schema, there is no schema
+/// resolution
+pub struct ContainerReader<R: Read> {
+    reader: R,
+    schema: RecordSchema,
+    sync: Vec<u8>,
+    block: Vec<u8>,
+    offset: usize,
+    remaining: i64,
+}
+
+impl<R: Read> ContainerReader<R> {
+    pub fn new(mut reader: R, schema: RecordSchema) -> Result<Self, String> {
+        if read_exact(&mut reader, 4)? != MAGIC {
+            return Err("not an avro file".to_string());
+        }
+
+        let mut writer_schema = None;
+        let mut codec = None;
+        loop {
+            let mut entries = read_long_from(&mut reader)?.ok_or("truncated avro header")?;
+            if entries == 0 {
+                break;
+            }
+            // a negative count is followed by the size of the entries
+            if entries < 0 {
+                entries = -entries;
+                read_long_from(&mut reader)?;
+            }
+            for _ in 0..entries {
+                let mut entry = || {
+                    let length = read_long_from(&mut reader)?.ok_or("truncated avro header")?;
+                    read_exact(&mut reader, length)
+                };
+                let (key, value) = (entry()?, entry()?);
+                match key.as_slice() {
+                    b"avro.schema" => writer_schema = Some(value),
+                    b"avro.codec" => codec = Some(value),
+                    _ => {}
+                }
+            }
+        }
+        if writer_schema.as_deref() != Some(avro_schema(&schema).as_bytes()) {
+            return Err(format!(
+                "the avro file was not written with the {} schema",
+                schema.name
+            ));
+        }
+        if !matches!(codec.as_deref(), None | Some(b"null")) {
+            return Err("only the null avro codec is supported".to_string());
+        }
+        let sync = read_exact(&mut reader, 16)?;
+
+        Ok(ContainerReader {
+            reader,
+            schema,
+            sync,
+            block: vec![],
+            offset: 0,
+            remaining: 0,
+        })
+    }
+
+    fn read_block(&mut self) -> Result<bool, String> {
+        let Some(records) = read_long_from(&mut self.reader)? else {
+            return Ok(false);
+        };
+        let size = read_long_from(&mut self.reader)?.ok_or("truncated avro block")?;
+        self.block = read_exact(&mut self.reader, size)?;
+        if read_exact(&mut self.reader, 16)? != self.sync {
+            return Err("invalid avro sync marker".to_string());
+        }
+        self.offset = 0;
+        self.remaining = records;
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> Iterator for ContainerReader<R> {
+    type Item = Result<Vec<Value>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining <= 0 {
+            match self.read_block() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        let mut bytes = &self.block[self.offset..];
+        let record = read_record(&mut bytes, &self.schema);
+        self.offset = self.block.len() - bytes.len();
+        self.remaining -= 1;
+        // nothing after a broken record can be trusted
+        if record.is_err() {
+            self.remaining = 0;
+        }
+
+        Some(record)
+    }
+}
+
+pub fn event_values(event: &AccountEvent) -> Vec<Value> {
+    vec![
+        Value::Text(event.action_type.to_string()),
+        Value::Integer(event.client_id as i64),
+        Value::Integer(event.transaction_id as i64),
+        event
+            .amount
+            .map(|amount| Value::Decimal(amount.minor_units() as i128))
+            .unwrap_or(Value::Null),
+    ]
+}
+
+pub fn event_from_values(values: &[Value]) -> Result<AccountEvent, String> {
+    let invalid = |what: &str| format!("invalid {} in avro event: {:?}", what, values);
+    let [Value::Text(action_type), Value::Integer(client_id), Value::Integer(transaction_id), amount] =
+        values
+    else {
+        return Err(invalid("record"));
+    };
+    let amount = match amount {
+        Value::Null => None,
+        Value::Decimal(amount) => Some(Money::from_minor_units(
+            u64::try_from(*amount).map_err(|_| invalid("amount"))?,
+        )),
+        _ => return Err(invalid("amount")),
+    };
+
+    Ok(AccountEvent {
+        transaction_id: i32::try_from(*transaction_id).map_err(|_| invalid("tx"))?,
+        action_type: action_type.parse()?,
+        client_id: u16::try_from(*client_id).map_err(|_| invalid("client"))?,
+        amount,
+    })
+}
+
+/// unlike the csv the optional fields are always there, null without a clients file or scoring
+pub fn account_values(processing: &AccountProcessing, account: &ClientAccount) -> Vec<Value> {
+    let metadata = processing.client_metadata.get(&account.id);
+    let statistics = processing.statistics.get(&account.id);
+    let text = |text: Option<&String>| text.cloned().map(Value::Text).unwrap_or(Value::Null);
+    vec![
+        Value::Integer(account.id as i64),
+        Value::Decimal(account.available.minor_units() as i128),
+        Value::Decimal(account.held.minor_units() as i128),
+        account
+            .total()
+            .map(|total| Value::Decimal(total.minor_units() as i128))
+            .unwrap_or(Value::Null),
+        Value::Bool(account.locked),
+        text(metadata.map(|metadata| &metadata.name)),
+        text(metadata.map(|metadata| &metadata.country)),
+        metadata
+            .map(|metadata| Value::Integer(metadata.risk_score as i64))
+            .unwrap_or(Value::Null),
+        processing
+            .risk()
+            .map(|risk| Value::Integer(risk.score(account.id) as i64))
+            .unwrap_or(Value::Null),
+        match processing.config.account_versions {
+            true => Value::Integer(statistics.map_or(0, |statistics| statistics.version) as i64),
+            false => Value::Null,
+        },
+        statistics
+            .and_then(|statistics| statistics.last_tx)
+            .filter(|_| processing.config.account_versions)
+            .map(|tx| Value::Integer(tx as i64))
+            .unwrap_or(Value::Null),
+    ]
+}
+
+pub fn write_accounts<'a, W: Write>(
+    processing: &AccountProcessing,
+    writer: W,
+    accounts: impl Iterator<Item = &'a ClientAccount>,
+) -> Result<(), String> {
+    let mut container = ContainerWriter::new(writer, record_schema("accounts"))?;
+    for account in accounts {
+        container.append(&account_values(processing, account))?;
+    }
+
+    container.finish().map(|_| ())
+}
+
+pub fn csv_to_avro<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    config: &Config,
+) -> Result<u64, String> {
+    let mut container = ContainerWriter::new(writer, record_schema("input"))?;
+    let events = read_csv_events(reader, config, |event| {
+        container.append(&event_values(event))
+    })?;
+    container.finish()?;
+
+    Ok(events)
+}
+
+pub fn avro_to_csv<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    rounding: RoundingMode,
+) -> Result<u64, String> {
+    let records = ContainerReader::new(reader, record_schema("input"))?;
+    let events = records.map(|values| values.and_then(|values| event_from_values(&values)));
+
+    write_csv_events(writer, events, rounding)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::avro::{
+        account_values, avro_to_csv, csv_to_avro, read_record, record_schema, write_accounts,
+        write_record, ContainerReader, Value, BLOCK_RECORDS,
+    };
+    use crate::money::{Money, RoundingMode, SignedMoney};
+    use crate::{AccountProcessing, ClientAccount, Config};
+
+    #[test]
+    fn binary_encoding() {
+        let schema = record_schema("input");
+        let values = [
+            Value::Text("deposit".to_string()),
+            Value::Integer(1),
+            Value::Integer(-2),
+            Value::Decimal(15000),
+        ];
+        let mut buffer = vec![];
+        write_record(&mut buffer, &schema, &values).unwrap();
+        // "deposit", 1, -2, union branch 1 with the decimal 0x3a98
+        assert_eq!(
+            buffer,
+            [14, b'd', b'e', b'p', b'o', b's', b'i', b't', 2, 3, 2, 4, 0x3a, 0x98]
+        );
+        assert_eq!(
+            read_record(&mut buffer.as_slice(), &schema).unwrap(),
+            values
+        );
+
+        let mut buffer = vec![];
+        let schema = record_schema("accounts");
+        let mut account = ClientAccount::new(1, SignedMoney::from_minor_units(-1));
+        account.held = Money::from_minor_units(128);
+        let values = account_values(&AccountProcessing::new(Config::default()), &account);
+        write_record(&mut buffer, &schema, &values).unwrap();
+        // -1 is a single 0xff, 128 needs a leading zero to stay positive
+        assert_eq!(
+            buffer,
+            [2, 2, 0xff, 4, 0, 0x80, 2, 2, 0x7f, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            read_record(&mut buffer.as_slice(), &schema).unwrap(),
+            values
+        );
+
+        assert!(write_record(&mut vec![], &schema, &[Value::Null]).is_err());
+        assert!(read_record(&mut [14, b'd'].as_slice(), &record_schema("input")).is_err());
+    }
+
+    #[test]
+    fn byte_exact_round_trips() {
+        let mut csv = "type,client,tx,amount\n".to_string();
+        for tx in 0..BLOCK_RECORDS + 2 {
+            csv.push_str(&format!("deposit,{},{},1.2345\n", tx % 7, tx));
+        }
+        csv.push_str("dispute,1,1,\n");
+
+        let mut avro = vec![];
+        assert_eq!(
+            csv_to_avro(csv.as_bytes(), &mut avro, &Config::default()),
+            Ok(BLOCK_RECORDS + 3)
+        );
+        let mut back = vec![];
+        avro_to_csv(avro.as_slice(), &mut back, RoundingMode::default()).unwrap();
+        assert_eq!(String::from_utf8(back.clone()).unwrap(), csv);
+
+        let mut again = vec![];
+        csv_to_avro(back.as_slice(), &mut again, &Config::default()).unwrap();
+        assert_eq!(again, avro);
+
+        // the accounts cannot be read as events
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader(csv.as_bytes());
+        let mut accounts = vec![];
+        write_accounts(&app, &mut accounts, app.accounts_iter()).unwrap();
+        assert_eq!(
+            ContainerReader::new(accounts.as_slice(), record_schema("accounts"))
+                .unwrap()
+                .count(),
+            7
+        );
+        assert!(ContainerReader::new(accounts.as_slice(), record_schema("input")).is_err());
+
+        avro.truncate(avro.len() - 1);
+        assert!(avro_to_csv(avro.as_slice(), &mut vec![], RoundingMode::default()).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+//! compact event encoding for producers where writing csv is the bottleneck.
+//!
+//! a stream starts with the magic "KREV" and a version u16, after that every event is a frame,
+//! everything little endian:
+//!
+//! ```text
+//! length u8 | action u8 | client u16 | tx i32 | amount u64 (only deposits and withdrawals)
+//! ```
+//!
+//! the length is the size of the frame without itself, 7 without and 15 with an amount. a frame
+//! of length 0 ends the stream, on a socket it stops the listener like `{"shutdown": true}`.
+//! the tx is signed like everywhere else in the engine
+
+use std::io::{BufRead, Read, Write};
+use std::str::FromStr;
+
+use crate::money::{Money, RoundingMode};
+use crate::snapshot::{action_code, action_from_code};
+use crate::source::{CsvSource, EventSource};
+use crate::{AccountEvent, AccountProcessing, Config, COLUMNS};
+
+/// "KREV" kraken events
+pub const MAGIC: &[u8; 4] = b"KREV";
+const VERSION: u16 = 1;
+const WITHOUT_AMOUNT: u8 = 7;
+const WITH_AMOUNT: u8 = 15;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum InputFormat {
+    // csv files, json lines on the socket
+    #[default]
+    Csv,
+    Binary,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "binary" => Ok(InputFormat::Binary),
+            _ => Err(format!("unknown input format: {} (csv, binary)", s)),
+        }
+    }
+}
+
+pub fn write_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())
+}
+
+pub fn write_event<W: Write>(writer: &mut W, event: &AccountEvent) -> std::io::Result<()> {
+    let length = match event.amount {
+        Some(_) => WITH_AMOUNT,
+        None => WITHOUT_AMOUNT,
+    };
+    writer.write_all(&[length, action_code(event.action_type)?])?;
+    writer.write_all(&event.client_id.to_le_bytes())?;
+    writer.write_all(&event.transaction_id.to_le_bytes())?;
+    if let Some(amount) = event.amount {
+        writer.write_all(&amount.minor_units().to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// only producers on a socket need it, a file just ends
+#[allow(dead_code)]
+pub fn write_end<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writer.write_all(&[0])
+}
+
+pub fn read_header<R: Read>(reader: &mut R) -> Result<(), String> {
+    let mut header = [0u8; 6];
+    reader
+        .read_exact(&mut header)
+        .map_err(|e| format!("cannot read the header: {}", e))?;
+    if &header[..4] != MAGIC {
+        return Err("not a binary event stream".to_string());
+    }
+    if u16::from_le_bytes([header[4], header[5]]) != VERSION {
+        return Err("unsupported binary event version".to_string());
+    }
+
+    Ok(())
+}
+
+/// a frame that cannot be read means the rest of the stream cannot be trusted either
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Frame {
+    Event(AccountEvent),
+    // the frame was read but is no event, the next frame can still be read
+    Invalid(String),
+    End,
+}
+
+/// None at the end of the input, an error if it ends within a frame
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Frame>, String> {
+    let mut length = [0u8; 1];
+    match reader.read(&mut length) {
+        Ok(0) => return Ok(None),
+        Ok(_) => {}
+        Err(e) => return Err(format!("cannot read frame: {}", e)),
+    }
+    let length = length[0];
+    if length == 0 {
+        return Ok(Some(Frame::End));
+    }
+
+    let mut frame = vec![0u8; length as usize];
+    reader
+        .read_exact(&mut frame)
+        .map_err(|e| format!("truncated frame: {}", e))?;
+    if length != WITHOUT_AMOUNT && length != WITH_AMOUNT {
+        return Ok(Some(Frame::Invalid(format!(
+            "invalid frame length {}",
+            length
+        ))));
+    }
+
+    let action_type = match action_from_code(frame[0]) {
+        Ok(action_type) => action_type,
+        Err(_) => {
+            return Ok(Some(Frame::Invalid(format!(
+                "invalid action type {}",
+                frame[0]
+            ))))
+        }
+    };
+    let amount = (length == WITH_AMOUNT).then(|| {
+        let mut minor_units = [0u8; 8];
+        minor_units.copy_from_slice(&frame[7..15]);
+        Money::from_minor_units(u64::from_le_bytes(minor_units))
+    });
+
+    Ok(Some(Frame::Event(AccountEvent {
+        transaction_id: i32::from_le_bytes([frame[3], frame[4], frame[5], frame[6]]),
+        action_type,
+        client_id: u16::from_le_bytes([frame[1], frame[2]]),
+        amount,
+    })))
+}
+
+/// the counterpart of process_reader, true if the stream was ended with an end frame.
+/// the parse errors are numbered by frame, the header is frame 0
+pub fn process_binary<R: BufRead>(app: &mut AccountProcessing, mut reader: R) -> bool {
+    if let Err(e) = read_header(&mut reader) {
+        app.parse_error(0, String::new(), e);
+        return false;
+    }
+
+    for number in 1.. {
+        match read_frame(&mut reader) {
+            Ok(Some(Frame::Event(event))) => app.process_events([event]),
+            Ok(Some(Frame::Invalid(e))) => app.parse_error(number, String::new(), e),
+            Ok(Some(Frame::End)) => return true,
+            Ok(None) => break,
+            Err(e) => {
+                app.parse_error(number, String::new(), e);
+                break;
+            }
+        }
+    }
+
+    info!("{} events processed", app.summary.processed);
+    false
+}
+
+/// the csv events of the built-in types, read by the same source as the input of a run with
+/// the config. a row that a run would quarantine fails the conversion
+pub fn read_csv_events<R: Read, F: FnMut(&AccountEvent) -> Result<(), String>>(
+    reader: R,
+    config: &Config,
+    mut on_event: F,
+) -> Result<u64, String> {
+    let mut source = CsvSource::new(reader, config, vec![]).map_err(|e| e.to_string())?;
+    let mut events = 0;
+    while let Some(next) = source.next_event() {
+        on_event(&next.map_err(|e| e.to_string())?)?;
+        events += 1;
+    }
+
+    Ok(events)
+}
+
+/// the input columns, the amounts with all 4 decimals
+pub fn write_csv_events<W: Write, I: IntoIterator<Item = Result<AccountEvent, String>>>(
+    writer: W,
+    events: I,
+    rounding: RoundingMode,
+) -> Result<u64, String> {
+    let mut writer = csv::Writer::from_writer(writer);
+    let io = |e: csv::Error| format!("cannot write csv: {}", e);
+    writer.write_record(COLUMNS).map_err(io)?;
+
+    let mut written = 0;
+    for event in events {
+        let event = event?;
+        writer
+            .write_record([
+                event.action_type.to_string(),
+                event.client_id.to_string(),
+                event.transaction_id.to_string(),
+                event
+                    .amount
+                    .map(|amount| amount.format(rounding))
+                    .unwrap_or_default(),
+            ])
+            .map_err(io)?;
+        written += 1;
+    }
+    writer
+        .flush()
+        .map_err(|e| format!("cannot write csv: {}", e))?;
+
+    Ok(written)
+}
+
+pub fn csv_to_binary<R: Read, W: Write>(
+    reader: R,
+    writer: &mut W,
+    config: &Config,
+) -> Result<u64, String> {
+    let io = |e: std::io::Error| format!("cannot write binary events: {}", e);
+    write_header(writer).map_err(io)?;
+    let events = read_csv_events(reader, config, |event| {
+        write_event(writer, event).map_err(io)
+    })?;
+    writer.flush().map_err(io)?;
+
+    Ok(events)
+}
+
+pub fn binary_to_csv<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    rounding: RoundingMode,
+) -> Result<u64, String> {
+    read_header(&mut reader)?;
+    let mut number = 0;
+    let frames = std::iter::from_fn(|| {
+        number += 1;
+        match read_frame(&mut reader) {
+            Ok(Some(Frame::Event(event))) => Some(Ok(event)),
+            Ok(Some(Frame::Invalid(e))) => Some(Err(format!("frame {}: {}", number, e))),
+            Ok(Some(Frame::End)) | Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    });
+
+    write_csv_events(writer, frames, rounding)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::binary::{
+        binary_to_csv, csv_to_binary, process_binary, read_frame, write_end, write_event,
+        write_header, Frame, MAGIC,
+    };
+    use crate::money::{Money, RoundingMode};
+    use crate::quarantine::Quarantine;
+    use crate::{AccountActions, AccountEvent, AccountProcessing, Config, SchemaMode};
+
+    const CSV: &str = "type,client,tx,amount\n\
+                       deposit,1,1,1.2345\n\
+                       withdrawal,1,-2,0.5000\n\
+                       dispute,1,1,\n";
+
+    #[test]
+    fn fixed_width_frames() {
+        let event = AccountEvent {
+            transaction_id: 2,
+            action_type: AccountActions::Withdrawal,
+            client_id: 1,
+            amount: Some(Money::from_minor_units(5000)),
+        };
+        let mut frames = vec![];
+        write_event(&mut frames, &event).unwrap();
+        assert_eq!(
+            frames,
+            [15, 1, 1, 0, 2, 0, 0, 0, 0x88, 0x13, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            read_frame(&mut frames.as_slice()),
+            Ok(Some(Frame::Event(event)))
+        );
+
+        assert!(read_frame(&mut [15u8, 1, 1].as_slice()).is_err());
+        assert_eq!(
+            read_frame(&mut [7u8, 9, 1, 0, 1, 0, 0, 0].as_slice()),
+            Ok(Some(Frame::Invalid("invalid action type 9".to_string())))
+        );
+        assert_eq!(read_frame(&mut [].as_slice()), Ok(None));
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let mut binary = vec![];
+        let events = csv_to_binary(CSV.as_bytes(), &mut binary, &Config::default()).unwrap();
+        assert_eq!(events, 3);
+        assert!(binary.starts_with(MAGIC));
+        assert_eq!(binary.len(), 6 + 16 + 16 + 8);
+
+        let mut csv = vec![];
+        binary_to_csv(binary.as_slice(), &mut csv, RoundingMode::default()).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), CSV);
+
+        let custom = "type,client,tx,amount\nbonus,1,1,1.0\n";
+        assert!(csv_to_binary(custom.as_bytes(), &mut vec![], &Config::default()).is_err());
+    }
+
+    #[test]
+    fn csv_is_read_like_the_input() {
+        let with_bom = format!("\u{feff}{}", CSV);
+        let mut binary = vec![];
+        let events = csv_to_binary(with_bom.as_bytes(), &mut binary, &Config::default()).unwrap();
+        assert_eq!(events, 3);
+
+        let extra_column = "type,client,tx,amount,note\ndeposit,1,1,1.0,x\n";
+        assert!(csv_to_binary(extra_column.as_bytes(), &mut vec![], &Config::default()).is_ok());
+        let strict = Config {
+            schema: SchemaMode::Strict,
+            ..Config::default()
+        };
+        assert!(csv_to_binary(extra_column.as_bytes(), &mut vec![], &strict).is_err());
+    }
+
+    #[test]
+    fn process_like_the_csv() {
+        let mut binary = vec![];
+        csv_to_binary(CSV.as_bytes(), &mut binary, &Config::default()).unwrap();
+        binary.extend([7, 9, 1, 0, 1, 0, 0, 0]);
+        write_end(&mut binary).unwrap();
+
+        let mut app = AccountProcessing::new(Config::default());
+        app.subsystems_mut().quarantine = Some(Quarantine::default());
+        assert!(process_binary(&mut app, binary.as_slice()));
+        let mut expected = AccountProcessing::new(Config::default());
+        expected.process_reader(CSV.as_bytes());
+        assert_eq!(app.account(1), expected.account(1));
+        assert_eq!(app.quarantine().unwrap().parse_errors[0].line, 4);
+
+        let mut header_only = vec![];
+        write_header(&mut header_only).unwrap();
+        let mut app = AccountProcessing::new(Config::default());
+        assert!(!process_binary(&mut app, header_only.as_slice()));
+        assert!(!process_binary(&mut app, "type,client".as_bytes()));
+    }
+}
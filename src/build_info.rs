@@ -0,0 +1,87 @@
+use crate::audit::{AuditLog, AuditValue};
+use crate::money::{RoundingMode, DECIMAL_PLACES};
+
+/// set by build.rs, "unknown" if the build did not happen in a git checkout
+pub const GIT_HASH: &str = env!("KRAKEN_GIT_HASH");
+pub const BUILD_DATE: &str = env!("KRAKEN_BUILD_DATE");
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// the enabled cargo features, none for the default build
+pub fn features() -> String {
+    let mut features = vec![];
+    if cfg!(feature = "testkit") {
+        features.push("testkit");
+    }
+    if cfg!(feature = "events") {
+        features.push("events");
+    }
+
+    match features.is_empty() {
+        true => "none".to_string(),
+        false => features.join(","),
+    }
+}
+
+pub fn version() -> String {
+    format!("{} {}", env!("CARGO_PKG_NAME"), VERSION)
+}
+
+/// one line so it fits into the run summary
+pub fn summary() -> String {
+    format!(
+        "{} (git {}, built {}, features: {}, {} decimals, rounding {})",
+        version(),
+        GIT_HASH,
+        BUILD_DATE,
+        features(),
+        DECIMAL_PLACES,
+        RoundingMode::default()
+    )
+}
+
+/// --build-info
+pub fn build_info() -> String {
+    format!(
+        "{}\ngit: {}\nbuilt: {}\nfeatures: {}\ndecimal places: {}\ndefault rounding: {}",
+        version(),
+        GIT_HASH,
+        BUILD_DATE,
+        features(),
+        DECIMAL_PLACES,
+        RoundingMode::default()
+    )
+}
+
+/// the first line of an audit log file, the decisions after it were made by this binary
+pub fn write_audit_header(audit: &mut AuditLog) {
+    audit.record(&[
+        ("event", AuditValue::Str("build")),
+        ("version", AuditValue::Str(VERSION)),
+        ("git", AuditValue::Str(GIT_HASH)),
+        ("built", AuditValue::Str(BUILD_DATE)),
+        ("features", AuditValue::Str(&features())),
+        ("decimal_places", AuditValue::Int(DECIMAL_PLACES as i128)),
+    ]);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::audit::test::SharedBuffer;
+    use crate::audit::AuditLog;
+    use crate::build_info::{build_info, summary, write_audit_header, BUILD_DATE, GIT_HASH};
+
+    #[test]
+    fn build_metadata() {
+        assert_eq!(BUILD_DATE.len(), 10);
+        assert!(!GIT_HASH.is_empty());
+        assert!(summary().starts_with("kraken_test 0.1.0 (git "));
+        assert!(build_info().contains("\ndecimal places: 4\n"));
+
+        let buffer = SharedBuffer::default();
+        let mut audit = AuditLog::new(Box::new(buffer.clone()));
+        write_audit_header(&mut audit);
+        let header = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(header.starts_with("{\"event\":\"build\",\"version\":\"0.1.0\",\"git\":"));
+        assert!(header.ends_with(",\"decimal_places\":4}\n"));
+    }
+}
@@ -0,0 +1,2425 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::admin::AdminBatch;
+use crate::analytics::write_statistics_report;
+use crate::audit::AuditLog;
+use crate::avro::OutputFormat;
+use crate::binary::{
+    binary_to_csv, csv_to_binary, process_binary, read_frame, read_header, Frame, InputFormat,
+};
+use crate::compaction::Compaction;
+use crate::compliance::{write_compliance_report, ComplianceRules, ComplianceViolation};
+use crate::engine::{AccountProcessing, Config, ProcessingSummary};
+use crate::graph::{DisputeGraph, GraphFormat};
+use crate::holdback::Holdback;
+use crate::incremental::{open_from_offset, read_offset_file, write_offset_file};
+use crate::integrity::{write_accounts_with, OutputIntegrity};
+use crate::invariants::InvariantMode;
+use crate::io::COLUMNS;
+use crate::latency::LatencyHistogram;
+use crate::metadata::load_client_metadata;
+use crate::metrics::{Metrics, PrometheusMetrics};
+use crate::money::{Money, RoundingMode};
+use crate::opening::{export_closing, load_opening_state};
+use crate::partition::{partition_of, write_partitioned, Partition};
+use crate::policy::Policy;
+use crate::quarantine::Quarantine;
+use crate::replay::Exclusions;
+use crate::risk::RiskScoring;
+use crate::sampling::Sample;
+use crate::schema::{write_schemas, SchemaFormat};
+use crate::shadow::{divergence, run_shadow, ShadowEngine};
+use crate::simulation::compare;
+use crate::sink::CsvSink;
+use crate::snapshot::Snapshot;
+use crate::source::{EventSource, FanIn};
+use crate::structuring::{write_suspicious_activity_report, StructuringDetector};
+use crate::{AccountActions, AccountEvent};
+
+#[derive(Clone)]
+pub struct Args {
+    pub config: Config,
+    pub path: String,
+    // processed after path like one long input, e.g. the files of several days
+    pub more_inputs: Vec<String>,
+    pub clients_path: Option<String>,
+    pub compliance: ComplianceRules,
+    pub compliance_report: Option<String>,
+    pub structuring: Option<StructuringDetector>,
+    pub structuring_report: Option<String>,
+    pub activity_score: Option<RiskScoring>,
+    // the lifetime numbers per client, with --state they cover the previous runs as well
+    pub statistics_report: Option<String>,
+    pub graph_out: Option<String>,
+    pub graph_format: GraphFormat,
+    // only used by the simulate subcommand
+    pub policy_path: Option<String>,
+    // the accounts csv is written to this file instead of stdout
+    pub output: Option<String>,
+    // instead of stdout the accounts are split into this many files
+    pub partition_output: Option<usize>,
+    // a checksum for the accounts output, a sidecar needs the partition files
+    pub output_integrity: Option<OutputIntegrity>,
+    // csv or an avro container file, only for the accounts on stdout
+    pub output_format: OutputFormat,
+    // the input is processed by this many sub-engines in parallel
+    pub partition_by_client: Option<usize>,
+    pub output_dir: String,
+    // state that is imported before and exported after the run
+    pub state_path: Option<String>,
+    // an imported state is used even if it violates the invariants
+    pub skip_state_check: bool,
+    // deposited before the input, the closing balances of the previous period
+    pub opening_balances: Option<String>,
+    // the balances and open disputes in the format of the opening balances
+    pub export_closing: Option<String>,
+    // only the rows after this byte offset are processed
+    pub since_offset: Option<u64>,
+    // sidecar file with the offset, read before the run and updated after the state was saved
+    pub offset_file: Option<String>,
+    pub audit_log: Option<String>,
+    // events that take longer than this to apply are logged
+    pub slow_event_threshold: Option<Duration>,
+    pub latency_report: Option<String>,
+    // the path is a unix socket the events are read from instead of a csv
+    pub listen_uds: bool,
+    // csv or the framed binary events, for files and the socket
+    pub input_format: InputFormat,
+    // the input is processed a second time by this engine and the final states are compared
+    pub shadow_engine: Option<ShadowEngine>,
+    pub shadow_report: Option<String>,
+    // parse errors and rejected events are written to 2 files in it
+    pub quarantine_dir: Option<String>,
+    pub exclusions: Exclusions,
+    // action types that are not processed, written to the holdback file if there is one
+    pub holdback: Holdback,
+    pub holdback_path: Option<String>,
+    // csv files that are processed after the input, e.g. a corrected holdback
+    pub then: Vec<String>,
+    // csv files read at the same time as the input, by name. the input itself is "input"
+    pub sources: Vec<(String, String)>,
+    // shared by the sub-engines, written in the prometheus text format after the run
+    pub metrics: Option<Arc<PrometheusMetrics>>,
+    pub metrics_out: Option<String>,
+    // accounts without a balance and without an event in this many events are removed
+    pub compact_dormant: Option<u64>,
+    // the removed accounts in the format of the opening balances
+    pub compact_archive: Option<String>,
+    // corrections that are applied after the input, only with the hmac of the file
+    pub admin_batch: Option<String>,
+    pub admin_hmac: Option<String>,
+    // the file with the key the hmac is checked with
+    pub admin_hmac_key: Option<String>,
+    // the author of the notes of the batch, defaults to $USER
+    pub admin_author: Option<String>,
+}
+
+/// the path of the input to read it from stdin
+pub const STDIN: &str = "-";
+
+/// very small hand rolled parser, the non flag arguments are the csv paths or - for stdin.
+/// a * or ? in the file name is expanded to the matching files in name order
+///  --rounding half-up|half-even|truncate
+///  --output-number-format standard|eu
+///  --account-versions (version and last_tx columns, the version counts the applied events)
+///  --clients clients.csv
+///  --high-risk-score 80
+///  --high-risk-withdrawal-limit 1000.0
+///  --embargoed-countries KP,IR
+///  --country-ceiling US=10000 (repeatable)
+///  --compliance-report violations.csv
+///  --structuring 10000 (reporting threshold, enables the detector)
+///  --structuring-window 10
+///  --structuring-count 3
+///  --structuring-report sar.csv
+///  --activity-score (0-100 per client from disputes, chargebacks and velocity, an output column)
+///  --activity-score-rules rules.toml (the \[activity_score\] section of a policy file, implies the above)
+///  --statistics-report statistics.csv (lifetime deposits and disputes per client, kept in --state)
+///  --graph-out disputes.dot
+///  --graph-format dot|graphml
+///  --policy alt.toml (simulate only)
+///  --sample 1%
+///  --seed 7 (for the sample, defaults to 0)
+///  --output accounts.csv (instead of stdout, only for the plain csv)
+///  --partition-output 16
+///  --output-integrity trailer|sidecar (#rows=..,sha256=.. line or a .sha256 file per partition)
+///  --output-format csv|avro (avro only for the accounts on stdout)
+///  --partition-by-client 4 (parallel sub-engines, one per client partition. an input that
+///    reuses tx ids across partitions is processed by one engine)
+///  --output-dir out (for the partition files, defaults to the current directory)
+///  --state state.bin (loaded if it exists, written after the run)
+///  --skip-state-check (a loaded state that violates the invariants is only logged, not refused)
+///  --opening-balances balances.csv (client,available,locked,tx,held,type,amount, before the input)
+///  --export-closing closing.csv (the opening balances of the next run)
+///  --since-offset 1024
+///  --offset-file input.csv.offset (needs --state, written after it)
+///  --dispute-policy allow-negative|partial-hold|reject-and-report
+///  --account-creation deposit|any (which events create the account of an unknown client)
+///  --audit-log audit.jsonl
+///  --disputable deposit,withdrawal (defaults to deposit)
+///  --tolerate-missing-columns
+///  --skip-embedded-headers
+///  --schema strict|flexible (flexible ignores the column order and unknown columns)
+///  --schema-version 1|2 (2 adds timestamp and currency, a schema_version column overrides it
+///    per row, without both the version is detected from the header)
+///  --input-encoding auto|utf-8|utf-16le|utf-16be|windows-1252 (auto only detects BOMs)
+///  --slow-event-us 500 (events that take longer to apply are logged)
+///  --latency-report latency.csv
+///  --metrics-out metrics.prom (prometheus text format, for the textfile collector)
+///  --listen-uds (the path is a unix socket json events are read from)
+///  --input-format csv|binary (binary is the framed encoding of src/binary.rs)
+///  --shadow-engine single|partitioned:4 (compares the final state with a second run, a failed
+///    second run diverges on every client)
+///  --shadow-report divergence.csv
+///  --quarantine-dir rejects (parse-errors.csv and policy-rejects.csv)
+///  --log-balances (info line with the balances before and after every applied event)
+///  --check-invariants (stops with an error on the first violation)
+///  --redact-amounts (masks the amounts in the logs, the audit log and the quarantine)
+///  --strict (a panic while applying an event aborts the run, otherwise the event is rejected)
+///  --locked-deposits (deposits to locked accounts are credited, withdrawals stay rejected)
+///  --reject-duplicate-tx (a deposit or withdrawal with a known tx id is rejected)
+///  --report-invariants (logs every violation and continues)
+///  --exclude-tx 1234,1235 (repeatable, also excludes the disputes of the tx)
+///  --exclude-client 42 (repeatable)
+///  --ignore chargeback (repeatable, the action types are not processed in this run)
+///  --holdback held.csv (the ignored events in the input format)
+///  --then held.csv (repeatable, csv files processed after the input, nothing is ignored in them)
+///  --source acquirer-b=b.csv (repeatable, merged with the input, the audit log and the metrics name the source)
+///  --compact-dormant 100000 (removes unlocked accounts without a balance, open dispute or an
+///    event in the last 100000 events)
+///  --compact-archive dormant.csv (the removed accounts, readable as --opening-balances. an
+///    existing archive is loaded and its accounts are restored when their client has an event)
+///  --admin-batch corrections.csv --admin-hmac `<hmac-sha256 of the file>`
+///    --admin-hmac-key batch.key (unlock, adjust, close and annotate rows applied after the
+///    input, needs --audit-log. the hmac is an integrity check of the file, not an approval)
+///  --admin-author alice (of the annotations of the batch, defaults to $USER)
+pub fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut config = Config::default();
+    let mut inputs: Vec<String> = vec![];
+    let mut clients_path: Option<String> = None;
+    let mut compliance = ComplianceRules::default();
+    let mut compliance_report: Option<String> = None;
+    let mut structuring_report: Option<String> = None;
+    let mut graph_out: Option<String> = None;
+    let mut graph_format = GraphFormat::default();
+    let mut policy_path: Option<String> = None;
+    let mut sample_rate: Option<String> = None;
+    let mut seed: u64 = 0;
+    let mut output: Option<String> = None;
+    let mut partition_output: Option<usize> = None;
+    let mut output_integrity: Option<OutputIntegrity> = None;
+    let mut output_format = OutputFormat::default();
+    let mut partition_by_client: Option<usize> = None;
+    let mut output_dir = ".".to_string();
+    let mut state_path: Option<String> = None;
+    let mut skip_state_check = false;
+    let mut opening_balances: Option<String> = None;
+    let mut export_closing: Option<String> = None;
+    let mut since_offset: Option<u64> = None;
+    let mut offset_file: Option<String> = None;
+    let mut audit_log: Option<String> = None;
+    let mut slow_event_threshold: Option<Duration> = None;
+    let mut latency_report: Option<String> = None;
+    let mut listen_uds = false;
+    let mut input_format = InputFormat::default();
+    let mut shadow_engine: Option<ShadowEngine> = None;
+    let mut shadow_report: Option<String> = None;
+    let mut quarantine_dir: Option<String> = None;
+    let mut exclusions = Exclusions::default();
+    let mut holdback = Holdback::default();
+    let mut holdback_path: Option<String> = None;
+    let mut then: Vec<String> = vec![];
+    let mut sources: Vec<(String, String)> = vec![];
+    let mut compact_dormant: Option<u64> = None;
+    let mut compact_archive: Option<String> = None;
+    let mut admin_batch: Option<String> = None;
+    let mut admin_hmac: Option<String> = None;
+    let mut admin_hmac_key: Option<String> = None;
+    let mut admin_author: Option<String> = None;
+    let mut metrics_out: Option<String> = None;
+    let mut activity_score: Option<RiskScoring> = None;
+    let mut statistics_report: Option<String> = None;
+    // the amounts are parsed once --rounding is known, wherever it was given
+    let mut withdrawal_limit: Option<String> = None;
+    let mut country_ceilings: Vec<String> = vec![];
+    let mut structuring_threshold: Option<String> = None;
+    let mut structuring_window: Option<usize> = None;
+    let mut structuring_count: Option<usize> = None;
+    let parse_usize = |value: &str| {
+        value
+            .parse::<usize>()
+            .map_err(|_| format!("invalid number: {}", value))
+    };
+    let mut iter = args.iter().skip(1);
+
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| format!("{} needs a value", arg));
+
+        match arg.as_str() {
+            "--rounding" => config.rounding = value()?.parse()?,
+            "--output-number-format" => config.number_format = value()?.parse()?,
+            "--clients" => clients_path = Some(value()?.to_string()),
+            "--high-risk-score" => {
+                let score = value()?;
+                config.high_risk_score = Some(
+                    score
+                        .parse()
+                        .map_err(|_| format!("invalid risk score: {}", score))?,
+                )
+            }
+            "--high-risk-withdrawal-limit" => withdrawal_limit = Some(value()?.to_string()),
+            "--embargoed-countries" => compliance.add_embargoed(value()?),
+            "--country-ceiling" => country_ceilings.push(value()?.to_string()),
+            "--compliance-report" => compliance_report = Some(value()?.to_string()),
+            "--structuring" => structuring_threshold = Some(value()?.to_string()),
+            "--structuring-window" => structuring_window = Some(parse_usize(value()?)?),
+            "--structuring-count" => structuring_count = Some(parse_usize(value()?)?),
+            "--structuring-report" => structuring_report = Some(value()?.to_string()),
+            "--graph-out" => graph_out = Some(value()?.to_string()),
+            "--graph-format" => graph_format = value()?.parse()?,
+            "--policy" => policy_path = Some(value()?.to_string()),
+            "--sample" => sample_rate = Some(value()?.to_string()),
+            "--partition-output" => {
+                let partitions = parse_usize(value()?)?;
+                if partitions == 0 {
+                    return Err("--partition-output needs at least 1 partition".to_string());
+                }
+                partition_output = Some(partitions)
+            }
+            "--output-integrity" => output_integrity = Some(value()?.parse()?),
+            "--output-format" => output_format = value()?.parse()?,
+            "--partition-by-client" => {
+                let partitions = parse_usize(value()?)?;
+                if partitions == 0 {
+                    return Err("--partition-by-client needs at least 1 partition".to_string());
+                }
+                partition_by_client = Some(partitions)
+            }
+            "--output-dir" => output_dir = value()?.to_string(),
+            "--state" => state_path = Some(value()?.to_string()),
+            "--skip-state-check" => skip_state_check = true,
+            "--opening-balances" => opening_balances = Some(value()?.to_string()),
+            "--export-closing" => export_closing = Some(value()?.to_string()),
+            "--since-offset" => {
+                let raw = value()?;
+                since_offset = Some(
+                    raw.parse()
+                        .map_err(|_| format!("invalid offset: {}", raw))?,
+                )
+            }
+            "--offset-file" => offset_file = Some(value()?.to_string()),
+            "--dispute-policy" => config.dispute_policy = value()?.parse()?,
+            "--account-creation" => config.account_creation = value()?.parse()?,
+            "--disputable" => config.disputable = value()?.parse()?,
+            "--audit-log" => audit_log = Some(value()?.to_string()),
+            "--slow-event-us" => {
+                let micros = value()?;
+                slow_event_threshold = Some(Duration::from_micros(
+                    micros
+                        .parse()
+                        .map_err(|_| format!("invalid number: {}", micros))?,
+                ))
+            }
+            "--latency-report" => latency_report = Some(value()?.to_string()),
+            "--tolerate-missing-columns" => config.tolerate_missing_columns = true,
+            "--skip-embedded-headers" => config.skip_embedded_headers = true,
+            "--listen-uds" => listen_uds = true,
+            "--input-format" => input_format = value()?.parse()?,
+            "--shadow-engine" => shadow_engine = Some(value()?.parse()?),
+            "--shadow-report" => shadow_report = Some(value()?.to_string()),
+            "--quarantine-dir" => quarantine_dir = Some(value()?.to_string()),
+            "--log-balances" => config.log_balances = true,
+            "--account-versions" => config.account_versions = true,
+            "--check-invariants" => config.invariants = Some(InvariantMode::Abort),
+            "--report-invariants" => config.invariants = Some(InvariantMode::Report),
+            "--strict" => config.strict = true,
+            "--redact-amounts" => config.redact_amounts = true,
+            "--locked-deposits" => config.locked_deposits = true,
+            "--reject-duplicate-tx" => config.reject_duplicate_tx = true,
+            "--exclude-tx" => exclusions.add_transactions(value()?)?,
+            "--exclude-client" => exclusions.add_clients(value()?)?,
+            "--ignore" => holdback.add_actions(value()?)?,
+            "--holdback" => holdback_path = Some(value()?.to_string()),
+            "--then" => then.push(value()?.to_string()),
+            "--source" => {
+                let raw = value()?;
+                let (name, path) = raw
+                    .split_once('=')
+                    .filter(|(name, _)| !name.is_empty() && *name != "input")
+                    .ok_or_else(|| format!("invalid source, expected name=path: {}", raw))?;
+                if sources.iter().any(|(known, _)| known == name) {
+                    return Err(format!("source {} is given twice", name));
+                }
+                sources.push((name.to_string(), path.to_string()));
+            }
+            "--compact-dormant" => {
+                let raw = value()?;
+                compact_dormant = Some(
+                    raw.parse()
+                        .ok()
+                        .filter(|events| *events > 0)
+                        .ok_or_else(|| format!("invalid number of events: {}", raw))?,
+                )
+            }
+            "--compact-archive" => compact_archive = Some(value()?.to_string()),
+            "--admin-batch" => admin_batch = Some(value()?.to_string()),
+            "--admin-hmac" => admin_hmac = Some(value()?.to_string()),
+            "--admin-hmac-key" => admin_hmac_key = Some(value()?.to_string()),
+            "--admin-author" => admin_author = Some(value()?.to_string()),
+            "--metrics-out" => metrics_out = Some(value()?.to_string()),
+            "--statistics-report" => statistics_report = Some(value()?.to_string()),
+            "--activity-score" => {
+                activity_score.get_or_insert_with(RiskScoring::default);
+            }
+            "--activity-score-rules" => Policy::load(value()?)?
+                .apply_activity_score(activity_score.get_or_insert_with(RiskScoring::default))?,
+            "--schema" => config.schema = value()?.parse()?,
+            "--output" => output = Some(value()?.to_string()),
+            "--schema-version" => config.schema_version = Some(value()?.parse()?),
+            "--input-encoding" => config.input_encoding = value()?.parse()?,
+            "--seed" => {
+                let raw = value()?;
+                seed = raw.parse().map_err(|_| format!("invalid seed: {}", raw))?
+            }
+            // a mistyped flag would otherwise be looked for as an input file
+            _ if arg.starts_with('-') && arg != STDIN => {
+                return Err(format!("unknown argument: {}", arg))
+            }
+            _ => inputs.extend(expand_glob(arg)?),
+        }
+    }
+
+    let mut inputs = inputs.into_iter();
+    let path = inputs
+        .next()
+        .ok_or("needs the path of the csv as CLI parameter")?;
+    let more_inputs: Vec<String> = inputs.collect();
+    if !more_inputs.is_empty()
+        && (listen_uds
+            || input_format == InputFormat::Binary
+            || since_offset.is_some()
+            || offset_file.is_some()
+            || !sources.is_empty())
+    {
+        return Err(
+            "several inputs are only csv files, without --listen-uds, binary input, offsets \
+             and --source"
+                .to_string(),
+        );
+    }
+    let stdin_count = std::iter::once(&path)
+        .chain(&more_inputs)
+        .filter(|input| *input == STDIN)
+        .count();
+    if stdin_count > 1 {
+        return Err("stdin can only be one of the inputs".to_string());
+    }
+    if stdin_count == 1
+        && (partition_by_client.is_some()
+            || shadow_engine.is_some()
+            || since_offset.is_some()
+            || offset_file.is_some())
+    {
+        return Err(
+            "stdin is read once, without --partition-by-client, --shadow-engine and offsets"
+                .to_string(),
+        );
+    }
+    if offset_file.is_some() && state_path.is_none() {
+        return Err(
+            "--offset-file needs --state, the offset is only stored together with the state"
+                .to_string(),
+        );
+    }
+    config.check()?;
+    if admin_batch.is_some() != admin_hmac.is_some()
+        || admin_batch.is_some() != admin_hmac_key.is_some()
+    {
+        return Err(
+            "--admin-batch, --admin-hmac and --admin-hmac-key are only given together".to_string(),
+        );
+    }
+    if admin_batch.is_some() && audit_log.is_none() {
+        return Err("--admin-batch needs --audit-log".to_string());
+    }
+    if admin_author.is_some() && admin_batch.is_none() {
+        return Err("--admin-author needs --admin-batch".to_string());
+    }
+    if compact_archive.is_some() && compact_dormant.is_none() {
+        return Err("--compact-archive needs --compact-dormant".to_string());
+    }
+    if !sources.is_empty()
+        && (listen_uds
+            || input_format == InputFormat::Binary
+            || since_offset.is_some()
+            || offset_file.is_some())
+    {
+        return Err(
+            "--source only merges csv files, without --listen-uds, binary input and offsets"
+                .to_string(),
+        );
+    }
+    if holdback_path.is_some() && holdback.is_empty() {
+        return Err("--holdback needs at least one --ignore".to_string());
+    }
+    if output.is_some()
+        && (partition_output.is_some()
+            || output_integrity.is_some()
+            || output_format == OutputFormat::Avro)
+    {
+        return Err(
+            "--output is only for the plain csv, without --partition-output, \
+             --output-integrity and avro"
+                .to_string(),
+        );
+    }
+    if output_integrity == Some(OutputIntegrity::Sidecar) && partition_output.is_none() {
+        return Err("a sidecar checksum needs the files of --partition-output".to_string());
+    }
+    if output_format == OutputFormat::Avro
+        && (partition_output.is_some() || output_integrity.is_some())
+    {
+        return Err(
+            "avro is only written to stdout, without --partition-output and --output-integrity"
+                .to_string(),
+        );
+    }
+    if let Some(limit) = &withdrawal_limit {
+        config.high_risk_withdrawal_limit = Some(Money::parse(limit, config.rounding)?);
+    }
+    for ceiling in &country_ceilings {
+        compliance.add_ceiling(ceiling, config.rounding)?;
+    }
+    if !compliance.is_empty() && clients_path.is_none() {
+        return Err("compliance rules need the client metadata (--clients)".to_string());
+    }
+
+    if let Some(rate) = &sample_rate {
+        config.sample = Some(Sample::parse(rate, seed)?);
+    }
+
+    let structuring_threshold = structuring_threshold
+        .map(|threshold| Money::parse(&threshold, config.rounding))
+        .transpose()?;
+    let structuring = structuring_threshold.map(|threshold| {
+        let mut detector = StructuringDetector::new(threshold);
+        detector.window = structuring_window.unwrap_or(detector.window);
+        detector.min_count = structuring_count.unwrap_or(detector.min_count);
+        detector
+    });
+
+    Ok(Args {
+        config,
+        path,
+        more_inputs,
+        clients_path,
+        compliance,
+        compliance_report,
+        structuring,
+        structuring_report,
+        activity_score,
+        statistics_report,
+        graph_out,
+        graph_format,
+        policy_path,
+        output,
+        partition_output,
+        output_integrity,
+        output_format,
+        partition_by_client,
+        output_dir,
+        state_path,
+        skip_state_check,
+        opening_balances,
+        export_closing,
+        since_offset,
+        offset_file,
+        audit_log,
+        slow_event_threshold,
+        latency_report,
+        listen_uds,
+        input_format,
+        shadow_engine,
+        shadow_report,
+        quarantine_dir,
+        exclusions,
+        holdback,
+        holdback_path,
+        then,
+        sources,
+        metrics: metrics_out.as_ref().map(|_| Arc::default()),
+        metrics_out,
+        compact_dormant,
+        compact_archive,
+        admin_batch,
+        admin_hmac,
+        admin_hmac_key,
+        admin_author,
+    })
+}
+
+pub fn build_processing(args: &Args) -> Result<AccountProcessing, String> {
+    let mut app = AccountProcessing::new(args.config);
+    if let Some(clients_path) = &args.clients_path {
+        app.client_metadata = load_client_metadata(clients_path)?;
+    }
+    app.compliance = args.compliance.clone();
+    if let Some(structuring) = &args.structuring {
+        app.subsystems_mut().structuring = Some(structuring.clone());
+    }
+    if let Some(risk) = &args.activity_score {
+        app.subsystems_mut().risk = Some(risk.clone());
+    }
+    if args.graph_out.is_some() {
+        app.subsystems_mut().dispute_graph = Some(DisputeGraph::default());
+    }
+
+    if let Some(audit_log) = &args.audit_log {
+        let mut audit = AuditLog::create(audit_log)
+            .map_err(|e| format!("cannot create audit log {}: {}", audit_log, e))?;
+        audit.redact_amounts = args.config.redact_amounts;
+        crate::build_info::write_audit_header(&mut audit);
+        app.subsystems_mut().audit = Some(audit);
+    }
+
+    if args.slow_event_threshold.is_some() || args.latency_report.is_some() {
+        app.subsystems_mut().latency = Some(LatencyHistogram::new(args.slow_event_threshold));
+    }
+    if args.quarantine_dir.is_some() {
+        app.subsystems_mut().quarantine = Some(Quarantine {
+            redact_amounts: args.config.redact_amounts,
+            ..Quarantine::default()
+        });
+    }
+    app.exclusions = args.exclusions.clone();
+    app.holdback = args.holdback.clone();
+    if let Some(metrics) = &args.metrics {
+        app.subsystems_mut().metrics = Some(metrics.clone());
+    }
+    if args.holdback_path.is_some() {
+        app.holdback.events = Some(vec![]);
+    }
+    if let Some(dormant_after) = args.compact_dormant {
+        let mut compaction = Compaction::new(dormant_after);
+        if let Some(path) = &args.compact_archive {
+            compaction.archive = Some(Default::default());
+            if Path::new(path).exists() {
+                compaction.load_archive(path, args.config.rounding)?;
+            }
+        }
+        app.subsystems_mut().compaction = Some(compaction);
+    }
+
+    if let Some(state_path) = &args.state_path {
+        if Path::new(state_path).exists() {
+            let snapshot = Snapshot::load(state_path)
+                .map_err(|e| format!("cannot load state {}: {}", state_path, e))?;
+            app.restore(snapshot);
+            // a broken state would only be found by the events that happen to touch it
+            let violations = crate::invariants::check_state(&app);
+            if !violations.is_empty() && !args.skip_state_check {
+                return Err(format!(
+                    "the state {} is inconsistent, nothing was processed:\n{}",
+                    state_path,
+                    violations.join("\n")
+                ));
+            }
+            for violation in &violations {
+                warn!("{}", violation);
+            }
+        }
+    }
+    if let Some(path) = &args.opening_balances {
+        let opening = load_opening_state(path, args.config.rounding)?;
+        for balance in opening.balances.values() {
+            app.open_balance(balance)?;
+        }
+        for dispute in opening.disputes.values() {
+            app.carry_dispute(dispute)?;
+        }
+    }
+
+    Ok(app)
+}
+
+/// the primary input and after it the --then files with the same state
+/// the end offset of the input is returned instead of written, it is only stored after the
+/// state so a failed run reads the same rows again
+pub fn process_input(app: &mut AccountProcessing, args: &Args) -> Result<Option<u64>, String> {
+    let end = process_primary(app, args)?;
+    // the csv inputs stop on their own, the socket and the binary input only leave it behind
+    if let Some(violation) = app.halted() {
+        return Err(violation.to_string());
+    }
+    for path in &args.more_inputs {
+        if path != STDIN && !Path::new(path).exists() {
+            return Err(format!("file does not exist: {}", path));
+        }
+        info!("processing {}", path);
+        app.try_process_reader(BufReader::new(open_input(path)?))
+            .map_err(|e| e.to_string())?;
+    }
+
+    // the ignored action types are what the late files are for
+    app.holdback.actions.clear();
+    for path in &args.then {
+        if !Path::new(path).exists() {
+            return Err(format!("file does not exist: {}", path));
+        }
+        info!("processing {} after the input", path);
+        app.process_file(path).map_err(|e| e.to_string())?;
+    }
+
+    if let (Some(path), Some(hmac), Some(key_path)) =
+        (&args.admin_batch, &args.admin_hmac, &args.admin_hmac_key)
+    {
+        let mut batch = AdminBatch::load(path, app.config.rounding)?;
+        let key = std::fs::read(key_path)
+            .map_err(|e| format!("cannot read hmac key {}: {}", key_path, e))?;
+        // the key as `$(cat batch.key)` passes it to openssl
+        batch.check_hmac(hmac, key.trim_ascii_end())?;
+        batch.author = match &args.admin_author {
+            Some(author) => author.clone(),
+            None => std::env::var("USER").unwrap_or_else(|_| "unknown".to_string()),
+        };
+        batch.apply(app)?;
+    }
+
+    Ok(end)
+}
+
+/// the files in the directory of the pattern whose name matches it, sorted by name. a path
+/// without * or ? is taken as it is
+fn expand_glob(pattern: &str) -> Result<Vec<String>, String> {
+    let path = Path::new(pattern);
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(vec![pattern.to_string()]);
+    };
+    if !name.contains(['*', '?']) {
+        return Ok(vec![pattern.to_string()]);
+    }
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| format!("cannot read {}: {}", dir.display(), e))?;
+    let mut matched = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("cannot read {}: {}", dir.display(), e))?;
+        let file_name = entry.file_name();
+        let is_file = entry.file_type().is_ok_and(|file_type| file_type.is_file());
+        if let Some(file_name) = file_name.to_str().filter(|_| is_file) {
+            if matches_glob(name.as_bytes(), file_name.as_bytes()) {
+                matched.push(
+                    path.with_file_name(file_name)
+                        .to_string_lossy()
+                        .into_owned(),
+                );
+            }
+        }
+    }
+    if matched.is_empty() {
+        return Err(format!("no file matches {}", pattern));
+    }
+
+    matched.sort();
+    Ok(matched)
+}
+
+/// * is any number of characters and ? exactly one
+fn matches_glob(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            matches_glob(&pattern[1..], name)
+                || (!name.is_empty() && matches_glob(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => matches_glob(&pattern[1..], &name[1..]),
+        (Some(expected), Some(actual)) if expected == actual => {
+            matches_glob(&pattern[1..], &name[1..])
+        }
+        _ => false,
+    }
+}
+
+/// the file or stdin for STDIN
+fn open_input(path: &str) -> Result<Box<dyn Read>, String> {
+    if path == STDIN {
+        return Ok(Box::new(std::io::stdin().lock()));
+    }
+    let file = File::open(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    Ok(Box::new(file))
+}
+
+/// the whole file or only the newly appended rows if we were given an offset, then the offset
+/// the rows were read up to
+fn process_primary(app: &mut AccountProcessing, args: &Args) -> Result<Option<u64>, String> {
+    if args.listen_uds {
+        return listen(app, args).map(|_| None);
+    }
+
+    if args.path != STDIN && !Path::new(&args.path).exists() {
+        return Err("file does not exist".to_string());
+    }
+
+    if args.input_format == InputFormat::Binary {
+        if args.since_offset.is_some() || args.offset_file.is_some() {
+            return Err("offsets are only supported for csv input".to_string());
+        }
+        process_binary(app, BufReader::new(open_input(&args.path)?));
+        return Ok(None);
+    }
+
+    if !args.sources.is_empty() {
+        return process_fan_in(app, args).map(|_| None);
+    }
+
+    if args.since_offset.is_none() && args.offset_file.is_none() {
+        app.try_process_reader(BufReader::new(open_input(&args.path)?))
+            .map_err(|e| e.to_string())?;
+        return Ok(None);
+    }
+
+    let offset = match (args.since_offset, &args.offset_file) {
+        (Some(offset), _) => offset,
+        (None, Some(offset_file)) => read_offset_file(offset_file)?,
+        (None, None) => 0,
+    };
+    let (reader, end) =
+        open_from_offset(&args.path, offset).map_err(|e| format!("cannot read input: {}", e))?;
+    // the offset is only stored for a complete read, otherwise the rows are skipped for good
+    app.try_process_reader(reader).map_err(|e| e.to_string())?;
+    info!("processed up to offset {}", end);
+
+    Ok(Some(end))
+}
+
+/// the input and the --source files are read in turns, every event is attributed to its file
+fn process_fan_in(app: &mut AccountProcessing, args: &Args) -> Result<(), String> {
+    let mut sources = vec![];
+    let named = std::iter::once(("input", &args.path)).chain(
+        args.sources
+            .iter()
+            .map(|(name, path)| (name.as_str(), path)),
+    );
+    for (name, path) in named {
+        let source = app
+            .csv_source(BufReader::new(open_input(path)?))
+            .map_err(|e| format!("{}: {}", path, e))?;
+        sources.push((name.to_string(), source));
+    }
+
+    let mut fan_in = FanIn::new(sources);
+    let result = app.run_from(&mut fan_in);
+    for (_, source) in fan_in.sources() {
+        app.summary.embedded_headers += source.embedded_headers;
+        app.summary.missing_columns += source.missing_columns;
+    }
+    info!(
+        "{} events processed from {} sources",
+        app.summary.processed,
+        args.sources.len() + 1
+    );
+    result.map_err(|e| e.to_string())
+}
+
+#[cfg(unix)]
+fn listen(app: &mut AccountProcessing, args: &Args) -> Result<(), String> {
+    if args.since_offset.is_some() || args.offset_file.is_some() {
+        return Err("a socket has no offsets".to_string());
+    }
+
+    crate::uds::serve(app, &args.path, args.input_format)
+}
+
+#[cfg(not(unix))]
+fn listen(_app: &mut AccountProcessing, _args: &Args) -> Result<(), String> {
+    Err("unix domain sockets are not supported on this platform".to_string())
+}
+
+/// every sub-engine reads the whole input but only keeps the clients of its partition, so the
+/// memory per worker is bounded by its share of the clients. the results are merged into one
+/// processing afterwards.
+///
+/// the audit log, structuring, the activity score, the graph, the latency report, the state,
+/// offsets, the socket and the quarantine are not split between the workers so they cannot be
+/// combined with it
+pub fn process_partitioned(args: &Args, partitions: usize) -> Result<AccountProcessing, String> {
+    if args.audit_log.is_some()
+        || args.structuring.is_some()
+        || args.activity_score.is_some()
+        || args.graph_out.is_some()
+        || args.latency_report.is_some()
+        || args.state_path.is_some()
+        || args.since_offset.is_some()
+        || args.offset_file.is_some()
+        || args.listen_uds
+        || args.quarantine_dir.is_some()
+        || args.holdback_path.is_some()
+        || args.compact_dormant.is_some()
+        || args.admin_batch.is_some()
+    {
+        return Err(
+            "--partition-by-client cannot be combined with --audit-log, --structuring, \
+             --activity-score, --graph-out, --latency-report, --state, offsets, --listen-uds, --quarantine-dir, --holdback, --compact-dormant or --admin-batch"
+                .to_string(),
+        );
+    }
+
+    // the partitions could not tell which of them saw the tx id first
+    if partitions_share_tx_ids(args, partitions)? {
+        warn!("tx ids are reused across partitions, the input is processed by one engine");
+        let mut app = build_processing(args)?;
+        process_input(&mut app, args)?;
+        return Ok(app);
+    }
+
+    type WorkerResult = Result<(Snapshot, ProcessingSummary, Vec<ComplianceViolation>), String>;
+    let results: Vec<WorkerResult> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..partitions)
+            .map(|index| {
+                scope.spawn(move || {
+                    let mut worker_args = args.clone();
+                    worker_args.config.partition = Some(Partition {
+                        index,
+                        count: partitions,
+                    });
+                    let mut worker = build_processing(&worker_args)?;
+                    process_input(&mut worker, &worker_args)?;
+                    Ok((worker.snapshot(), worker.summary, worker.violations))
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|_| Err("partition worker panicked".to_string()))
+            })
+            .collect()
+    });
+
+    let mut app = build_processing(args)?;
+    let mut merged = Snapshot::default();
+    for result in results {
+        let (snapshot, summary, violations) = result?;
+        merged = merged.merge(snapshot)?;
+        app.summary.processed += summary.processed;
+        app.summary.rejected += summary.rejected;
+        app.summary.skipped += summary.skipped;
+        app.summary.embedded_headers += summary.embedded_headers;
+        app.summary.missing_columns += summary.missing_columns;
+        app.summary.excluded += summary.excluded;
+        app.summary.ignored += summary.ignored;
+        app.summary.invariant_violations += summary.invariant_violations;
+        app.summary.parse_errors += summary.parse_errors;
+        app.violations.extend(violations);
+    }
+    app.restore(merged);
+
+    Ok(app)
+}
+
+/// the tx ids are global, a deposit or withdrawal of one partition can replace or duplicate the
+/// tx of another one. only then the outcome depends on the order of events across partitions
+fn partitions_share_tx_ids(args: &Args, partitions: usize) -> Result<bool, String> {
+    let app = build_processing(args)?;
+    let mut owners: BTreeMap<i32, usize> = BTreeMap::new();
+    let mut shared = false;
+    let mut register = |event: &AccountEvent| {
+        if matches!(
+            event.action_type,
+            AccountActions::Deposit | AccountActions::Withdrawal
+        ) {
+            let partition = partition_of(event.client_id, partitions);
+            shared |= *owners.entry(event.transaction_id).or_insert(partition) != partition;
+        }
+    };
+
+    if args.input_format == InputFormat::Binary {
+        let mut reader = BufReader::new(open_input(&args.path)?);
+        read_header(&mut reader)?;
+        while let Ok(Some(frame)) = read_frame(&mut reader) {
+            match frame {
+                Frame::Event(event) => register(&event),
+                Frame::Invalid(_) => {}
+                Frame::End => break,
+            }
+        }
+    }
+    let csv_inputs = std::iter::once(&args.path)
+        .filter(|_| args.input_format != InputFormat::Binary)
+        .chain(&args.more_inputs)
+        .chain(&args.then)
+        .chain(args.sources.iter().map(|(_, path)| path));
+    for path in csv_inputs {
+        let mut source = app
+            .csv_source(BufReader::new(open_input(path)?))
+            .map_err(|e| format!("{}: {}", path, e))?;
+        while let Some(next) = source.next_event() {
+            if let Ok(event) = next {
+                register(&event);
+            }
+        }
+    }
+
+    Ok(shared)
+}
+
+/// the real run is always the output, a divergence of the shadow is only reported
+fn write_shadow_report(
+    app: &AccountProcessing,
+    args: &Args,
+    engine: ShadowEngine,
+) -> Result<(), String> {
+    let shadow = match run_shadow(args, engine) {
+        Ok(shadow) => shadow,
+        // compared with nothing, every client diverges
+        Err(message) => {
+            eprintln!("shadow engine {} failed: {}", engine, message);
+            AccountProcessing::new(app.config)
+        }
+    };
+
+    let diff = divergence(app, &shadow);
+    if diff.changes.is_empty() {
+        info!("shadow engine {} matches", engine);
+    } else {
+        eprintln!(
+            "shadow engine {} diverged on {} clients",
+            engine,
+            diff.changes.len()
+        );
+    }
+
+    if let Some(report) = &args.shadow_report {
+        let written = File::create(report).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            diff.write(&mut writer, app.config.rounding)?;
+            writer.flush()
+        });
+        written.map_err(|e| format!("cannot write shadow report {}: {}", report, e))?;
+    }
+
+    Ok(())
+}
+
+/// every report is attempted, a failed one fails the run before the state is saved so nothing
+/// that only went into a report is lost
+fn write_reports(app: &AccountProcessing, args: &Args) -> Result<(), String> {
+    let mut failed = vec![];
+    if let (Some(dir), Some(quarantine)) = (&args.quarantine_dir, app.quarantine()) {
+        eprintln!(
+            "{} parse errors and {} rejected events quarantined in {}",
+            quarantine.parse_errors.len(),
+            quarantine.policy_rejects.len(),
+            dir
+        );
+        if let Err(e) = quarantine.export(Path::new(dir), app.config.rounding) {
+            failed.push(format!("cannot write quarantine {}: {}", dir, e));
+        }
+    }
+    // flagged events are not silently dropped, they are reported on stderr and optionally in a file
+    if !app.violations.is_empty() {
+        eprintln!("{} events violated compliance rules", app.violations.len());
+    }
+    if let Some(report) = &args.compliance_report {
+        if let Err(e) = write_compliance_report(report, &app.violations, app.config.rounding) {
+            failed.push(format!("cannot write compliance report {}: {}", report, e));
+        }
+    }
+
+    if let Some(detector) = app.structuring() {
+        if !detector.reports.is_empty() {
+            eprintln!("{} clients flagged for structuring", detector.reports.len());
+        }
+        if let Some(report) = &args.structuring_report {
+            let written =
+                write_suspicious_activity_report(report, &detector.reports, app.config.rounding);
+            if let Err(e) = written {
+                failed.push(format!(
+                    "cannot write suspicious activity report {}: {}",
+                    report, e
+                ));
+            }
+        }
+    }
+
+    if let (Some(graph), Some(path)) = (app.dispute_graph(), &args.graph_out) {
+        if let Err(e) = graph.export(path, args.graph_format) {
+            failed.push(format!("cannot write dispute graph {}: {}", path, e));
+        }
+    }
+
+    if let (Some(path), Some(metrics)) = (&args.metrics_out, &args.metrics) {
+        let finished = app.clock.unix_seconds() as i64;
+        metrics.gauge(crate::metrics::RUN_FINISHED, &[], finished);
+        if let Err(e) = metrics.write_textfile(path) {
+            failed.push(format!("cannot write metrics {}: {}", path, e));
+        }
+    }
+
+    if let Some(path) = &args.holdback_path {
+        if let Err(e) = app.holdback.export(path, app.config.rounding) {
+            failed.push(format!("cannot write holdback {}: {}", path, e));
+        }
+    }
+
+    if let (Some(path), Some(compaction)) = (&args.compact_archive, app.compaction()) {
+        if let Err(e) = compaction.export(path, app.config.rounding) {
+            failed.push(format!("cannot write compaction archive {}: {}", path, e));
+        }
+    }
+
+    if let Some(path) = &args.export_closing {
+        if let Err(e) = export_closing(path, app) {
+            failed.push(format!("cannot write closing balances {}: {}", path, e));
+        }
+    }
+
+    if let Some(path) = &args.statistics_report {
+        if let Err(e) = write_statistics_report(path, &app.statistics, app.config.rounding) {
+            failed.push(format!("cannot write statistics report {}: {}", path, e));
+        }
+    }
+
+    if let Some(latency) = app.latency() {
+        if latency.slow_events > 0 {
+            eprintln!("{} events were slow to apply", latency.slow_events);
+        }
+        if let Some(path) = &args.latency_report {
+            if let Err(e) = latency.export(path) {
+                failed.push(format!("cannot write latency report {}: {}", path, e));
+            }
+        }
+    }
+
+    match failed.is_empty() {
+        true => Ok(()),
+        false => Err(failed.join("\n")),
+    }
+}
+
+/// runs the input with the given flags as baseline and again with the policy file applied on top
+/// and prints the differences, nothing else is written
+fn simulate(args: &Args) -> Result<(), String> {
+    let policy_path = args
+        .policy_path
+        .as_ref()
+        .ok_or("simulate needs the alternative policy (--policy)")?;
+    let policy = Policy::load(policy_path)?;
+
+    let mut alternative_args = args.clone();
+    policy.apply(
+        &mut alternative_args.config,
+        &mut alternative_args.compliance,
+    )?;
+
+    if args.path == STDIN || args.more_inputs.iter().any(|path| path == STDIN) {
+        return Err("simulate reads the input twice, it cannot be stdin".to_string());
+    }
+    if !Path::new(&args.path).exists() {
+        return Err("file does not exist".to_string());
+    }
+
+    let mut baseline = build_processing(args)?;
+    let mut alternative = build_processing(&alternative_args)?;
+    for path in std::iter::once(&args.path).chain(&args.more_inputs) {
+        baseline.process_file(path).map_err(|e| e.to_string())?;
+        alternative.process_file(path).map_err(|e| e.to_string())?;
+    }
+
+    compare(&baseline, &alternative)
+        .write(&mut std::io::stdout(), args.config.rounding)
+        .map_err(|e| format!("cannot write simulation report: {}", e))
+}
+
+/// preview withdrawal,1,7,2.5 --state state.bin [flags], the event takes the place of the csv
+/// path. prints the outcome and the account the event would leave behind, the state is not
+/// written back
+fn preview(args: &Args) -> Result<(), String> {
+    if args.state_path.is_none() {
+        return Err("preview needs the state to check the event against (--state)".to_string());
+    }
+    let app = build_processing(args)?;
+
+    let row = format!("{}\n{}\n", COLUMNS.join(","), args.path);
+    let mut events = vec![];
+    crate::binary::read_csv_events(row.as_bytes(), &args.config, |event| {
+        events.push(*event);
+        Ok(())
+    })?;
+    let [event] = events[..] else {
+        return Err(format!("preview needs exactly one event: {}", args.path));
+    };
+
+    let preview = app.preview(&event);
+    let mut fields = vec![match &preview.outcome {
+        Ok(()) => "applied".to_string(),
+        Err(reason) => reason.to_string(),
+    }];
+    fields.extend(
+        preview
+            .account
+            .to_fields(args.config.rounding, args.config.number_format),
+    );
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+    writer
+        .write_record(["outcome", "client", "available", "held", "total", "locked"])
+        .and_then(|_| writer.write_record(&fields))
+        .and_then(|_| writer.flush().map_err(csv::Error::from))
+        .map_err(|e| format!("cannot write preview: {}", e))?;
+
+    Ok(())
+}
+
+/// yyyy-mm-ddThh:mm:ssZ of unix seconds, the days are converted with the civil calendar
+/// algorithm of howard hinnant
+fn utc(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    let time = seconds % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// the account, its transactions and its notes as lines for a person
+fn write_explanation<W: Write>(
+    writer: &mut W,
+    app: &AccountProcessing,
+    client_id: u16,
+) -> std::io::Result<()> {
+    let rounding = app.config.rounding;
+    match app.account(client_id) {
+        Some(account) => {
+            let fields = account.to_fields(rounding, app.config.number_format);
+            writeln!(
+                writer,
+                "client {}: available {} held {} total {} locked {}",
+                fields[0], fields[1], fields[2], fields[3], fields[4]
+            )?;
+        }
+        None => writeln!(writer, "client {}: no open account", client_id)?,
+    }
+    for (transaction_id, transaction) in app.transactions_for(client_id) {
+        let amount = transaction.amount.format(rounding);
+        match app.open_dispute(transaction_id) {
+            Some(held) => writeln!(
+                writer,
+                "tx {} {} {}, disputed with {} held",
+                transaction_id,
+                transaction.action_type,
+                amount,
+                held.format(rounding)
+            )?,
+            None => writeln!(
+                writer,
+                "tx {} {} {}",
+                transaction_id, transaction.action_type, amount
+            )?,
+        }
+    }
+    for note in app.notes(client_id) {
+        writeln!(
+            writer,
+            "note {} {}: {}",
+            utc(note.at),
+            note.author,
+            note.text
+        )?;
+    }
+
+    Ok(())
+}
+
+/// explain 42 --state state.bin [flags], the client takes the place of the csv path. prints
+/// what the state knows about the client, nothing is processed
+fn explain(args: &Args) -> Result<(), String> {
+    if args.state_path.is_none() {
+        return Err("explain needs the state of the client (--state)".to_string());
+    }
+    let client_id = args
+        .path
+        .parse()
+        .map_err(|_| format!("invalid client: {}", args.path))?;
+    let app = build_processing(args)?;
+
+    write_explanation(&mut std::io::stdout().lock(), &app, client_id)
+        .map_err(|e| format!("cannot write explanation: {}", e))
+}
+
+/// merge-state a.bin b.bin -o merged.bin
+pub fn merge_state(args: &[String]) -> Result<(), String> {
+    let mut inputs: Vec<&String> = vec![];
+    let mut output: Option<&String> = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output = Some(
+                    iter.next()
+                        .ok_or_else(|| format!("{} needs a value", arg))?,
+                )
+            }
+            _ => inputs.push(arg),
+        }
+    }
+    let output = output.ok_or("merge-state needs the output (-o merged.bin)")?;
+    if inputs.len() < 2 {
+        return Err("merge-state needs at least 2 states".to_string());
+    }
+
+    let mut merged = Snapshot::default();
+    for input in inputs {
+        let snapshot =
+            Snapshot::load(input).map_err(|e| format!("cannot read state {}: {}", input, e))?;
+        merged = merged
+            .merge(snapshot)
+            .map_err(|e| format!("cannot merge {}: {}", input, e))?;
+    }
+
+    merged
+        .save(output)
+        .map_err(|e| format!("cannot write state {}: {}", output, e))
+}
+
+/// state-diff old.bin new.bin [--rounding half-up] [-o diff.csv], stdout without -o
+pub fn state_diff(args: &[String]) -> Result<(), String> {
+    let mut inputs: Vec<&String> = vec![];
+    let mut output: Option<&String> = None;
+    let mut rounding = RoundingMode::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value()?),
+            "--rounding" => rounding = value()?.parse()?,
+            _ => inputs.push(arg),
+        }
+    }
+    let [old, new] = inputs[..] else {
+        return Err("state-diff needs exactly 2 states (old.bin new.bin)".to_string());
+    };
+
+    let load = |path: &String| {
+        Snapshot::load(path).map_err(|e| format!("cannot read state {}: {}", path, e))
+    };
+    let diff = crate::state_diff::diff(&load(old)?, &load(new)?);
+
+    let written = match output {
+        Some(path) => File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            diff.write(&mut writer, rounding)?;
+            writer.flush()
+        }),
+        None => diff.write(&mut std::io::stdout().lock(), rounding),
+    };
+    written.map_err(|e| format!("cannot write state diff: {}", e))
+}
+
+/// convert in out [--from csv|binary|proto|avro] [--to csv|binary|proto|avro] [--rounding half-up]
+/// [--schema strict] [--input-encoding latin1]. a csv is read like the input of a run, without --from a binary input is detected by its magic, without --to csv becomes binary
+/// and everything else csv
+fn convert(args: &[String]) -> Result<(), String> {
+    let mut inputs: Vec<&String> = vec![];
+    let mut config = Config::default();
+    let mut rounding = RoundingMode::default();
+    let mut from: Option<&str> = None;
+    let mut to: Option<&str> = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--rounding" => rounding = value()?.parse()?,
+            "--schema" => config.schema = value()?.parse()?,
+            "--input-encoding" => config.input_encoding = value()?.parse()?,
+            "--from" => from = Some(value()?),
+            "--to" => to = Some(value()?),
+            _ => inputs.push(arg),
+        }
+    }
+    let [input, output] = inputs[..] else {
+        return Err("convert needs an input and an output (in.csv out.bin)".to_string());
+    };
+
+    let mut reader =
+        BufReader::new(File::open(input).map_err(|e| format!("cannot read {}: {}", input, e))?);
+    let from = match from {
+        Some(from) => from,
+        None => match reader
+            .fill_buf()
+            .map_err(|e| format!("cannot read {}: {}", input, e))?
+            .starts_with(crate::binary::MAGIC)
+        {
+            true => "binary",
+            false => "csv",
+        },
+    };
+    let to = to.unwrap_or(match from {
+        "csv" => "binary",
+        _ => "csv",
+    });
+    let mut writer = BufWriter::new(
+        File::create(output).map_err(|e| format!("cannot create {}: {}", output, e))?,
+    );
+
+    config.rounding = rounding;
+    let events = match (from, to) {
+        ("binary", "csv") => binary_to_csv(reader, &mut writer, rounding)?,
+        ("csv", "binary") => csv_to_binary(reader, &mut writer, &config)?,
+        ("proto", "csv") => crate::proto::proto_to_csv(reader, &mut writer, rounding)?,
+        ("csv", "proto") => crate::proto::csv_to_proto(reader, &mut writer, &config)?,
+        ("avro", "csv") => crate::avro::avro_to_csv(reader, &mut writer, rounding)?,
+        ("csv", "avro") => crate::avro::csv_to_avro(reader, &mut writer, &config)?,
+        _ => return Err(format!("cannot convert from {} to {}", from, to)),
+    };
+    writer
+        .flush()
+        .map_err(|e| format!("cannot write {}: {}", output, e))?;
+    info!("{} events converted", events);
+
+    Ok(())
+}
+
+/// schema [--format json-schema|arrow|avro] [-o schema.json], stdout without -o
+fn export_schema(args: &[String]) -> Result<(), String> {
+    let mut format = SchemaFormat::default();
+    let mut output: Option<&String> = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--format" => format = value()?.parse()?,
+            "-o" | "--output" => output = Some(value()?),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+
+    let written = match output {
+        Some(path) => File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write_schemas(&mut writer, format)?;
+            writer.flush()
+        }),
+        None => write_schemas(&mut std::io::stdout().lock(), format),
+    };
+    written.map_err(|e| format!("cannot write schema: {}", e))
+}
+
+/// everything the binary does, the arguments include the program name. the error is for
+/// stderr, the output may already be partly written
+pub fn run(args: Vec<String>) -> Result<(), String> {
+    if args.iter().any(|arg| arg == "--build-info") {
+        println!("{}", crate::build_info::build_info());
+        return Ok(());
+    }
+    if args.iter().any(|arg| arg == "--version") {
+        println!("{}", crate::build_info::version());
+        return Ok(());
+    }
+
+    // subcommands are the first argument, everything after it is parsed the same way
+    if args.get(1).map(String::as_str) == Some("simulate") {
+        return parse_args(&args[1..]).and_then(|args| simulate(&args));
+    }
+    if args.get(1).map(String::as_str) == Some("preview") {
+        return parse_args(&args[1..]).and_then(|args| preview(&args));
+    }
+    if args.get(1).map(String::as_str) == Some("explain") {
+        return parse_args(&args[1..]).and_then(|args| explain(&args));
+    }
+    if args.get(1).map(String::as_str) == Some("merge-state") {
+        return merge_state(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("schema") {
+        return export_schema(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("convert") {
+        return convert(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("state-diff") {
+        return state_diff(&args[2..]);
+    }
+
+    // replay is a normal run that has to leave out something, like simulate it takes the
+    // place of the program name
+    let replay = args.get(1).map(String::as_str) == Some("replay");
+    let args = parse_args(if replay { &args[1..] } else { &args })?;
+    if replay && args.exclusions.is_empty() {
+        return Err("replay needs at least one --exclude-tx or --exclude-client".to_string());
+    }
+
+    let processed = match args.partition_by_client {
+        Some(partitions) => process_partitioned(&args, partitions).map(|app| (app, None)),
+        None => build_processing(&args)
+            .and_then(|mut app| process_input(&mut app, &args).map(|end| (app, end))),
+    };
+    let (mut app, end) = processed?;
+
+    match args.partition_output {
+        Some(partitions) => {
+            let directory = Path::new(&args.output_dir);
+            write_partitioned(&app, directory, partitions, args.output_integrity)
+                .map_err(|e| format!("cannot write partitioned output: {}", e))?;
+        }
+        None => match args.output_integrity {
+            Some(integrity) => {
+                let accounts: Vec<_> = app.accounts_iter().collect();
+                let stdout = std::io::stdout();
+                write_accounts_with(&app, &mut stdout.lock(), &accounts, integrity)
+                    .map_err(|e| format!("cannot write accounts: {}", e))?;
+            }
+            None if args.output_format == OutputFormat::Avro => {
+                let stdout = std::io::stdout();
+                crate::avro::write_accounts(&app, stdout.lock(), app.accounts_iter())
+                    .map_err(|e| format!("cannot write accounts: {}", e))?;
+            }
+            None => {
+                if let Some(path) = &args.output {
+                    let sink = CsvSink::create(path)
+                        .map_err(|e| format!("cannot create output {}: {}", path, e))?;
+                    app.output = Some(Box::new(sink));
+                }
+                app.display().map_err(|e| e.to_string())?;
+            }
+        },
+    }
+    write_reports(&app, &args)?;
+    info!(
+        "{} events processed, {} rejected, {} skipped by {}",
+        app.summary.processed,
+        app.summary.rejected,
+        app.summary.skipped,
+        crate::build_info::summary()
+    );
+    if app.summary.invariant_violations > 0 {
+        eprintln!(
+            "{} ledger invariants violated",
+            app.summary.invariant_violations
+        );
+    }
+    if !args.exclusions.is_empty() {
+        eprintln!(
+            "{} events excluded ({})",
+            app.summary.excluded, args.exclusions
+        );
+    }
+    if !args.holdback.is_empty() {
+        eprintln!("{} events ignored ({})", app.summary.ignored, args.holdback);
+    }
+    if let Some(compaction) = app.compaction() {
+        eprintln!(
+            "{} dormant accounts compacted, {} restored",
+            compaction.compacted, compaction.restored
+        );
+    }
+    if let Some(engine) = args.shadow_engine {
+        write_shadow_report(&app, &args, engine)?;
+    }
+    if let Some(audit) = app.audit_mut() {
+        audit
+            .flush()
+            .map_err(|e| format!("cannot write audit log: {}", e))?;
+    }
+
+    if let Some(state_path) = &args.state_path {
+        app.snapshot()
+            .save(state_path)
+            .map_err(|e| format!("cannot write state {}: {}", state_path, e))?;
+    }
+    // the offset moves only once the effects of the rows before it are stored
+    if let (Some(offset_file), Some(end)) = (&args.offset_file, end) {
+        write_offset_file(offset_file, end)
+            .map_err(|e| format!("cannot write offset file {}: {}", offset_file, e))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::encoding::InputEncoding;
+
+    use crate::money::{Money, RoundingMode};
+
+    use crate::cli::{
+        build_processing, matches_glob, merge_state, parse_args, process_input,
+        process_partitioned, run, state_diff, utc, write_explanation, STDIN,
+    };
+    use crate::shadow::{divergence, run_shadow, ShadowEngine};
+
+    use crate::snapshot::Snapshot;
+    use crate::{AccountProcessing, Config, Note};
+    use crate::{SchemaMode, SchemaVersion};
+
+    fn money(minor_units: u64) -> Money {
+        Money::from_minor_units(minor_units)
+    }
+
+    #[test]
+    fn parse_rounding_argument() {
+        let args: Vec<String> = ["app", "--rounding", "half-even", "in.csv"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let args = parse_args(&args).unwrap();
+
+        assert_eq!(args.config.rounding, RoundingMode::HalfEven);
+        assert_eq!(args.path, "in.csv");
+    }
+
+    #[test]
+    fn amounts_do_not_depend_on_the_argument_order() {
+        let limit = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            parse_args(&args).unwrap().config.high_risk_withdrawal_limit
+        };
+        let before = limit(&[
+            "app",
+            "--high-risk-withdrawal-limit",
+            "1.00005",
+            "--rounding",
+            "truncate",
+            "in.csv",
+        ]);
+        let after = limit(&[
+            "app",
+            "--rounding",
+            "truncate",
+            "--high-risk-withdrawal-limit",
+            "1.00005",
+            "in.csv",
+        ]);
+        assert_eq!(before, Some(money(10000)));
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn parse_arguments_without_path() {
+        let args = vec!["app".to_string()];
+        assert!(parse_args(&args).is_err());
+    }
+
+    #[test]
+    fn unknown_flags_are_rejected() {
+        let args: Vec<String> = ["app", "input.csv", "--sate", "state"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(parse_args(&args).err().unwrap(), "unknown argument: --sate");
+    }
+
+    #[test]
+    fn stdin_is_read_once() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            parse_args(&args)
+        };
+        assert_eq!(parse(&["app", "-"]).unwrap().path, STDIN);
+        assert!(parse(&["app", "-", "--partition-by-client", "2"]).is_err());
+        assert!(parse(&["app", "-", "--shadow-engine", "single"]).is_err());
+        assert!(parse(&["app", "-", "--offset-file", "offset", "--state", "state"]).is_err());
+    }
+
+    #[test]
+    fn errors_are_returned_for_the_exit_code() {
+        let run = |args: &[&str]| run(args.iter().map(|arg| arg.to_string()).collect());
+        assert_eq!(
+            run(&["app", "kraken_test_missing.csv"]),
+            Err("file does not exist".to_string())
+        );
+        assert!(run(&["app", "replay", "in.csv"]).is_err());
+        assert!(run(&["app", "merge-state"]).is_err());
+    }
+
+    #[test]
+    fn failed_reports_fail_the_run() {
+        let dir = std::env::temp_dir();
+        let input = dir.join("kraken_test_failed_reports.csv");
+        let state = dir.join("kraken_test_failed_reports.bin");
+        std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+        let _ = std::fs::remove_file(&state);
+
+        let report = dir.join("kraken_test_missing_dir").join("statistics.csv");
+        let result = run([
+            "app",
+            input.to_str().unwrap(),
+            "--statistics-report",
+            report.to_str().unwrap(),
+            "--state",
+            state.to_str().unwrap(),
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect());
+        let saved = state.exists();
+        std::fs::remove_file(&input).unwrap();
+        let _ = std::fs::remove_file(&state);
+
+        assert!(result
+            .unwrap_err()
+            .starts_with("cannot write statistics report"));
+        assert!(!saved, "the state is only saved with its reports");
+    }
+
+    #[test]
+    fn offsets_are_only_stored_with_the_state() {
+        let dir = std::env::temp_dir();
+        let input = dir.join("kraken_test_offset_input.csv");
+        let offset_file = dir.join("kraken_test_offset_input.csv.offset");
+        let content = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+        std::fs::write(&input, content).unwrap();
+        let _ = std::fs::remove_file(&offset_file);
+
+        let args = |extra: &[&str]| {
+            let mut args = vec![
+                "app",
+                input.to_str().unwrap(),
+                "--offset-file",
+                offset_file.to_str().unwrap(),
+            ];
+            args.extend_from_slice(extra);
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            parse_args(&args)
+        };
+        assert!(
+            args(&[]).is_err(),
+            "an offset without the state loses events"
+        );
+
+        let args = args(&["--state", "state.bin"]).unwrap();
+        let mut app = build_processing(&args).unwrap();
+        let processed = process_input(&mut app, &args);
+        let written = offset_file.exists();
+        std::fs::remove_file(&input).unwrap();
+
+        // run writes it once the state is saved
+        assert_eq!(processed, Ok(Some(content.len() as u64)));
+        assert!(!written);
+    }
+
+    #[test]
+    fn offsets_are_not_stored_for_a_failed_read() {
+        let dir = std::env::temp_dir();
+        let input = dir.join("kraken_test_offset_bad_header.csv");
+        let state = dir.join("kraken_test_offset_bad_header.bin");
+        let offset_file = dir.join("kraken_test_offset_bad_header.offset");
+        std::fs::write(&input, "kind,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+        let _ = std::fs::remove_file(&state);
+        let _ = std::fs::remove_file(&offset_file);
+
+        let result = run([
+            "app",
+            input.to_str().unwrap(),
+            "--schema",
+            "strict",
+            "--state",
+            state.to_str().unwrap(),
+            "--offset-file",
+            offset_file.to_str().unwrap(),
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect());
+        let written = offset_file.exists();
+        std::fs::remove_file(&input).unwrap();
+        let _ = std::fs::remove_file(&state);
+
+        assert!(result.is_err());
+        assert!(!written);
+    }
+
+    #[test]
+    fn parse_metadata_arguments() {
+        let args: Vec<String> = [
+            "app",
+            "in.csv",
+            "--clients",
+            "clients.csv",
+            "--high-risk-score",
+            "75",
+            "--high-risk-withdrawal-limit",
+            "1000.5",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let args = parse_args(&args).unwrap();
+
+        assert_eq!(args.clients_path, Some("clients.csv".to_string()));
+        assert_eq!(args.config.high_risk_score, Some(75));
+        assert_eq!(
+            args.config.high_risk_withdrawal_limit,
+            Some(money(10005000))
+        );
+    }
+
+    #[test]
+    fn compliance_rules_need_metadata() {
+        let args: Vec<String> = ["app", "in.csv", "--embargoed-countries", "KP"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        assert!(parse_args(&args).is_err());
+    }
+
+    #[test]
+    fn parse_structuring_arguments() {
+        let args: Vec<String> = [
+            "app",
+            "in.csv",
+            "--structuring",
+            "10000",
+            "--structuring-count",
+            "5",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let detector = parse_args(&args).unwrap().structuring.unwrap();
+
+        assert_eq!(detector.threshold, money(100000000));
+        assert_eq!(detector.min_count, 5);
+        assert_eq!(detector.window, 10);
+    }
+
+    #[test]
+    fn sampled_run_only_contains_sampled_clients() {
+        let mut content = "type,client,tx,amount\n".to_string();
+        for client in 0..200 {
+            content.push_str(&format!("deposit,{},{},1.0\n", client, client));
+            content.push_str(&format!("withdrawal,{},{},0.5\n", client, client + 1000));
+        }
+
+        let args: Vec<String> = ["app", "in.csv", "--sample", "10%", "--seed", "7"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let config = parse_args(&args).unwrap().config;
+        let sample = config.sample.unwrap();
+
+        let mut app = AccountProcessing::new(config);
+        app.process_reader(content.as_bytes());
+
+        assert!(!app.accounts.is_empty());
+        assert!(app.accounts.len() < 200);
+        assert!(app.accounts.keys().all(|id| sample.contains(*id)));
+        assert!(app
+            .accounts
+            .values()
+            .all(|account| account.available.minor_units() == 5000));
+        assert_eq!(
+            app.summary.processed + app.summary.skipped,
+            400,
+            "every event is either processed or skipped"
+        );
+    }
+
+    #[test]
+    fn parse_csv_tolerance_flags() {
+        let args: Vec<String> = [
+            "app",
+            "in.csv",
+            "--tolerate-missing-columns",
+            "--skip-embedded-headers",
+            "--input-encoding",
+            "windows-1252",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let config = parse_args(&args).unwrap().config;
+
+        assert!(config.tolerate_missing_columns);
+        assert!(config.skip_embedded_headers);
+        assert_eq!(config.input_encoding, InputEncoding::Windows1252);
+    }
+
+    #[test]
+    fn merge_state_files() {
+        let dir = std::env::temp_dir();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let (a, b, merged) = (
+            path("kraken_test_merge_a.bin"),
+            path("kraken_test_merge_b.bin"),
+            path("kraken_test_merge.bin"),
+        );
+
+        let mut first = AccountProcessing::new(Config::default());
+        first.process_reader("type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes());
+        first.snapshot().save(&a).unwrap();
+        let mut second = AccountProcessing::new(Config::default());
+        second.process_reader("type,client,tx,amount\ndeposit,2,2,2.0\n".as_bytes());
+        second.snapshot().save(&b).unwrap();
+
+        let args = [a.clone(), b.clone(), "-o".to_string(), merged.clone()];
+        merge_state(&args).unwrap();
+        let snapshot = Snapshot::load(&merged).unwrap();
+        assert_eq!(snapshot.accounts.len(), 2);
+
+        // the first state twice conflicts
+        assert!(merge_state(&[a.clone(), a.clone(), "-o".to_string(), merged.clone()]).is_err());
+        assert!(merge_state(&[a.clone(), b.clone()]).is_err(), "no output");
+
+        for file in [a, b, merged] {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[test]
+    fn parse_compaction_arguments() {
+        let args = |rest: &[&str]| {
+            let args: Vec<String> = ["app", "in.csv"]
+                .iter()
+                .chain(rest)
+                .map(|arg| arg.to_string())
+                .collect();
+            parse_args(&args)
+        };
+
+        let parsed = args(&[
+            "--compact-dormant",
+            "10",
+            "--compact-archive",
+            "dormant.csv",
+        ])
+        .unwrap();
+        let app = build_processing(&parsed).unwrap();
+        let compaction = app.compaction().unwrap();
+        assert_eq!(compaction.dormant_after, 10);
+        assert!(compaction.archive.is_some());
+
+        assert!(args(&["--compact-dormant", "0"]).is_err());
+        assert!(args(&["--compact-archive", "dormant.csv"]).is_err());
+    }
+
+    #[test]
+    fn ignored_actions_are_held_back() {
+        let args: Vec<String> = [
+            "app",
+            "in.csv",
+            "--ignore",
+            "chargeback",
+            "--holdback",
+            "held.csv",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let mut app = build_processing(&parse_args(&args).unwrap()).unwrap();
+        app.process_reader(
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndispute,1,1,\nchargeback,1,1,\n".as_bytes(),
+        );
+
+        assert_eq!(app.summary.ignored, 1);
+        assert_eq!(app.summary.processed, 2);
+        assert!(!app.accounts[&1].locked);
+        assert_eq!(app.holdback.events.as_ref().unwrap().len(), 1);
+
+        let args: Vec<String> = ["app", "in.csv", "--holdback", "held.csv"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert!(parse_args(&args).is_err());
+    }
+
+    #[test]
+    fn inconsistent_state_is_refused() {
+        let state = std::env::temp_dir().join("kraken_test_inconsistent.bin");
+        let state = state.to_str().unwrap().to_string();
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader("type,client,tx,amount\ndeposit,1,1,2.0\n".as_bytes());
+        // held without the dispute that would explain it
+        app.accounts.get_mut(&1).unwrap().held = money(10000);
+        app.snapshot().save(&state).unwrap();
+
+        let args = |skip: bool| {
+            let mut args: Vec<String> = ["app", "in.csv", "--state", state.as_str()]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect();
+            if skip {
+                args.push("--skip-state-check".to_string());
+            }
+            parse_args(&args).unwrap()
+        };
+        let refused = build_processing(&args(false)).err();
+        let skipped = build_processing(&args(true));
+        std::fs::remove_file(&state).unwrap();
+
+        let refused = refused.unwrap();
+        assert!(refused.contains("is inconsistent, nothing was processed"));
+        assert!(refused.contains("invariant held_matches_open_disputes violated"));
+        assert_eq!(skipped.unwrap().accounts[&1].held, money(10000));
+    }
+
+    #[test]
+    fn explain_a_client() {
+        assert_eq!(utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc(951_826_332), "2000-02-29T12:12:12Z");
+        assert_eq!(utc(1_791_936_000), "2026-10-14T00:00:00Z");
+
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,2.0\n\
+             withdrawal,1,2,0.5\n\
+             deposit,1,3,1.0\n\
+             dispute,1,3,\n"
+                .as_bytes(),
+        );
+        let note = Note {
+            author: "ops".to_string(),
+            at: 1_791_936_000,
+            text: "customer called".to_string(),
+        };
+        app.notes.insert(1, vec![note]);
+
+        let mut output = vec![];
+        write_explanation(&mut output, &app, 1).unwrap();
+        write_explanation(&mut output, &app, 2).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client 1: available 1.5000 held 1.0000 total 2.5000 locked false\n\
+             tx 1 deposit 2.0000\n\
+             tx 2 withdrawal 0.5000\n\
+             tx 3 deposit 1.0000, disputed with 1.0000 held\n\
+             note 2026-10-14T00:00:00Z ops: customer called\n\
+             client 2: no open account\n"
+        );
+    }
+
+    #[test]
+    fn then_files_are_processed_after_the_input() {
+        let dir = std::env::temp_dir();
+        let input = dir.join("kraken_test_then_input.csv");
+        let late = dir.join("kraken_test_then_late.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndispute,1,1,\nchargeback,1,1,\n",
+        )
+        .unwrap();
+        std::fs::write(&late, "type,client,tx,amount\nchargeback,1,1,\n").unwrap();
+
+        let args: Vec<String> = [
+            "app",
+            input.to_str().unwrap(),
+            "--ignore",
+            "chargeback",
+            "--then",
+            late.to_str().unwrap(),
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let args = parse_args(&args).unwrap();
+        let mut app = build_processing(&args).unwrap();
+        let processed = process_input(&mut app, &args);
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&late).unwrap();
+
+        processed.unwrap();
+        assert_eq!(app.summary.ignored, 1);
+        assert!(app.accounts[&1].locked, "charged back by the late file");
+    }
+
+    #[test]
+    fn inputs_are_processed_in_order() {
+        let dir = std::env::temp_dir().join("kraken_test_daily");
+        std::fs::create_dir_all(&dir).unwrap();
+        let day = |name: &str, rows: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, format!("type,client,tx,amount\n{}", rows)).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let second = day("2024-01-02.csv", "dispute,1,1,\n");
+        let first = day("2024-01-01.csv", "deposit,1,1,1.0\n");
+        day("notes.txt", "");
+        let pattern = dir.join("2024-01-0?.csv").to_str().unwrap().to_string();
+
+        let args: Vec<String> = ["app", pattern.as_str()]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let args = parse_args(&args).unwrap();
+        assert_eq!(args.path, first);
+        assert_eq!(args.more_inputs, vec![second.clone()]);
+        let mut app = build_processing(&args).unwrap();
+        let processed = process_input(&mut app, &args);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        processed.unwrap();
+        assert_eq!(app.open_dispute(1), Some(money(10000)));
+
+        let args: Vec<String> = ["app", first.as_str(), second.as_str(), "--listen-uds"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert!(parse_args(&args).is_err());
+        let missing = vec!["app".to_string(), pattern];
+        assert!(parse_args(&missing).is_err(), "nothing matches any more");
+
+        assert!(matches_glob(b"*.csv", b"a.csv"));
+        assert!(matches_glob(b"day-*-?.csv", b"day-12-3.csv"));
+        assert!(!matches_glob(b"*.csv", b"a.csv.gz"));
+        assert!(!matches_glob(b"?.csv", b".csv"));
+    }
+
+    #[test]
+    fn sources_are_merged_with_the_input() {
+        let dir = std::env::temp_dir();
+        let input = dir.join("kraken_test_source_input.csv");
+        let other = dir.join("kraken_test_source_b.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0\n",
+        )
+        .unwrap();
+        std::fs::write(&other, "type,client,tx,amount\ndispute,1,1,\n").unwrap();
+
+        let source = format!("b={}", other.to_str().unwrap());
+        let args: Vec<String> = ["app", input.to_str().unwrap(), "--source", &source]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let args = parse_args(&args).unwrap();
+        let mut app = build_processing(&args).unwrap();
+        let processed = process_input(&mut app, &args);
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&other).unwrap();
+
+        processed.unwrap();
+        assert_eq!(app.summary.processed, 3);
+        assert_eq!(app.open_dispute(1), Some(money(10000)));
+
+        for invalid in [
+            vec!["app", "in.csv", "--source", "b.csv"],
+            vec!["app", "in.csv", "--source", "input=b.csv"],
+            vec![
+                "app", "in.csv", "--source", "b=b.csv", "--source", "b=c.csv",
+            ],
+            vec![
+                "app",
+                "in.csv",
+                "--source",
+                "b=b.csv",
+                "--since-offset",
+                "3",
+            ],
+        ] {
+            let invalid: Vec<String> = invalid.iter().map(|arg| arg.to_string()).collect();
+            assert!(parse_args(&invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn metrics_are_shared_by_the_partitions() {
+        let input = std::env::temp_dir().join("kraken_test_metrics.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,1.0\nwithdrawal,2,3,5.0\n",
+        )
+        .unwrap();
+        let args: Vec<String> = [
+            "app",
+            input.to_str().unwrap(),
+            "--partition-by-client",
+            "2",
+            "--metrics-out",
+            "metrics.prom",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let args = parse_args(&args).unwrap();
+        let processed = process_partitioned(&args, 2);
+        std::fs::remove_file(&input).unwrap();
+
+        processed.unwrap();
+        let metrics = args.metrics.unwrap();
+        let deposits =
+            metrics.counter_value(crate::metrics::EVENTS_APPLIED, &[("type", "deposit")]);
+        assert_eq!(deposits, 2);
+        let rejected = metrics.counter_value(
+            crate::metrics::EVENTS_REJECTED,
+            &[("reason", "insufficient_funds")],
+        );
+        assert_eq!(rejected, 1);
+        let accounts =
+            |partition| metrics.gauge_value(crate::metrics::ACCOUNTS, &[("partition", partition)]);
+        assert_eq!(accounts("0").unwrap() + accounts("1").unwrap(), 2);
+    }
+
+    #[test]
+    fn state_diff_of_a_replay() {
+        let dir = std::env::temp_dir();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let (old, new, report) = (
+            path("kraken_test_diff_old.bin"),
+            path("kraken_test_diff_new.bin"),
+            path("kraken_test_diff.csv"),
+        );
+
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader("type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes());
+        app.snapshot().save(&old).unwrap();
+        app.process_reader("type,client,tx,amount\ndeposit,1,2,0.5\n".as_bytes());
+        app.snapshot().save(&new).unwrap();
+
+        state_diff(&[old.clone(), new.clone(), "-o".to_string(), report.clone()]).unwrap();
+        let content = std::fs::read_to_string(&report).unwrap();
+        assert!(content.contains("\n1,changed,0.5000,0.0000,0.5000,false\n"));
+        assert!(content.contains("\ntransactions,1,2,1\n"));
+
+        assert!(
+            state_diff(&[old.clone(), "--rounding".to_string(), "half-up".to_string()]).is_err(),
+            "only one state"
+        );
+        for file in [old, new, report] {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[test]
+    fn partitioned_run_matches_the_single_run() {
+        let path = std::env::temp_dir().join("kraken_test_partitioned.csv");
+        let mut content = "type,client,tx,amount\n".to_string();
+        for client in 0..50 {
+            content.push_str(&format!("deposit,{},{},2.0\n", client, client));
+            content.push_str(&format!("withdrawal,{},{},0.5\n", client, client + 1000));
+            content.push_str(&format!("dispute,{},{},\n", client, client));
+        }
+        std::fs::write(&path, content).unwrap();
+
+        let args: Vec<String> = ["app", path.to_str().unwrap(), "--partition-by-client", "4"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let args = parse_args(&args).unwrap();
+        let partitioned = process_partitioned(&args, 4).unwrap();
+        let mut single = AccountProcessing::new(Config::default());
+        single.process_file(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(partitioned.accounts, single.accounts);
+        assert_eq!(partitioned.open_disputes, single.open_disputes);
+        assert_eq!(partitioned.summary, single.summary);
+    }
+
+    #[test]
+    fn partitioned_run_with_reused_tx_ids_matches_the_single_run() {
+        let path = std::env::temp_dir().join("kraken_test_partitioned_reused.csv");
+        let mut content = "type,client,tx,amount\n".to_string();
+        for client in 0..20 {
+            content.push_str(&format!("deposit,{},1,2.0\n", client));
+            content.push_str(&format!("dispute,{},1,\n", client));
+        }
+        std::fs::write(&path, content).unwrap();
+
+        for extra in [None, Some("--reject-duplicate-tx")] {
+            let mut args: Vec<String> =
+                ["app", path.to_str().unwrap(), "--partition-by-client", "4"]
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect();
+            args.extend(extra.map(str::to_string));
+            let args = parse_args(&args).unwrap();
+            let partitioned = process_partitioned(&args, 4).unwrap();
+            let mut single = build_processing(&args).unwrap();
+            single.process_file(path.to_str().unwrap()).unwrap();
+
+            assert_eq!(partitioned.accounts, single.accounts);
+            assert_eq!(partitioned.open_disputes, single.open_disputes);
+            assert_eq!(partitioned.summary, single.summary);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn partitioned_run_rejects_unsplittable_options() {
+        let args: Vec<String> = ["app", "in.csv", "--audit-log", "audit.jsonl"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let args = parse_args(&args).unwrap();
+
+        assert!(process_partitioned(&args, 2).is_err());
+    }
+
+    #[test]
+    fn latency_is_recorded_per_event() {
+        let args: Vec<String> = ["app", "in.csv", "--slow-event-us", "500"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let args = parse_args(&args).unwrap();
+        assert_eq!(
+            args.slow_event_threshold,
+            Some(std::time::Duration::from_micros(500))
+        );
+
+        let mut app = build_processing(&args).unwrap();
+        app.process_reader("type,client,tx,amount\ndeposit,1,1,1.0\ndispute,1,2,\n".as_bytes());
+
+        // the dispute of an unknown tx is rejected before it is applied
+        assert_eq!(app.latency().unwrap().count(), 1);
+    }
+
+    #[test]
+    fn parse_schema_mode() {
+        let args: Vec<String> = ["app", "in.csv", "--schema", "strict"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(parse_args(&args).unwrap().config.schema, SchemaMode::Strict);
+
+        let args: Vec<String> = ["app", "in.csv", "--schema-version", "2"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            parse_args(&args).unwrap().config.schema_version,
+            Some(SchemaVersion::V2)
+        );
+
+        let args: Vec<String> = [
+            "app",
+            "in.csv",
+            "--schema",
+            "strict",
+            "--tolerate-missing-columns",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert!(parse_args(&args).is_err());
+    }
+
+    #[test]
+    fn shadow_engines_agree_with_the_real_run() {
+        let path = std::env::temp_dir().join("kraken_test_shadow.csv");
+        let mut content = "type,client,tx,amount\n".to_string();
+        for client in 0..20 {
+            content.push_str(&format!("deposit,{},{},2.0\n", client, client));
+            content.push_str(&format!("dispute,{},{},\n", client, client));
+            content.push_str(&format!("chargeback,{},{},\n", client, client));
+        }
+        std::fs::write(&path, content).unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let args = parse_args(&["app".to_string(), path.clone()]).unwrap();
+        let mut app = build_processing(&args).unwrap();
+        app.process_file(&path).unwrap();
+
+        for engine in [ShadowEngine::Single, ShadowEngine::Partitioned(3)] {
+            let shadow = run_shadow(&args, engine).unwrap();
+            assert!(divergence(&app, &shadow).changes.is_empty(), "{}", engine);
+        }
+
+        let mut stateful = args.clone();
+        stateful.state_path = Some("state.bin".to_string());
+        assert!(run_shadow(&stateful, ShadowEngine::Single).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replay_without_the_excluded_events() {
+        let args: Vec<String> = [
+            "replay",
+            "in.csv",
+            "--exclude-tx",
+            "2",
+            "--exclude-client",
+            "3,4",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let args = parse_args(&args).unwrap();
+        let mut app = build_processing(&args).unwrap();
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,1.0\n\
+             deposit,1,2,5.0\n\
+             dispute,1,2,\n\
+             chargeback,1,2,\n\
+             deposit,3,3,1.0\n\
+             deposit,4,4,1.0\n"
+                .as_bytes(),
+        );
+
+        assert_eq!(app.accounts[&1].available.minor_units(), 10000);
+        assert!(
+            !app.accounts[&1].locked,
+            "the chargeback of the bad tx is gone too"
+        );
+        assert_eq!(app.accounts.len(), 1);
+        assert_eq!(app.summary.excluded, 5);
+        assert_eq!(app.summary.processed, 1);
+    }
+
+    #[test]
+    fn report_invariant_violations() {
+        let args: Vec<String> = ["kraken_test", "in.csv", "--report-invariants"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let args = parse_args(&args).unwrap();
+        let mut app = build_processing(&args).unwrap();
+        app.process_reader("type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,1,2,2.0\n".as_bytes());
+        // held without the dispute that would explain it
+        app.accounts.get_mut(&1).unwrap().held = money(10000);
+        app.process_reader("type,client,tx,amount\ndispute,1,1,\ndeposit,1,3,1.0\n".as_bytes());
+
+        // reported and counted, the processing goes on
+        assert_eq!(app.summary.invariant_violations, 1);
+        assert_eq!(app.summary.processed, 4);
+    }
+
+    #[test]
+    fn accounts_with_decimal_commas() {
+        let args: Vec<String> = ["kraken_test", "in.csv", "--output-number-format", "eu"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let mut app = build_processing(&parse_args(&args).unwrap()).unwrap();
+        app.process_reader("type,client,tx,amount\ndeposit,1,1,2.5\ndispute,1,1,\n".as_bytes());
+
+        let mut output = vec![];
+        app.write_accounts(&mut output, app.accounts_iter())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,\"0,0000\",\"2,5000\",\"2,5000\",false\n"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// where the processing gets "now" from, the apply times of the latency histogram and the
+/// metrics are measured with it
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// wall clock seconds since the unix epoch, for the timestamps that are stored
+    fn unix_seconds(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs())
+    }
+}
+
+/// the monotonic clock of the system
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// only moves when it is advanced, so time dependent things can be tested without sleeps.
+/// its wall clock starts at the unix epoch
+#[derive(Debug)]
+pub struct ManualClock {
+    start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+}
+
+impl ManualClock {
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner()) += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn unix_seconds(&self) -> u64 {
+        self.elapsed
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_secs()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::clock::{Clock, ManualClock};
+    use std::time::Duration;
+
+    #[test]
+    fn manual_clock_only_moves_when_advanced() {
+        let clock = ManualClock::default();
+        let before = clock.now();
+        assert_eq!(clock.now(), before);
+
+        clock.advance(Duration::from_millis(3));
+        assert_eq!(clock.now() - before, Duration::from_millis(3));
+        assert_eq!(clock.unix_seconds(), 0);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(clock.unix_seconds(), 2);
+    }
+}
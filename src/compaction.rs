@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::money::{Money, RoundingMode, SignedMoney};
+use crate::opening::{load_opening_state, COLUMNS};
+use crate::ClientAccount;
+
+/// accounts without a balance, an open dispute or an event of their client in the last
+/// `dormant_after` events are removed from the live state. we don't have timestamps so like the
+/// velocity of the risk score the dormancy is counted in events of the input. locked accounts
+/// are never compacted, the next deposit would open a new unlocked one
+#[derive(Debug, Clone, Default)]
+pub struct Compaction {
+    pub dormant_after: u64,
+    // position of the last event per client, positions are the processed count of the summary
+    last_seen: BTreeMap<u16, u64>,
+    // the removed accounts if they are archived instead of dropped
+    pub archive: Option<BTreeMap<u16, ClientAccount>>,
+    pub compacted: u64,
+    // archived accounts that got an event again
+    pub restored: u64,
+}
+
+impl Compaction {
+    pub fn new(dormant_after: u64) -> Self {
+        Compaction {
+            dormant_after,
+            ..Default::default()
+        }
+    }
+
+    pub fn observe(&mut self, client_id: u16, position: u64) {
+        self.last_seen.insert(client_id, position);
+    }
+
+    /// checking after every event would go through all accounts every time
+    pub fn is_due(&self, position: u64) -> bool {
+        self.dormant_after > 0 && position.is_multiple_of(self.dormant_after)
+    }
+
+    /// a client without any event, e.g. restored from a state, is dormant as well
+    pub fn is_dormant(&self, client_id: u16, position: u64) -> bool {
+        self.last_seen
+            .get(&client_id)
+            .is_none_or(|last| position - last >= self.dormant_after)
+    }
+
+    pub fn remove(&mut self, account: ClientAccount) {
+        self.last_seen.remove(&account.id);
+        self.compacted += 1;
+        if let Some(archive) = self.archive.as_mut() {
+            archive.insert(account.id, account);
+        }
+    }
+
+    pub fn is_archived(&self, client_id: u16) -> bool {
+        self.archive
+            .as_ref()
+            .is_some_and(|archive| archive.contains_key(&client_id))
+    }
+
+    /// the account goes back to the live state when its client has an event again
+    pub fn restore(&mut self, client_id: u16) -> Option<ClientAccount> {
+        let account = self.archive.as_mut()?.remove(&client_id)?;
+        self.restored += 1;
+        Some(account)
+    }
+
+    /// the archive of a previous run, its accounts are restored like the ones of this run
+    pub fn load_archive(&mut self, path: &str, rounding: RoundingMode) -> Result<(), String> {
+        let state = load_opening_state(path, rounding)?;
+        if !state.disputes.is_empty() {
+            return Err(format!("the archive {} cannot have open disputes", path));
+        }
+        let archive = self.archive.get_or_insert_with(Default::default);
+        for balance in state.balances.values() {
+            if !balance.available.is_zero() || balance.locked {
+                return Err(format!(
+                    "client {} in the archive {} has a balance or is locked",
+                    balance.client_id, path
+                ));
+            }
+            archive.insert(
+                balance.client_id,
+                ClientAccount::new(balance.client_id, SignedMoney::ZERO),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// in the format of the opening balances, the archive can be the opening file of a run
+    pub fn write<W: Write>(&self, writer: W, rounding: RoundingMode) -> std::io::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(&COLUMNS[..3])?;
+        for account in self.archive.iter().flat_map(|archive| archive.values()) {
+            writer.write_record([
+                account.id.to_string(),
+                Money::ZERO.format(rounding),
+                account.locked.to_string(),
+            ])?;
+        }
+
+        writer.flush()
+    }
+
+    pub fn export(&self, path: &str, rounding: RoundingMode) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer, rounding)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compaction::Compaction;
+    use crate::money::{RoundingMode, SignedMoney};
+    use crate::ClientAccount;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn dormancy_is_counted_in_events() {
+        let mut compaction = Compaction::new(3);
+        compaction.observe(1, 1);
+        compaction.observe(2, 2);
+
+        assert!(!compaction.is_due(2));
+        assert!(compaction.is_due(3));
+        assert!(!compaction.is_dormant(1, 3));
+        assert!(compaction.is_dormant(1, 4));
+        assert!(!compaction.is_dormant(2, 4));
+        assert!(compaction.is_dormant(3, 4), "never seen");
+    }
+
+    #[test]
+    fn archive_is_an_opening_file() {
+        let mut compaction = Compaction::new(3);
+        compaction.archive = Some(BTreeMap::new());
+        compaction.remove(ClientAccount::new(2, SignedMoney::ZERO));
+        compaction.remove(ClientAccount::new(1, SignedMoney::ZERO));
+
+        let mut output = vec![];
+        compaction
+            .write(&mut output, RoundingMode::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,locked\n1,0.0000,false\n2,0.0000,false\n"
+        );
+        assert_eq!(compaction.compacted, 2);
+
+        let path = std::env::temp_dir().join("kraken_test_archive.csv");
+        let path = path.to_str().unwrap();
+        compaction.export(path, RoundingMode::default()).unwrap();
+        let mut next = Compaction::new(3);
+        next.load_archive(path, RoundingMode::default()).unwrap();
+        assert!(next.is_archived(1) && next.is_archived(2));
+        assert_eq!(
+            next.restore(2),
+            Some(ClientAccount::new(2, SignedMoney::ZERO))
+        );
+        assert_eq!(next.restore(2), None);
+        assert_eq!(next.restored, 1);
+
+        std::fs::write(path, "client,available,locked\n1,1.0,false\n").unwrap();
+        assert!(Compaction::new(3)
+            .load_archive(path, RoundingMode::default())
+            .is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
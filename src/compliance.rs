@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::metadata::ClientMetadata;
+use crate::money::{Money, RoundingMode};
+use crate::AccountEvent;
+
+/// built in rules that need the client metadata, without a country we cannot evaluate them
+/// so clients without metadata are never flagged
+#[derive(Debug, Clone, Default)]
+pub struct ComplianceRules {
+    // no event of clients from these countries is processed
+    pub embargoed_countries: BTreeSet<String>,
+    // maximum amount of a single transaction per country
+    pub country_ceilings: BTreeMap<String, Money>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ComplianceRule {
+    Embargo,
+    CountryCeiling(Money),
+}
+
+impl Display for ComplianceRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComplianceRule::Embargo => write!(f, "embargo"),
+            ComplianceRule::CountryCeiling(_) => write!(f, "country_ceiling"),
+        }
+    }
+}
+
+/// an event that was not processed because of a rule, kept for the report
+#[derive(Debug, Clone)]
+pub struct ComplianceViolation {
+    pub event: AccountEvent,
+    pub country: String,
+    pub rule: ComplianceRule,
+}
+
+impl ComplianceRules {
+    pub fn is_empty(&self) -> bool {
+        self.embargoed_countries.is_empty() && self.country_ceilings.is_empty()
+    }
+
+    pub fn check(
+        &self,
+        event: &AccountEvent,
+        metadata: Option<&ClientMetadata>,
+    ) -> Option<ComplianceViolation> {
+        let country = &metadata?.country;
+        let violation = |rule| {
+            Some(ComplianceViolation {
+                event: *event,
+                country: country.to_string(),
+                rule,
+            })
+        };
+
+        if self.embargoed_countries.contains(country) {
+            return violation(ComplianceRule::Embargo);
+        }
+
+        match self.country_ceilings.get(country) {
+            Some(ceiling) if event.amount.unwrap_or_default() > *ceiling => {
+                violation(ComplianceRule::CountryCeiling(*ceiling))
+            }
+            _ => None,
+        }
+    }
+
+    /// COUNTRY=amount
+    pub fn add_ceiling(&mut self, value: &str, rounding: RoundingMode) -> Result<(), String> {
+        let (country, amount) = value
+            .split_once('=')
+            .ok_or_else(|| format!("invalid country ceiling: {} (expected XX=amount)", value))?;
+        let amount = Money::parse(amount, rounding)?;
+
+        self.country_ceilings
+            .insert(country.trim().to_uppercase(), amount);
+        Ok(())
+    }
+
+    /// comma separated list of countries
+    pub fn add_embargoed(&mut self, value: &str) {
+        value
+            .split(',')
+            .map(|country| country.trim().to_uppercase())
+            .filter(|country| !country.is_empty())
+            .for_each(|country| {
+                self.embargoed_countries.insert(country);
+            });
+    }
+}
+
+pub fn write_compliance_report(
+    path: &str,
+    violations: &[ComplianceViolation],
+    rounding: RoundingMode,
+) -> std::io::Result<()> {
+    let mut writer = csv::Writer::from_writer(BufWriter::new(File::create(path)?));
+    writer.write_record([
+        "type", "client", "tx", "amount", "country", "rule", "ceiling",
+    ])?;
+    for violation in violations {
+        let amount = violation
+            .event
+            .amount
+            .map(|amount| amount.format(rounding))
+            .unwrap_or_default();
+        let ceiling = match violation.rule {
+            ComplianceRule::CountryCeiling(ceiling) => ceiling.format(rounding),
+            ComplianceRule::Embargo => String::new(),
+        };
+
+        writer.write_record([
+            violation.event.action_type.to_string(),
+            violation.event.client_id.to_string(),
+            violation.event.transaction_id.to_string(),
+            amount,
+            violation.country.clone(),
+            violation.rule.to_string(),
+            ceiling,
+        ])?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compliance::{
+        write_compliance_report, ComplianceRule, ComplianceRules, ComplianceViolation,
+    };
+    use crate::metadata::ClientMetadata;
+    use crate::money::{Money, RoundingMode};
+    use crate::{AccountActions, AccountEvent};
+
+    fn metadata(country: &str) -> ClientMetadata {
+        ClientMetadata {
+            client_id: 1,
+            name: "name".to_string(),
+            country: country.to_string(),
+            risk_score: 0,
+        }
+    }
+
+    fn deposit(amount: u64) -> AccountEvent {
+        AccountEvent {
+            transaction_id: 1,
+            action_type: AccountActions::Deposit,
+            client_id: 1,
+            amount: Some(Money::from_minor_units(amount)),
+        }
+    }
+
+    #[test]
+    fn embargoed_country_is_blocked() {
+        let mut rules = ComplianceRules::default();
+        rules.add_embargoed("kp, IR");
+
+        let violation = rules.check(&deposit(1), Some(&metadata("KP"))).unwrap();
+        assert_eq!(violation.rule, ComplianceRule::Embargo);
+        assert_eq!(violation.country, "KP");
+        assert!(rules.check(&deposit(1), Some(&metadata("AT"))).is_none());
+    }
+
+    #[test]
+    fn country_ceiling() {
+        let mut rules = ComplianceRules::default();
+        rules.add_ceiling("us=1.5", RoundingMode::HalfUp).unwrap();
+
+        assert!(rules
+            .check(&deposit(15000), Some(&metadata("US")))
+            .is_none());
+        let violation = rules.check(&deposit(15001), Some(&metadata("US"))).unwrap();
+        assert_eq!(
+            violation.rule,
+            ComplianceRule::CountryCeiling(Money::from_minor_units(15000))
+        );
+        assert!(rules
+            .check(&deposit(15001), Some(&metadata("AT")))
+            .is_none());
+        assert!(rules.add_ceiling("US", RoundingMode::HalfUp).is_err());
+    }
+
+    #[test]
+    fn no_metadata_no_violation() {
+        let mut rules = ComplianceRules::default();
+        rules.add_embargoed("KP");
+
+        assert!(rules.check(&deposit(1), None).is_none());
+    }
+
+    #[test]
+    fn report_round_trip() {
+        let path = std::env::temp_dir().join("kraken_test_compliance_report.csv");
+        let violations = [ComplianceViolation {
+            event: deposit(15000),
+            country: "X,\"Y\"".to_string(),
+            rule: ComplianceRule::Embargo,
+        }];
+        write_compliance_report(path.to_str().unwrap(), &violations, RoundingMode::HalfUp).unwrap();
+
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(&rows[0][3], "1.5000");
+        assert_eq!(&rows[0][4], "X,\"Y\"");
+        assert_eq!(rows[0].len(), 7);
+    }
+}
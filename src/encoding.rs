@@ -0,0 +1,272 @@
+use std::fmt::{Display, Formatter};
+use std::io::{Cursor, Read};
+use std::str::FromStr;
+
+/// files exported by spreadsheets start with a BOM or are not utf-8 at all. the csv reader
+/// only understands utf-8 so everything else is transcoded before it gets there
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum InputEncoding {
+    // the BOM decides, utf-8 without one
+    #[default]
+    Auto,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    // cannot be detected, every byte sequence is valid windows-1252
+    Windows1252,
+}
+
+impl FromStr for InputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(InputEncoding::Auto),
+            "utf-8" => Ok(InputEncoding::Utf8),
+            "utf-16le" => Ok(InputEncoding::Utf16Le),
+            "utf-16be" => Ok(InputEncoding::Utf16Be),
+            "windows-1252" => Ok(InputEncoding::Windows1252),
+            _ => Err(format!(
+                "unknown input encoding: {} (auto, utf-8, utf-16le, utf-16be, windows-1252)",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for InputEncoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            InputEncoding::Auto => "auto",
+            InputEncoding::Utf8 => "utf-8",
+            InputEncoding::Utf16Le => "utf-16le",
+            InputEncoding::Utf16Be => "utf-16be",
+            InputEncoding::Windows1252 => "windows-1252",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// 0x80 - 0x9F are the only bytes where windows-1252 differs from latin-1,
+/// the 5 undefined ones are passed through as their C1 control character
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+fn windows_1252(byte: u8) -> char {
+    match byte {
+        0x80..=0x9F => WINDOWS_1252_HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+/// utf-8 with any BOM removed, the detection only looks at the first bytes
+pub fn decode<'r, R: Read + 'r>(
+    mut reader: R,
+    encoding: InputEncoding,
+) -> std::io::Result<Box<dyn Read + 'r>> {
+    let mut prefix = [0u8; 3];
+    let mut length = 0;
+    while length < prefix.len() {
+        match reader.read(&mut prefix[length..])? {
+            0 => break,
+            read => length += read,
+        }
+    }
+    let prefix = &prefix[..length];
+
+    let (encoding, bom) = match encoding {
+        InputEncoding::Auto if prefix.starts_with(UTF8_BOM) => (InputEncoding::Utf8, 3),
+        InputEncoding::Auto if prefix.starts_with(UTF16LE_BOM) => (InputEncoding::Utf16Le, 2),
+        InputEncoding::Auto if prefix.starts_with(UTF16BE_BOM) => (InputEncoding::Utf16Be, 2),
+        InputEncoding::Auto => (InputEncoding::Utf8, 0),
+        InputEncoding::Utf8 if prefix.starts_with(UTF8_BOM) => (encoding, 3),
+        InputEncoding::Utf16Le if prefix.starts_with(UTF16LE_BOM) => (encoding, 2),
+        InputEncoding::Utf16Be if prefix.starts_with(UTF16BE_BOM) => (encoding, 2),
+        _ => (encoding, 0),
+    };
+    if encoding != InputEncoding::Utf8 || bom > 0 {
+        debug!("input is read as {} (BOM: {} bytes)", encoding, bom);
+    }
+
+    let rest = Cursor::new(prefix[bom..].to_vec()).chain(reader);
+    Ok(match encoding {
+        InputEncoding::Utf8 | InputEncoding::Auto => Box::new(rest),
+        _ => Box::new(Transcoder::new(rest, encoding)),
+    })
+}
+
+/// streaming transcoder to utf-8, only whole characters are emitted so a surrogate pair split
+/// between two reads is kept until the next one
+struct Transcoder<R> {
+    inner: R,
+    encoding: InputEncoding,
+    // bytes of an incomplete utf-16 unit or surrogate pair
+    pending: Vec<u8>,
+    output: Vec<u8>,
+    position: usize,
+    eof: bool,
+}
+
+impl<R: Read> Transcoder<R> {
+    fn new(inner: R, encoding: InputEncoding) -> Self {
+        Transcoder {
+            inner,
+            encoding,
+            pending: vec![],
+            output: vec![],
+            position: 0,
+            eof: false,
+        }
+    }
+
+    fn push(&mut self, c: char) {
+        let mut buffer = [0u8; 4];
+        self.output
+            .extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+    }
+
+    fn fill(&mut self) -> std::io::Result<()> {
+        self.output.clear();
+        self.position = 0;
+
+        let mut chunk = [0u8; 8192];
+        let read = self.inner.read(&mut chunk)?;
+        if read == 0 {
+            self.eof = true;
+            // a truncated file, whatever is left cannot be a valid character
+            if !self.pending.is_empty() {
+                self.pending.clear();
+                self.push(char::REPLACEMENT_CHARACTER);
+            }
+            return Ok(());
+        }
+
+        if self.encoding == InputEncoding::Windows1252 {
+            for byte in &chunk[..read] {
+                self.push(windows_1252(*byte));
+            }
+            return Ok(());
+        }
+
+        self.pending.extend_from_slice(&chunk[..read]);
+        let big_endian = self.encoding == InputEncoding::Utf16Be;
+        let mut units: Vec<u16> = self
+            .pending
+            .chunks_exact(2)
+            .map(|pair| {
+                if big_endian {
+                    u16::from_be_bytes([pair[0], pair[1]])
+                } else {
+                    u16::from_le_bytes([pair[0], pair[1]])
+                }
+            })
+            .collect();
+        let mut consumed = units.len() * 2;
+        // a high surrogate at the end needs its partner from the next read
+        if matches!(units.last(), Some(0xD800..=0xDBFF)) {
+            units.pop();
+            consumed -= 2;
+        }
+
+        for c in char::decode_utf16(units) {
+            self.push(c.unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+        self.pending.drain(..consumed);
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Transcoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.output.len() {
+            if self.eof {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+
+        let length = buf.len().min(self.output.len() - self.position);
+        buf[..length].copy_from_slice(&self.output[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::encoding::{decode, InputEncoding};
+    use std::io::Read;
+
+    fn decoded(input: &[u8], encoding: InputEncoding) -> String {
+        let mut output = String::new();
+        decode(input, encoding)
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        output
+    }
+
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| {
+                if big_endian {
+                    unit.to_be_bytes()
+                } else {
+                    unit.to_le_bytes()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn utf8_bom_is_stripped() {
+        assert_eq!(decoded(b"\xEF\xBB\xBFtype", InputEncoding::Auto), "type");
+        assert_eq!(decoded(b"\xEF\xBB\xBFtype", InputEncoding::Utf8), "type");
+        assert_eq!(decoded(b"ty", InputEncoding::Auto), "ty");
+        assert_eq!(decoded(b"", InputEncoding::Auto), "");
+    }
+
+    #[test]
+    fn utf16_is_detected_by_its_bom() {
+        let mut little = vec![0xFF, 0xFE];
+        little.extend(utf16("type,client\n€ 𝄞", false));
+        assert_eq!(decoded(&little, InputEncoding::Auto), "type,client\n€ 𝄞");
+
+        let mut big = vec![0xFE, 0xFF];
+        big.extend(utf16("type", true));
+        assert_eq!(decoded(&big, InputEncoding::Auto), "type");
+
+        // without a BOM it has to be given
+        assert_eq!(decoded(&utf16("tx", true), InputEncoding::Utf16Be), "tx");
+    }
+
+    #[test]
+    fn surrogate_pairs_survive_chunk_boundaries() {
+        // 8192 bytes are read at once, the pair starts at byte 8190
+        let text = format!("{}𝄞", "a".repeat(4095));
+        assert_eq!(decoded(&utf16(&text, false), InputEncoding::Utf16Le), text);
+        assert_eq!(
+            decoded(&[0x61, 0x00, 0x3D], InputEncoding::Utf16Le),
+            "a\u{FFFD}"
+        );
+    }
+
+    #[test]
+    fn windows_1252() {
+        assert_eq!(
+            decoded(b"caf\xE9 \x80 \x93x\x94", InputEncoding::Windows1252),
+            "café € “x”"
+        );
+        assert!("latin-9".parse::<InputEncoding>().is_err());
+    }
+}
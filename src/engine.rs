@@ -0,0 +1,3150 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use log::debug;
+
+use crate::actions::{AccountAction, Outcome, TxContext};
+use crate::analytics::ClientStatistics;
+use crate::audit::{AuditLog, AuditValue};
+use crate::clock::{Clock, SystemClock};
+use crate::compaction::Compaction;
+use crate::compliance::{ComplianceRules, ComplianceViolation};
+use crate::encoding::InputEncoding;
+use crate::graph::DisputeGraph;
+use crate::holdback::Holdback;
+use crate::invariants::{InvariantMode, InvariantViolation};
+use crate::io::{CsvRecord, SchemaMode, SchemaVersion, CORRELATION};
+use crate::latency::LatencyHistogram;
+use crate::metadata::ClientMetadata;
+use crate::metrics::{reason_label, Metrics};
+use crate::model::{
+    AccountActions, AccountCreation, AccountEvent, ClientAccount, DisputeOutcome, DisputePolicy,
+    LoggedEvent, Note, Transaction,
+};
+use crate::money::{Money, NumberFormat, RoundingMode, SignedMoney};
+use crate::opening::{OpenDispute, OpeningBalance};
+use crate::partition::Partition;
+use crate::quarantine::{panic_message, Quarantine, RejectReason};
+use crate::recent::RecentTransactions;
+use crate::redact::Logged;
+use crate::replay::Exclusions;
+use crate::risk::RiskScoring;
+use crate::sampling::Sample;
+use crate::sink::{CsvSink, OutputSink};
+use crate::snapshot::Snapshot;
+use crate::source::{CsvSource, EventSource, SourceError};
+use crate::store::{AccountStore, TransactionStore};
+use crate::structuring::StructuringDetector;
+
+/// Certain assumptions: Floatings point numbers are tricky because 0.9 = 1 as we know from math and this attribute
+/// leads to our famous need for radix and other things because memory size and representation is tricky
+/// to avoid this we can either go for a fixed number or just remain within the realm of natural integers
+/// and only use it for representation purposes
+///
+/// since our transaction is defined with 4 decimal places we just multiply it by 10000 and this already allows us
+/// to ignore this hazard and the transformations just have to occur on the beginning and the end
+///
+/// we are not allowed to go into the negative number range so we are in ℕ and within ℕ we're in ℵ0
+/// fancy way of saying "more than we can count" in math.
+///
+/// u32 should have a range of 4 294 967 295 so just for the sake of it I will use u64
+///
+/// core flow idea
+/// ---------    --------------    ------------------    ---------------
+/// | start | -> | stream csv | -> | process events | -> | display csv |
+/// ---------    --------------    ------------------    ---------------
+///
+/// we have 5 Actions
+///  - withdraw
+///  - deposit
+///  - dispute
+///  - resolve
+///  - chargeback
+///
+/// we have a transaction stream that is applied to customer accounts
+/// we can be out of order (sequential consistency) so a form of eventual consistency
+/// if we do this in a concurrent stream form we have the obvious order problem to avoid this problem
+/// we will also log the last transaction within the account so we know that 6 cannot be applied before 2
+/// this still does not solve the possible dispute problem.
+/// I am not sure how the definition of a dispute is. Can it be 1 week later? 1h? 10sec?
+///
+/// now we have the resource problem to address, how can we store and apply things,
+/// if we just do resource streaming, this would solve some of the memory problems.
+///
+/// Since none of these problems are addressed in the specification I will take the simplest and safest
+/// approach. which means no concurrency (order problems) so this will basically a simple stream with 2
+/// BTrees as storage engine (client_id, ClientAccount) and (transaction_id, AccountEvent)
+///
+/// this should hopefully allows us quick lookups.
+///
+///
+/// we assume for argument sake 1 million records in the csv
+/// so we have size wise per record 24 bytes -> as seen in the test
+///
+/// u16 + i32 + u64 + enum (u8) <https://fasterthanli.me/articles/peeking-inside-a-rust-enum>
+/// which roughly would be (memory sizes in the tests) 22.888183594 MB if we keep it in memory (ofc I ignore the allocation of the BTree which basically will
+/// now we only need to store the actual ones with money in which with luck means an even smaller footprint
+/// since we're storing it in an BTreeMap this allows us to have theoretical O(1) lookup time to discard invalid transactions / out of order transactions
+///
+/// so lets assume ~ 50MB for 1 Million entries if we only have deposits
+///
+/// the problem in general is that in theory if these are web streams
+/// that we cannot assume the right order so probably what we will need is a retry consolidation concept.
+/// but the amount of different approaches is just to much
+///
+/// This is concept 1: straight forward
+/// Concept 2 would be using a state-machine using "from"
+/// would be more fun but we wanted maintainability as a focus so i kept a rather straight forward approach
+/// Concept 3 would be using a functional approach where we let the compiler realize the monads and optimize the resources
+/// Concept 4 would be an actor model with message passing and shared resource
+///
+/// the advantage of async here is not really given we still need to remain linear in processing unless
+/// we go from O(n) to O(k + n) so we basically just dispatch things to the kernel creating overhead to appear fancy.
+///
+/// the specification said we can discard non existing transactions. personally I would add a retry loop after finishing processing
+/// to verify no open dispute is there but I already can think of a lot of things that would be needed to be specified I am missing
+///
+/// also ofc I could've done simple line per line streams or pass by ref things
+///
+/// the ledger maps are private so nothing outside of the processing can break what the
+/// invariants check, they are read through the accessors and only changed by events or a restore.
+/// the accounts and transactions are in btree maps unless other stores are given
+pub struct AccountProcessing<A = BTreeMap<u16, ClientAccount>, T = BTreeMap<i32, Transaction>> {
+    pub(crate) accounts: A,
+    pub(crate) transactions: T,
+    // tx -> amount that is currently held for it, can be less than the transaction with partial holds
+    pub(crate) open_disputes: BTreeMap<i32, Money>,
+    // the last transactions per client, checked before the map of all transactions
+    pub(crate) recent_transactions: BTreeMap<u16, RecentTransactions>,
+    // optional reference data, keyed the same way as the accounts
+    pub client_metadata: BTreeMap<u16, ClientMetadata>,
+    pub compliance: ComplianceRules,
+    // events that were flagged by the compliance rules and therefore not processed
+    pub violations: Vec<ComplianceViolation>,
+    // lifetime numbers per client, carried over between runs by the state
+    pub statistics: BTreeMap<u16, ClientStatistics>,
+    // annotations of the admin batches, they are kept in the state like the statistics
+    pub(crate) notes: BTreeMap<u16, Vec<Note>>,
+    pub summary: ProcessingSummary,
+    // the apply times are measured with it
+    pub clock: Arc<dyn Clock>,
+    // events that are left out as if they were never in the input
+    pub exclusions: Exclusions,
+    // action types that are not processed in this run
+    pub holdback: Holdback,
+    // the correlation id of the row that is being ingested, only set while it is
+    correlation: Option<String>,
+    // the name of the source of the event that is being ingested, only set for a fan-in
+    source_name: Option<String>,
+    // the first violation of InvariantMode::Abort, no event is ingested after it
+    halted: Option<Box<InvariantViolation>>,
+    pub config: Config,
+    // registered next to the built-ins, the position is the id of AccountActions::Custom
+    pub custom_actions: Vec<(String, Box<dyn AccountAction>)>,
+    // the accounts of display, stdout without one
+    pub output: Option<Box<dyn OutputSink>>,
+    // the optional parts, only allocated once one of them is set
+    pub subsystems: Option<Box<Subsystems>>,
+}
+
+/// what a run can be extended with, none of it is needed to process the events
+#[derive(Default)]
+pub struct Subsystems {
+    // optional AML heuristic, sees every event that passed the compliance rules
+    pub structuring: Option<StructuringDetector>,
+    // optional score per client from its applied events, an extra column of the output
+    pub risk: Option<RiskScoring>,
+    // optional tx -> client -> outcome relations for the graph export
+    pub dispute_graph: Option<DisputeGraph>,
+    // decisions that are not visible in the balances end up here
+    pub audit: Option<AuditLog>,
+    // optional apply time per event
+    pub latency: Option<LatencyHistogram>,
+    // counters of the applied and rejected events for the telemetry of an embedder
+    pub metrics: Option<Arc<dyn Metrics>>,
+    // optional rows that could not be parsed and events that were rejected, with the reason
+    pub quarantine: Option<Quarantine>,
+    // optional removal of dormant accounts without a balance from the live state
+    pub compaction: Option<Compaction>,
+    // called with every decision of ingest, in the order they were registered
+    applied_hooks: Vec<AppliedHook>,
+    rejected_hooks: Vec<RejectedHook>,
+}
+
+/// sees the account of the client after the event was applied
+pub type AppliedHook = Box<dyn Fn(&AccountEvent, &ClientAccount)>;
+/// sees why the event did not change any account
+pub type RejectedHook = Box<dyn Fn(&AccountEvent, RejectReason)>;
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct ProcessingSummary {
+    // every event that was read
+    pub processed: u64,
+    // events that did not change any account
+    pub rejected: u64,
+    // events that were not looked at since their client is not in the sample
+    pub skipped: u64,
+    // repeated header rows of concatenated files, only counted if they are skipped
+    pub embedded_headers: u64,
+    // rows that were read without their optional amount column
+    pub missing_columns: u64,
+    // events that were cut out by the exclusions of a replay
+    pub excluded: u64,
+    // events of an action type that is ignored in this run
+    pub ignored: u64,
+    // only counted when the invariants are reported instead of aborting
+    pub invariant_violations: u64,
+    // rows that could not be read or parsed, they are in the quarantine if there is one
+    pub parse_errors: u64,
+}
+
+/// what stops the processing of an input or a single event
+#[derive(Debug)]
+pub enum EngineError {
+    Io(std::io::Error),
+    // the header does not fit, nothing of the input was processed
+    Parse { line: u64, message: String },
+    InvalidEvent(RejectReason),
+    // with InvariantMode::Abort, the processing stops after the event that broke the ledger
+    InvariantViolated(InvariantViolation),
+    // the accounts could not be written to the output
+    Output(std::io::Error),
+}
+
+impl Display for EngineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EngineError::Io(e) => write!(f, "cannot read the input: {}", e),
+            EngineError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            EngineError::InvalidEvent(reason) => write!(f, "event rejected: {}", reason),
+            EngineError::InvariantViolated(violation) => write!(f, "{}", violation),
+            EngineError::Output(e) => write!(f, "cannot write accounts: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EngineError::Io(e) | EngineError::Output(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// an event that ingest applied, with the account after it
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Applied {
+    pub account: ClientAccount,
+}
+
+/// an event that ingest did not apply, the summary counts both kinds. a violated invariant
+/// is not a reject of the event but the end of the processing
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Rejected {
+    // not for this processing: another partition, not sampled, excluded or an ignored type
+    Skipped,
+    Invalid(RejectReason),
+    // with InvariantMode::Abort, for the event that broke the ledger and every event after it
+    InvariantViolated(InvariantViolation),
+}
+
+impl Display for Rejected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejected::Skipped => write!(f, "skipped"),
+            Rejected::Invalid(reason) => write!(f, "rejected: {}", reason),
+            Rejected::InvariantViolated(violation) => write!(f, "{}", violation),
+        }
+    }
+}
+
+impl From<std::io::Error> for EngineError {
+    fn from(e: std::io::Error) -> Self {
+        EngineError::Io(e)
+    }
+}
+
+impl From<RejectReason> for EngineError {
+    fn from(reason: RejectReason) -> Self {
+        EngineError::InvalidEvent(reason)
+    }
+}
+
+/// the account after the action and what the action reported, not applied yet
+struct Decision {
+    account: ClientAccount,
+    outcome: Outcome,
+    // of the event or, for the dispute family, of the referenced transaction
+    amount: Money,
+}
+
+/// the outcome an event would have, the account is the one it would leave behind
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Preview {
+    pub outcome: Result<(), RejectReason>,
+    pub account: ClientAccount,
+}
+
+/// what a single batch added to the summary
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct BatchOutcome {
+    pub applied: u64,
+    pub rejected: u64,
+    // not in the sample or excluded
+    pub skipped: u64,
+}
+
+impl BatchOutcome {
+    fn between(before: &ProcessingSummary, after: &ProcessingSummary) -> Self {
+        let rejected = after.rejected - before.rejected;
+        BatchOutcome {
+            applied: after.processed - before.processed - rejected,
+            rejected,
+            skipped: after.skipped + after.excluded + after.ignored
+                - before.skipped
+                - before.excluded
+                - before.ignored,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Config {
+    // used whenever minor units are converted from or to their decimal representation
+    pub rounding: RoundingMode,
+    // clients with a risk score at or above this one are treated as high risk
+    pub high_risk_score: Option<u8>,
+    // high risk clients cannot withdraw more than this in a single transaction
+    pub high_risk_withdrawal_limit: Option<Money>,
+    // only process the events of a reproducible subset of the clients
+    pub sample: Option<Sample>,
+    // what happens if a dispute references more than is available
+    pub dispute_policy: DisputePolicy,
+    // which kind of transactions can be disputed
+    pub disputable: DisputableActions,
+    // which events create the account of a client we have not seen yet
+    pub account_creation: AccountCreation,
+    // rows can be shorter than the header, the missing optional columns are empty
+    pub tolerate_missing_columns: bool,
+    // rows that repeat the header are not counted as invalid rows
+    pub skip_embedded_headers: bool,
+    // the clients of the other partitions are processed by another sub-engine
+    pub partition: Option<Partition>,
+    // the input is transcoded to utf-8 before it is parsed
+    pub input_encoding: InputEncoding,
+    pub schema: SchemaMode,
+    // the version of rows without a schema_version column, detected from the header if not set
+    pub schema_version: Option<SchemaVersion>,
+    // every applied event is followed by a check of the ledger invariants
+    pub invariants: Option<InvariantMode>,
+    // one structured info line with the balances before and after every applied event
+    pub log_balances: bool,
+    // decimal separator of the amounts in the accounts output
+    pub number_format: NumberFormat,
+    // version and last_tx columns in the accounts output
+    pub account_versions: bool,
+    // a panic while applying an event ends the run instead of rejecting the event
+    pub strict: bool,
+    // amounts in the logs, the audit log and the quarantine are masked, ids and outcomes stay
+    pub redact_amounts: bool,
+    // a locked account still receives deposits, withdrawals stay rejected
+    pub locked_deposits: bool,
+    // a reused tx id is rejected instead of replacing the earlier transaction
+    pub reject_duplicate_tx: bool,
+}
+
+impl Config {
+    /// options that cannot be combined
+    pub fn check(&self) -> Result<(), String> {
+        if self.schema == SchemaMode::Strict && self.tolerate_missing_columns {
+            return Err("a strict schema cannot tolerate missing columns".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// the options of a processing that are fixed once it is built, everything not set is the
+/// default of Config
+#[derive(Default)]
+pub struct AccountProcessingBuilder {
+    config: Config,
+    output: Option<Box<dyn OutputSink>>,
+}
+
+impl AccountProcessingBuilder {
+    pub fn new() -> Self {
+        AccountProcessingBuilder::default()
+    }
+
+    /// the starting point, the other setters change single options of it
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// how closely the header has to follow the specification
+    pub fn schema(mut self, schema: SchemaMode) -> Self {
+        self.config.schema = schema;
+        self
+    }
+
+    /// the version of the rows that don't have their own schema_version
+    pub fn schema_version(mut self, version: SchemaVersion) -> Self {
+        self.config.schema_version = Some(version);
+        self
+    }
+
+    pub fn tolerate_missing_columns(mut self, tolerate: bool) -> Self {
+        self.config.tolerate_missing_columns = tolerate;
+        self
+    }
+
+    pub fn locked_deposits(mut self, allowed: bool) -> Self {
+        self.config.locked_deposits = allowed;
+        self
+    }
+
+    pub fn reject_duplicate_tx(mut self, reject: bool) -> Self {
+        self.config.reject_duplicate_tx = reject;
+        self
+    }
+
+    pub fn dispute_policy(mut self, policy: DisputePolicy) -> Self {
+        self.config.dispute_policy = policy;
+        self
+    }
+
+    pub fn disputable(mut self, disputable: DisputableActions) -> Self {
+        self.config.disputable = disputable;
+        self
+    }
+
+    /// amounts always have four decimals, this is how more precise input is cut to them
+    pub fn rounding(mut self, rounding: RoundingMode) -> Self {
+        self.config.rounding = rounding;
+        self
+    }
+
+    pub fn number_format(mut self, number_format: NumberFormat) -> Self {
+        self.config.number_format = number_format;
+        self
+    }
+
+    /// where display writes the accounts, stdout if there is none
+    pub fn output(mut self, output: Box<dyn OutputSink>) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    pub fn build(self) -> Result<AccountProcessing, String> {
+        self.config.check()?;
+        let mut processing = AccountProcessing::new(self.config);
+        processing.output = self.output;
+        Ok(processing)
+    }
+}
+
+/// deposits and withdrawals are the transactions a dispute can reference
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DisputableActions {
+    pub deposit: bool,
+    pub withdrawal: bool,
+}
+
+impl Default for DisputableActions {
+    // disputing a withdrawal would hold money that already left the account
+    fn default() -> Self {
+        DisputableActions {
+            deposit: true,
+            withdrawal: false,
+        }
+    }
+}
+
+impl DisputableActions {
+    pub fn allows(&self, action_type: AccountActions) -> bool {
+        match action_type {
+            AccountActions::Deposit => self.deposit,
+            AccountActions::Withdrawal => self.withdrawal,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for DisputableActions {
+    type Err = String;
+
+    /// comma separated list: deposit,withdrawal
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut disputable = DisputableActions {
+            deposit: false,
+            withdrawal: false,
+        };
+
+        for action in s.split(',').map(str::trim) {
+            match action {
+                "deposit" => disputable.deposit = true,
+                "withdrawal" => disputable.withdrawal = true,
+                _ => {
+                    return Err(format!(
+                        "{} cannot be disputed (deposit, withdrawal)",
+                        action
+                    ))
+                }
+            }
+        }
+
+        Ok(disputable)
+    }
+}
+
+impl AccountProcessing {
+    pub fn builder() -> AccountProcessingBuilder {
+        AccountProcessingBuilder::new()
+    }
+
+    pub fn new(config: Config) -> Self {
+        Self::with_stores(config, BTreeMap::new(), BTreeMap::new())
+    }
+
+    pub fn event_needs_transaction_lookup(account_action: AccountActions) -> bool {
+        account_action == AccountActions::ChargeBack
+            || account_action == AccountActions::Resolve
+            || account_action == AccountActions::Dispute
+    }
+}
+
+impl<A: AccountStore, T: TransactionStore> AccountProcessing<A, T> {
+    /// e.g. stores that keep only a part of the ledger in memory, they should start empty
+    pub fn with_stores(config: Config, accounts: A, transactions: T) -> Self {
+        AccountProcessing {
+            accounts,
+            transactions,
+            open_disputes: Default::default(),
+            recent_transactions: Default::default(),
+            client_metadata: Default::default(),
+            compliance: Default::default(),
+            violations: vec![],
+            statistics: Default::default(),
+            notes: Default::default(),
+            summary: Default::default(),
+            clock: Arc::new(SystemClock),
+            exclusions: Default::default(),
+            holdback: Default::default(),
+            correlation: None,
+            source_name: None,
+            halted: None,
+            config,
+            custom_actions: vec![],
+            output: None,
+            subsystems: None,
+        }
+    }
+
+    /// the optional parts, they are created on the first use
+    pub fn subsystems_mut(&mut self) -> &mut Subsystems {
+        self.subsystems.get_or_insert_with(Default::default)
+    }
+
+    pub fn structuring(&self) -> Option<&StructuringDetector> {
+        self.subsystems.as_ref()?.structuring.as_ref()
+    }
+
+    pub fn risk(&self) -> Option<&RiskScoring> {
+        self.subsystems.as_ref()?.risk.as_ref()
+    }
+
+    pub fn dispute_graph(&self) -> Option<&DisputeGraph> {
+        self.subsystems.as_ref()?.dispute_graph.as_ref()
+    }
+
+    pub fn audit(&self) -> Option<&AuditLog> {
+        self.subsystems.as_ref()?.audit.as_ref()
+    }
+
+    pub fn audit_mut(&mut self) -> Option<&mut AuditLog> {
+        self.subsystems.as_mut()?.audit.as_mut()
+    }
+
+    pub fn latency(&self) -> Option<&LatencyHistogram> {
+        self.subsystems.as_ref()?.latency.as_ref()
+    }
+
+    pub fn metrics(&self) -> Option<&Arc<dyn Metrics>> {
+        self.subsystems.as_ref()?.metrics.as_ref()
+    }
+
+    pub fn quarantine(&self) -> Option<&Quarantine> {
+        self.subsystems.as_ref()?.quarantine.as_ref()
+    }
+
+    pub fn quarantine_mut(&mut self) -> Option<&mut Quarantine> {
+        self.subsystems.as_mut()?.quarantine.as_mut()
+    }
+
+    pub fn compaction(&self) -> Option<&Compaction> {
+        self.subsystems.as_ref()?.compaction.as_ref()
+    }
+
+    fn applied_hooks(&self) -> &[AppliedHook] {
+        self.subsystems
+            .as_ref()
+            .map_or(&[], |subsystems| &subsystems.applied_hooks)
+    }
+
+    fn rejected_hooks(&self) -> &[RejectedHook] {
+        self.subsystems
+            .as_ref()
+            .map_or(&[], |subsystems| &subsystems.rejected_hooks)
+    }
+
+    /// e.g. to log, meter or forward every applied event
+    pub fn on_applied(&mut self, hook: impl Fn(&AccountEvent, &ClientAccount) + 'static) {
+        self.subsystems_mut().applied_hooks.push(Box::new(hook));
+    }
+
+    /// every rejected event, skipped ones of another partition or outside the sample are not
+    /// rejections and don't get here
+    pub fn on_rejected(&mut self, hook: impl Fn(&AccountEvent, RejectReason) + 'static) {
+        self.subsystems_mut().rejected_hooks.push(Box::new(hook));
+    }
+
+    /// rows with this type are applied through the given action, the returned type can be used
+    /// for events that don't come from a csv
+    pub fn register_action(
+        &mut self,
+        name: &str,
+        action: Box<dyn AccountAction>,
+    ) -> Result<AccountActions, String> {
+        if self.action_type(name).is_some() {
+            return Err(format!("action {} is already registered", name));
+        }
+
+        let id = u8::try_from(self.custom_actions.len())
+            .map_err(|_| format!("too many custom actions to register {}", name))?;
+        self.custom_actions.push((name.to_string(), action));
+        Ok(AccountActions::Custom(id))
+    }
+
+    /// built-in or registered action type for the type column of a row
+    pub fn action_type(&self, name: &str) -> Option<AccountActions> {
+        name.parse().ok().or_else(|| {
+            self.custom_actions
+                .iter()
+                .position(|(custom, _)| custom == name)
+                .map(|id| AccountActions::Custom(id as u8))
+        })
+    }
+
+    /// unparseable rows don't stop the run, they are counted in the summary
+    pub fn run(&mut self, path_to_csv: String) -> Result<ProcessingSummary, EngineError> {
+        let file = File::open(path_to_csv)?;
+        self.run_reader(BufReader::new(file))
+    }
+
+    /// like run for any csv, e.g. stdin
+    pub fn run_reader<R: Read>(&mut self, reader: R) -> Result<ProcessingSummary, EngineError> {
+        self.try_process_reader(reader)?;
+        self.display()?;
+        Ok(self.summary)
+    }
+
+    pub fn process_file(&mut self, path_to_csv: &str) -> Result<(), EngineError> {
+        let file = File::open(path_to_csv)?;
+        self.try_process_reader(BufReader::new(file))
+    }
+
+    /// any csv with a header row, the file is just one possible source
+    pub fn process_reader<R: Read>(&mut self, reader: R) {
+        if let Err(e) = self.try_process_reader(reader) {
+            error!("{}", e);
+        }
+    }
+
+    /// like process_reader but an input that cannot be read at all is returned
+    pub fn try_process_reader<R: Read>(&mut self, reader: R) -> Result<(), EngineError> {
+        let mut source = self.csv_source(reader)?;
+        let result = self.run_from(&mut source);
+        self.summary.embedded_headers += source.embedded_headers;
+        self.summary.missing_columns += source.missing_columns;
+
+        info!(
+            "{} events processed, {} excluded, {} embedded headers skipped, {} rows without amount column",
+            self.summary.processed,
+            self.summary.excluded,
+            self.summary.embedded_headers,
+            self.summary.missing_columns
+        );
+        result
+    }
+
+    /// a csv source that knows the custom actions registered so far
+    pub fn csv_source<'r, R: Read + 'r>(&self, reader: R) -> Result<CsvSource<'r>, EngineError> {
+        let custom_actions = self
+            .custom_actions
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+        CsvSource::new(reader, &self.config, custom_actions)
+    }
+
+    /// every event of the source, invalid rows are parse errors. only a source that cannot
+    /// continue stops the run
+    pub fn run_from<S: EventSource + ?Sized>(&mut self, source: &mut S) -> Result<(), EngineError> {
+        while let Some(next) = source.next_event() {
+            match next {
+                Ok(event) => {
+                    self.correlation = source.correlation().map(str::to_string);
+                    self.source_name = source.source_name().map(str::to_string);
+                    let result = self.ingest(event);
+                    if let (Some(name), Some(metrics)) = (&self.source_name, self.metrics()) {
+                        let outcome = match result {
+                            Ok(_) => "applied",
+                            Err(Rejected::Skipped) => "skipped",
+                            Err(Rejected::Invalid(_)) => "rejected",
+                            Err(Rejected::InvariantViolated(_)) => "invariant_violated",
+                        };
+                        let labels = [("source", name.as_str()), ("outcome", outcome)];
+                        metrics.counter(crate::metrics::SOURCE_EVENTS, &labels, 1);
+                    }
+                    self.correlation = None;
+                    self.source_name = None;
+                    if let Err(Rejected::InvariantViolated(violation)) = result {
+                        return Err(EngineError::InvariantViolated(violation));
+                    }
+                }
+                Err(SourceError::Row { line, row, message }) => {
+                    self.parse_error(line, row, message)
+                }
+                Err(SourceError::Io(e)) => return Err(EngineError::Io(e)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// a parsed row of any source, an unknown type is a parse error of the source
+    pub fn ingest_record(&mut self, record: CsvRecord<String>) -> Result<(), String> {
+        let action_type = match self.action_type(&record.r#type) {
+            Some(action_type) => action_type,
+            None => {
+                debug!("unknown action type: {}", record.r#type);
+                return Err(format!("unknown action type: {}", record.r#type));
+            }
+        };
+
+        self.correlation = record.correlation;
+        let record = CsvRecord {
+            r#type: action_type,
+            client: record.client,
+            tx: record.tx,
+            amount: record.amount,
+            correlation: None,
+        };
+        let event = AccountEvent::from_record(record, self.config.rounding);
+        if let Ok(event) = event {
+            let _ = self.ingest(event);
+        }
+        self.correlation = None;
+        event.map(|_| ())
+    }
+
+    pub fn parse_error(&mut self, line: u64, row: String, error: String) {
+        self.summary.parse_errors += 1;
+        if let Some(quarantine) = self.quarantine_mut() {
+            quarantine.parse_error(line, row, error);
+        }
+    }
+
+    fn reject(&mut self, event: &AccountEvent, reason: RejectReason) {
+        match &self.correlation {
+            Some(correlation) => info!(
+                "rejected ({}) [{}]: {}",
+                reason,
+                correlation,
+                self.logged(event)
+            ),
+            None => info!("rejected ({}): {}", reason, self.logged(event)),
+        }
+        self.summary.rejected += 1;
+        for hook in self.rejected_hooks() {
+            hook(event, reason.clone());
+        }
+        if let Some(metrics) = self.metrics() {
+            let reason = reason_label(&reason);
+            metrics.counter(crate::metrics::EVENTS_REJECTED, &[("reason", &reason)], 1);
+        }
+        if let Some(quarantine) = self.quarantine_mut() {
+            quarantine.reject(event, reason);
+        }
+    }
+
+    /// the whole pipeline without any csv involved, events are processed in the given order
+    pub fn process_events<I: IntoIterator<Item = AccountEvent>>(&mut self, events: I) {
+        for event in events {
+            let _ = self.ingest(event);
+        }
+    }
+
+    /// the events in the given order with what the batch added to the summary. the order is
+    /// not changed, a tx id that is reused by another client decides differently otherwise
+    pub fn apply_batch(&mut self, events: &[AccountEvent]) -> BatchOutcome {
+        let before = self.summary;
+        for event in events {
+            let _ = self.ingest(*event);
+        }
+
+        BatchOutcome::between(&before, &self.summary)
+    }
+
+    /// the violation that stopped the processing with InvariantMode::Abort
+    pub fn halted(&self) -> Option<&InvariantViolation> {
+        self.halted.as_deref()
+    }
+
+    /// the whole pipeline for one event of any source, the csv reader is one of them
+    pub fn ingest(&mut self, event: AccountEvent) -> Result<Applied, Rejected> {
+        // the ledger is inconsistent, nothing should be built on it
+        if let Some(violation) = &self.halted {
+            return Err(Rejected::InvariantViolated((**violation).clone()));
+        }
+
+        // not counted, the event belongs to the sub-engine of another partition
+        if !self.is_in_partition(event.client_id) {
+            return Err(Rejected::Skipped);
+        }
+
+        if self.exclusions.excludes(&event) {
+            debug!("excluded: {}", self.logged(&event));
+            self.summary.excluded += 1;
+            return Err(Rejected::Skipped);
+        }
+
+        if self.holdback.holds_back(&event) {
+            debug!("ignored: {}", self.logged(&event));
+            self.summary.ignored += 1;
+            return Err(Rejected::Skipped);
+        }
+
+        if !self.is_sampled(event.client_id) {
+            self.summary.skipped += 1;
+            return Err(Rejected::Skipped);
+        }
+
+        self.summary.processed += 1;
+        if let Some(compaction) = self
+            .subsystems
+            .as_deref_mut()
+            .and_then(|subsystems| subsystems.compaction.as_mut())
+        {
+            if let Some(account) = compaction.restore(event.client_id) {
+                debug!("client {} restored from the archive", event.client_id);
+                self.accounts.insert(account);
+            }
+            compaction.observe(event.client_id, self.summary.processed);
+            if compaction.is_due(self.summary.processed) {
+                self.compact();
+            }
+        }
+        if self.dispute_action_with_invalid_transaction(&event) {
+            debug!(
+                "no transaction exists in lookup for: {}",
+                self.logged(&event)
+            );
+            self.reject(&event, RejectReason::UnknownTransaction);
+            return Err(Rejected::Invalid(RejectReason::UnknownTransaction));
+        }
+
+        let before = self.balances_of(event.client_id);
+        let timed = self.latency().is_some() || self.metrics().is_some();
+        let started = timed.then(|| self.clock.now());
+        let mut violated = None;
+        let result = match self.apply_event_guarded(&event) {
+            Ok(()) => {
+                if let Some(before) = before {
+                    info!("{}", self.balance_change(&event, &before));
+                }
+                if let Some(risk) = self
+                    .subsystems
+                    .as_deref_mut()
+                    .and_then(|subsystems| subsystems.risk.as_mut())
+                {
+                    risk.observe(&event, self.summary.processed);
+                }
+                self.statistics
+                    .entry(event.client_id)
+                    .or_default()
+                    .observe(&event);
+                if let Some(metrics) = self.metrics() {
+                    let action_type = event.action_type.to_string();
+                    metrics.counter(crate::metrics::EVENTS_APPLIED, &[("type", &action_type)], 1);
+                    // every sub-engine only knows the accounts of its partition
+                    let partition = self
+                        .config
+                        .partition
+                        .map(|partition| partition.index.to_string());
+                    let labels = match &partition {
+                        Some(partition) => vec![("partition", partition.as_str())],
+                        None => vec![],
+                    };
+                    metrics.gauge(
+                        crate::metrics::ACCOUNTS,
+                        &labels,
+                        self.accounts.len() as i64,
+                    );
+                    let open_disputes = self.open_disputes.len() as i64;
+                    metrics.gauge(crate::metrics::OPEN_DISPUTES, &labels, open_disputes);
+                }
+                violated = self.check_invariants(&event);
+                // the events of a fan-in are attributed to their source
+                if let (Some(source), Some(audit)) = (
+                    &self.source_name,
+                    self.subsystems
+                        .as_deref_mut()
+                        .and_then(|subsystems| subsystems.audit.as_mut()),
+                ) {
+                    let action_type = event.action_type.to_string();
+                    audit.record(&[
+                        ("event", AuditValue::Str("applied")),
+                        ("source", AuditValue::Str(source)),
+                        ("type", AuditValue::Str(&action_type)),
+                        ("client", AuditValue::Int(event.client_id as i128)),
+                        ("tx", AuditValue::Int(event.transaction_id as i128)),
+                    ]);
+                }
+                let account = self
+                    .accounts
+                    .get(event.client_id)
+                    .copied()
+                    .unwrap_or_else(|| ClientAccount::new(event.client_id, SignedMoney::ZERO));
+                for hook in self.applied_hooks() {
+                    hook(&event, &account);
+                }
+                Ok(Applied { account })
+            }
+            Err(reason) => {
+                self.reject(&event, reason.clone());
+                Err(Rejected::Invalid(reason))
+            }
+        };
+        let elapsed = started.map(|started| self.clock.now().saturating_duration_since(started));
+        if let (Some(elapsed), Some(latency)) = (
+            elapsed,
+            self.subsystems
+                .as_deref_mut()
+                .and_then(|subsystems| subsystems.latency.as_mut()),
+        ) {
+            latency.record(&event, elapsed);
+        }
+        if let (Some(elapsed), Some(metrics)) = (elapsed, self.metrics()) {
+            metrics.histogram(crate::metrics::APPLY_SECONDS, &[], elapsed.as_secs_f64());
+        }
+
+        // we can only dispute what we have so only applied transactions can be disputed.
+        // a rejected duplicate keeps the transaction it would have replaced
+        if result.is_ok()
+            && matches!(
+                event.action_type,
+                AccountActions::Deposit | AccountActions::Withdrawal
+            )
+        {
+            debug!("transaction added: {}", &event.transaction_id);
+            let transaction = Transaction {
+                client_id: event.client_id,
+                action_type: event.action_type,
+                amount: event.amount.unwrap_or_default(),
+            };
+            let replaced = self.transactions.insert(event.transaction_id, transaction);
+            // a reused tx id of another client would otherwise still be found in its cache
+            if let Some(replaced) =
+                replaced.filter(|replaced| replaced.client_id != event.client_id)
+            {
+                if let Some(recent) = self.recent_transactions.get_mut(&replaced.client_id) {
+                    recent.remove(event.transaction_id);
+                }
+            }
+            self.recent_transactions
+                .entry(event.client_id)
+                .or_default()
+                .push(event.transaction_id, transaction);
+            if let Some(graph) = self
+                .subsystems
+                .as_deref_mut()
+                .and_then(|subsystems| subsystems.dispute_graph.as_mut())
+            {
+                graph.register_transaction(&event);
+            }
+        }
+
+        // the event stays applied, the caller decides what to do with the processing
+        if let Some(violation) = violated {
+            error!("{}", violation);
+            self.halted = Some(Box::new(violation.clone()));
+            return Err(Rejected::InvariantViolated(violation));
+        }
+        result
+    }
+
+    /// only taken with --log-balances, an account that does not exist yet is logged as empty
+    fn balances_of(&self, client_id: u16) -> Option<ClientAccount> {
+        self.config.log_balances.then(|| {
+            self.accounts
+                .get(client_id)
+                .copied()
+                .unwrap_or_else(|| ClientAccount::new(client_id, SignedMoney::ZERO))
+        })
+    }
+
+    /// the event for a log line, masked with --redact-amounts
+    fn logged<'e>(&self, event: &'e AccountEvent) -> LoggedEvent<'e> {
+        event.logged(self.config.redact_amounts)
+    }
+
+    /// key=value pairs so the lines can be picked up by log aggregation
+    fn balance_change(&self, event: &AccountEvent, before: &ClientAccount) -> String {
+        let after = self
+            .accounts
+            .get(event.client_id)
+            .copied()
+            .unwrap_or_else(|| ClientAccount::new(event.client_id, SignedMoney::ZERO));
+        let rounding = self.config.rounding;
+        let redact = self.config.redact_amounts;
+        format!(
+            "balance_change client={} tx={} type={} amount={} available_before={} available_after={} held_before={} held_after={} locked_before={} locked_after={}",
+            event.client_id,
+            event.transaction_id,
+            event.action_type,
+            Logged(
+                event
+                    .amount
+                    .map(|amount| amount.format(rounding))
+                    .unwrap_or_default(),
+                redact
+            ),
+            Logged(before.available.format(rounding), redact),
+            Logged(after.available.format(rounding), redact),
+            Logged(before.held.format(rounding), redact),
+            Logged(after.held.format(rounding), redact),
+            before.locked,
+            after.locked
+        )
+    }
+
+    /// only a violation of InvariantMode::Abort is returned, a reported one is counted
+    fn check_invariants(&mut self, event: &AccountEvent) -> Option<InvariantViolation> {
+        let mode = self.config.invariants?;
+        let violation = crate::invariants::check(self, event).err()?;
+        match mode {
+            InvariantMode::Abort => Some(violation),
+            InvariantMode::Report => {
+                error!("{}", violation);
+                self.summary.invariant_violations += 1;
+                None
+            }
+        }
+    }
+
+    /// a single pathological event must not end a long run, outside of --strict a panic is
+    /// reported like any other reject. the account is only stored after the action returned so
+    /// a panicking action leaves it as it was
+    fn apply_event_guarded(&mut self, event: &AccountEvent) -> Result<(), RejectReason> {
+        if self.config.strict {
+            return self.apply_event(event);
+        }
+
+        panic::catch_unwind(AssertUnwindSafe(|| self.apply_event(event))).unwrap_or_else(
+            |payload| {
+                let message = panic_message(payload.as_ref());
+                error!("panic while applying {}: {}", self.logged(event), message);
+                Err(RejectReason::Panic(message))
+            },
+        )
+    }
+
+    /// the reason is returned if the event was not applied to the client account
+    pub fn process_event(&mut self, event: &AccountEvent) -> Result<(), EngineError> {
+        Ok(self.apply_event(event)?)
+    }
+
+    /// the account is unchanged after a rejected action so its state tells why
+    fn rejection(account: &ClientAccount, action_type: AccountActions) -> RejectReason {
+        if account.locked {
+            return RejectReason::AccountLocked;
+        }
+
+        match action_type {
+            AccountActions::Deposit => RejectReason::Overflow,
+            AccountActions::Withdrawal => RejectReason::InsufficientFunds,
+            AccountActions::Dispute => RejectReason::DisputeExceedsAvailable,
+            AccountActions::Resolve | AccountActions::ChargeBack => RejectReason::NoOpenDispute,
+            AccountActions::Custom(_) => RejectReason::ActionRejected,
+        }
+    }
+
+    fn apply_event(&mut self, event: &AccountEvent) -> Result<(), RejectReason> {
+        if let Some(violation) = self
+            .compliance
+            .check(event, self.client_metadata.get(&event.client_id))
+        {
+            warn!(
+                "compliance rule {} ({}) violated: {}",
+                violation.rule,
+                violation.country,
+                self.logged(event)
+            );
+            let reason = RejectReason::Compliance(violation.rule.clone());
+            self.violations.push(violation);
+            return Err(reason);
+        }
+
+        if let Some(detector) = self
+            .subsystems
+            .as_deref_mut()
+            .and_then(|subsystems| subsystems.structuring.as_mut())
+        {
+            detector.observe(event);
+        }
+
+        if self.exceeds_high_risk_limit(event) {
+            info!("high risk limit exceeded: {}", self.logged(event));
+            return Err(RejectReason::HighRiskLimit);
+        }
+
+        let known = self.accounts.contains(event.client_id);
+        if !known && !self.config.account_creation.creates(event.action_type) {
+            info!("no account for the client of: {}", self.logged(event));
+            return Err(RejectReason::UnknownClient);
+        }
+
+        let decision = self.decide(event)?;
+        let applied = decision.outcome.is_applied();
+        // a rejected event leaves the account as it was and a new client without one
+        if applied {
+            if !known {
+                debug!("client created with id: {}", &event.client_id);
+            }
+            self.accounts.insert(decision.account);
+        }
+
+        if AccountProcessing::event_needs_transaction_lookup(event.action_type) {
+            if let (Outcome::Disputed(DisputeOutcome::Exceeded(policy, held)), Some(audit)) = (
+                decision.outcome,
+                self.subsystems
+                    .as_deref_mut()
+                    .and_then(|subsystems| subsystems.audit.as_mut()),
+            ) {
+                let rounding = self.config.rounding;
+                let policy = policy.to_string();
+                let requested = decision.amount.format(rounding);
+                let held = held.format(rounding);
+                let mut fields = vec![
+                    ("event", AuditValue::Str("dispute_exceeds_available")),
+                    ("client", AuditValue::Int(event.client_id as i128)),
+                    ("tx", AuditValue::Int(event.transaction_id as i128)),
+                    ("policy", AuditValue::Str(&policy)),
+                    ("requested", AuditValue::Amount(&requested)),
+                    ("held", AuditValue::Amount(&held)),
+                ];
+                if let Some(correlation) = &self.correlation {
+                    fields.push((CORRELATION, AuditValue::Str(correlation)));
+                }
+                if let Some(source) = &self.source_name {
+                    fields.push(("source", AuditValue::Str(source)));
+                }
+                audit.record(&fields);
+            }
+
+            match decision.outcome {
+                Outcome::Disputed(disputed) if applied => {
+                    self.open_disputes
+                        .insert(event.transaction_id, disputed.held());
+                }
+                _ if applied => {
+                    self.open_disputes.remove(&event.transaction_id);
+                }
+                _ => {}
+            }
+            if let Some(graph) = self
+                .subsystems
+                .as_deref_mut()
+                .and_then(|subsystems| subsystems.dispute_graph.as_mut())
+            {
+                graph.record_dispute(event, applied);
+            }
+        }
+
+        match applied {
+            true => Ok(()),
+            false => Err(Self::rejection(&decision.account, event.action_type)),
+        }
+    }
+
+    /// the action applied to a copy of the account, nothing of the processing is changed
+    fn decide(&self, event: &AccountEvent) -> Result<Decision, RejectReason> {
+        // a partition only knows the tx ids of its own clients
+        if self.config.reject_duplicate_tx
+            && matches!(
+                event.action_type,
+                AccountActions::Deposit | AccountActions::Withdrawal
+            )
+            && self.transactions.contains(event.transaction_id)
+        {
+            info!("duplicate transaction: {}", self.logged(event));
+            return Err(RejectReason::DuplicateTransaction);
+        }
+
+        let mut account = self
+            .accounts
+            .get(event.client_id)
+            .copied()
+            .unwrap_or_else(|| ClientAccount::new(event.client_id, SignedMoney::ZERO));
+
+        // we create a new event for our dispute cases because they don't have an active amount
+        let amount = if AccountProcessing::event_needs_transaction_lookup(event.action_type) {
+            let transaction = match Self::lookup_transaction(
+                &self.recent_transactions,
+                &self.transactions,
+                event,
+            ) {
+                Some(transaction) => transaction,
+                // should actually be checked before but for sanity reasons
+                None => {
+                    debug!("non existing transaction for: {}", self.logged(event));
+                    return Err(RejectReason::UnknownTransaction);
+                }
+            };
+
+            if event.action_type == AccountActions::Dispute
+                && self.open_disputes.contains_key(&event.transaction_id)
+            {
+                info!("already disputed: {}", self.logged(event));
+                return Err(RejectReason::AlreadyDisputed);
+            }
+            if event.action_type == AccountActions::Dispute
+                && !self.config.disputable.allows(transaction.action_type)
+            {
+                info!(
+                    "{} transactions cannot be disputed: {}",
+                    transaction.action_type,
+                    self.logged(event)
+                );
+                return Err(RejectReason::NotDisputable);
+            }
+
+            // a partial hold only releases what was actually held, without a dispute of this tx
+            // the held funds belong to another one
+            let amount = match event.action_type {
+                AccountActions::Resolve | AccountActions::ChargeBack => {
+                    match self.open_disputes.get(&event.transaction_id) {
+                        Some(held) => *held,
+                        None => {
+                            info!("no open dispute for: {}", self.logged(event));
+                            return Err(RejectReason::NoOpenDispute);
+                        }
+                    }
+                }
+                _ => transaction.amount,
+            };
+            debug!(
+                "{} applied with the transaction amount {}",
+                self.logged(event),
+                Logged(amount, self.config.redact_amounts)
+            );
+            amount
+        } else {
+            debug!("normal event consumed: {}", self.logged(event));
+            event.amount.unwrap_or_default()
+        };
+
+        let ctx = TxContext {
+            transaction_id: event.transaction_id,
+            amount,
+            dispute_policy: self.config.dispute_policy,
+            locked_deposits: self.config.locked_deposits,
+        };
+        let outcome =
+            Self::action(&self.custom_actions, event.action_type).apply(&mut account, &ctx);
+
+        Ok(Decision {
+            account,
+            outcome,
+            amount,
+        })
+    }
+
+    /// what ingesting the event would do to the current state without changing or recording
+    /// anything, e.g. to check a withdrawal before it is authorized. the sample, partition and
+    /// exclusions are left out, they decide whether an event is looked at and not its outcome
+    pub fn preview(&self, event: &AccountEvent) -> Preview {
+        let account = self
+            .accounts
+            .get(event.client_id)
+            .copied()
+            .unwrap_or_else(|| ClientAccount::new(event.client_id, SignedMoney::ZERO));
+        let rejected = |reason| Preview {
+            outcome: Err(reason),
+            account,
+        };
+
+        if let Some(violation) = self
+            .compliance
+            .check(event, self.client_metadata.get(&event.client_id))
+        {
+            return rejected(RejectReason::Compliance(violation.rule));
+        }
+        if self.exceeds_high_risk_limit(event) {
+            return rejected(RejectReason::HighRiskLimit);
+        }
+        let archived = self
+            .compaction()
+            .is_some_and(|compaction| compaction.is_archived(event.client_id));
+        if !self.accounts.contains(event.client_id)
+            && !archived
+            && !self.config.account_creation.creates(event.action_type)
+        {
+            return rejected(RejectReason::UnknownClient);
+        }
+
+        match self.decide(event) {
+            Ok(decision) if decision.outcome.is_applied() => Preview {
+                outcome: Ok(()),
+                account: decision.account,
+            },
+            Ok(decision) => rejected(Self::rejection(&decision.account, event.action_type)),
+            Err(reason) => rejected(reason),
+        }
+    }
+
+    /// not a method on self since the account that is passed to the action is borrowed from it
+    pub fn action(
+        custom_actions: &[(String, Box<dyn AccountAction>)],
+        action_type: AccountActions,
+    ) -> &dyn AccountAction {
+        match action_type {
+            AccountActions::Withdrawal => &crate::actions::Withdrawal,
+            AccountActions::Deposit => &crate::actions::Deposit,
+            AccountActions::Dispute => &crate::actions::Dispute,
+            AccountActions::ChargeBack => &crate::actions::ChargeBack,
+            AccountActions::Resolve => &crate::actions::Resolve,
+            AccountActions::Custom(id) => custom_actions[id as usize].1.as_ref(),
+        }
+    }
+
+    /// the state another run needs to continue where this one stopped
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            accounts: self
+                .accounts
+                .iter()
+                .map(|account| (account.id, *account))
+                .collect(),
+            transactions: self
+                .transactions
+                .iter()
+                .map(|(transaction_id, transaction)| (transaction_id, *transaction))
+                .collect(),
+            open_disputes: self.open_disputes.clone(),
+            statistics: self.statistics.clone(),
+            notes: self.notes.clone(),
+        }
+    }
+
+    /// the live account, a compacted one is only in the archive of the compaction
+    pub fn account(&self, client_id: u16) -> Option<&ClientAccount> {
+        self.accounts.get(client_id)
+    }
+
+    pub fn account_count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    /// the deposits and withdrawals that can be disputed, the dispute family is not kept
+    pub fn transaction(&self, transaction_id: i32) -> Option<&Transaction> {
+        self.transactions.get(transaction_id)
+    }
+
+    /// what is held for the tx while it is disputed
+    pub fn open_dispute(&self, transaction_id: i32) -> Option<Money> {
+        self.open_disputes.get(&transaction_id).copied()
+    }
+
+    /// the annotations of a client in the order they were added, closing the account keeps them
+    pub fn notes(&self, client_id: u16) -> &[Note] {
+        self.notes.get(&client_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// an account that is opened outside of the events, e.g. a migrated balance.
+    /// whatever it holds has to be covered by open disputes of its own transactions
+    pub fn insert_account(&mut self, account: ClientAccount) -> Result<(), String> {
+        let disputed = self
+            .transactions_for(account.id)
+            .filter_map(|(transaction_id, _)| self.open_disputes.get(&transaction_id))
+            .try_fold(Money::ZERO, |sum, held| sum.checked_add(*held))
+            .ok_or_else(|| format!("the open disputes of client {} overflow", account.id))?;
+        if account.held != disputed {
+            return Err(format!(
+                "client {} holds {} but has {} in open disputes",
+                account.id, account.held, disputed
+            ));
+        }
+
+        self.accounts.insert(account);
+        Ok(())
+    }
+
+    /// the accounts ordered by client id
+    pub fn accounts_iter(&self) -> impl Iterator<Item = &ClientAccount> + '_ {
+        self.accounts.iter()
+    }
+
+    /// tx -> amount that is currently held for it
+    pub fn open_disputes_iter(&self) -> impl Iterator<Item = (i32, Money)> + '_ {
+        self.open_disputes
+            .iter()
+            .map(|(transaction_id, held)| (*transaction_id, *held))
+    }
+
+    /// the deposits and withdrawals of a client that can still be disputed, ordered by tx.
+    /// this is a scan over all transactions, they are not indexed by client
+    pub fn transactions_for(
+        &self,
+        client_id: u16,
+    ) -> impl Iterator<Item = (i32, &Transaction)> + '_ {
+        self.transactions
+            .iter()
+            .filter(move |(_, transaction)| transaction.client_id == client_id)
+    }
+
+    /// a deposit outside of the input, it is not a transaction so it cannot be disputed
+    pub fn open_balance(&mut self, balance: &OpeningBalance) -> Result<(), String> {
+        if !self.is_in_partition(balance.client_id) {
+            return Ok(());
+        }
+
+        // opened on a copy, a rejected balance leaves no account behind
+        let mut account = self
+            .accounts
+            .get(balance.client_id)
+            .copied()
+            .unwrap_or_else(|| ClientAccount::new(balance.client_id, SignedMoney::ZERO));
+        if !account.deposit(balance.available) {
+            return Err(format!(
+                "cannot open client {} with {}",
+                balance.client_id, balance.available
+            ));
+        }
+        account.locked |= balance.locked;
+        self.accounts.insert(account);
+
+        if let Some(audit) = self
+            .subsystems
+            .as_deref_mut()
+            .and_then(|subsystems| subsystems.audit.as_mut())
+        {
+            audit.record(&[
+                ("event", AuditValue::Str("opening_balance")),
+                ("client", AuditValue::Int(balance.client_id as i128)),
+                (
+                    "amount",
+                    AuditValue::Amount(&balance.available.format(self.config.rounding)),
+                ),
+                ("locked", AuditValue::Bool(balance.locked)),
+            ]);
+        }
+        Ok(())
+    }
+
+    /// the dispute is held again and its tx is known so it can be resolved or charged back
+    pub fn carry_dispute(&mut self, dispute: &OpenDispute) -> Result<(), String> {
+        if !self.is_in_partition(dispute.client_id) {
+            return Ok(());
+        }
+        if self.transactions.contains(dispute.transaction_id) {
+            return Err(format!(
+                "the open dispute of tx {} is already known",
+                dispute.transaction_id
+            ));
+        }
+
+        let account = self.accounts.get_mut(dispute.client_id).ok_or_else(|| {
+            format!(
+                "the open dispute of tx {} has no balance of client {}",
+                dispute.transaction_id, dispute.client_id
+            )
+        })?;
+        let mut carried = *account;
+        carried.held = carried.held.checked_add(dispute.held).unwrap_or(Money::MAX);
+        if carried.total().is_none() {
+            return Err(format!(
+                "client {} cannot hold {}",
+                dispute.client_id, dispute.held
+            ));
+        }
+        *account = carried;
+        self.transactions.insert(
+            dispute.transaction_id,
+            Transaction {
+                client_id: dispute.client_id,
+                action_type: dispute.action_type,
+                amount: dispute.amount,
+            },
+        );
+        self.open_disputes
+            .insert(dispute.transaction_id, dispute.held);
+
+        if let Some(audit) = self
+            .subsystems
+            .as_deref_mut()
+            .and_then(|subsystems| subsystems.audit.as_mut())
+        {
+            audit.record(&[
+                ("event", AuditValue::Str("opening_dispute")),
+                ("client", AuditValue::Int(dispute.client_id as i128)),
+                ("tx", AuditValue::Int(dispute.transaction_id as i128)),
+                (
+                    "held",
+                    AuditValue::Amount(&dispute.held.format(self.config.rounding)),
+                ),
+            ]);
+        }
+        Ok(())
+    }
+
+    /// removes the dormant accounts, the number of removed accounts is returned. the
+    /// transactions stay, a dispute of one of them cannot hold anything without a balance
+    pub fn compact(&mut self) -> usize {
+        let position = self.summary.processed;
+        let Some(compaction) = self
+            .subsystems
+            .as_deref_mut()
+            .and_then(|subsystems| subsystems.compaction.as_mut())
+        else {
+            return 0;
+        };
+
+        let disputed: BTreeSet<u16> = self
+            .open_disputes
+            .keys()
+            .filter_map(|tx| self.transactions.get(*tx))
+            .map(|transaction| transaction.client_id)
+            .collect();
+        let dormant: Vec<u16> = self
+            .accounts
+            .iter()
+            .filter(|account| {
+                account.available.is_zero()
+                    && account.held.is_zero()
+                    && !account.locked
+                    && !disputed.contains(&account.id)
+                    && compaction.is_dormant(account.id, position)
+            })
+            .map(|account| account.id)
+            .collect();
+        for client_id in &dormant {
+            if let Some(account) = self.accounts.remove(*client_id) {
+                self.recent_transactions.remove(client_id);
+                compaction.remove(account);
+            }
+        }
+
+        if !dormant.is_empty() {
+            debug!("{} dormant accounts compacted", dormant.len());
+        }
+        dormant.len()
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.accounts.clear();
+        for account in snapshot.accounts.into_values() {
+            self.accounts.insert(account);
+        }
+        self.transactions.clear();
+        for (transaction_id, transaction) in snapshot.transactions {
+            self.transactions.insert(transaction_id, transaction);
+        }
+        self.open_disputes = snapshot.open_disputes;
+        self.statistics = snapshot.statistics;
+        self.notes = snapshot.notes;
+        self.recent_transactions.clear();
+    }
+
+    pub fn display(&mut self) -> Result<(), EngineError> {
+        let written = match self.output.take() {
+            Some(mut output) => {
+                let written = self.write_rows(output.as_mut(), self.accounts.iter());
+                self.output = Some(output);
+                written
+            }
+            None => self.write_rows(&mut CsvSink::stdout(), self.accounts.iter()),
+        };
+        written.map_err(EngineError::Output)
+    }
+
+    /// the output format as csv, used for stdout as well as for the partition files
+    pub fn write_accounts<'a, W: Write>(
+        &self,
+        writer: &mut W,
+        accounts: impl Iterator<Item = &'a ClientAccount>,
+    ) -> std::io::Result<()> {
+        self.write_rows(&mut CsvSink::new(writer), accounts)
+    }
+
+    /// the columns and the fields of the output format, for any sink
+    pub fn write_rows<'a>(
+        &self,
+        sink: &mut dyn OutputSink,
+        accounts: impl Iterator<Item = &'a ClientAccount>,
+    ) -> std::io::Result<()> {
+        // the metadata columns are only there if we got a clients file
+        let mut header = vec!["client", "available", "held", "total", "locked"];
+        if !self.client_metadata.is_empty() {
+            header.extend(["name", "country", "risk_score"]);
+        }
+        if self.risk().is_some() {
+            header.push("activity_score");
+        }
+        if self.config.account_versions {
+            header.extend(["version", "last_tx"]);
+        }
+        sink.header(&header)?;
+
+        for client_account in accounts {
+            let mut fields =
+                client_account.to_fields(self.config.rounding, self.config.number_format);
+            if !self.client_metadata.is_empty() {
+                match self.client_metadata.get(&client_account.id) {
+                    Some(metadata) => fields.extend([
+                        metadata.name.clone(),
+                        metadata.country.clone(),
+                        metadata.risk_score.to_string(),
+                    ]),
+                    None => fields.extend([String::new(), String::new(), String::new()]),
+                }
+            }
+            if let Some(risk) = self.risk() {
+                fields.push(risk.score(client_account.id).to_string());
+            }
+            if self.config.account_versions {
+                let statistics = self.statistics.get(&client_account.id);
+                fields.extend([
+                    statistics
+                        .map_or(0, |statistics| statistics.version)
+                        .to_string(),
+                    statistics
+                        .and_then(|statistics| statistics.last_tx)
+                        .map(|tx| tx.to_string())
+                        .unwrap_or_default(),
+                ]);
+            }
+            sink.row(client_account, &fields)?;
+        }
+
+        sink.flush()
+    }
+
+    pub fn is_sampled(&self, client_id: u16) -> bool {
+        self.config
+            .sample
+            .map(|sample| sample.contains(client_id))
+            .unwrap_or(true)
+    }
+
+    pub fn is_in_partition(&self, client_id: u16) -> bool {
+        self.config
+            .partition
+            .map(|partition| partition.contains(client_id))
+            .unwrap_or(true)
+    }
+
+    pub fn is_high_risk(&self, client_id: u16) -> bool {
+        match (
+            self.config.high_risk_score,
+            self.client_metadata.get(&client_id),
+        ) {
+            (Some(threshold), Some(metadata)) => metadata.risk_score >= threshold,
+            _ => false,
+        }
+    }
+
+    /// stricter withdrawal limit for clients the metadata marks as high risk
+    pub fn exceeds_high_risk_limit(&self, event: &AccountEvent) -> bool {
+        let limit = match self.config.high_risk_withdrawal_limit {
+            Some(limit) => limit,
+            None => return false,
+        };
+
+        event.action_type == AccountActions::Withdrawal
+            && event.amount.unwrap_or_default() > limit
+            && self.is_high_risk(event.client_id)
+    }
+
+    /// primarily a semantic extraction. do we really need to inline it? probably not.
+    /// but well this as good as any reason <https://www.youtube.com/watch?v=QayoudZnjF8> ;)
+    #[inline]
+    pub fn dispute_action_with_invalid_transaction(
+        &mut self,
+        account_event: &AccountEvent,
+    ) -> bool {
+        AccountProcessing::event_needs_transaction_lookup(account_event.action_type)
+            && Self::lookup_transaction(
+                &self.recent_transactions,
+                &self.transactions,
+                account_event,
+            )
+            .is_none()
+    }
+
+    /// the cache of the client first, then the map. a tx of another client is not found, a
+    /// client can only dispute its own transactions
+    fn lookup_transaction(
+        recent_transactions: &BTreeMap<u16, RecentTransactions>,
+        transactions: &T,
+        event: &AccountEvent,
+    ) -> Option<Transaction> {
+        recent_transactions
+            .get(&event.client_id)
+            .and_then(|recent| recent.get(event.transaction_id))
+            .or_else(|| transactions.get(event.transaction_id).copied())
+            .filter(|transaction| transaction.client_id == event.client_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::actions::{AccountAction, Outcome, TxContext};
+    use crate::audit::AuditLog;
+    use crate::clock::{Clock, ManualClock};
+    use crate::compaction::Compaction;
+    use crate::invariants::InvariantMode;
+    use crate::latency::LatencyHistogram;
+    use crate::metadata::ClientMetadata;
+    use crate::metrics::PrometheusMetrics;
+    use crate::money::{Money, SignedMoney};
+    use crate::opening::OpeningBalance;
+    use crate::partition::Partition;
+    use crate::quarantine::{Quarantine, RejectReason};
+    use crate::sink::{CsvSink, MemorySink, OutputSink};
+    use crate::snapshot::Snapshot;
+    use crate::source::{EventSource, FanIn, SourceError};
+    use crate::{
+        AccountActions, AccountCreation, AccountEvent, AccountProcessing, ClientAccount, Config,
+        DisputableActions, DisputePolicy, EngineError, Rejected, Subsystems, Transaction,
+    };
+    use crate::{BatchOutcome, SchemaMode};
+    use std::cell::RefCell;
+    use std::mem;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn money(minor_units: u64) -> Money {
+        Money::from_minor_units(minor_units)
+    }
+
+    fn dispute_of(app: &mut AccountProcessing, available: i64, amount: u64) -> bool {
+        let available = SignedMoney::from_minor_units(available);
+        app.accounts.insert(1, ClientAccount::new(1, available));
+        app.transactions.insert(
+            1,
+            Transaction {
+                client_id: 1,
+                action_type: AccountActions::Deposit,
+                amount: money(amount),
+            },
+        );
+        app.process_event(&AccountEvent {
+            transaction_id: 1,
+            action_type: AccountActions::Dispute,
+            client_id: 1,
+            amount: None,
+        })
+        .is_ok()
+    }
+
+    fn resolve_of(app: &mut AccountProcessing, action_type: AccountActions) -> bool {
+        app.process_event(&AccountEvent {
+            transaction_id: 1,
+            action_type,
+            client_id: 1,
+            amount: None,
+        })
+        .is_ok()
+    }
+
+    fn event(action_type: AccountActions, tx: i32, amount: Option<u64>) -> AccountEvent {
+        AccountEvent {
+            transaction_id: tx,
+            client_id: 1,
+            action_type,
+            amount: amount.map(money),
+        }
+    }
+
+    // charged even if it takes available below zero
+    struct Fee;
+
+    impl AccountAction for Fee {
+        fn apply(&self, account: &mut ClientAccount, ctx: &TxContext) -> Outcome {
+            match account.available.checked_sub(ctx.amount) {
+                Some(available) => {
+                    account.available = available;
+                    Outcome::Applied
+                }
+                None => Outcome::Rejected,
+            }
+        }
+    }
+
+    // takes as long as the test clock is told
+    struct Slow(Arc<ManualClock>, Duration);
+
+    impl AccountAction for Slow {
+        fn apply(&self, _: &mut ClientAccount, _: &TxContext) -> Outcome {
+            self.0.advance(self.1);
+            Outcome::Applied
+        }
+    }
+
+    struct Broken;
+
+    impl AccountAction for Broken {
+        fn apply(&self, _: &mut ClientAccount, _: &TxContext) -> Outcome {
+            panic!("broken action")
+        }
+    }
+
+    // holds funds without the open dispute that would explain them, breaks the invariant
+    struct Hold;
+
+    impl AccountAction for Hold {
+        fn apply(&self, account: &mut ClientAccount, ctx: &TxContext) -> Outcome {
+            match account.available.checked_sub(ctx.amount) {
+                Some(available) => {
+                    account.available = available;
+                    account.held = account.held.checked_add(ctx.amount).unwrap();
+                    Outcome::Applied
+                }
+                None => Outcome::Rejected,
+            }
+        }
+    }
+
+    const UNEXPLAINED_HOLD: &str =
+        "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,1,2,2.0\ndispute,1,1,\nhold,1,3,1.0\n";
+
+    #[test]
+    fn memory_layout_processing() {
+        // a budget instead of the exact size, 616 bytes at the moment. what is not needed for
+        // every event belongs into the subsystems, they are one pointer here
+        assert!(mem::size_of::<AccountProcessing>() <= 640);
+        assert_eq!(
+            mem::size_of::<Option<Box<Subsystems>>>(),
+            mem::size_of::<usize>()
+        );
+    }
+
+    #[test]
+    fn high_risk_clients_have_a_withdrawal_limit() {
+        let mut app = AccountProcessing::new(Config {
+            high_risk_score: Some(80),
+            high_risk_withdrawal_limit: Some(money(100)),
+            ..Default::default()
+        });
+        for (client_id, risk_score) in [(1, 90), (2, 10)] {
+            app.client_metadata.insert(
+                client_id,
+                ClientMetadata {
+                    client_id,
+                    name: "name".to_string(),
+                    country: "AT".to_string(),
+                    risk_score,
+                },
+            );
+            app.accounts.insert(
+                client_id,
+                ClientAccount::new(client_id, SignedMoney::from_minor_units(1000)),
+            );
+        }
+
+        for client_id in [1, 2] {
+            let result = app.process_event(&AccountEvent {
+                transaction_id: client_id as i32,
+                action_type: AccountActions::Withdrawal,
+                client_id,
+                amount: Some(money(500)),
+            });
+            assert_eq!(result.is_ok(), client_id == 2);
+        }
+
+        assert_eq!(
+            app.accounts[&1].available.minor_units(),
+            1000,
+            "high risk is limited"
+        );
+        assert_eq!(
+            app.accounts[&2].available.minor_units(),
+            500,
+            "low risk is not limited"
+        );
+    }
+
+    #[test]
+    fn compliance_violations_are_recorded() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.compliance.add_embargoed("KP");
+        app.client_metadata.insert(
+            1,
+            ClientMetadata {
+                client_id: 1,
+                name: "name".to_string(),
+                country: "KP".to_string(),
+                risk_score: 0,
+            },
+        );
+
+        let result = app.process_event(&AccountEvent {
+            transaction_id: 1,
+            action_type: AccountActions::Deposit,
+            client_id: 1,
+            amount: Some(money(500)),
+        });
+
+        assert!(matches!(
+            result,
+            Err(EngineError::InvalidEvent(RejectReason::Compliance(_)))
+        ));
+        assert!(app.accounts.is_empty(), "no account for blocked events");
+        assert_eq!(app.violations.len(), 1);
+        assert_eq!(app.violations[0].event.transaction_id, 1);
+    }
+
+    #[test]
+    fn summary_counts_rejected_events() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,2.0\ndispute,1,3,\n";
+
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader(csv.as_bytes());
+
+        assert_eq!(app.summary.processed, 3);
+        assert_eq!(app.summary.rejected, 2);
+    }
+
+    #[test]
+    fn dispute_above_available_is_rejected_by_default() {
+        let mut app = AccountProcessing::new(Config::default());
+
+        assert!(!dispute_of(&mut app, 5, 10));
+        assert_eq!(app.accounts[&1].available.minor_units(), 5);
+        assert_eq!(app.accounts[&1].held, money(0));
+        assert!(app.open_disputes.is_empty());
+    }
+
+    #[test]
+    fn dispute_above_available_allow_negative() {
+        let mut app = AccountProcessing::new(Config {
+            dispute_policy: DisputePolicy::AllowNegative,
+            ..Default::default()
+        });
+
+        assert!(dispute_of(&mut app, 5, 10));
+        assert_eq!(app.accounts[&1].available.minor_units(), -5);
+        assert_eq!(app.accounts[&1].held, money(10));
+        assert_eq!(
+            "1,-0.0005,0.0010,0.0005,false",
+            app.accounts[&1].to_string()
+        );
+
+        assert!(resolve_of(&mut app, AccountActions::ChargeBack));
+        assert_eq!(
+            app.accounts[&1].total().map(SignedMoney::minor_units),
+            Some(-5)
+        );
+        assert!(app.accounts[&1].locked);
+    }
+
+    #[test]
+    fn dispute_above_available_partial_hold() {
+        let mut app = AccountProcessing::new(Config {
+            dispute_policy: DisputePolicy::PartialHold,
+            ..Default::default()
+        });
+
+        assert!(dispute_of(&mut app, 5, 10));
+        assert_eq!(app.accounts[&1].available.minor_units(), 0);
+        assert_eq!(app.accounts[&1].held, money(5));
+        assert_eq!(app.open_disputes[&1], money(5));
+
+        // only what was held is released again
+        assert!(resolve_of(&mut app, AccountActions::Resolve));
+        assert_eq!(app.accounts[&1].available.minor_units(), 5);
+        assert_eq!(app.accounts[&1].held, money(0));
+        assert!(app.open_disputes.is_empty());
+    }
+
+    #[test]
+    fn dispute_policy_is_audited() {
+        let buffer = crate::audit::test::SharedBuffer::default();
+        let mut app = AccountProcessing::new(Config {
+            dispute_policy: DisputePolicy::PartialHold,
+            ..Default::default()
+        });
+        app.subsystems_mut().audit = Some(AuditLog::new(Box::new(buffer.clone())));
+
+        dispute_of(&mut app, 5, 10);
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+
+        assert_eq!(
+            output,
+            "{\"event\":\"dispute_exceeds_available\",\"client\":1,\"tx\":1,\"policy\":\"partial-hold\",\"requested\":\"0.0010\",\"held\":\"0.0005\"}\n"
+        );
+    }
+
+    #[test]
+    fn builder_options() {
+        let buffer = crate::audit::test::SharedBuffer::default();
+        let mut app = AccountProcessing::builder()
+            .schema(SchemaMode::Strict)
+            .locked_deposits(true)
+            .output(Box::new(CsvSink::new(buffer.clone())))
+            .build()
+            .unwrap();
+        assert!(app.config.locked_deposits);
+
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,2.0\n\
+             dispute,1,1,\n\
+             chargeback,1,1,\n\
+             deposit,1,2,1.5\n\
+             withdrawal,1,3,1.0\n"
+                .as_bytes(),
+        );
+        app.display().unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output,
+            "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,true\n"
+        );
+        assert_eq!(app.summary.rejected, 1, "the withdrawal stays rejected");
+
+        assert!(AccountProcessing::builder()
+            .schema(SchemaMode::Strict)
+            .tolerate_missing_columns(true)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn dormant_accounts_are_compacted() {
+        let mut app = AccountProcessing::new(Config::default());
+        let mut compaction = Compaction::new(4);
+        compaction.archive = Some(Default::default());
+        app.subsystems_mut().compaction = Some(compaction);
+
+        // 1 is emptied, 2 keeps a balance, 3 is locked and 4 has an open dispute. the
+        // compaction runs after the 4th and the 8th event
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,1.0\n\
+             withdrawal,1,2,1.0\n\
+             deposit,2,3,1.0\n\
+             deposit,3,4,1.0\n\
+             dispute,3,4,\n\
+             chargeback,3,4,\n\
+             deposit,4,5,1.0\n\
+             dispute,4,5,\n"
+                .as_bytes(),
+        );
+        let compaction = app.compaction().unwrap();
+        assert_eq!(compaction.compacted, 1);
+        assert!(compaction.archive.as_ref().unwrap().contains_key(&1));
+        assert_eq!(app.accounts.keys().copied().collect::<Vec<_>>(), [2, 3, 4]);
+
+        // nothing else is dormant yet
+        assert_eq!(app.compact(), 0);
+
+        // only a deposit creates an account, the archived one is restored for the withdrawal
+        let restored = event(AccountActions::Withdrawal, 6, Some(5000));
+        assert_eq!(
+            app.preview(&restored).outcome,
+            Err(RejectReason::InsufficientFunds)
+        );
+        app.process_events([restored]);
+        assert_eq!(app.accounts[&1], ClientAccount::new(1, SignedMoney::ZERO));
+        let compaction = app.compaction().unwrap();
+        assert_eq!(compaction.restored, 1);
+        assert!(!compaction.is_archived(1));
+    }
+
+    #[test]
+    fn correlation_ids_are_audited() {
+        let buffer = crate::audit::test::SharedBuffer::default();
+        let mut app = AccountProcessing::new(Config {
+            dispute_policy: DisputePolicy::PartialHold,
+            schema: SchemaMode::Strict,
+            ..Default::default()
+        });
+        app.subsystems_mut().audit = Some(AuditLog::new(Box::new(buffer.clone())));
+
+        app.process_reader(
+            "type,client,tx,amount,correlation\n\
+             deposit,1,1,2.0,req-1\n\
+             withdrawal,1,2,1.0,\n\
+             dispute,1,1,,req-3\n"
+                .as_bytes(),
+        );
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+
+        assert_eq!(
+            output,
+            "{\"event\":\"dispute_exceeds_available\",\"client\":1,\"tx\":1,\"policy\":\"partial-hold\",\"requested\":\"2.0000\",\"held\":\"1.0000\",\"correlation\":\"req-3\"}\n"
+        );
+        assert!(app.correlation.is_none());
+    }
+
+    #[test]
+    fn withdrawals_are_not_disputable_by_default() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.accounts
+            .insert(1, ClientAccount::new(1, SignedMoney::from_minor_units(100)));
+        app.transactions.insert(
+            1,
+            Transaction {
+                client_id: 1,
+                action_type: AccountActions::Withdrawal,
+                amount: money(10),
+            },
+        );
+
+        assert!(!resolve_of(&mut app, AccountActions::Dispute));
+        assert_eq!(app.accounts[&1].held, money(0));
+
+        app.config.disputable = "deposit,withdrawal".parse().unwrap();
+        assert!(resolve_of(&mut app, AccountActions::Dispute));
+        assert_eq!(app.accounts[&1].held, money(10));
+    }
+
+    #[test]
+    fn parse_disputable_actions() {
+        let disputable: DisputableActions = "withdrawal".parse().unwrap();
+        assert!(!disputable.allows(AccountActions::Deposit));
+        assert!(disputable.allows(AccountActions::Withdrawal));
+        assert!(!disputable.allows(AccountActions::Dispute));
+        assert!("dispute".parse::<DisputableActions>().is_err());
+    }
+
+    #[test]
+    fn transactions_remember_their_action_type() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,1.0\ndispute,1,2,\n";
+
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader(csv.as_bytes());
+
+        assert_eq!(app.transactions[&2].action_type, AccountActions::Withdrawal);
+        assert_eq!(app.accounts[&1].available.minor_units(), 10000);
+        assert_eq!(
+            app.accounts[&1].held,
+            money(0),
+            "the withdrawal was not disputed"
+        );
+    }
+
+    #[test]
+    fn pipeline_dispute_and_resolve() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_events([
+            event(AccountActions::Deposit, 1, Some(20)),
+            event(AccountActions::Deposit, 2, Some(10)),
+            event(AccountActions::Dispute, 1, None),
+        ]);
+
+        assert_eq!(app.accounts[&1].available.minor_units(), 10);
+        assert_eq!(app.accounts[&1].held, money(20));
+        assert_eq!(app.open_disputes.get(&1), Some(&money(20)));
+
+        app.process_events([event(AccountActions::Resolve, 1, None)]);
+
+        assert_eq!(app.accounts[&1].available.minor_units(), 30);
+        assert_eq!(app.accounts[&1].held, money(0));
+        assert!(app.open_disputes.is_empty());
+        assert_eq!(app.summary.processed, 4);
+        assert_eq!(app.summary.rejected, 0);
+    }
+
+    #[test]
+    fn pipeline_chargeback_locks_the_account() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_events([
+            event(AccountActions::Deposit, 1, Some(20)),
+            event(AccountActions::Dispute, 1, None),
+            event(AccountActions::ChargeBack, 1, None),
+            event(AccountActions::Deposit, 2, Some(10)),
+        ]);
+
+        let account = app.accounts[&1];
+        assert!(account.locked);
+        assert_eq!(account.available.minor_units(), 0);
+        assert_eq!(account.held, money(0));
+        assert_eq!(
+            app.summary.rejected, 1,
+            "the locked account rejects the deposit"
+        );
+    }
+
+    #[test]
+    fn pipeline_rejects_unknown_and_overdrawn() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_events([
+            event(AccountActions::Deposit, 1, Some(20)),
+            event(AccountActions::Withdrawal, 2, Some(30)),
+            event(AccountActions::Dispute, 7, None),
+            event(AccountActions::Resolve, 1, None),
+        ]);
+
+        assert_eq!(app.accounts[&1].available.minor_units(), 20);
+        assert_eq!(app.accounts[&1].held, money(0));
+        assert_eq!(app.summary.processed, 4);
+        assert_eq!(app.summary.rejected, 3);
+    }
+
+    #[test]
+    fn reader_and_events_produce_the_same_accounts() {
+        let csv = "type,client,tx,amount\n\
+            deposit,1,1,2.5\n\
+            deposit,2,2,1.0\n\
+            withdrawal,1,3,0.5\n\
+            dispute,2,2,\n";
+
+        let mut from_reader = AccountProcessing::new(Config::default());
+        from_reader.process_reader(csv.as_bytes());
+
+        let mut from_events = AccountProcessing::new(Config::default());
+        from_events.process_events([
+            event(AccountActions::Deposit, 1, Some(25000)),
+            AccountEvent {
+                client_id: 2,
+                ..event(AccountActions::Deposit, 2, Some(10000))
+            },
+            event(AccountActions::Withdrawal, 3, Some(5000)),
+            AccountEvent {
+                client_id: 2,
+                ..event(AccountActions::Dispute, 2, None)
+            },
+        ]);
+
+        assert_eq!(from_reader.accounts, from_events.accounts);
+        assert_eq!(from_reader.accounts[&1].available.minor_units(), 20000);
+        assert_eq!(from_reader.accounts[&2].held, money(10000));
+    }
+
+    #[test]
+    fn custom_actions_can_be_registered() {
+        let mut app = AccountProcessing::new(Config::default());
+        let fee = app.register_action("fee", Box::new(Fee)).unwrap();
+        assert_eq!(fee, AccountActions::Custom(0));
+        assert!(app.register_action("fee", Box::new(Fee)).is_err());
+        assert!(app.register_action("deposit", Box::new(Fee)).is_err());
+
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\nfee,1,2,1.5\nbonus,1,3,1.0\n";
+        app.process_reader(csv.as_bytes());
+        app.process_events([event(fee, 4, Some(5000))]);
+
+        assert_eq!(app.accounts[&1].available.minor_units(), -10000);
+        assert_eq!(app.summary.processed, 3, "the unknown bonus row is dropped");
+        assert!(
+            !app.transactions.contains_key(&2),
+            "custom actions cannot be disputed"
+        );
+    }
+
+    #[test]
+    fn apply_times_come_from_the_clock() {
+        let clock = Arc::new(ManualClock::default());
+        let mut app = AccountProcessing::new(Config::default());
+        app.clock = clock.clone();
+        app.subsystems_mut().latency = Some(LatencyHistogram::new(Some(Duration::from_millis(1))));
+        let fast = app
+            .register_action("fast", Box::new(Slow(clock.clone(), Duration::ZERO)))
+            .unwrap();
+        let slow = app
+            .register_action(
+                "slow",
+                Box::new(Slow(clock.clone(), Duration::from_millis(2))),
+            )
+            .unwrap();
+
+        let started = clock.now();
+        app.process_events([event(AccountActions::Deposit, 1, Some(1))]);
+        app.process_events([event(fast, 2, None), event(slow, 3, None)]);
+
+        let latency = app.latency().unwrap();
+        assert_eq!(latency.buckets[0], 2, "deposit and fast took no time");
+        assert_eq!(
+            latency.buckets[LatencyHistogram::bucket_of(Duration::from_millis(2))],
+            1
+        );
+        assert_eq!(latency.slow_events, 1);
+        assert_eq!(clock.now() - started, Duration::from_millis(2));
+    }
+
+    #[test]
+    fn panics_are_quarantined() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\nbroken,1,2,1.0\ndeposit,1,3,1.0\n";
+        let mut app = AccountProcessing::new(Config::default());
+        app.subsystems_mut().quarantine = Some(Quarantine::default());
+        app.register_action("broken", Box::new(Broken)).unwrap();
+        app.process_reader(csv.as_bytes());
+
+        assert_eq!(app.summary.rejected, 1);
+        assert_eq!(app.accounts[&1].available.minor_units(), 20000);
+        assert_eq!(
+            app.quarantine().unwrap().policy_rejects[0].reason,
+            RejectReason::Panic("broken action".to_string())
+        );
+
+        let mut strict = AccountProcessing::new(Config {
+            strict: true,
+            ..Default::default()
+        });
+        strict.register_action("broken", Box::new(Broken)).unwrap();
+        let run = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            strict.process_reader(csv.as_bytes())
+        }));
+        assert!(run.is_err());
+    }
+
+    #[test]
+    fn missing_amount_column_is_tolerated() {
+        let csv = "type,client,tx\ndeposit,1,1\ndispute,1,1,\n";
+
+        let mut strict = AccountProcessing::new(Config::default());
+        strict.process_reader(csv.as_bytes());
+        assert_eq!(strict.summary.processed, 1, "the longer row is invalid");
+
+        let mut app = AccountProcessing::new(Config {
+            tolerate_missing_columns: true,
+            ..Default::default()
+        });
+        app.process_reader("type,client,tx,amount\ndeposit,1,1,2.0\ndispute,1,1\n".as_bytes());
+        assert_eq!(app.summary.processed, 2);
+        assert_eq!(app.summary.missing_columns, 1);
+        assert_eq!(app.accounts[&1].held, money(20000));
+    }
+
+    #[test]
+    fn embedded_headers_are_skipped() {
+        let csv = "type,client,tx,amount\n\
+            deposit,1,1,1.0\n\
+            type,client,tx,amount\n\
+            deposit,1,2,1.0\n";
+
+        let mut app = AccountProcessing::new(Config {
+            skip_embedded_headers: true,
+            ..Default::default()
+        });
+        app.process_reader(csv.as_bytes());
+
+        assert_eq!(app.summary.embedded_headers, 1);
+        assert_eq!(app.summary.processed, 2);
+        assert_eq!(app.accounts[&1].available.minor_units(), 20000);
+    }
+
+    #[test]
+    fn preview_does_not_change_the_state() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader("type,client,tx,amount\ndeposit,1,1,2.0\n".as_bytes());
+        let before = app.snapshot();
+        let event = |action_type, transaction_id, amount: Option<u64>| AccountEvent {
+            transaction_id,
+            action_type,
+            client_id: 1,
+            amount: amount.map(Money::from_minor_units),
+        };
+
+        let withdrawal = app.preview(&event(AccountActions::Withdrawal, 2, Some(15000)));
+        assert_eq!(withdrawal.outcome, Ok(()));
+        assert_eq!(withdrawal.account.available.minor_units(), 5000);
+        let too_much = app.preview(&event(AccountActions::Withdrawal, 2, Some(30000)));
+        assert_eq!(too_much.outcome, Err(RejectReason::InsufficientFunds));
+        assert_eq!(too_much.account.available.minor_units(), 20000);
+
+        let dispute = app.preview(&event(AccountActions::Dispute, 1, None));
+        assert_eq!(dispute.account.held, Money::from_minor_units(20000));
+        let unknown = app.preview(&event(AccountActions::Resolve, 3, None));
+        assert_eq!(unknown.outcome, Err(RejectReason::UnknownTransaction));
+
+        assert_eq!(app.snapshot(), before);
+        assert_eq!(app.summary.processed, 1);
+
+        // the same decision as ingesting it
+        app.process_events([event(AccountActions::Withdrawal, 2, Some(15000))]);
+        assert_eq!(app.account(1), Some(&withdrawal.account));
+    }
+
+    #[test]
+    fn only_deposits_create_accounts() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     withdrawal,2,2,1.0\n\
+                     dispute,3,1,\n\
+                     deposit,4,3,1.0\n";
+        let mut app = AccountProcessing::new(Config::default());
+        app.subsystems_mut().quarantine = Some(Quarantine::default());
+        app.process_reader(input.as_bytes());
+
+        assert_eq!(app.accounts.keys().copied().collect::<Vec<_>>(), vec![1, 4]);
+        let reasons: Vec<(u16, RejectReason)> = app
+            .quarantine()
+            .unwrap()
+            .policy_rejects
+            .iter()
+            .map(|reject| (reject.event.client_id, reject.reason.clone()))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (2, RejectReason::UnknownClient),
+                // tx 1 belongs to client 1
+                (3, RejectReason::UnknownTransaction)
+            ]
+        );
+
+        let mut app = AccountProcessing::new(Config {
+            account_creation: AccountCreation::Any,
+            ..Config::default()
+        });
+        app.subsystems_mut().quarantine = Some(Quarantine::default());
+        app.process_reader(input.as_bytes());
+        // rejected for their own reasons and still without an account
+        assert_eq!(app.accounts.keys().copied().collect::<Vec<_>>(), vec![1, 4]);
+        let reasons: Vec<RejectReason> = app
+            .quarantine()
+            .unwrap()
+            .policy_rejects
+            .iter()
+            .map(|reject| reject.reason.clone())
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                RejectReason::InsufficientFunds,
+                RejectReason::UnknownTransaction
+            ]
+        );
+    }
+
+    #[test]
+    fn opening_balances_are_deposited_before_the_input() {
+        let mut app = AccountProcessing::new(Config::default());
+        let opening = |client_id, available, locked| OpeningBalance {
+            client_id,
+            available: money(available),
+            locked,
+        };
+        app.open_balance(&opening(1, 20000, false)).unwrap();
+        app.open_balance(&opening(2, 10000, true)).unwrap();
+        app.process_reader(
+            "type,client,tx,amount\nwithdrawal,1,1,0.5\ndeposit,2,2,1.0\n".as_bytes(),
+        );
+
+        assert_eq!(app.accounts[&1].available.minor_units(), 15000);
+        assert_eq!(
+            app.accounts[&2].available.minor_units(),
+            10000,
+            "still locked"
+        );
+        assert_eq!(app.summary.processed, 2, "they are not events of the input");
+    }
+
+    #[test]
+    fn rejected_opening_balances_leave_no_account() {
+        let mut app = AccountProcessing::new(Config::default());
+        let too_much = OpeningBalance {
+            client_id: 3,
+            available: Money::MAX,
+            locked: false,
+        };
+        assert!(app.open_balance(&too_much).is_err());
+        assert!(app.account(3).is_none());
+        assert_eq!(app.account_count(), 0);
+    }
+
+    #[test]
+    fn statistics_carry_over_between_runs() {
+        let mut monday = AccountProcessing::new(Config::default());
+        monday.process_reader(
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\ndispute,1,1,\n".as_bytes(),
+        );
+        let mut state = vec![];
+        monday.snapshot().write(&mut state).unwrap();
+
+        let mut tuesday = AccountProcessing::new(Config::default());
+        tuesday.restore(Snapshot::read(&mut state.as_slice()).unwrap());
+        tuesday.process_reader(
+            "type,client,tx,amount\ndeposit,1,3,0.5\nwithdrawal,1,4,9.0\nchargeback,1,1,\n"
+                .as_bytes(),
+        );
+
+        let statistics = tuesday.statistics[&1];
+        assert_eq!(statistics.deposits, 3);
+        assert_eq!(statistics.deposit_volume, Money::from_minor_units(35000));
+        assert_eq!((statistics.disputes, statistics.chargebacks), (1, 1));
+    }
+
+    #[test]
+    fn display_into_a_memory_sink() {
+        let memory = MemorySink::default();
+        let mut app = AccountProcessing::builder()
+            .output(Box::new(memory.clone()))
+            .build()
+            .unwrap();
+        app.process_reader("type,client,tx,amount\ndeposit,2,1,1.5\ndeposit,1,2,1.0\n".as_bytes());
+        app.display().unwrap();
+
+        assert_eq!(
+            memory.columns(),
+            ["client", "available", "held", "total", "locked"]
+        );
+        assert_eq!(
+            memory.accounts(),
+            [
+                ClientAccount::new(1, SignedMoney::from_minor_units(10000)),
+                ClientAccount::new(2, SignedMoney::from_minor_units(15000))
+            ]
+        );
+        assert_eq!(
+            memory.rows()[1],
+            ["2", "1.5000", "0.0000", "1.5000", "false"]
+        );
+    }
+
+    #[test]
+    fn output_fields_are_quoted() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.client_metadata.insert(
+            1,
+            ClientMetadata {
+                client_id: 1,
+                name: "Doe, \"Jane\"".to_string(),
+                country: "AT".to_string(),
+                risk_score: 5,
+            },
+        );
+        app.accounts.insert(
+            1,
+            ClientAccount::new(1, SignedMoney::from_minor_units(15000)),
+        );
+        app.accounts
+            .insert(2, ClientAccount::new(2, SignedMoney::ZERO));
+
+        let mut output = vec![];
+        app.write_accounts(&mut output, app.accounts.values())
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("1,1.5000,0.0000,1.5000,false,\"Doe, \"\"Jane\"\"\",AT,5\n"));
+
+        let mut reader = csv::Reader::from_reader(output.as_bytes());
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(&rows[0][5], "Doe, \"Jane\"");
+        assert_eq!(&rows[1][5], "");
+        assert_eq!(
+            rows[1].len(),
+            8,
+            "clients without metadata keep the columns"
+        );
+    }
+
+    #[test]
+    fn excel_exports_are_readable() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader("\u{FEFF}type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes());
+        assert_eq!(
+            app.accounts[&1].available.minor_units(),
+            10000,
+            "the BOM is not part of type"
+        );
+
+        let mut utf16 = vec![0xFF, 0xFE];
+        for unit in "type,client,tx,amount\r\ndeposit,2,2,2.0\r\n".encode_utf16() {
+            utf16.extend(unit.to_le_bytes());
+        }
+        app.process_reader(utf16.as_slice());
+        assert_eq!(app.accounts[&2].available.minor_units(), 20000);
+    }
+
+    #[test]
+    fn flexible_schema_matches_columns_by_name() {
+        let input = "amount,note,tx,client,type\n1.5,first,1,7,deposit\n0.5,,2,7,withdrawal\n";
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader(input.as_bytes());
+
+        assert_eq!(app.accounts[&7].available.minor_units(), 10000);
+        assert_eq!(app.summary.processed, 2);
+        assert_eq!(app.summary.missing_columns, 0);
+    }
+
+    #[test]
+    fn strict_schema_rejects_other_headers() {
+        let config = Config {
+            schema: SchemaMode::Strict,
+            ..Default::default()
+        };
+
+        for header in [
+            "client,type,tx,amount",
+            "type,client,tx,amount,note",
+            "type,client,tx",
+            "type, client, tx, amount",
+        ] {
+            let mut app = AccountProcessing::new(config);
+            app.process_reader(format!("{}\ndeposit,1,1,1.0\n", header).as_bytes());
+            assert!(app.accounts.is_empty(), "{} should be rejected", header);
+            assert_eq!(app.summary.processed, 0);
+        }
+
+        let mut app = AccountProcessing::new(config);
+        app.process_reader("type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes());
+        assert_eq!(app.accounts[&1].available.minor_units(), 10000);
+    }
+
+    #[test]
+    fn ingest_tells_what_happened_to_the_event() {
+        let mut app = AccountProcessing::new(Config::default());
+        let applied = app
+            .ingest(event(AccountActions::Deposit, 1, Some(2)))
+            .unwrap();
+        assert_eq!(applied.account.available.minor_units(), 2);
+
+        assert_eq!(
+            app.ingest(event(AccountActions::Withdrawal, 2, Some(3))),
+            Err(Rejected::Invalid(RejectReason::InsufficientFunds))
+        );
+        assert_eq!(
+            app.ingest(event(AccountActions::Dispute, 9, None)),
+            Err(Rejected::Invalid(RejectReason::UnknownTransaction))
+        );
+
+        app.config.partition = Some(Partition { index: 1, count: 2 });
+        let other = (0..).find(|client| !app.is_in_partition(*client)).unwrap();
+        let mut skipped = event(AccountActions::Deposit, 3, Some(1));
+        skipped.client_id = other;
+        assert_eq!(app.ingest(skipped), Err(Rejected::Skipped));
+        assert_eq!(
+            app.summary.processed, 3,
+            "the other partition is not counted"
+        );
+    }
+
+    #[test]
+    fn hooks_see_every_decision() {
+        let decisions = Rc::new(RefCell::new(vec![]));
+        let mut app = AccountProcessing::new(Config::default());
+        let applied = decisions.clone();
+        app.on_applied(move |event, account| {
+            applied
+                .borrow_mut()
+                .push(format!("{} {}", event.transaction_id, account.available));
+        });
+        let rejected = decisions.clone();
+        app.on_rejected(move |event, reason| {
+            rejected
+                .borrow_mut()
+                .push(format!("{} {}", event.transaction_id, reason));
+        });
+
+        app.process_events([
+            event(AccountActions::Deposit, 1, Some(2)),
+            event(AccountActions::Withdrawal, 2, Some(3)),
+            event(AccountActions::Dispute, 9, None),
+        ]);
+        assert_eq!(
+            *decisions.borrow(),
+            [
+                "1 0.0002".to_string(),
+                format!("2 {}", RejectReason::InsufficientFunds),
+                format!("9 {}", RejectReason::UnknownTransaction),
+            ]
+        );
+    }
+
+    // a source that is not a csv, the events are already parsed
+    struct Events(std::vec::IntoIter<Result<AccountEvent, SourceError>>);
+
+    impl EventSource for Events {
+        fn next_event(&mut self) -> Option<Result<AccountEvent, SourceError>> {
+            self.0.next()
+        }
+    }
+
+    #[test]
+    fn run_from_any_source() {
+        let invalid = SourceError::Row {
+            line: 2,
+            row: "deposit,1".to_string(),
+            message: "too short".to_string(),
+        };
+        let mut source = Events(
+            vec![
+                Ok(event(AccountActions::Deposit, 1, Some(2))),
+                Err(invalid),
+                Ok(event(AccountActions::Withdrawal, 2, Some(1))),
+            ]
+            .into_iter(),
+        );
+        let mut app = AccountProcessing::new(Config::default());
+        app.run_from(&mut source).unwrap();
+        assert_eq!(app.accounts[&1].available.minor_units(), 1);
+        assert_eq!(app.summary.parse_errors, 1);
+
+        let broken = std::io::Error::other("connection reset");
+        let mut source = Events(vec![Err(SourceError::Io(broken))].into_iter());
+        assert!(matches!(app.run_from(&mut source), Err(EngineError::Io(_))));
+    }
+
+    #[test]
+    fn fan_in_attributes_the_events() {
+        let buffer = crate::audit::test::SharedBuffer::default();
+        let metrics = Arc::new(PrometheusMetrics::default());
+        let mut app = AccountProcessing::new(Config::default());
+        app.subsystems_mut().audit = Some(AuditLog::new(Box::new(buffer.clone())));
+        app.subsystems_mut().metrics = Some(metrics.clone());
+
+        let mut fan_in = FanIn::new(vec![
+            (
+                "acquirer-a".to_string(),
+                Events(vec![Ok(event(AccountActions::Deposit, 1, Some(2)))].into_iter()),
+            ),
+            (
+                "acquirer-b".to_string(),
+                Events(vec![Ok(event(AccountActions::Withdrawal, 2, Some(3)))].into_iter()),
+            ),
+        ]);
+        app.run_from(&mut fan_in).unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output,
+            "{\"event\":\"applied\",\"source\":\"acquirer-a\",\"type\":\"deposit\",\"client\":1,\"tx\":1}\n"
+        );
+        let count = |source, outcome| {
+            metrics.counter_value(
+                crate::metrics::SOURCE_EVENTS,
+                &[("source", source), ("outcome", outcome)],
+            )
+        };
+        assert_eq!(count("acquirer-a", "applied"), 1);
+        assert_eq!(count("acquirer-b", "rejected"), 1);
+        assert!(app.source_name.is_none());
+    }
+
+    #[test]
+    fn run_returns_what_stopped_it() {
+        let mut app = AccountProcessing::new(Config::default());
+        let missing = std::env::temp_dir().join("kraken_test_missing.csv");
+        let missing = missing.to_str().unwrap().to_string();
+        assert!(matches!(
+            app.run(missing),
+            Err(EngineError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound
+        ));
+
+        let strict = Config {
+            schema: SchemaMode::Strict,
+            ..Default::default()
+        };
+        let mut app = AccountProcessing::new(strict);
+        assert!(matches!(
+            app.try_process_reader("client,type,tx,amount\n".as_bytes()),
+            Err(EngineError::Parse { line: 1, .. })
+        ));
+
+        let path = std::env::temp_dir().join("kraken_test_run.csv");
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,x,2,1.0\n",
+        )
+        .unwrap();
+        let mut app = AccountProcessing::new(Config::default());
+        let summary = app.run(path.to_str().unwrap().to_string()).unwrap();
+        assert_eq!((summary.processed, summary.parse_errors), (1, 1));
+    }
+
+    #[test]
+    fn quarantine_separates_parse_errors_from_rejects() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.subsystems_mut().quarantine = Some(Quarantine::default());
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,1.0\n\
+             deposit,no-client,2,1.0\n\
+             refund,1,3,1.0\n\
+             withdrawal,1,4,5.0\n\
+             dispute,1,99,\n\
+             resolve,1,1,\n\
+             deposit,1\n"
+                .as_bytes(),
+        );
+
+        let quarantine = app.quarantine().unwrap();
+        let parse_errors: Vec<(u64, &str)> = quarantine
+            .parse_errors
+            .iter()
+            .map(|parse_error| (parse_error.line, parse_error.row.as_str()))
+            .collect();
+        assert_eq!(
+            parse_errors,
+            vec![
+                (3, "deposit,no-client,2,1.0"),
+                (4, "refund,1,3,1.0"),
+                (8, "")
+            ]
+        );
+        assert!(quarantine.parse_errors[1]
+            .error
+            .contains("unknown action type"));
+
+        let reasons: Vec<(i32, RejectReason)> = quarantine
+            .policy_rejects
+            .iter()
+            .map(|reject| (reject.event.transaction_id, reject.reason.clone()))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (4, RejectReason::InsufficientFunds),
+                (99, RejectReason::UnknownTransaction),
+                (1, RejectReason::NoOpenDispute),
+            ]
+        );
+        assert_eq!(app.summary.rejected, 3);
+    }
+
+    #[test]
+    fn abort_on_invariant_violation() {
+        let mut app = AccountProcessing::new(Config {
+            invariants: Some(InvariantMode::Abort),
+            ..Config::default()
+        });
+        app.register_action("hold", Box::new(Hold)).unwrap();
+        let input = format!("{}deposit,1,4,1.0\n", UNEXPLAINED_HOLD);
+        let error = app.try_process_reader(input.as_bytes()).unwrap_err();
+        assert!(matches!(error, EngineError::InvariantViolated(_)));
+        assert!(error
+            .to_string()
+            .starts_with("invariant held_matches_open_disputes violated"));
+
+        // the deposit after it was never read
+        assert_eq!(app.summary.processed, 4);
+        assert_eq!(
+            app.halted().unwrap().invariant,
+            "held_matches_open_disputes"
+        );
+        assert!(matches!(
+            app.ingest(event(AccountActions::Deposit, 3, Some(1))),
+            Err(Rejected::InvariantViolated(_))
+        ));
+        assert_eq!(app.summary.processed, 4);
+    }
+
+    #[test]
+    fn ledger_iterators() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,2,1,2.0\n\
+             deposit,1,2,2.0\n\
+             withdrawal,2,3,0.5\n\
+             dispute,1,2,\n"
+                .as_bytes(),
+        );
+
+        let clients: Vec<u16> = app.accounts_iter().map(|account| account.id).collect();
+        assert_eq!(clients, [1, 2]);
+        assert_eq!(
+            app.open_disputes_iter().collect::<Vec<_>>(),
+            [(2, money(20000))]
+        );
+        let transactions: Vec<(i32, AccountActions)> = app
+            .transactions_for(2)
+            .map(|(transaction_id, transaction)| (transaction_id, transaction.action_type))
+            .collect();
+        assert_eq!(
+            transactions,
+            [
+                (1, AccountActions::Deposit),
+                (3, AccountActions::Withdrawal)
+            ]
+        );
+        assert_eq!(app.transactions_for(3).count(), 0);
+    }
+
+    #[test]
+    fn accounts_are_only_inserted_when_consistent() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader("type,client,tx,amount\ndeposit,1,1,2.0\ndispute,1,1,\n".as_bytes());
+        assert_eq!(app.account(1).unwrap().held, money(20000));
+        assert_eq!(app.transaction(1).unwrap().client_id, 1);
+        assert_eq!(app.open_dispute(1), Some(money(20000)));
+
+        let mut holding = ClientAccount::new(2, SignedMoney::ZERO);
+        holding.held = money(1);
+        assert!(app.insert_account(holding).is_err());
+        assert!(app
+            .insert_account(ClientAccount::new(2, SignedMoney::from_minor_units(5)))
+            .is_ok());
+
+        let mut migrated = ClientAccount::new(1, SignedMoney::ZERO);
+        migrated.held = money(20000);
+        assert!(app.insert_account(migrated).is_ok());
+        assert_eq!(app.account_count(), 2);
+        // a restored state can hold more in open disputes than money can express
+        for (transaction_id, held) in [(10, Money::MAX), (11, money(1))] {
+            app.transactions.insert(
+                transaction_id,
+                Transaction {
+                    client_id: 3,
+                    action_type: AccountActions::Deposit,
+                    amount: held,
+                },
+            );
+            app.open_disputes.insert(transaction_id, held);
+        }
+        assert_eq!(
+            app.insert_account(ClientAccount::new(3, SignedMoney::ZERO)),
+            Err("the open disputes of client 3 overflow".to_string())
+        );
+    }
+
+    #[test]
+    fn balance_changes() {
+        let mut app = AccountProcessing::new(Config {
+            log_balances: true,
+            ..Config::default()
+        });
+        let before = app.balances_of(1).unwrap();
+        let event = AccountEvent {
+            transaction_id: 1,
+            action_type: AccountActions::Deposit,
+            client_id: 1,
+            amount: Some(money(15000)),
+        };
+        app.process_events([event]);
+
+        assert_eq!(
+            app.balance_change(&event, &before),
+            "balance_change client=1 tx=1 type=deposit amount=1.5000 \
+             available_before=0.0000 available_after=1.5000 held_before=0.0000 held_after=0.0000 \
+             locked_before=false locked_after=false"
+        );
+        assert!(AccountProcessing::new(Config::default())
+            .balances_of(1)
+            .is_none());
+    }
+
+    #[test]
+    fn batches_are_applied_in_the_given_order() {
+        let event = |action_type, client_id, transaction_id, amount: Option<u64>| AccountEvent {
+            transaction_id,
+            action_type,
+            client_id,
+            amount: amount.map(money),
+        };
+        let events = [
+            event(AccountActions::Deposit, 2, 1, Some(100)),
+            event(AccountActions::Deposit, 1, 2, Some(50)),
+            event(AccountActions::Withdrawal, 2, 3, Some(80)),
+            event(AccountActions::Withdrawal, 1, 4, Some(60)),
+            event(AccountActions::Dispute, 2, 1, None),
+            event(AccountActions::Deposit, 3, 5, Some(10)),
+        ];
+
+        let mut sequential = AccountProcessing::new(Config::default());
+        sequential.exclusions.clients.insert(3);
+        sequential.process_events(events);
+        let mut batched = AccountProcessing::new(Config::default());
+        batched.exclusions.clients.insert(3);
+        let outcome = batched.apply_batch(&events);
+
+        assert_eq!(
+            outcome,
+            BatchOutcome {
+                applied: 3,
+                rejected: 2,
+                skipped: 1,
+            }
+        );
+        assert_eq!(batched.accounts, sequential.accounts);
+        assert_eq!(batched.transactions, sequential.transactions);
+        assert_eq!(batched.open_disputes, sequential.open_disputes);
+
+        // the first deposit of tx 9 wins, whichever client it belongs to
+        let mut app = AccountProcessing::builder()
+            .reject_duplicate_tx(true)
+            .build()
+            .unwrap();
+        app.apply_batch(&[
+            event(AccountActions::Deposit, 2, 9, Some(100)),
+            event(AccountActions::Deposit, 1, 9, Some(50)),
+        ]);
+        assert_eq!(app.transaction(9).unwrap().client_id, 2);
+        assert!(app.account(1).is_none());
+    }
+
+    #[test]
+    fn reused_tx_ids_are_not_served_from_the_cache() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             deposit,2,2,1.0\n\
+             deposit,2,1,1.0\n\
+             dispute,1,1,\n"
+                .as_bytes(),
+        );
+
+        // the map only knows the deposit of client 2, which client 1 cannot dispute
+        assert_eq!(app.account(1).unwrap().held, Money::ZERO);
+        assert_eq!(app.open_dispute(1), None);
+        assert_eq!(app.summary.rejected, 1);
+    }
+
+    #[test]
+    fn transactions_of_other_clients_cannot_be_disputed() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,2.0\n\
+             deposit,2,2,3.0\n\
+             dispute,2,1,\n\
+             resolve,2,1,\n\
+             chargeback,2,1,\n"
+                .as_bytes(),
+        );
+
+        assert_eq!(app.account(1).unwrap().held, Money::ZERO);
+        assert_eq!(app.account(2).unwrap().held, Money::ZERO);
+        assert!(!app.account(2).unwrap().locked);
+        assert_eq!(app.open_dispute(1), None);
+        assert_eq!(app.summary.rejected, 3);
+    }
+
+    #[test]
+    fn duplicate_tx_ids_can_be_rejected() {
+        let mut app = AccountProcessing::builder()
+            .reject_duplicate_tx(true)
+            .build()
+            .unwrap();
+        app.ingest(event(AccountActions::Deposit, 1, Some(5)))
+            .unwrap();
+        assert_eq!(
+            app.preview(&event(AccountActions::Withdrawal, 1, Some(1)))
+                .outcome,
+            Err(RejectReason::DuplicateTransaction)
+        );
+        assert_eq!(
+            app.ingest(event(AccountActions::Deposit, 1, Some(1))),
+            Err(Rejected::Invalid(RejectReason::DuplicateTransaction))
+        );
+        app.ingest(event(AccountActions::Dispute, 1, None)).unwrap();
+
+        assert_eq!(app.open_dispute(1), Some(money(5)));
+        assert_eq!(app.summary.rejected, 1);
+    }
+
+    #[test]
+    fn rejected_deposits_cannot_be_disputed() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,1.0\n\
+             dispute,1,1,\n\
+             chargeback,1,1,\n\
+             deposit,1,3,4.0\n\
+             dispute,1,3,\n"
+                .as_bytes(),
+        );
+
+        // the deposit on the locked account was never applied so there is nothing to hold
+        assert!(app.transaction(3).is_none());
+        assert!(app.open_dispute(3).is_none());
+        assert_eq!(app.account(1).unwrap().held, Money::ZERO);
+        assert_eq!(app.summary.rejected, 2);
+    }
+
+    #[test]
+    fn undisputed_transactions_cannot_be_resolved() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             deposit,1,2,10.0\n\
+             dispute,1,2,\n\
+             resolve,1,1,\n\
+             chargeback,1,1,\n"
+                .as_bytes(),
+        );
+
+        // the hold of tx 2 is left alone
+        let account = app.account(1).unwrap();
+        assert_eq!(account.available, SignedMoney::from_minor_units(100000));
+        assert_eq!(account.held, money(100000));
+        assert!(!account.locked);
+        assert_eq!(app.open_dispute(2), Some(money(100000)));
+        assert_eq!(app.summary.rejected, 2);
+    }
+
+    #[test]
+    fn disputed_transactions_cannot_be_disputed_again() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             deposit,1,2,10.0\n\
+             dispute,1,1,\n\
+             dispute,1,1,\n\
+             resolve,1,1,\n"
+                .as_bytes(),
+        );
+
+        let account = app.account(1).unwrap();
+        assert_eq!(account.available, SignedMoney::from_minor_units(200000));
+        assert_eq!(account.held, Money::ZERO);
+        assert_eq!(app.open_dispute(1), None);
+        assert_eq!(app.summary.rejected, 1);
+    }
+
+    // like a full disk, nothing can be written
+    struct Full;
+
+    impl OutputSink for Full {
+        fn header(&mut self, _: &[&str]) -> std::io::Result<()> {
+            Err(std::io::Error::other("no space left"))
+        }
+
+        fn row(&mut self, _: &ClientAccount, _: &[String]) -> std::io::Result<()> {
+            Err(std::io::Error::other("no space left"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output_errors_are_returned() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.output = Some(Box::new(Full));
+        let error = app
+            .run_reader("type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes())
+            .unwrap_err();
+        assert!(matches!(error, EngineError::Output(_)));
+        assert_eq!(error.to_string(), "cannot write accounts: no space left");
+    }
+}
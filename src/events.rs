@@ -0,0 +1,3 @@
+//! building blocks for producers that generate input for the engine, enabled with the
+//! `events` feature
+pub mod writer;
@@ -0,0 +1,201 @@
+//! typed events and writers for the csv and the json lines input, everything that can be
+//! built here is read back by the engine exactly as it was given
+#![allow(dead_code)]
+
+use std::io::Write;
+
+use crate::audit::json_string;
+use crate::money::{Money, RoundingMode, DECIMAL_PLACES};
+use crate::AccountActions;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Event {
+    pub action_type: AccountActions,
+    pub client: u16,
+    pub tx: i32,
+    // only deposits and withdrawals have one
+    pub amount: Option<Money>,
+}
+
+/// more decimals than the engine has would be rounded
+fn exact_amount(amount: &str) -> Result<Money, String> {
+    let decimals = amount.trim().split_once('.').map_or(0, |(_, f)| f.len());
+    if decimals > DECIMAL_PLACES as usize {
+        return Err(format!(
+            "{} has more than {} decimal places",
+            amount, DECIMAL_PLACES
+        ));
+    }
+
+    Money::parse(amount, RoundingMode::default())
+}
+
+impl Event {
+    pub fn deposit(client: u16, tx: i32, amount: &str) -> Result<Event, String> {
+        Ok(Event {
+            action_type: AccountActions::Deposit,
+            client,
+            tx,
+            amount: Some(exact_amount(amount)?),
+        })
+    }
+
+    pub fn withdrawal(client: u16, tx: i32, amount: &str) -> Result<Event, String> {
+        Ok(Event {
+            action_type: AccountActions::Withdrawal,
+            client,
+            tx,
+            amount: Some(exact_amount(amount)?),
+        })
+    }
+
+    /// tx is the one of the disputed deposit or withdrawal
+    pub fn dispute(client: u16, tx: i32) -> Event {
+        Event::reference(AccountActions::Dispute, client, tx)
+    }
+
+    pub fn resolve(client: u16, tx: i32) -> Event {
+        Event::reference(AccountActions::Resolve, client, tx)
+    }
+
+    pub fn chargeback(client: u16, tx: i32) -> Event {
+        Event::reference(AccountActions::ChargeBack, client, tx)
+    }
+
+    fn reference(action_type: AccountActions, client: u16, tx: i32) -> Event {
+        Event {
+            action_type,
+            client,
+            tx,
+            amount: None,
+        }
+    }
+
+    fn amount(self) -> String {
+        self.amount
+            .map(|amount| amount.format(RoundingMode::default()))
+            .unwrap_or_default()
+    }
+
+    /// one line of the unix socket input
+    pub fn to_json(self) -> String {
+        let mut json = format!(
+            "{{\"type\":{},\"client\":{},\"tx\":{}",
+            json_string(&self.action_type.to_string()),
+            self.client,
+            self.tx
+        );
+        if self.amount.is_some() {
+            json.push_str(&format!(",\"amount\":{}", json_string(&self.amount())));
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// the csv input format including its header
+pub struct CsvEventWriter<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> CsvEventWriter<W> {
+    pub fn new(writer: W) -> std::io::Result<Self> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(crate::COLUMNS)?;
+        Ok(CsvEventWriter { writer })
+    }
+
+    pub fn write(&mut self, event: &Event) -> std::io::Result<()> {
+        self.writer.write_record([
+            event.action_type.to_string(),
+            event.client.to_string(),
+            event.tx.to_string(),
+            event.amount(),
+        ])?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// newline-delimited json like the unix socket input expects it
+pub struct JsonEventWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonEventWriter<W> {
+    pub fn new(writer: W) -> Self {
+        JsonEventWriter { writer }
+    }
+
+    pub fn write(&mut self, event: &Event) -> std::io::Result<()> {
+        writeln!(self.writer, "{}", event.to_json())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::events::writer::{CsvEventWriter, Event, JsonEventWriter};
+    use crate::{AccountProcessing, Config};
+
+    fn events() -> Vec<Event> {
+        vec![
+            Event::deposit(1, 1, "12.3456").unwrap(),
+            Event::dispute(1, 1),
+            Event::resolve(1, 1),
+            Event::withdrawal(1, 2, "2").unwrap(),
+        ]
+    }
+
+    #[test]
+    fn amounts_have_to_arrive_exactly() {
+        assert!(Event::deposit(1, 1, "1.23456").is_err());
+        assert!(Event::deposit(1, 1, "-1").is_err());
+        assert!(Event::deposit(1, 1, "one").is_err());
+        assert!(Event::withdrawal(1, 1, "999.9999").is_ok());
+    }
+
+    #[test]
+    fn csv_is_read_back_by_the_engine() {
+        let mut output = vec![];
+        let mut writer = CsvEventWriter::new(&mut output).unwrap();
+        events()
+            .iter()
+            .for_each(|event| writer.write(event).unwrap());
+        writer.flush().unwrap();
+        drop(writer);
+
+        assert_eq!(
+            String::from_utf8(output.clone()).unwrap(),
+            "type,client,tx,amount\ndeposit,1,1,12.3456\ndispute,1,1,\nresolve,1,1,\n\
+             withdrawal,1,2,2.0000\n"
+        );
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader(output.as_slice());
+        assert_eq!(app.accounts[&1].available.minor_units(), 103456);
+        assert_eq!(app.summary.rejected, 0);
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut output = vec![];
+        let mut writer = JsonEventWriter::new(&mut output);
+        events()
+            .iter()
+            .for_each(|event| writer.write(event).unwrap());
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[0],
+            r#"{"type":"deposit","client":1,"tx":1,"amount":"12.3456"}"#
+        );
+        assert_eq!(lines[1], r#"{"type":"dispute","client":1,"tx":1}"#);
+    }
+}
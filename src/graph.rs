@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+
+use crate::{AccountActions, AccountEvent};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum GraphFormat {
+    #[default]
+    Dot,
+    GraphMl,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            "graphml" => Ok(GraphFormat::GraphMl),
+            _ => Err(format!("unknown graph format: {} (dot, graphml)", s)),
+        }
+    }
+}
+
+/// one dispute family event against a transaction and if it was applied
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DisputeEdge {
+    pub client_id: u16,
+    pub transaction_id: i32,
+    pub action_type: AccountActions,
+    pub applied: bool,
+}
+
+/// the final balances don't tell us who disputed what, so next to the processing we keep
+/// tx -> owning client and every dispute/resolve/chargeback against a tx.
+///
+/// only transactions that were touched by a dispute end up in the export
+#[derive(Debug, Clone, Default)]
+pub struct DisputeGraph {
+    owners: BTreeMap<i32, u16>,
+    pub edges: Vec<DisputeEdge>,
+}
+
+impl DisputeGraph {
+    pub fn register_transaction(&mut self, event: &AccountEvent) {
+        self.owners.insert(event.transaction_id, event.client_id);
+    }
+
+    pub fn record_dispute(&mut self, event: &AccountEvent, applied: bool) {
+        self.edges.push(DisputeEdge {
+            client_id: event.client_id,
+            transaction_id: event.transaction_id,
+            action_type: event.action_type,
+            applied,
+        });
+    }
+
+    pub fn owner(&self, transaction_id: i32) -> Option<u16> {
+        self.owners.get(&transaction_id).copied()
+    }
+
+    /// the last applied dispute family action of a transaction
+    pub fn outcome(&self, transaction_id: i32) -> Option<AccountActions> {
+        self.edges
+            .iter()
+            .rev()
+            .find(|edge| edge.transaction_id == transaction_id && edge.applied)
+            .map(|edge| edge.action_type)
+    }
+
+    fn nodes(&self) -> (BTreeSet<u16>, BTreeSet<i32>) {
+        let mut clients = BTreeSet::new();
+        let mut transactions = BTreeSet::new();
+        for edge in &self.edges {
+            clients.insert(edge.client_id);
+            transactions.insert(edge.transaction_id);
+            if let Some(owner) = self.owner(edge.transaction_id) {
+                clients.insert(owner);
+            }
+        }
+
+        (clients, transactions)
+    }
+
+    fn outcome_label(&self, transaction_id: i32) -> String {
+        self.outcome(transaction_id)
+            .map(|action| action.to_string())
+            .unwrap_or_else(|| "rejected".to_string())
+    }
+
+    pub fn write_dot<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let (clients, transactions) = self.nodes();
+        writeln!(writer, "digraph disputes {{")?;
+        for client_id in &clients {
+            writeln!(
+                writer,
+                "  \"client_{}\" [shape=box, label=\"client {}\"];",
+                client_id, client_id
+            )?;
+        }
+        for transaction_id in &transactions {
+            writeln!(
+                writer,
+                "  \"tx_{}\" [shape=ellipse, label=\"tx {}\\n{}\"];",
+                transaction_id,
+                transaction_id,
+                self.outcome_label(*transaction_id)
+            )?;
+            if let Some(owner) = self.owner(*transaction_id) {
+                writeln!(
+                    writer,
+                    "  \"client_{}\" -> \"tx_{}\" [label=\"owns\"];",
+                    owner, transaction_id
+                )?;
+            }
+        }
+        for edge in &self.edges {
+            let style = if edge.applied { "solid" } else { "dashed" };
+            writeln!(
+                writer,
+                "  \"client_{}\" -> \"tx_{}\" [label=\"{}\", style={}];",
+                edge.client_id, edge.transaction_id, edge.action_type, style
+            )?;
+        }
+        writeln!(writer, "}}")
+    }
+
+    pub fn write_graphml<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let (clients, transactions) = self.nodes();
+        writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(
+            writer,
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">"
+        )?;
+        writeln!(
+            writer,
+            "  <key id=\"kind\" for=\"node\" attr.name=\"kind\" attr.type=\"string\"/>"
+        )?;
+        writeln!(
+            writer,
+            "  <key id=\"outcome\" for=\"node\" attr.name=\"outcome\" attr.type=\"string\"/>"
+        )?;
+        writeln!(
+            writer,
+            "  <key id=\"action\" for=\"edge\" attr.name=\"action\" attr.type=\"string\"/>"
+        )?;
+        writeln!(
+            writer,
+            "  <key id=\"applied\" for=\"edge\" attr.name=\"applied\" attr.type=\"boolean\"/>"
+        )?;
+        writeln!(writer, "  <graph id=\"disputes\" edgedefault=\"directed\">")?;
+        for client_id in &clients {
+            writeln!(
+                writer,
+                "    <node id=\"client_{}\"><data key=\"kind\">client</data></node>",
+                client_id
+            )?;
+        }
+        for transaction_id in &transactions {
+            writeln!(
+                writer,
+                "    <node id=\"tx_{}\"><data key=\"kind\">transaction</data><data key=\"outcome\">{}</data></node>",
+                transaction_id,
+                self.outcome_label(*transaction_id)
+            )?;
+            if let Some(owner) = self.owner(*transaction_id) {
+                writeln!(
+                    writer,
+                    "    <edge source=\"client_{}\" target=\"tx_{}\"><data key=\"action\">owns</data></edge>",
+                    owner, transaction_id
+                )?;
+            }
+        }
+        for edge in &self.edges {
+            writeln!(
+                writer,
+                "    <edge source=\"client_{}\" target=\"tx_{}\"><data key=\"action\">{}</data><data key=\"applied\">{}</data></edge>",
+                edge.client_id, edge.transaction_id, edge.action_type, edge.applied
+            )?;
+        }
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")
+    }
+
+    pub fn export(&self, path: &str, format: GraphFormat) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match format {
+            GraphFormat::Dot => self.write_dot(&mut writer)?,
+            GraphFormat::GraphMl => self.write_graphml(&mut writer)?,
+        }
+
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::graph::DisputeGraph;
+    use crate::{AccountActions, AccountEvent};
+
+    fn event(action_type: AccountActions, client_id: u16, transaction_id: i32) -> AccountEvent {
+        AccountEvent {
+            transaction_id,
+            action_type,
+            client_id,
+            amount: None,
+        }
+    }
+
+    fn graph() -> DisputeGraph {
+        let mut graph = DisputeGraph::default();
+        graph.register_transaction(&event(AccountActions::Deposit, 1, 1));
+        graph.register_transaction(&event(AccountActions::Deposit, 1, 2));
+        graph.record_dispute(&event(AccountActions::Dispute, 1, 1), true);
+        graph.record_dispute(&event(AccountActions::ChargeBack, 1, 1), true);
+        graph.record_dispute(&event(AccountActions::Dispute, 2, 2), false);
+        graph
+    }
+
+    #[test]
+    fn outcome_is_the_last_applied_action() {
+        let graph = graph();
+
+        assert_eq!(graph.outcome(1), Some(AccountActions::ChargeBack));
+        assert_eq!(graph.outcome(2), None);
+        assert_eq!(graph.owner(2), Some(1));
+    }
+
+    #[test]
+    fn dot_export() {
+        let mut output = vec![];
+        graph().write_dot(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.starts_with("digraph disputes {"));
+        assert!(output.contains("\"tx_1\" [shape=ellipse, label=\"tx 1\\nchargeback\"];"));
+        assert!(output.contains("\"client_1\" -> \"tx_2\" [label=\"owns\"];"));
+        assert!(output.contains("\"client_2\" -> \"tx_2\" [label=\"dispute\", style=dashed];"));
+        assert!(output.trim_end().ends_with('}'));
+    }
+
+    #[test]
+    fn graphml_export() {
+        let mut output = vec![];
+        graph().write_graphml(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("<node id=\"client_2\">"));
+        assert!(output.contains("<data key=\"outcome\">rejected</data>"));
+        assert!(output.trim_end().ends_with("</graphml>"));
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::binary::write_csv_events;
+use crate::money::RoundingMode;
+use crate::{AccountActions, AccountEvent};
+
+/// action types that are left out of a run, e.g. while the upstream chargeback feed is known to
+/// be broken. the events can be kept and written in the input format to replay them later
+#[derive(Debug, Clone, Default)]
+pub struct Holdback {
+    pub actions: Vec<AccountActions>,
+    // only kept if they are written to a holdback file
+    pub events: Option<Vec<AccountEvent>>,
+}
+
+impl Holdback {
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// chargeback or chargeback,resolve, only the built-in actions
+    pub fn add_actions(&mut self, list: &str) -> Result<(), String> {
+        for action in list.split(',').map(str::trim) {
+            let action_type: AccountActions = action.parse()?;
+            if !self.actions.contains(&action_type) {
+                self.actions.push(action_type);
+            }
+        }
+        Ok(())
+    }
+
+    /// true if the event is not processed in this run
+    pub fn holds_back(&mut self, event: &AccountEvent) -> bool {
+        if !self.actions.contains(&event.action_type) {
+            return false;
+        }
+
+        if let Some(events) = self.events.as_mut() {
+            events.push(*event);
+        }
+        true
+    }
+
+    pub fn export(&self, path: &str, rounding: RoundingMode) -> Result<u64, String> {
+        let file = File::create(path).map_err(|e| format!("cannot create {}: {}", path, e))?;
+        let events = self.events.iter().flatten().copied().map(Ok);
+        write_csv_events(BufWriter::new(file), events, rounding)
+    }
+}
+
+impl Display for Holdback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let actions: Vec<String> = self.actions.iter().map(AccountActions::to_string).collect();
+        write!(f, "{}", actions.join(","))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::holdback::Holdback;
+    use crate::{AccountActions, AccountEvent};
+
+    #[test]
+    fn held_back_events() {
+        let mut holdback = Holdback::default();
+        holdback.add_actions("chargeback, resolve").unwrap();
+        holdback.add_actions("chargeback").unwrap();
+        assert!(holdback.add_actions("refund").is_err());
+        assert_eq!(holdback.to_string(), "chargeback,resolve");
+
+        let event = |action_type| AccountEvent {
+            transaction_id: 1,
+            action_type,
+            client_id: 1,
+            amount: None,
+        };
+        assert!(holdback.holds_back(&event(AccountActions::ChargeBack)));
+        assert!(holdback.events.is_none(), "only kept for a holdback file");
+
+        holdback.events = Some(vec![]);
+        assert!(holdback.holds_back(&event(AccountActions::Resolve)));
+        assert!(!holdback.holds_back(&event(AccountActions::Dispute)));
+        assert_eq!(holdback.events, Some(vec![event(AccountActions::Resolve)]));
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+
+/// only the rows after `offset` of an append only csv, the header of the file is put in front
+/// so the csv reader can still map the columns.
+///
+/// a writer could be in the middle of appending a row, so we stop at the last complete line
+/// and return that position as the offset for the next run
+pub fn open_from_offset(path: &str, offset: u64) -> std::io::Result<(impl Read, u64)> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+    if offset > length {
+        return Err(invalid(format!(
+            "offset {} is behind the end of {} ({} bytes)",
+            offset, path, length
+        )));
+    }
+
+    let mut header = String::new();
+    BufReader::new(&mut file).read_line(&mut header)?;
+    let header_end = header.len() as u64;
+    // 0 means from the beginning, the header is read as part of the rows then
+    let start = if offset == 0 { header_end } else { offset };
+    if start < header_end {
+        return Err(invalid(format!("offset {} is inside the header", offset)));
+    }
+
+    if start > header_end {
+        file.seek(SeekFrom::Start(start - 1))?;
+        let mut previous = [0u8; 1];
+        file.read_exact(&mut previous)?;
+        if previous[0] != b'\n' {
+            return Err(invalid(format!(
+                "offset {} is not at a row boundary",
+                offset
+            )));
+        }
+    }
+
+    let end = last_line_end(&mut file, start, length)?;
+    file.seek(SeekFrom::Start(start))?;
+
+    let rows = file.take(end - start);
+    Ok((Cursor::new(header.into_bytes()).chain(rows), end))
+}
+
+/// position after the last newline between start and length
+fn last_line_end(file: &mut File, start: u64, length: u64) -> std::io::Result<u64> {
+    const CHUNK: u64 = 4096;
+    let mut position = length;
+
+    while position > start {
+        let chunk_start = position.saturating_sub(CHUNK).max(start);
+        let mut buffer = vec![0u8; (position - chunk_start) as usize];
+        file.seek(SeekFrom::Start(chunk_start))?;
+        file.read_exact(&mut buffer)?;
+
+        if let Some(index) = buffer.iter().rposition(|byte| *byte == b'\n') {
+            return Ok(chunk_start + index as u64 + 1);
+        }
+        position = chunk_start;
+    }
+
+    Ok(start)
+}
+
+/// the sidecar file only contains the offset as text, a missing file means from the beginning
+pub fn read_offset_file(path: &str) -> Result<u64, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => content
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid offset in {}: {}", path, content.trim())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(format!("cannot read offset file {}: {}", path, e)),
+    }
+}
+
+pub fn write_offset_file(path: &str, offset: u64) -> std::io::Result<()> {
+    fs::write(path, format!("{}\n", offset))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::incremental::{open_from_offset, read_offset_file, write_offset_file};
+    use std::fs;
+    use std::io::Read;
+
+    fn read_all(path: &str, offset: u64) -> (String, u64) {
+        let (mut reader, end) = open_from_offset(path, offset).unwrap();
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        (content, end)
+    }
+
+    #[test]
+    fn only_new_rows_with_header() {
+        let path = std::env::temp_dir().join("kraken_test_incremental.csv");
+        let path = path.to_str().unwrap();
+        let header = "type,client,tx,amount\n";
+        let first = "deposit,1,1,1.0\n";
+        fs::write(path, format!("{}{}", header, first)).unwrap();
+
+        let (content, end) = read_all(path, 0);
+        assert_eq!(content, format!("{}{}", header, first));
+        assert_eq!(end, (header.len() + first.len()) as u64);
+
+        // a half written row is not processed yet
+        fs::write(
+            path,
+            format!("{}{}deposit,1,2,2.0\ndeposit,1", header, first),
+        )
+        .unwrap();
+        let (content, next_end) = read_all(path, end);
+        assert_eq!(content, format!("{}deposit,1,2,2.0\n", header));
+        assert_eq!(next_end, end + 16);
+
+        assert!(open_from_offset(path, 3).is_err(), "inside the header");
+        assert!(open_from_offset(path, end + 1).is_err(), "not a boundary");
+        assert!(open_from_offset(path, 10_000).is_err(), "behind the end");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn offset_file() {
+        let path = std::env::temp_dir().join("kraken_test_offset");
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        assert_eq!(read_offset_file(path), Ok(0));
+        write_offset_file(path, 42).unwrap();
+        assert_eq!(read_offset_file(path), Ok(42));
+        fs::remove_file(path).unwrap();
+    }
+}
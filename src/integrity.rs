@@ -0,0 +1,135 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::hashing::Sha256;
+use crate::{AccountProcessing, ClientAccount};
+
+/// lets the loaders of our files verify that a transfer is complete
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OutputIntegrity {
+    // a last line #rows=2,sha256=... over everything before it
+    Trailer,
+    // accounts-00.csv.sha256 next to the file in the format of sha256sum
+    Sidecar,
+}
+
+impl FromStr for OutputIntegrity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trailer" => Ok(OutputIntegrity::Trailer),
+            "sidecar" => Ok(OutputIntegrity::Sidecar),
+            _ => Err(format!(
+                "unknown output integrity: {} (trailer, sidecar)",
+                s
+            )),
+        }
+    }
+}
+
+/// hashes everything that goes through it
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChecksumWriter {
+            inner,
+            hasher: Sha256::default(),
+        }
+    }
+
+    pub fn finish(self) -> (W, String) {
+        (self.inner, self.hasher.finish())
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// the rows are the accounts, the header is not counted.
+/// a trailer is appended for `Trailer`, the digest is returned in any case
+pub fn write_accounts_with<W: Write>(
+    processing: &AccountProcessing,
+    writer: &mut W,
+    accounts: &[&ClientAccount],
+    integrity: OutputIntegrity,
+) -> std::io::Result<String> {
+    let mut checked = ChecksumWriter::new(&mut *writer);
+    processing.write_accounts(&mut checked, accounts.iter().copied())?;
+    let (_, digest) = checked.finish();
+
+    if integrity == OutputIntegrity::Trailer {
+        writeln!(writer, "#rows={},sha256={}", accounts.len(), digest)?;
+    }
+    writer.flush()?;
+
+    Ok(digest)
+}
+
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".sha256");
+    path.with_file_name(file_name)
+}
+
+/// `sha256sum -c accounts-00.csv.sha256` works from the directory of the file
+pub fn write_sidecar(path: &Path, digest: &str) -> std::io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut sidecar = File::create(sidecar_path(path))?;
+    writeln!(sidecar, "{}  {}", digest, file_name)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::hashing::Sha256;
+    use crate::integrity::{sidecar_path, write_accounts_with, OutputIntegrity};
+    use crate::{AccountProcessing, Config};
+    use std::path::Path;
+
+    #[test]
+    fn trailer_covers_everything_before_it() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader("type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\n".as_bytes());
+        let accounts: Vec<_> = app.accounts_iter().collect();
+
+        let mut output = vec![];
+        let digest =
+            write_accounts_with(&app, &mut output, &accounts, OutputIntegrity::Trailer).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let (report, trailer) = output.rsplit_once('#').unwrap();
+
+        let mut hasher = Sha256::default();
+        hasher.update(report.as_bytes());
+        assert_eq!(hasher.finish(), digest);
+        assert_eq!(trailer, format!("rows=2,sha256={}\n", digest));
+        assert_eq!(report.lines().count(), 3);
+
+        let mut plain = vec![];
+        write_accounts_with(&app, &mut plain, &accounts, OutputIntegrity::Sidecar).unwrap();
+        assert_eq!(plain, report.as_bytes());
+    }
+
+    #[test]
+    fn sidecar_next_to_the_file() {
+        assert_eq!(
+            sidecar_path(Path::new("out/accounts-00.csv")),
+            Path::new("out/accounts-00.csv.sha256")
+        );
+        assert!("md5".parse::<OutputIntegrity>().is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use std::fmt::{Display, Formatter};
+
+use crate::money::Money;
+use crate::redact::Logged;
+use crate::store::{AccountStore, TransactionStore};
+use crate::{AccountActions, AccountEvent, AccountProcessing, DisputePolicy};
+
+/// development aid for new action types, checked after every applied event
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InvariantMode {
+    // stop on the first violation, ingest returns it with the event and the state around it
+    Abort,
+    // log every violation and count it, the processing continues
+    Report,
+}
+
+/// held cannot go below zero since Money is unsigned, the invariants cover what can still
+/// break: an overflowing total or sum of holds, available below zero without the policy
+/// allowing it and holds that do not match the open disputes
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InvariantViolation {
+    pub invariant: &'static str,
+    pub event: AccountEvent,
+    pub context: String,
+    // the event is masked in the message like the context, see Config::redact_amounts
+    pub redact_amounts: bool,
+}
+
+impl Display for InvariantViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invariant {} violated after {}: {}",
+            self.invariant,
+            self.event.logged(self.redact_amounts),
+            self.context
+        )
+    }
+}
+
+/// summed with checked additions, a corrupt state must be reported instead of wrapping
+fn checked_sum(amounts: impl IntoIterator<Item = Money>) -> Option<Money> {
+    amounts
+        .into_iter()
+        .try_fold(Money::ZERO, |sum, amount| sum.checked_add(amount))
+}
+
+/// the holds of all clients and of the open disputes, or the violation if they differ
+fn compare_holds<A: AccountStore, T: TransactionStore>(
+    processing: &AccountProcessing<A, T>,
+) -> Option<(&'static str, String)> {
+    let held = checked_sum(processing.accounts_iter().map(|account| account.held));
+    let open_disputes = processing.open_disputes_iter().count();
+    let disputed = checked_sum(processing.open_disputes_iter().map(|(_, held)| held));
+    match (held, disputed) {
+        (Some(held), Some(disputed)) if held == disputed => None,
+        (Some(held), Some(disputed)) => Some((
+            "held_matches_open_disputes",
+            format!(
+                "{} held by all clients but {} in {} open disputes",
+                held, disputed, open_disputes
+            ),
+        )),
+        (None, _) => Some((
+            "held_representable",
+            "the held amounts of all clients overflow".to_string(),
+        )),
+        (_, None) => Some((
+            "held_representable",
+            format!(
+                "the held amounts of {} open disputes overflow",
+                open_disputes
+            ),
+        )),
+    }
+}
+
+/// the account of the event, its tx and for the dispute family the holds of all clients.
+/// the open disputes are not kept per client so that last one is a scan over all accounts
+pub fn check<A: AccountStore, T: TransactionStore>(
+    processing: &AccountProcessing<A, T>,
+    event: &AccountEvent,
+) -> Result<(), InvariantViolation> {
+    // the context is made of balances, with --redact-amounts none of it is shown
+    let redact_amounts = processing.config.redact_amounts;
+    let violation = |invariant, context: String| {
+        Err(InvariantViolation {
+            invariant,
+            event: *event,
+            context: Logged(context, redact_amounts).to_string(),
+            redact_amounts,
+        })
+    };
+
+    if let Some(account) = processing.account(event.client_id) {
+        if account.total().is_none() {
+            return violation("total_representable", format!("{:?}", account));
+        }
+        if account.available.is_negative()
+            && processing.config.dispute_policy != DisputePolicy::AllowNegative
+        {
+            return violation(
+                "available_not_negative",
+                format!("{:?} with {}", account, processing.config.dispute_policy),
+            );
+        }
+    }
+
+    let transaction = processing.transaction(event.transaction_id);
+    let open_dispute = processing.open_dispute(event.transaction_id);
+    if let (Some(transaction), Some(held)) = (transaction, open_dispute) {
+        if held > transaction.amount {
+            return violation(
+                "dispute_within_transaction",
+                format!("{} held for {:?}", held, transaction),
+            );
+        }
+    }
+
+    if AccountProcessing::event_needs_transaction_lookup(event.action_type)
+        || matches!(event.action_type, AccountActions::Custom(_))
+    {
+        if let Some((invariant, context)) = compare_holds(processing) {
+            return violation(invariant, context);
+        }
+    }
+
+    Ok(())
+}
+
+/// the invariants over the whole state instead of around one event, e.g. for a state that was
+/// restored before anything is processed with it. every violation is one line of the diagnosis
+pub fn check_state<A: AccountStore, T: TransactionStore>(
+    processing: &AccountProcessing<A, T>,
+) -> Vec<String> {
+    let mut violations = vec![];
+    let mut violation = |invariant: &str, context: String| {
+        violations.push(format!(
+            "invariant {} violated: {}",
+            invariant,
+            Logged(context, processing.config.redact_amounts)
+        ));
+    };
+
+    for account in processing.accounts_iter() {
+        if account.total().is_none() {
+            violation("total_representable", format!("{:?}", account));
+        }
+        if account.available.is_negative()
+            && processing.config.dispute_policy != DisputePolicy::AllowNegative
+        {
+            violation(
+                "available_not_negative",
+                format!("{:?} with {}", account, processing.config.dispute_policy),
+            );
+        }
+    }
+
+    for (transaction_id, held) in processing.open_disputes_iter() {
+        match processing.transaction(transaction_id) {
+            Some(transaction) if held > transaction.amount => violation(
+                "dispute_within_transaction",
+                format!("{} held for {:?}", held, transaction),
+            ),
+            Some(_) => {}
+            None => violation(
+                "dispute_has_transaction",
+                format!("{} held for the unknown tx {}", held, transaction_id),
+            ),
+        }
+    }
+
+    if let Some((invariant, context)) = compare_holds(processing) {
+        violation(invariant, context);
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod test {
+    use crate::invariants::{check, check_state};
+    use crate::money::{Money, SignedMoney};
+    use crate::{AccountActions, AccountEvent, AccountProcessing, ClientAccount, Config};
+
+    fn event(action_type: AccountActions) -> AccountEvent {
+        AccountEvent {
+            transaction_id: 1,
+            action_type,
+            client_id: 1,
+            amount: None,
+        }
+    }
+
+    #[test]
+    fn consistent_state() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader("type,client,tx,amount\ndeposit,1,1,2.0\ndispute,1,1,\n".as_bytes());
+
+        assert!(check(&app, &event(AccountActions::Dispute)).is_ok());
+    }
+
+    #[test]
+    fn violations() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.accounts
+            .insert(1, ClientAccount::new(1, SignedMoney::from_minor_units(-1)));
+        let violation = check(&app, &event(AccountActions::Withdrawal)).unwrap_err();
+        assert_eq!(violation.invariant, "available_not_negative");
+
+        app.accounts
+            .insert(1, ClientAccount::new(1, SignedMoney::from_minor_units(10)));
+        app.accounts.get_mut(&1).unwrap().held = Money::from_minor_units(5);
+        let violation = check(&app, &event(AccountActions::Resolve)).unwrap_err();
+        assert_eq!(violation.invariant, "held_matches_open_disputes");
+        assert!(violation
+            .to_string()
+            .contains("0.0005 held by all clients but 0.0000"));
+    }
+
+    #[test]
+    fn whole_state() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader("type,client,tx,amount\ndeposit,1,1,2.0\ndispute,1,1,\n".as_bytes());
+        assert!(check_state(&app).is_empty());
+
+        app.accounts
+            .insert(2, ClientAccount::new(2, SignedMoney::from_minor_units(-1)));
+        app.open_disputes.insert(7, Money::from_minor_units(3));
+        let violations = check_state(&app);
+        assert_eq!(violations.len(), 3);
+        assert!(violations[0].starts_with("invariant available_not_negative violated: "));
+        assert_eq!(
+            violations[1],
+            "invariant dispute_has_transaction violated: 0.0003 held for the unknown tx 7"
+        );
+        assert!(violations[2].contains("2.0000 held by all clients but 2.0003"));
+    }
+
+    #[test]
+    fn overflowing_holds_are_violations() {
+        let mut app = AccountProcessing::new(Config::default());
+        for client_id in [1, 2] {
+            let mut account = ClientAccount::new(client_id, SignedMoney::ZERO);
+            account.held = Money::MAX;
+            app.accounts.insert(client_id, account);
+        }
+        let violations = check_state(&app);
+        assert!(violations
+            .iter()
+            .any(|violation| violation.starts_with("invariant held_representable violated: ")));
+
+        app.accounts.clear();
+        app.open_disputes.insert(1, Money::MAX);
+        app.open_disputes.insert(2, Money::MAX);
+        let violation = check(&app, &event(AccountActions::Dispute)).unwrap_err();
+        assert_eq!(violation.invariant, "held_representable");
+    }
+}
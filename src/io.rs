@@ -0,0 +1,167 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use log::debug;
+
+use serde::Deserialize;
+
+use crate::model::AccountActions;
+
+/// the columns of the input in the order of the specification
+pub const COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// the columns of the second version, when and in which currency the event happened
+pub const COLUMNS_V2: [&str; 6] = ["type", "client", "tx", "amount", "timestamp", "currency"];
+
+/// an id of the producer that is carried into the logs and the audit lines of its event
+pub const CORRELATION: &str = "correlation";
+
+/// the version of a single row, for files that mix the vintages of a migration
+pub const SCHEMA_VERSION: &str = "schema_version";
+
+/// which columns a row of the input has. the fields that v2 adds are validated but the engine
+/// does not use them yet
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum SchemaVersion {
+    #[default]
+    V1,
+    V2,
+}
+
+impl SchemaVersion {
+    pub fn columns(&self) -> &'static [&'static str] {
+        match self {
+            SchemaVersion::V1 => &COLUMNS,
+            SchemaVersion::V2 => &COLUMNS_V2,
+        }
+    }
+
+    /// without a configured version a header with any of the v2 columns is v2
+    pub fn detect(headers: &csv::StringRecord) -> Self {
+        match headers
+            .iter()
+            .any(|header| COLUMNS_V2[4..].contains(&header))
+        {
+            true => SchemaVersion::V2,
+            false => SchemaVersion::V1,
+        }
+    }
+
+    /// the reason why the fields this version adds are invalid
+    pub fn check_fields(&self, timestamp: &str, currency: &str) -> Result<(), String> {
+        if *self == SchemaVersion::V1 {
+            return Ok(());
+        }
+        if timestamp.is_empty() || !timestamp.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(format!(
+                "a v2 row needs the timestamp in unix seconds: {:?}",
+                timestamp
+            ));
+        }
+        if currency.len() != 3 || !currency.bytes().all(|byte| byte.is_ascii_uppercase()) {
+            return Err(format!(
+                "a v2 row needs the iso 4217 code of the currency: {:?}",
+                currency
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Display for SchemaVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaVersion::V1 => write!(f, "1"),
+            SchemaVersion::V2 => write!(f, "2"),
+        }
+    }
+}
+
+impl FromStr for SchemaVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1" | "v1" => Ok(SchemaVersion::V1),
+            "2" | "v2" => Ok(SchemaVersion::V2),
+            _ => Err(format!("unknown schema version: {} (1, 2)", s)),
+        }
+    }
+}
+
+/// how closely the header of an input has to follow the specification
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum SchemaMode {
+    // columns are matched by name, their order does not matter and unknown ones are ignored
+    #[default]
+    Flexible,
+    // the header has to be exactly the specified columns, otherwise nothing of the file is read
+    Strict,
+}
+
+impl SchemaMode {
+    /// the reason why the header does not fit, without a version the header can be any of them
+    pub fn check(
+        &self,
+        headers: &csv::StringRecord,
+        version: Option<SchemaVersion>,
+    ) -> Result<(), String> {
+        if *self == SchemaMode::Flexible {
+            let unknown: Vec<&str> = headers
+                .iter()
+                .filter(|header| {
+                    !COLUMNS_V2.contains(header) && ![CORRELATION, SCHEMA_VERSION].contains(header)
+                })
+                .collect();
+            if !unknown.is_empty() {
+                debug!("unknown columns are ignored: {:?}", unknown);
+            }
+            return Ok(());
+        }
+
+        // the only columns that may follow the specified ones, in this order
+        let mut names: Vec<&str> = headers.iter().collect();
+        if names.last() == Some(&CORRELATION) {
+            names.pop();
+        }
+        if names.last() == Some(&SCHEMA_VERSION) {
+            names.pop();
+        }
+        let fits = match version {
+            Some(version) => names == version.columns(),
+            None => names == COLUMNS || names == COLUMNS_V2,
+        };
+        match fits {
+            true => Ok(()),
+            false => Err(format!(
+                "the header {:?} is not the expected {:?}",
+                headers.iter().collect::<Vec<_>>(),
+                version.unwrap_or_default().columns()
+            )),
+        }
+    }
+}
+
+impl FromStr for SchemaMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flexible" => Ok(SchemaMode::Flexible),
+            "strict" => Ok(SchemaMode::Strict),
+            _ => Err(format!("unknown schema mode: {} (strict, flexible)", s)),
+        }
+    }
+}
+
+/// the type is read as a string first so registered custom actions can be resolved. the
+/// amount stays the decimal string, it is parsed exactly when the record becomes an event
+#[derive(Debug, Deserialize)]
+pub struct CsvRecord<T = AccountActions> {
+    pub r#type: T,
+    pub client: u16,
+    pub tx: i32,
+    pub amount: Option<String>,
+    #[serde(default)]
+    pub correlation: Option<String>,
+}
@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Duration;
+
+use crate::AccountEvent;
+
+/// bucket i counts events that took less than 2^i microseconds, the last one everything above
+pub const BUCKETS: usize = 24;
+
+/// how long applying single events took, the buckets are powers of two so a stall of the
+/// storage or a pathological client stands out without keeping every measurement
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    // always BUCKETS long, on the heap to keep the processing itself small
+    pub buckets: Vec<u64>,
+    // events above this are logged with their type and client
+    pub slow_threshold: Option<Duration>,
+    pub slow_events: u64,
+}
+
+impl LatencyHistogram {
+    pub fn new(slow_threshold: Option<Duration>) -> Self {
+        LatencyHistogram {
+            buckets: vec![0; BUCKETS],
+            slow_threshold,
+            slow_events: 0,
+        }
+    }
+
+    pub fn bucket_of(elapsed: Duration) -> usize {
+        let micros = elapsed.as_micros();
+        // 0 -> 0, 1 -> 1, 2..3 -> 2, 4..7 -> 3 ...
+        let bucket = (u128::BITS - micros.leading_zeros()) as usize;
+        bucket.min(BUCKETS - 1)
+    }
+
+    pub fn record(&mut self, event: &AccountEvent, elapsed: Duration) {
+        self.buckets[Self::bucket_of(elapsed)] += 1;
+
+        if let Some(threshold) = self.slow_threshold {
+            if elapsed > threshold {
+                self.slow_events += 1;
+                warn!(
+                    "slow event: {} client_id: {} took {}us",
+                    event.action_type,
+                    event.client_id,
+                    elapsed.as_micros()
+                );
+            }
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// one row per bucket with its upper bound, the last bucket has no bound
+    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["below_us", "count"])?;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            let below = if bucket == BUCKETS - 1 {
+                String::new()
+            } else {
+                (1u64 << bucket).to_string()
+            };
+            writer.write_record([below, count.to_string()])?;
+        }
+
+        writer.flush()
+    }
+
+    pub fn export(&self, path: &str) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::latency::{LatencyHistogram, BUCKETS};
+    use crate::{AccountActions, AccountEvent};
+    use std::time::Duration;
+
+    #[test]
+    fn buckets_are_powers_of_two() {
+        assert_eq!(LatencyHistogram::bucket_of(Duration::ZERO), 0);
+        assert_eq!(LatencyHistogram::bucket_of(Duration::from_micros(1)), 1);
+        assert_eq!(LatencyHistogram::bucket_of(Duration::from_micros(3)), 2);
+        assert_eq!(LatencyHistogram::bucket_of(Duration::from_micros(4)), 3);
+        assert_eq!(
+            LatencyHistogram::bucket_of(Duration::from_secs(3600)),
+            BUCKETS - 1
+        );
+    }
+
+    #[test]
+    fn slow_events_are_counted() {
+        let event = AccountEvent {
+            transaction_id: 1,
+            action_type: AccountActions::Deposit,
+            client_id: 1,
+            amount: None,
+        };
+        let mut histogram = LatencyHistogram::new(Some(Duration::from_millis(1)));
+        histogram.record(&event, Duration::from_micros(10));
+        histogram.record(&event, Duration::from_millis(2));
+
+        assert_eq!(histogram.count(), 2);
+        assert_eq!(histogram.slow_events, 1);
+
+        let mut output = vec![];
+        histogram.write(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("below_us,count\n1,0\n2,0\n4,0\n8,0\n16,1\n"));
+        assert_eq!(output.lines().count(), BUCKETS + 1);
+    }
+}
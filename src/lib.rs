@@ -0,0 +1,109 @@
+//! a payments engine that applies deposits, withdrawals and the dispute family to client
+//! accounts. `AccountProcessing` is the engine, it is fed csv readers, single events or
+//! batches and keeps the accounts, the disputable transactions and the open disputes.
+//! `run` is everything the binary does around it, parsing the arguments, the reports and the
+//! subcommands.
+//!
+//! ```no_run
+//! use kraken_test::{AccountActions, AccountEvent, AccountProcessing, Config};
+//! use kraken_test::money::{Money, SignedMoney};
+//!
+//! let mut engine = AccountProcessing::new(Config::default());
+//! engine.process_events([AccountEvent {
+//!     transaction_id: 1,
+//!     action_type: AccountActions::Deposit,
+//!     client_id: 1,
+//!     amount: Some(Money::from_minor_units(15000)),
+//! }]);
+//! assert_eq!(
+//!     engine.account(1).unwrap().available,
+//!     SignedMoney::from_minor_units(15000)
+//! );
+//! ```
+//!
+//! after the processing the state is read through the accessors instead of the output,
+//! `account`, `accounts_iter`, `transaction`, `transactions_for`, `open_dispute` and
+//! `open_disputes_iter`. `summary` has the counts of the run.
+//!
+//! ```no_run
+//! # use kraken_test::{AccountProcessing, Config};
+//! let mut engine = AccountProcessing::new(Config::default());
+//! engine.process_reader("type,client,tx,amount\ndeposit,1,1,1.5\n".as_bytes());
+//!
+//! for account in engine.accounts_iter() {
+//!     println!("{} {} {}", account.id, account.available, account.locked);
+//! }
+//! assert_eq!(engine.transaction(1).map(|tx| tx.client_id), Some(1));
+//! assert_eq!(engine.summary.processed, 1);
+//! ```
+extern crate csv;
+#[macro_use]
+extern crate log;
+extern crate serde;
+
+pub mod actions;
+pub mod admin;
+pub mod analytics;
+pub mod audit;
+pub mod avro;
+pub mod binary;
+pub mod build_info;
+pub(crate) mod cli;
+pub mod clock;
+pub mod compaction;
+pub mod compliance;
+pub mod encoding;
+pub mod engine;
+#[cfg(any(test, feature = "events"))]
+pub mod events;
+pub mod graph;
+pub mod hashing;
+pub mod holdback;
+pub mod incremental;
+pub mod integrity;
+pub mod invariants;
+pub mod io;
+pub mod latency;
+pub mod memory;
+pub mod metadata;
+pub mod metrics;
+pub mod model;
+pub mod money;
+pub mod opening;
+pub mod partition;
+pub mod policy;
+pub mod proto;
+pub mod quarantine;
+pub mod recent;
+pub mod redact;
+pub mod replay;
+pub mod risk;
+pub mod sampling;
+pub mod schema;
+pub mod shadow;
+pub mod simulation;
+pub mod sink;
+pub mod snapshot;
+pub mod source;
+pub mod state_diff;
+pub mod store;
+pub mod structuring;
+#[cfg(any(test, feature = "testkit"))]
+pub mod testkit;
+#[cfg(unix)]
+pub mod uds;
+
+pub use cli::run;
+pub use engine::{
+    AccountProcessing, AccountProcessingBuilder, Applied, AppliedHook, BatchOutcome, Config,
+    DisputableActions, EngineError, Preview, ProcessingSummary, Rejected, RejectedHook, Subsystems,
+};
+pub use io::{CsvRecord, SchemaMode, SchemaVersion, COLUMNS};
+pub use model::{
+    AccountActions, AccountCreation, AccountEvent, ClientAccount, DisputeOutcome, DisputePolicy,
+    LoggedEvent, Note, Transaction,
+};
+pub use quarantine::RejectReason;
+pub use sink::{CsvSink, MemorySink, OutputSink};
+pub use source::{CsvSource, EventSource, FanIn, SourceError};
+pub use store::{AccountStore, TransactionStore};
@@ -0,0 +1,139 @@
+use std::fmt::{Display, Formatter};
+
+use crate::metrics::{BUFFER_CAPACITY, BUFFER_LENGTH};
+use crate::AccountProcessing;
+
+/// length vs capacity of one of the buffers that only grow while events are processed.
+/// the ledger maps are btrees, they free their nodes on their own and are not listed
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Gauge {
+    pub name: &'static str,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl Display for Gauge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}/{}", self.name, self.len, self.capacity)
+    }
+}
+
+fn gauge<T>(name: &'static str, buffer: &Vec<T>) -> Gauge {
+    Gauge {
+        name,
+        len: buffer.len(),
+        capacity: buffer.capacity(),
+    }
+}
+
+pub fn gauges(processing: &AccountProcessing) -> Vec<Gauge> {
+    let mut gauges = vec![gauge("violations", &processing.violations)];
+    if let Some(quarantine) = processing.quarantine() {
+        gauges.push(gauge("parse_errors", &quarantine.parse_errors));
+        gauges.push(gauge("policy_rejects", &quarantine.policy_rejects));
+    }
+    if let Some(graph) = processing.dispute_graph() {
+        gauges.push(gauge("dispute_edges", &graph.edges));
+    }
+    if let Some(structuring) = processing.structuring() {
+        gauges.push(gauge("suspicious_activity", &structuring.reports));
+    }
+    gauges
+}
+
+// below this many spare elements a buffer is left alone, and every buffer keeps as much headroom
+// as it is long so the pushes after a shrink stay amortized
+const MIN_SPARE: usize = 1024;
+
+fn shrink_buffer<T>(buffer: &mut Vec<T>) {
+    let spare = buffer.capacity() - buffer.len();
+    if spare > MIN_SPARE && spare > buffer.len() {
+        buffer.shrink_to(buffer.len() * 2);
+    }
+}
+
+/// gives back what a burst left over, for the idle time of a long running listener. only
+/// buffers with a lot more capacity than length are shrunk
+pub fn shrink(processing: &mut AccountProcessing) {
+    shrink_buffer(&mut processing.violations);
+    let Some(subsystems) = processing.subsystems.as_deref_mut() else {
+        return;
+    };
+    if let Some(quarantine) = subsystems.quarantine.as_mut() {
+        shrink_buffer(&mut quarantine.parse_errors);
+        shrink_buffer(&mut quarantine.policy_rejects);
+    }
+    if let Some(graph) = subsystems.dispute_graph.as_mut() {
+        shrink_buffer(&mut graph.edges);
+    }
+    if let Some(structuring) = subsystems.structuring.as_mut() {
+        shrink_buffer(&mut structuring.reports);
+    }
+}
+
+/// the gauges as metrics of the processing, labeled with the buffer
+pub fn record(processing: &AccountProcessing) {
+    if let Some(metrics) = processing.metrics() {
+        for gauge in gauges(processing) {
+            let labels = [("buffer", gauge.name)];
+            metrics.gauge(BUFFER_LENGTH, &labels, gauge.len as i64);
+            metrics.gauge(BUFFER_CAPACITY, &labels, gauge.capacity as i64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory::{gauges, record, shrink};
+    use crate::metrics::{PrometheusMetrics, BUFFER_CAPACITY, BUFFER_LENGTH};
+    use crate::quarantine::Quarantine;
+    use crate::{AccountProcessing, Config};
+    use std::sync::Arc;
+
+    #[test]
+    fn shrink_a_burst() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.subsystems_mut().quarantine = Some(Quarantine::default());
+        for line in 0..5000 {
+            app.parse_error(line, String::new(), "invalid".to_string());
+        }
+        app.quarantine_mut().unwrap().parse_errors.truncate(3);
+        let before = gauges(&app);
+        assert_eq!(before[1].name, "parse_errors");
+        assert!(before[1].capacity >= 5000);
+
+        shrink(&mut app);
+        let after = gauges(&app);
+        assert_eq!(after[1].len, 3);
+        assert_eq!(after[1].capacity, 6);
+        assert_eq!(after[1].to_string(), "parse_errors=3/6");
+    }
+
+    #[test]
+    fn small_buffers_are_not_shrunk() {
+        let mut app = AccountProcessing::new(Config::default());
+        app.subsystems_mut().quarantine = Some(Quarantine::default());
+        for line in 0..100 {
+            app.parse_error(line, String::new(), "invalid".to_string());
+        }
+        app.quarantine_mut().unwrap().parse_errors.truncate(3);
+        let before = gauges(&app)[1].capacity;
+
+        shrink(&mut app);
+        assert_eq!(gauges(&app)[1].capacity, before);
+    }
+
+    #[test]
+    fn gauges_are_recorded_as_metrics() {
+        let metrics = Arc::new(PrometheusMetrics::default());
+        let mut app = AccountProcessing::new(Config::default());
+        app.subsystems_mut().metrics = Some(metrics.clone());
+        app.subsystems_mut().quarantine = Some(Quarantine::default());
+        app.parse_error(1, String::new(), "invalid".to_string());
+
+        record(&app);
+        let labels = [("buffer", "parse_errors")];
+        assert_eq!(metrics.gauge_value(BUFFER_LENGTH, &labels), Some(1));
+        assert!(metrics.gauge_value(BUFFER_CAPACITY, &labels).unwrap() >= 1);
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+
+use serde::Deserialize;
+
+/// reference data about a client that is not part of the transaction stream
+/// it's joined into the output and used by the rule evaluation
+#[derive(Debug, Clone, Deserialize, Eq, PartialEq)]
+pub struct ClientMetadata {
+    pub client_id: u16,
+    pub name: String,
+    // ISO 3166 alpha 2 code
+    pub country: String,
+    // 0 - 100 the higher the riskier
+    pub risk_score: u8,
+}
+
+/// the metadata is keyed like the accounts so we can just look it up next to them
+pub fn load_client_metadata(path: &str) -> Result<BTreeMap<u16, ClientMetadata>, String> {
+    let file = File::open(path).map_err(|e| format!("cannot open clients file {}: {}", path, e))?;
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(BufReader::new(file));
+
+    let mut metadata = BTreeMap::new();
+    for result in rdr.deserialize() {
+        let record: ClientMetadata =
+            result.map_err(|e| format!("invalid clients file {}: {}", path, e))?;
+        metadata.insert(record.client_id, record);
+    }
+
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::metadata::load_client_metadata;
+    use std::fs;
+
+    #[test]
+    fn load_clients_file() {
+        let path = std::env::temp_dir().join("kraken_test_clients.csv");
+        fs::write(
+            &path,
+            "client_id,name,country,risk_score\n1,Jane Doe,AT,12\n2, John Doe ,KP,95\n",
+        )
+        .unwrap();
+
+        let metadata = load_client_metadata(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata[&1].country, "AT");
+        assert_eq!(metadata[&2].name, "John Doe");
+        assert_eq!(metadata[&2].risk_score, 95);
+    }
+
+    #[test]
+    fn missing_clients_file() {
+        assert!(load_client_metadata("/does/not/exist.csv").is_err());
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use crate::quarantine::RejectReason;
+
+pub const EVENTS_APPLIED: &str = "events_applied_total";
+pub const EVENTS_REJECTED: &str = "events_rejected_total";
+pub const APPLY_SECONDS: &str = "apply_seconds";
+pub const ACCOUNTS: &str = "accounts";
+pub const OPEN_DISPUTES: &str = "open_disputes";
+// only for the events of a fan-in, labeled with the source and the outcome
+pub const SOURCE_EVENTS: &str = "source_events_total";
+// unix seconds when the outputs of a batch run were written, alerts on runs that stopped
+pub const RUN_FINISHED: &str = "run_finished_timestamp_seconds";
+// the buffers of a long running listener, labeled with the buffer
+pub const BUFFER_LENGTH: &str = "buffer_length";
+pub const BUFFER_CAPACITY: &str = "buffer_capacity";
+
+/// telemetry of the processing, whoever embeds it implements this for their own system. the
+/// sub-engines of a partitioned run share one instance so it has to be safe to call from all
+/// of them at once
+pub trait Metrics: Send + Sync {
+    fn counter(&self, name: &str, labels: &[(&str, &str)], increment: u64);
+    fn gauge(&self, name: &str, labels: &[(&str, &str)], value: i64);
+    fn histogram(&self, name: &str, labels: &[(&str, &str)], value: f64);
+}
+
+/// the rule id without a panic message, the label values have to stay a small set
+pub fn reason_label(reason: &RejectReason) -> String {
+    match reason {
+        RejectReason::Panic(_) => "panic".to_string(),
+        reason => reason.to_string(),
+    }
+}
+
+/// upper bounds of the histogram buckets, in seconds
+pub const BUCKETS: [f64; 7] = [0.00001, 0.0001, 0.001, 0.01, 0.1, 1.0, 10.0];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    // not cumulative, one more than BUCKETS for everything above the last bound
+    buckets: [u64; BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+// name and the rendered labels
+type Series = (String, String);
+
+#[derive(Debug, Default)]
+struct Values {
+    counters: BTreeMap<Series, u64>,
+    gauges: BTreeMap<Series, i64>,
+    histograms: BTreeMap<Series, Histogram>,
+}
+
+/// the built-in implementation, rendered in the prometheus text format for the textfile
+/// collector of the node exporter
+#[derive(Debug, Default)]
+pub struct PrometheusMetrics {
+    values: Mutex<Values>,
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn series(name: &str, labels: &[(&str, &str)]) -> Series {
+    let labels = labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+        .collect::<Vec<_>>()
+        .join(",");
+    (name.to_string(), labels)
+}
+
+fn with_labels(name: &str, labels: &str, extra: &str) -> String {
+    match (labels.is_empty(), extra.is_empty()) {
+        (true, true) => name.to_string(),
+        (false, true) => format!("{}{{{}}}", name, labels),
+        (true, false) => format!("{}{{{}}}", name, extra),
+        (false, false) => format!("{}{{{},{}}}", name, labels, extra),
+    }
+}
+
+fn type_line(out: &mut String, name: &str, kind: &str) {
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+impl PrometheusMetrics {
+    /// a poisoned lock only means another thread panicked while counting, the values stay usable
+    fn values(&self) -> std::sync::MutexGuard<'_, Values> {
+        self.values.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn counter_value(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        let key = series(name, labels);
+        self.values().counters.get(&key).copied().unwrap_or(0)
+    }
+
+    pub fn gauge_value(&self, name: &str, labels: &[(&str, &str)]) -> Option<i64> {
+        let key = series(name, labels);
+        self.values().gauges.get(&key).copied()
+    }
+
+    /// for the textfile collector, it reads every *.prom in its directory at any time. the
+    /// metrics are written next to the path and renamed so it never sees half a file
+    pub fn write_textfile(&self, path: &str) -> std::io::Result<()> {
+        let partial = format!("{}.partial", path);
+        std::fs::write(&partial, self.render())?;
+        std::fs::rename(&partial, path)
+    }
+
+    pub fn render(&self) -> String {
+        let values = self.values();
+        let mut out = String::new();
+        let mut last = "";
+        for ((name, labels), value) in &values.counters {
+            if name != last {
+                type_line(&mut out, name, "counter");
+                last = name;
+            }
+            let _ = writeln!(out, "{} {}", with_labels(name, labels, ""), value);
+        }
+        last = "";
+        for ((name, labels), value) in &values.gauges {
+            if name != last {
+                type_line(&mut out, name, "gauge");
+                last = name;
+            }
+            let _ = writeln!(out, "{} {}", with_labels(name, labels, ""), value);
+        }
+        last = "";
+        for ((name, labels), histogram) in &values.histograms {
+            if name != last {
+                type_line(&mut out, name, "histogram");
+                last = name;
+            }
+            let bucket = format!("{}_bucket", name);
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                let le = format!("le=\"{}\"", bound);
+                let _ = writeln!(out, "{} {}", with_labels(&bucket, labels, &le), cumulative);
+            }
+            let le = "le=\"+Inf\"";
+            let _ = writeln!(
+                out,
+                "{} {}",
+                with_labels(&bucket, labels, le),
+                histogram.count
+            );
+            let sum = format!("{}_sum", name);
+            let _ = writeln!(out, "{} {}", with_labels(&sum, labels, ""), histogram.sum);
+            let count = format!("{}_count", name);
+            let _ = writeln!(
+                out,
+                "{} {}",
+                with_labels(&count, labels, ""),
+                histogram.count
+            );
+        }
+
+        out
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn counter(&self, name: &str, labels: &[(&str, &str)], increment: u64) {
+        *self
+            .values()
+            .counters
+            .entry(series(name, labels))
+            .or_default() += increment;
+    }
+
+    fn gauge(&self, name: &str, labels: &[(&str, &str)], value: i64) {
+        self.values().gauges.insert(series(name, labels), value);
+    }
+
+    fn histogram(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let mut values = self.values();
+        let histogram = values.histograms.entry(series(name, labels)).or_default();
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(BUCKETS.len());
+        histogram.buckets[bucket] += 1;
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::metrics::{Metrics, PrometheusMetrics};
+
+    #[test]
+    fn text_format() {
+        let metrics = PrometheusMetrics::default();
+        metrics.counter("events_total", &[("type", "deposit")], 2);
+        metrics.counter("events_total", &[("type", "deposit")], 1);
+        metrics.counter("events_total", &[("type", "say \"hi\"")], 1);
+        metrics.gauge("accounts", &[], 7);
+        metrics.histogram("apply_seconds", &[], 0.00005);
+        metrics.histogram("apply_seconds", &[], 20.0);
+
+        assert_eq!(
+            metrics.counter_value("events_total", &[("type", "deposit")]),
+            3
+        );
+        assert_eq!(metrics.gauge_value("accounts", &[]), Some(7));
+        assert_eq!(
+            metrics.render(),
+            "# TYPE events_total counter\n\
+             events_total{type=\"deposit\"} 3\n\
+             events_total{type=\"say \\\"hi\\\"\"} 1\n\
+             # TYPE accounts gauge\n\
+             accounts 7\n\
+             # TYPE apply_seconds histogram\n\
+             apply_seconds_bucket{le=\"0.00001\"} 0\n\
+             apply_seconds_bucket{le=\"0.0001\"} 1\n\
+             apply_seconds_bucket{le=\"0.001\"} 1\n\
+             apply_seconds_bucket{le=\"0.01\"} 1\n\
+             apply_seconds_bucket{le=\"0.1\"} 1\n\
+             apply_seconds_bucket{le=\"1\"} 1\n\
+             apply_seconds_bucket{le=\"10\"} 1\n\
+             apply_seconds_bucket{le=\"+Inf\"} 2\n\
+             apply_seconds_sum 20.00005\n\
+             apply_seconds_count 2\n"
+        );
+    }
+
+    #[test]
+    fn textfile_is_replaced_at_once() {
+        let path = std::env::temp_dir().join("kraken_test_metrics.prom");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "# from the last run\n").unwrap();
+
+        let metrics = PrometheusMetrics::default();
+        metrics.gauge("run_finished_timestamp_seconds", &[], 1_700_000_000);
+        metrics.write_textfile(path).unwrap();
+        let written = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(written, metrics.render());
+        assert!(!std::path::Path::new(&format!("{}.partial", path)).exists());
+    }
+}
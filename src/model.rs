@@ -0,0 +1,727 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use log::debug;
+
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::io::CsvRecord;
+use crate::money::{Money, NumberFormat, RoundingMode, SignedMoney};
+use crate::redact::Logged;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountActions {
+    // only positive if the active available is bigger or equal the withdraw
+    Withdrawal,
+    // always possible but can be disputed
+    Deposit,
+    // needs a valid transaction id
+    Dispute,
+    // if the dispute is resolved there is a chargeback
+    ChargeBack,
+    // resolve means that the amount of the transaction is either available for held or not
+    Resolve,
+    // registered on the processing, see AccountProcessing::register_action
+    #[serde(skip)]
+    Custom(u8),
+}
+
+impl FromStr for AccountActions {
+    type Err = String;
+
+    /// only the built-ins, custom actions are resolved by the processing they are registered on
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "withdrawal" => Ok(AccountActions::Withdrawal),
+            "deposit" => Ok(AccountActions::Deposit),
+            "dispute" => Ok(AccountActions::Dispute),
+            "chargeback" => Ok(AccountActions::ChargeBack),
+            "resolve" => Ok(AccountActions::Resolve),
+            _ => Err(format!("unknown action type: {}", s)),
+        }
+    }
+}
+
+impl Display for AccountActions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AccountActions::Withdrawal => "withdrawal",
+            AccountActions::Deposit => "deposit",
+            AccountActions::Dispute => "dispute",
+            AccountActions::ChargeBack => "chargeback",
+            AccountActions::Resolve => "resolve",
+            AccountActions::Custom(id) => return write!(f, "custom-{}", id),
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// what we remember of a deposit or withdrawal so it can be disputed later
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Transaction {
+    pub client_id: u16,
+    pub action_type: AccountActions,
+    pub amount: Money,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct AccountEvent {
+    pub transaction_id: i32,
+    pub action_type: AccountActions,
+    pub client_id: u16,
+    pub amount: Option<Money>,
+}
+
+impl Display for AccountEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.logged(false).fmt(f)
+    }
+}
+
+/// an event as it appears in a log line, the amount is masked if the flag is set
+pub struct LoggedEvent<'e>(pub &'e AccountEvent, pub bool);
+
+impl Display for LoggedEvent<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let event = self.0;
+        let mut amount = String::new();
+        if let Some(value) = event.amount {
+            amount = Logged(value, self.1).to_string();
+        }
+
+        write!(
+            f,
+            "{},{},{},{}",
+            event.action_type, event.client_id, event.transaction_id, amount
+        )
+    }
+}
+
+impl AccountEvent {
+    /// with redact the amount is masked like every other amount in the logs
+    pub fn logged(&self, redact: bool) -> LoggedEvent<'_> {
+        LoggedEvent(self, redact)
+    }
+
+    /// the digits beyond our minor units are rounded with the given mode
+    pub fn from_record(r: CsvRecord, rounding: RoundingMode) -> Result<Self, String> {
+        let amount = match &r.amount {
+            Some(amount) => Some(Money::parse(amount, rounding)?),
+            None => None,
+        };
+        Ok(AccountEvent {
+            transaction_id: r.tx,
+            client_id: r.client,
+            action_type: r.r#type,
+            amount,
+        })
+    }
+}
+
+impl TryFrom<CsvRecord> for AccountEvent {
+    type Error = String;
+
+    fn try_from(r: CsvRecord) -> Result<Self, Self::Error> {
+        AccountEvent::from_record(r, RoundingMode::default())
+    }
+}
+
+/// free text against an account, e.g. the context of an investigation
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Note {
+    pub author: String,
+    // unix seconds of when it was added
+    pub at: u64,
+    pub text: String,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ClientAccount {
+    // the id is also the lookup in the btree
+    pub id: u16,
+    // amount of money available for the client, only negative if the dispute policy allows it
+    pub available: SignedMoney,
+    // amount of money that is held till the dispute is settled
+    pub held: Money,
+    // possible fraud after chargeback
+    pub locked: bool,
+}
+
+/// what happens if a dispute references more than the client has available
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum DisputePolicy {
+    // the full amount is held, available goes below zero
+    AllowNegative,
+    // only what is available is held
+    PartialHold,
+    // nothing is held, the dispute is reported
+    #[default]
+    RejectAndReport,
+}
+
+impl FromStr for DisputePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow-negative" => Ok(DisputePolicy::AllowNegative),
+            "partial-hold" => Ok(DisputePolicy::PartialHold),
+            "reject-and-report" => Ok(DisputePolicy::RejectAndReport),
+            _ => Err(format!(
+                "unknown dispute policy: {} (allow-negative, partial-hold, reject-and-report)",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for DisputePolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DisputePolicy::AllowNegative => "allow-negative",
+            DisputePolicy::PartialHold => "partial-hold",
+            DisputePolicy::RejectAndReport => "reject-and-report",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// which events create the account of a client we have not seen yet
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum AccountCreation {
+    // the other events of an unknown client are rejected and reported
+    #[default]
+    Deposit,
+    // every applied event creates the account
+    Any,
+}
+
+impl AccountCreation {
+    pub fn creates(&self, action_type: AccountActions) -> bool {
+        match self {
+            AccountCreation::Deposit => action_type == AccountActions::Deposit,
+            AccountCreation::Any => true,
+        }
+    }
+}
+
+impl FromStr for AccountCreation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(AccountCreation::Deposit),
+            "any" => Ok(AccountCreation::Any),
+            _ => Err(format!("unknown account creation: {} (deposit, any)", s)),
+        }
+    }
+}
+
+/// the amount that was actually moved to held, it can differ from the requested amount
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DisputeOutcome {
+    Held(Money),
+    // the requested amount exceeded available and the policy decided what happened
+    Exceeded(DisputePolicy, Money),
+}
+
+impl DisputeOutcome {
+    pub fn held(&self) -> Money {
+        match self {
+            DisputeOutcome::Held(amount) | DisputeOutcome::Exceeded(_, amount) => *amount,
+        }
+    }
+}
+
+impl ClientAccount {
+    pub fn new(id: u16, available: SignedMoney) -> Self {
+        ClientAccount {
+            id,
+            available,
+            held: Money::ZERO,
+            locked: false,
+        }
+    }
+
+    pub fn withdraw(&mut self, amount: Money) -> bool {
+        if self.locked {
+            debug!(
+                "client_id: {} cannot withdraw: {} the account is locked",
+                self.id, amount
+            );
+            // locked accounts cannot withdraw
+            return false;
+        }
+
+        // we only check for available since these are the accessible funds even if there is theoretically more that is held
+        if !self.available.covers(amount) {
+            debug!(
+                "client_id: {} cannot withdraw: {} from {}",
+                self.id, amount, self.available
+            );
+            return false;
+        }
+
+        match self.available.checked_sub(amount) {
+            Some(available) => {
+                self.available = available;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // we always can let the possible disputes increase
+    // so no lock check needed
+    pub fn dispute(&mut self, amount: Money) -> bool {
+        matches!(
+            self.dispute_with(amount, DisputePolicy::default()),
+            DisputeOutcome::Held(_)
+        )
+    }
+
+    pub fn dispute_with(&mut self, amount: Money, policy: DisputePolicy) -> DisputeOutcome {
+        if amount.to_signed().is_none() {
+            debug!(
+                "client_id: {} cannot dispute: {} the amount is not representable",
+                self.id, amount
+            );
+            return DisputeOutcome::Exceeded(DisputePolicy::RejectAndReport, Money::ZERO);
+        }
+
+        let before = self.available;
+        if before.covers(amount) {
+            if let (Some(available), Some(held)) =
+                (before.checked_sub(amount), self.held.checked_add(amount))
+            {
+                self.available = available;
+                self.held = held;
+                return DisputeOutcome::Held(amount);
+            }
+        }
+
+        let (available, held) = match policy {
+            DisputePolicy::AllowNegative => match before.checked_sub(amount) {
+                Some(available) => (available, amount),
+                None => (before, Money::ZERO),
+            },
+            DisputePolicy::PartialHold => {
+                // taking everything above zero leaves exactly zero
+                let held = before.positive_part();
+                (before.checked_sub(held).unwrap_or(before), held)
+            }
+            DisputePolicy::RejectAndReport => (before, Money::ZERO),
+        };
+        let total_held = match self.held.checked_add(held) {
+            Some(total_held) => total_held,
+            None => {
+                debug!(
+                    "client_id: {} cannot dispute: {} the held funds would overflow",
+                    self.id, amount
+                );
+                return DisputeOutcome::Exceeded(DisputePolicy::RejectAndReport, Money::ZERO);
+            }
+        };
+
+        self.available = available;
+        self.held = total_held;
+        debug!(
+            "client_id: {} dispute of {} exceeds available {}, {} held {}",
+            self.id, amount, before, policy, held
+        );
+        DisputeOutcome::Exceeded(policy, held)
+    }
+
+    pub fn deposit(&mut self, amount: Money) -> bool {
+        if self.locked {
+            debug!(
+                "client_id: {} cannot deposit: {} the account is locked",
+                self.id, amount
+            );
+            return false;
+        }
+
+        self.credit(amount)
+    }
+
+    /// a deposit without the check of the lock, for processings that accept deposits to
+    /// locked accounts
+    pub fn credit(&mut self, amount: Money) -> bool {
+        // available alone could still fit but the total has to be representable as well
+        let available = self.available.checked_add(amount);
+        let total = self.total().and_then(|total| total.checked_add(amount));
+        let available = match (available, total) {
+            (Some(available), Some(_)) => available,
+            _ => {
+                debug!(
+                    "client_id: {} cannot deposit: {} the total would overflow",
+                    self.id, amount
+                );
+                return false;
+            }
+        };
+
+        self.available = available;
+        true
+    }
+
+    pub fn charge_back(&mut self, amount: Money) -> bool {
+        // we can only give back what is there and within the disputed transaction
+        if self.held.is_zero() || self.held < amount {
+            debug!(
+                "client_id: {} cannot charge back: {} it is more than is held",
+                self.id, amount
+            );
+            return false;
+        }
+
+        let Some(held) = self.held.checked_sub(amount) else {
+            return false;
+        };
+        self.held = held;
+        self.locked = true;
+        true
+    }
+
+    pub fn resolve(&mut self, amount: Money) -> bool {
+        if self.held.is_zero() || self.held < amount {
+            debug!(
+                "client_id: {} cannot resolve: {} it is more than is held",
+                self.id, amount
+            );
+            return false;
+        }
+
+        // held came out of available so it fits back, unless the state was tampered with
+        let (Some(available), Some(held)) = (
+            self.available.checked_add(amount),
+            self.held.checked_sub(amount),
+        ) else {
+            return false;
+        };
+        self.held = held;
+        self.available = available;
+        self.locked = false;
+        true
+    }
+
+    /// available + held, None if the sum cannot be represented
+    /// every consumer should use this instead of adding the fields itself
+    pub fn total(&self) -> Option<SignedMoney> {
+        self.available.checked_add(self.held)
+    }
+
+    /// csv row in the output format, the amounts are formatted with 4 zeros after the dot.
+    /// none of the fields can contain a separator so joining them is safe
+    pub fn to_row(&self, rounding: RoundingMode) -> String {
+        self.to_fields(rounding, NumberFormat::Standard).join(",")
+    }
+
+    /// the output columns client, available, held, total, locked
+    pub fn to_fields(&self, rounding: RoundingMode, number_format: NumberFormat) -> Vec<String> {
+        let total = match self.total() {
+            Some(total) => total.format_in(rounding, number_format),
+            None => {
+                error!("client_id: {} total overflows", self.id);
+                String::new()
+            }
+        };
+
+        vec![
+            self.id.to_string(),
+            self.available.format_in(rounding, number_format),
+            self.held.format_in(rounding, number_format),
+            total,
+            self.locked.to_string(),
+        ]
+    }
+}
+
+/// total is not stored so we cannot derive it, the field order matches the csv output
+impl Serialize for ClientAccount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("ClientAccount", 5)?;
+        state.serialize_field("client", &self.id)?;
+        state.serialize_field("available", &self.available)?;
+        state.serialize_field("held", &self.held)?;
+        state.serialize_field("total", &self.total())?;
+        state.serialize_field("locked", &self.locked)?;
+        state.end()
+    }
+}
+
+impl Display for ClientAccount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_row(RoundingMode::default()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::{Money, RoundingMode, SignedMoney};
+
+    use crate::{AccountEvent, ClientAccount, CsvRecord};
+    use std::mem;
+
+    fn money(minor_units: u64) -> Money {
+        Money::from_minor_units(minor_units)
+    }
+
+    #[test]
+    fn builder_pattern() {
+        let id = 1414;
+        let amount = SignedMoney::from_minor_units(1414141);
+
+        let client_account = ClientAccount::new(id, amount);
+        assert_eq!(
+            client_account.id, id,
+            "id {} should be {}",
+            client_account.id, id
+        );
+        assert_eq!(
+            client_account.held,
+            money(0),
+            "held {} should be {}",
+            client_account.held,
+            amount
+        );
+        assert_eq!(
+            client_account.available.minor_units(),
+            1414141,
+            "available {} should be {}",
+            client_account.available,
+            amount
+        );
+        assert!(
+            !client_account.locked,
+            "id {} should be {}",
+            client_account.id, id
+        );
+    }
+
+    #[test]
+    fn memory_layout_event() {
+        assert_eq!(24, mem::size_of::<AccountEvent>());
+    }
+
+    #[test]
+    fn memory_layout_client() {
+        assert_eq!(24, mem::size_of::<ClientAccount>());
+    }
+
+    #[test]
+    fn deposit_in_active_client_account() {
+        let mut client_account = ClientAccount::new(14, SignedMoney::ZERO);
+        client_account.deposit(money(20));
+
+        assert_eq!(money(0), client_account.held);
+        assert_eq!(20, client_account.available.minor_units());
+    }
+
+    #[test]
+    fn deposit_with_dispute_client_account() {
+        let mut client_account = ClientAccount::new(14, SignedMoney::from_minor_units(20));
+        client_account.dispute(money(20));
+        client_account.deposit(money(20));
+
+        assert_eq!(
+            client_account.available.minor_units(),
+            20,
+            "it should be 20 available"
+        );
+        assert_eq!(client_account.held, money(20), "it should be 40 held");
+    }
+
+    #[test]
+    fn withdraw_from_client_account() {
+        let mut client_account = ClientAccount::new(14, SignedMoney::from_minor_units(20));
+        client_account.withdraw(money(20));
+
+        assert_eq!(
+            client_account.available.minor_units(),
+            0,
+            "it should be 0 available"
+        );
+        assert_eq!(client_account.held, money(0), "it should be 0 held");
+    }
+
+    #[test]
+    fn withdraw_to_much_from_client_account() {
+        let mut client_account = ClientAccount::new(14, SignedMoney::from_minor_units(20));
+        client_account.withdraw(money(40));
+
+        assert_eq!(
+            client_account.available.minor_units(),
+            20,
+            "it should be 20 available"
+        );
+        assert_eq!(client_account.held, money(0), "it should be 20 held");
+    }
+
+    #[test]
+    fn withdraw_from_disputed_account() {
+        let mut client_account = ClientAccount::new(14, SignedMoney::from_minor_units(20));
+        client_account.dispute(money(10));
+        client_account.withdraw(money(10));
+
+        assert_eq!(
+            client_account.available.minor_units(),
+            0,
+            "it should be 0 available"
+        );
+        assert_eq!(client_account.held, money(10), "it should be 10 held");
+    }
+
+    #[test]
+    fn withdraw_to_much_from_disputed_account() {
+        let mut client_account = ClientAccount::new(14, SignedMoney::from_minor_units(20));
+        client_account.dispute(money(10));
+        client_account.withdraw(money(20));
+
+        assert_eq!(
+            client_account.available.minor_units(),
+            10,
+            "it should be 10 available"
+        );
+        assert_eq!(client_account.held, money(10), "it should be 20 held");
+    }
+
+    #[test]
+    fn lock_account() {
+        let mut client_account = ClientAccount::new(14, SignedMoney::from_minor_units(20));
+        client_account.dispute(money(10));
+        assert_eq!(
+            client_account.available.minor_units(),
+            10,
+            "it should be 10 available"
+        );
+        assert_eq!(client_account.held, money(10), "it should be 10 held");
+
+        client_account.charge_back(money(10));
+
+        assert_eq!(
+            client_account.available.minor_units(),
+            10,
+            "it should be 10 available"
+        );
+        assert_eq!(client_account.held, money(0), "it should be 0 held");
+        assert!(client_account.locked, "it should be locked");
+    }
+
+    #[test]
+    fn resolve_dispute_account() {
+        let mut client_account = ClientAccount::new(14, SignedMoney::from_minor_units(20));
+        client_account.dispute(money(10));
+        client_account.resolve(money(10));
+
+        assert_eq!(
+            client_account.available.minor_units(),
+            20,
+            "it should be 10 available"
+        );
+        assert_eq!(client_account.held, money(0), "it should be 10 held");
+        assert!(!client_account.locked, "it should not be locked");
+    }
+
+    #[test]
+    fn record_conversion_uses_rounding_mode() {
+        let record = || CsvRecord {
+            r#type: crate::AccountActions::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some("1.13135".to_string()),
+            correlation: None,
+        };
+
+        let event = AccountEvent::from_record(record(), RoundingMode::Truncate).unwrap();
+        assert_eq!(event.amount, Some(money(11313)));
+
+        let event = AccountEvent::try_from(record()).unwrap();
+        assert_eq!(event.amount, Some(money(11314)));
+
+        let mut invalid = record();
+        invalid.amount = Some("-1.0".to_string());
+        assert!(AccountEvent::try_from(invalid).is_err());
+    }
+
+    #[test]
+    fn large_amounts_are_read_exactly() {
+        let record = |amount: &str| CsvRecord {
+            r#type: crate::AccountActions::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(amount.to_string()),
+            correlation: None,
+        };
+        let amount = |raw| AccountEvent::try_from(record(raw)).unwrap().amount;
+
+        assert_eq!(amount("1234567.8912"), Some(money(12345678912)));
+        assert_eq!(amount("100000.0001"), Some(money(1000000001)));
+    }
+
+    #[test]
+    fn display_large_balance_exactly() {
+        let mut client_account =
+            ClientAccount::new(3, SignedMoney::from_minor_units(900000000000001));
+        client_account.dispute(money(1));
+
+        assert_eq!(
+            "3,90000000000.0000,0.0001,90000000000.0001,false",
+            client_account.to_string()
+        );
+    }
+
+    #[test]
+    fn total_is_checked() {
+        let mut client_account =
+            ClientAccount::new(3, SignedMoney::from_minor_units(i64::MAX - 10));
+        client_account.dispute(money(10));
+        assert_eq!(
+            client_account.total().map(SignedMoney::minor_units),
+            Some(i64::MAX - 10)
+        );
+
+        client_account.held = money(21);
+        assert_eq!(client_account.total(), None);
+        assert_eq!(
+            "3,922337203685477.5787,0.0021,,false",
+            client_account.to_string()
+        );
+    }
+
+    #[test]
+    fn deposit_cannot_overflow_total() {
+        let mut client_account =
+            ClientAccount::new(3, SignedMoney::from_minor_units(i64::MAX - 10));
+        client_account.dispute(money(10));
+        client_account.deposit(money(11));
+        assert_eq!(
+            client_account.total().map(SignedMoney::minor_units),
+            Some(i64::MAX - 10)
+        );
+
+        client_account.deposit(money(10));
+        assert_eq!(
+            client_account.total().map(SignedMoney::minor_units),
+            Some(i64::MAX)
+        );
+    }
+
+    #[test]
+    fn serialize_includes_total() {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer
+            .serialize(ClientAccount::new(7, SignedMoney::from_minor_units(15)))
+            .unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        assert_eq!(
+            "client,available,held,total,locked\n7,15,0,15,false\n",
+            output
+        );
+    }
+}
@@ -0,0 +1,587 @@
+use std::fmt::{Display, Formatter};
+use std::ops::Div;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// amount of decimal places our minor units represent
+pub const DECIMAL_PLACES: u32 = 4;
+
+/// whenever we go from "outside" numbers to minor units or back we have to decide what
+/// happens with the digits we cannot represent. Instead of letting the float conversion decide
+/// implicitly this is an explicit choice.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum RoundingMode {
+    // 0.00005 -> 0.0001
+    #[default]
+    HalfUp,
+    // bankers rounding 0.00005 -> 0.0000, 0.00015 -> 0.0002
+    HalfEven,
+    // just cut it off, this was the implicit behaviour of `as u64`
+    Truncate,
+}
+
+impl RoundingMode {
+    /// integer division with the rounding applied to the remainder, u128 so the carry of
+    /// u64::MAX cannot overflow
+    pub fn round_div(&self, value: u128, divisor: u128) -> u128 {
+        let quotient = value / divisor;
+        let remainder = value % divisor;
+        // remainder * 2 compared against the divisor avoids the odd divisor problem of divisor / 2
+        let round_up = match self {
+            RoundingMode::HalfUp => remainder * 2 >= divisor,
+            RoundingMode::HalfEven => {
+                remainder * 2 > divisor || (remainder * 2 == divisor && quotient % 2 == 1)
+            }
+            RoundingMode::Truncate => false,
+        };
+
+        if round_up && remainder != 0 {
+            quotient + 1
+        } else {
+            quotient
+        }
+    }
+}
+
+impl FromStr for RoundingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-up" => Ok(RoundingMode::HalfUp),
+            "half-even" => Ok(RoundingMode::HalfEven),
+            "truncate" => Ok(RoundingMode::Truncate),
+            _ => Err(format!(
+                "unknown rounding mode: {} (half-up, half-even, truncate)",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for RoundingMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RoundingMode::HalfUp => "half-up",
+            RoundingMode::HalfEven => "half-even",
+            RoundingMode::Truncate => "truncate",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// how the formatted amounts are written, the input is always parsed with a decimal dot
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum NumberFormat {
+    // 1234.5000
+    #[default]
+    Standard,
+    // 1234,5000 for spreadsheets with european settings, csv writers quote the field
+    Eu,
+}
+
+impl NumberFormat {
+    /// only swaps the separator of an already formatted amount
+    pub fn localize(self, formatted: String) -> String {
+        match self {
+            NumberFormat::Standard => formatted,
+            NumberFormat::Eu => formatted.replacen('.', ",", 1),
+        }
+    }
+}
+
+impl FromStr for NumberFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "standard" => Ok(NumberFormat::Standard),
+            "eu" => Ok(NumberFormat::Eu),
+            _ => Err(format!("unknown number format: {} (standard, eu)", s)),
+        }
+    }
+}
+
+/// exact decimal string -> minor units, digits beyond our 4 decimal places are rounded. every
+/// amount from outside goes through here (input, CLI, config files) so no float is involved
+pub fn parse_amount(amount: &str, rounding: RoundingMode) -> Result<u64, String> {
+    let amount = amount.trim();
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+
+    let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty()) || !is_digits(whole) || !is_digits(fraction) {
+        return Err(format!("invalid amount: {}", amount));
+    }
+
+    let overflow = || format!("amount too large: {}", amount);
+    let digits = format!("{}{}", whole, fraction);
+    let value: u128 = if digits.is_empty() {
+        0
+    } else {
+        digits.parse().map_err(|_| overflow())?
+    };
+
+    let fraction_len = fraction.len() as u32;
+    let minor_units = if fraction_len > DECIMAL_PLACES {
+        let divisor = 10u128
+            .checked_pow(fraction_len - DECIMAL_PLACES)
+            .ok_or_else(overflow)?;
+        rounding.round_div(value, divisor)
+    } else {
+        value
+            .checked_mul(10u128.pow(DECIMAL_PLACES - fraction_len))
+            .ok_or_else(overflow)?
+    };
+
+    u64::try_from(minor_units).map_err(|_| overflow())
+}
+
+/// only for representation purposes, the minor units are shifted back with 4 decimal places
+pub fn format_minor_units(minor_units: u64, rounding: RoundingMode) -> String {
+    format_minor_units_with(minor_units, DECIMAL_PLACES, rounding)
+}
+
+/// available can be negative, the sign is put in front of the formatted absolute value
+pub fn format_signed_minor_units(minor_units: i64, rounding: RoundingMode) -> String {
+    let formatted = format_minor_units(minor_units.unsigned_abs(), rounding);
+    if minor_units < 0 {
+        format!("-{}", formatted)
+    } else {
+        formatted
+    }
+}
+
+/// exact integer -> decimal string, no float is involved we just insert the dot over the minor units.
+/// if fewer decimals than our minor units have are requested the dropped digits are rounded
+pub fn format_minor_units_with(minor_units: u64, decimals: u32, rounding: RoundingMode) -> String {
+    let decimals = decimals.min(DECIMAL_PLACES);
+    let value = rounding.round_div(minor_units as u128, 10u128.pow(DECIMAL_PLACES - decimals));
+
+    if decimals == 0 {
+        return value.to_string();
+    }
+
+    let shift = 10u128.pow(decimals);
+    format!(
+        "{}.{:0width$}",
+        value / shift,
+        value % shift,
+        width = decimals as usize
+    )
+}
+
+/// an amount in minor units. there is no implicit conversion from a plain number so a raw
+/// value cannot be mixed up with a shifted one, every way in states what it expects
+///
+/// serialized as the minor units, the decimal representation is only for display
+#[derive(
+    Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Money(u64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+    pub const MAX: Money = Money(u64::MAX);
+
+    pub const fn from_minor_units(minor_units: u64) -> Self {
+        Money(minor_units)
+    }
+
+    /// exact decimal string, see `parse_amount`
+    pub fn parse(amount: &str, rounding: RoundingMode) -> Result<Self, String> {
+        parse_amount(amount, rounding).map(Money)
+    }
+
+    pub const fn minor_units(self) -> u64 {
+        self.0
+    }
+
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Option<Money> {
+        self.0.checked_sub(other.0).map(Money)
+    }
+
+    pub fn saturating_sub(self, other: Money) -> Money {
+        Money(self.0.saturating_sub(other.0))
+    }
+
+    /// available is signed, anything above i64::MAX can never be applied to it
+    pub fn to_signed(self) -> Option<SignedMoney> {
+        i64::try_from(self.0).ok().map(SignedMoney)
+    }
+
+    pub fn format(self, rounding: RoundingMode) -> String {
+        format_minor_units(self.0, rounding)
+    }
+
+    pub fn format_with(self, decimals: u32, rounding: RoundingMode) -> String {
+        format_minor_units_with(self.0, decimals, rounding)
+    }
+
+    pub fn format_in(self, rounding: RoundingMode, number_format: NumberFormat) -> String {
+        number_format.localize(self.format(rounding))
+    }
+}
+
+/// scaling by a plain factor, the remainder is dropped
+impl Div<u64> for Money {
+    type Output = Money;
+
+    fn div(self, divisor: u64) -> Money {
+        Money(self.0 / divisor)
+    }
+}
+
+/// a signed amount in minor units, available goes below zero if the dispute policy allows it.
+/// like Money it is not made from a plain number by accident and it only moves by Money
+/// amounts, every step is checked
+///
+/// serialized as the minor units like Money
+#[derive(
+    Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct SignedMoney(i64);
+
+impl SignedMoney {
+    pub const ZERO: SignedMoney = SignedMoney(0);
+    pub const MIN: SignedMoney = SignedMoney(i64::MIN);
+    pub const MAX: SignedMoney = SignedMoney(i64::MAX);
+
+    pub const fn from_minor_units(minor_units: i64) -> Self {
+        SignedMoney(minor_units)
+    }
+
+    pub const fn minor_units(self) -> i64 {
+        self.0
+    }
+
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    /// an amount with an optional leading minus, the rest is parsed like Money
+    pub fn parse(amount: &str, rounding: RoundingMode) -> Result<Self, String> {
+        let (negative, unsigned) = match amount.strip_prefix('-') {
+            Some(unsigned) => (true, unsigned),
+            None => (false, amount),
+        };
+        let SignedMoney(minor_units) = Money::parse(unsigned, rounding)?
+            .to_signed()
+            .ok_or_else(|| format!("amount too large: {}", amount))?;
+        Ok(SignedMoney(if negative {
+            -minor_units
+        } else {
+            minor_units
+        }))
+    }
+
+    pub fn checked_add(self, amount: Money) -> Option<SignedMoney> {
+        let amount = amount.to_signed()?;
+        self.0.checked_add(amount.0).map(SignedMoney)
+    }
+
+    pub fn checked_sub(self, amount: Money) -> Option<SignedMoney> {
+        let amount = amount.to_signed()?;
+        self.0.checked_sub(amount.0).map(SignedMoney)
+    }
+
+    /// e.g. a correction that can go either way
+    pub fn checked_add_signed(self, other: SignedMoney) -> Option<SignedMoney> {
+        self.0.checked_add(other.0).map(SignedMoney)
+    }
+
+    /// true if the amount can be taken without going below zero
+    pub fn covers(self, amount: Money) -> bool {
+        amount.to_signed().is_some_and(|amount| amount <= self)
+    }
+
+    /// what is above zero, nothing for a negative amount
+    pub fn positive_part(self) -> Money {
+        Money(self.0.max(0) as u64)
+    }
+
+    pub fn format(self, rounding: RoundingMode) -> String {
+        format_signed_minor_units(self.0, rounding)
+    }
+
+    pub fn format_in(self, rounding: RoundingMode, number_format: NumberFormat) -> String {
+        number_format.localize(self.format(rounding))
+    }
+}
+
+impl Display for SignedMoney {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format(RoundingMode::default()))
+    }
+}
+
+impl FromStr for Money {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Money::parse(s, RoundingMode::default())
+    }
+}
+
+impl Display for Money {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format(RoundingMode::default()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::{
+        format_minor_units, format_minor_units_with, format_signed_minor_units, parse_amount,
+        Money, NumberFormat, RoundingMode, SignedMoney,
+    };
+
+    #[test]
+    fn rounding_mode_from_str() {
+        assert_eq!(RoundingMode::HalfUp, "half-up".parse().unwrap());
+        assert_eq!(RoundingMode::HalfEven, "half-even".parse().unwrap());
+        assert_eq!(RoundingMode::Truncate, "truncate".parse().unwrap());
+        assert!("up".parse::<RoundingMode>().is_err());
+    }
+
+    #[test]
+    fn representable_digits_are_not_rounded() {
+        // 1.1313 as f32 is 1.13129997..., the digits of the string are exact
+        for mode in [
+            RoundingMode::HalfUp,
+            RoundingMode::HalfEven,
+            RoundingMode::Truncate,
+        ] {
+            assert_eq!(parse_amount("1.1313", mode), Ok(11313));
+        }
+    }
+
+    #[test]
+    fn half_even_and_half_up_differ_on_ties() {
+        assert_eq!(RoundingMode::HalfUp.round_div(25, 10), 3);
+        assert_eq!(RoundingMode::HalfEven.round_div(25, 10), 2);
+        assert_eq!(RoundingMode::HalfEven.round_div(35, 10), 4);
+        assert_eq!(RoundingMode::Truncate.round_div(35, 10), 3);
+    }
+
+    #[test]
+    fn format_with_4_decimal_places() {
+        assert_eq!("1.1313", format_minor_units(11313, RoundingMode::HalfUp));
+        assert_eq!("0.0000", format_minor_units(0, RoundingMode::HalfUp));
+        assert_eq!(
+            "400.0000",
+            format_minor_units(4000000, RoundingMode::Truncate)
+        );
+    }
+
+    #[test]
+    fn format_is_exact_above_float_precision() {
+        // 2^24 + 1 is the first integer f32 cannot represent
+        assert_eq!(
+            "1677.7217",
+            format_minor_units(16777217, RoundingMode::HalfUp)
+        );
+        assert_eq!(
+            "400.0001",
+            format_minor_units(4000001, RoundingMode::HalfUp)
+        );
+        assert_eq!("0.0001", format_minor_units(1, RoundingMode::HalfUp));
+    }
+
+    #[test]
+    fn format_u64_extremes() {
+        assert_eq!(
+            "1844674407370955.1615",
+            format_minor_units(u64::MAX, RoundingMode::HalfUp)
+        );
+        assert_eq!(
+            "1844674407370955.1614",
+            format_minor_units(u64::MAX - 1, RoundingMode::Truncate)
+        );
+        assert_eq!(
+            "0.0000",
+            format_minor_units(u64::MIN, RoundingMode::HalfEven)
+        );
+    }
+
+    #[test]
+    fn format_fewer_decimals_rounds() {
+        assert_eq!(
+            "1844674407370955.16",
+            format_minor_units_with(u64::MAX, 2, RoundingMode::HalfUp)
+        );
+        // carry over the dot
+        assert_eq!(
+            "1.00",
+            format_minor_units_with(9950, 2, RoundingMode::HalfUp)
+        );
+        assert_eq!(
+            "0.99",
+            format_minor_units_with(9950, 2, RoundingMode::Truncate)
+        );
+        assert_eq!(
+            "0.12",
+            format_minor_units_with(1250, 2, RoundingMode::HalfEven)
+        );
+        assert_eq!(
+            "0.14",
+            format_minor_units_with(1350, 2, RoundingMode::HalfEven)
+        );
+        assert_eq!(
+            "1844674407370955",
+            format_minor_units_with(u64::MAX, 0, RoundingMode::Truncate)
+        );
+        assert_eq!(
+            "1844674407370955",
+            format_minor_units_with(u64::MAX, 0, RoundingMode::HalfUp)
+        );
+    }
+
+    #[test]
+    fn round_div_ties() {
+        assert_eq!(3, RoundingMode::HalfUp.round_div(25, 10));
+        assert_eq!(2, RoundingMode::HalfEven.round_div(25, 10));
+        assert_eq!(4, RoundingMode::HalfEven.round_div(35, 10));
+        assert_eq!(2, RoundingMode::Truncate.round_div(29, 10));
+    }
+
+    #[test]
+    fn parse_exact_amounts() {
+        let mode = RoundingMode::HalfUp;
+        assert_eq!(Ok(11313), parse_amount("1.1313", mode));
+        assert_eq!(Ok(4000000), parse_amount("400", mode));
+        assert_eq!(Ok(5000), parse_amount(".5", mode));
+        assert_eq!(Ok(10000), parse_amount("1.", mode));
+        assert_eq!(Ok(u64::MAX), parse_amount("1844674407370955.1615", mode));
+    }
+
+    #[test]
+    fn parse_rounds_extra_digits() {
+        assert_eq!(Ok(2), parse_amount("0.00015", RoundingMode::HalfUp));
+        assert_eq!(Ok(2), parse_amount("0.00015", RoundingMode::HalfEven));
+        assert_eq!(Ok(2), parse_amount("0.00025", RoundingMode::HalfEven));
+        assert_eq!(Ok(1), parse_amount("0.00019", RoundingMode::Truncate));
+    }
+
+    #[test]
+    fn parse_invalid_amounts() {
+        let mode = RoundingMode::HalfUp;
+        assert!(parse_amount("", mode).is_err());
+        assert!(parse_amount(".", mode).is_err());
+        assert!(parse_amount("-1", mode).is_err());
+        assert!(parse_amount("1.2.3", mode).is_err());
+        assert!(parse_amount("1e4", mode).is_err());
+        assert!(parse_amount("1844674407370955.1616", mode).is_err());
+    }
+
+    #[test]
+    fn format_signed() {
+        let mode = RoundingMode::HalfUp;
+        assert_eq!("-1.5000", format_signed_minor_units(-15000, mode));
+        assert_eq!("0.0001", format_signed_minor_units(1, mode));
+        assert_eq!(
+            "-922337203685477.5808",
+            format_signed_minor_units(i64::MIN, mode)
+        );
+    }
+
+    #[test]
+    fn money_constructors() {
+        let mode = RoundingMode::HalfUp;
+        assert_eq!(
+            Ok(Money::from_minor_units(11313)),
+            Money::parse("1.1313", mode)
+        );
+        assert_eq!(Ok(Money::from_minor_units(15000)), "1.5".parse());
+        assert!(Money::parse("-1", mode).is_err());
+        assert_eq!(Money::MAX.minor_units(), u64::MAX);
+    }
+
+    #[test]
+    fn money_checked_arithmetic() {
+        let one = Money::from_minor_units(1);
+        assert_eq!(Money::MAX.checked_add(one), None);
+        assert_eq!(Money::ZERO.checked_sub(one), None);
+        assert_eq!(Money::ZERO.saturating_sub(one), Money::ZERO);
+        assert_eq!(
+            one.checked_add(one).and_then(|two| two.checked_sub(one)),
+            Some(one)
+        );
+        assert_eq!(Money::MAX.to_signed(), None);
+        assert_eq!(one.to_signed(), Some(SignedMoney::from_minor_units(1)));
+    }
+
+    #[test]
+    fn signed_money_moves_by_checked_amounts() {
+        let mode = RoundingMode::HalfUp;
+        let one = Money::from_minor_units(1);
+        let minus_one = SignedMoney::from_minor_units(-1);
+        assert_eq!(SignedMoney::ZERO.checked_sub(one), Some(minus_one));
+        assert_eq!(SignedMoney::MIN.checked_sub(one), None);
+        assert_eq!(SignedMoney::MAX.checked_add(one), None);
+        assert_eq!(SignedMoney::ZERO.checked_sub(Money::MAX), None);
+        assert!(!minus_one.covers(one));
+        assert_eq!(minus_one.positive_part(), Money::ZERO);
+
+        assert_eq!(
+            SignedMoney::parse("-1.5", mode),
+            Ok(SignedMoney::from_minor_units(-15000))
+        );
+        assert!(SignedMoney::parse("1844674407370955.1615", mode).is_err());
+        assert_eq!("-0.0001", minus_one.to_string());
+    }
+
+    #[test]
+    fn money_display_and_serde() {
+        let amount = Money::from_minor_units(15000);
+        assert_eq!(amount.to_string(), "1.5000");
+        assert_eq!(amount.format_with(2, RoundingMode::HalfUp), "1.50");
+        assert_eq!(serialized(amount), "15000");
+    }
+
+    #[test]
+    fn european_number_format() {
+        let amount = Money::from_minor_units(12345000);
+        assert_eq!(
+            amount.format_in(RoundingMode::HalfUp, NumberFormat::Eu),
+            "1234,5000"
+        );
+        assert_eq!(
+            amount.format_in(RoundingMode::HalfUp, NumberFormat::Standard),
+            "1234.5000"
+        );
+        assert_eq!(
+            NumberFormat::Eu.localize(format_signed_minor_units(-5, RoundingMode::HalfUp)),
+            "-0,0005"
+        );
+        assert_eq!("eu".parse(), Ok(NumberFormat::Eu));
+        assert!("us".parse::<NumberFormat>().is_err());
+    }
+
+    // serialized through the csv writer since that is the serializer we have
+    fn serialized(amount: Money) -> String {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(vec![]);
+        writer.serialize(amount).unwrap();
+        String::from_utf8(writer.into_inner().unwrap())
+            .unwrap()
+            .trim()
+            .to_string()
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use crate::money::{Money, RoundingMode};
+use crate::{AccountActions, AccountProcessing};
+
+/// a row is either the balance of a client or one of its open disputes, the balance file of a
+/// run can be the opening file of the next one
+pub const COLUMNS: [&str; 7] = [
+    "client",
+    "available",
+    "locked",
+    "tx",
+    "held",
+    "type",
+    "amount",
+];
+// files without any disputes can leave out the last columns
+const BALANCE_COLUMNS: usize = 3;
+
+/// a client as the previous period left it, it is applied as a deposit before the input so a
+/// monthly run does not need the files of all the months before
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct OpeningBalance {
+    pub client_id: u16,
+    pub available: Money,
+    pub locked: bool,
+}
+
+/// what is held for a tx at the end of the previous period, the dispute can still be resolved
+/// or charged back in the next one
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct OpenDispute {
+    pub client_id: u16,
+    pub transaction_id: i32,
+    pub held: Money,
+    // the disputed transaction, a partial hold holds less than its amount
+    pub action_type: AccountActions,
+    pub amount: Money,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct OpeningState {
+    pub balances: BTreeMap<u16, OpeningBalance>,
+    pub disputes: BTreeMap<i32, OpenDispute>,
+}
+
+/// the locked column can be empty, a client is listed only once. a row with a tx is an open
+/// dispute and only has the client, the held amount and its transaction besides it. without the
+/// type and amount it is a deposit of what is held
+pub fn read_opening_state<R: Read>(
+    reader: R,
+    rounding: RoundingMode,
+) -> Result<OpeningState, String> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(reader);
+    let headers = rdr.headers().map_err(|e| e.to_string())?;
+    if headers.len() < BALANCE_COLUMNS
+        || !headers.iter().eq(COLUMNS.into_iter().take(headers.len()))
+    {
+        return Err(format!(
+            "opening balances need the columns {}",
+            COLUMNS.join(",")
+        ));
+    }
+
+    let mut state = OpeningState::default();
+    for (line, row) in rdr.records().enumerate() {
+        let row = row.map_err(|e| e.to_string())?;
+        let invalid = |what: &str| format!("invalid {} in line {}", what, line + 2);
+        let client_id: u16 = row
+            .get(0)
+            .and_then(|client| client.parse().ok())
+            .ok_or_else(|| invalid("client"))?;
+
+        let transaction_id = row.get(3).unwrap_or_default();
+        if !transaction_id.is_empty() {
+            if !row.get(1).unwrap_or_default().is_empty()
+                || !row.get(2).unwrap_or_default().is_empty()
+            {
+                return Err(invalid("dispute, it cannot have a balance"));
+            }
+            let held = Money::parse(row.get(4).unwrap_or_default(), rounding)
+                .map_err(|_| invalid("held"))?;
+            let action_type = match row.get(5).unwrap_or_default() {
+                "" | "deposit" => AccountActions::Deposit,
+                "withdrawal" => AccountActions::Withdrawal,
+                _ => return Err(invalid("type")),
+            };
+            let amount = match row.get(6).unwrap_or_default() {
+                "" => held,
+                amount => Money::parse(amount, rounding).map_err(|_| invalid("amount"))?,
+            };
+            if held > amount {
+                return Err(invalid("held, it is more than the amount"));
+            }
+            let dispute = OpenDispute {
+                client_id,
+                transaction_id: transaction_id.parse().map_err(|_| invalid("tx"))?,
+                held,
+                action_type,
+                amount,
+            };
+            if state
+                .disputes
+                .insert(dispute.transaction_id, dispute)
+                .is_some()
+            {
+                return Err(format!("tx {} is listed twice", dispute.transaction_id));
+            }
+            continue;
+        }
+
+        let available = Money::parse(row.get(1).unwrap_or_default(), rounding)
+            .map_err(|_| invalid("available"))?;
+        let locked = match row.get(2).unwrap_or_default() {
+            "" | "false" => false,
+            "true" => true,
+            _ => return Err(invalid("locked")),
+        };
+
+        let balance = OpeningBalance {
+            client_id,
+            available,
+            locked,
+        };
+        if state.balances.insert(client_id, balance).is_some() {
+            return Err(format!("client {} is listed twice", client_id));
+        }
+    }
+
+    Ok(state)
+}
+
+pub fn load_opening_state(path: &str, rounding: RoundingMode) -> Result<OpeningState, String> {
+    let file =
+        File::open(path).map_err(|e| format!("cannot open opening balances {}: {}", path, e))?;
+    read_opening_state(BufReader::new(file), rounding)
+        .map_err(|e| format!("invalid opening balances {}: {}", path, e))
+}
+
+/// the accounts and the open disputes in the format read_opening_state reads. a negative
+/// balance cannot be deposited in the next run so it is an error instead of a wrong file
+pub fn write_closing<W: Write>(writer: W, processing: &AccountProcessing) -> Result<(), String> {
+    let rounding = processing.config.rounding;
+    let mut writer = csv::Writer::from_writer(writer);
+    let io = |e: csv::Error| e.to_string();
+    writer.write_record(COLUMNS).map_err(io)?;
+    for account in processing.accounts_iter() {
+        if account.available.is_negative() {
+            return Err(format!("client {} has a negative balance", account.id));
+        }
+        writer
+            .write_record([
+                account.id.to_string(),
+                account.available.format(rounding),
+                account.locked.to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+            ])
+            .map_err(io)?;
+    }
+    for (transaction_id, held) in processing.open_disputes_iter() {
+        let transaction = processing.transaction(transaction_id).ok_or_else(|| {
+            format!(
+                "open dispute of tx {} without its transaction",
+                transaction_id
+            )
+        })?;
+        writer
+            .write_record([
+                transaction.client_id.to_string(),
+                String::new(),
+                String::new(),
+                transaction_id.to_string(),
+                held.format(rounding),
+                transaction.action_type.to_string(),
+                transaction.amount.format(rounding),
+            ])
+            .map_err(io)?;
+    }
+
+    writer.flush().map_err(|e| e.to_string())
+}
+
+pub fn export_closing(path: &str, processing: &AccountProcessing) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("cannot create {}: {}", path, e))?;
+    write_closing(BufWriter::new(file), processing)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::{Money, RoundingMode};
+    use crate::opening::{read_opening_state, write_closing, OpenDispute};
+    use crate::{AccountActions, AccountProcessing, Config, DisputePolicy};
+
+    #[test]
+    fn read_balances() {
+        let csv = "client,available,locked\n1,1.5,\n2, 0.25 ,true\n";
+        let state = read_opening_state(csv.as_bytes(), RoundingMode::default()).unwrap();
+        let balances = state.balances;
+        assert_eq!(balances[&1].available, Money::from_minor_units(15000));
+        assert!(!balances[&1].locked);
+        assert_eq!(balances[&2].available, Money::from_minor_units(2500));
+        assert!(balances[&2].locked);
+        assert!(state.disputes.is_empty());
+
+        let rounding = RoundingMode::default();
+        assert!(read_opening_state("client,available\n1,1.0\n".as_bytes(), rounding).is_err());
+        assert!(
+            read_opening_state("client,available,locked\n1,-1.0,\n".as_bytes(), rounding).is_err()
+        );
+        assert_eq!(
+            read_opening_state(
+                "client,available,locked\n1,1.0,\n1,2.0,\n".as_bytes(),
+                rounding
+            ),
+            Err("client 1 is listed twice".to_string())
+        );
+        assert!(read_opening_state(
+            "client,available,locked,tx,held\n1,1.0,,7,1.0\n".as_bytes(),
+            rounding
+        )
+        .is_err());
+        // a file of an older run without the transaction of the dispute
+        let state = read_opening_state(
+            "client,available,locked,tx,held\n1,1.0,,,\n1,,,7,1.0\n".as_bytes(),
+            rounding,
+        )
+        .unwrap();
+        assert_eq!(state.disputes[&7].amount, Money::from_minor_units(10000));
+        assert!(read_opening_state(
+            "client,available,locked,tx,held,type,amount\n1,,,7,2.0,deposit,1.0\n".as_bytes(),
+            rounding
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn closing_is_the_next_opening() {
+        let mut app = AccountProcessing::new(Config {
+            dispute_policy: DisputePolicy::PartialHold,
+            ..Config::default()
+        });
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,1.0\n\
+             deposit,1,2,2.0\n\
+             withdrawal,1,4,1.5\n\
+             dispute,1,2,\n\
+             deposit,2,3,1.0\n\
+             dispute,2,3,\n\
+             chargeback,2,3,\n"
+                .as_bytes(),
+        );
+
+        let mut closing = vec![];
+        write_closing(&mut closing, &app).unwrap();
+        let closing = String::from_utf8(closing).unwrap();
+        assert_eq!(
+            closing,
+            "client,available,locked,tx,held,type,amount\n\
+             1,0.0000,false,,,,\n\
+             2,0.0000,true,,,,\n\
+             1,,,2,1.5000,deposit,2.0000\n"
+        );
+
+        let state = read_opening_state(closing.as_bytes(), RoundingMode::default()).unwrap();
+        assert_eq!(state.balances.len(), 2);
+        assert_eq!(
+            state.disputes[&2],
+            OpenDispute {
+                client_id: 1,
+                transaction_id: 2,
+                held: Money::from_minor_units(15000),
+                action_type: AccountActions::Deposit,
+                amount: Money::from_minor_units(20000),
+            }
+        );
+
+        let mut next = AccountProcessing::new(Config::default());
+        state
+            .balances
+            .values()
+            .try_for_each(|balance| next.open_balance(balance))
+            .unwrap();
+        next.carry_dispute(&state.disputes[&2]).unwrap();
+        next.process_reader("type,client,tx,amount\nresolve,1,2,\n".as_bytes());
+        assert_eq!(next.account(1).unwrap().available.minor_units(), 15000);
+        assert!(next.account(1).unwrap().held.is_zero());
+        // the whole transaction can be disputed again
+        assert_eq!(
+            next.transaction(2).unwrap().amount,
+            Money::from_minor_units(20000)
+        );
+        assert!(next.account(2).unwrap().locked);
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::hashing::mix;
+use crate::integrity::{write_accounts_with, write_sidecar, OutputIntegrity};
+use crate::AccountProcessing;
+
+/// the same client always ends up in the same partition as long as the amount of partitions stays
+pub fn partition_of(client_id: u16, partitions: usize) -> usize {
+    (mix(client_id as u64) % partitions as u64) as usize
+}
+
+/// one of `count` independent sub-engines, it only processes the clients that hash into it
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Partition {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Partition {
+    pub fn contains(&self, client_id: u16) -> bool {
+        partition_of(client_id, self.count) == self.index
+    }
+}
+
+/// accounts-00.csv ... the index is padded to the width of the highest partition (at least 2)
+pub fn partition_file_name(index: usize, partitions: usize) -> String {
+    let width = (partitions.saturating_sub(1)).to_string().len().max(2);
+    format!("accounts-{:0width$}.csv", index, width = width)
+}
+
+/// one writer per partition, every file gets its own header so each can be loaded on its own
+/// and its own trailer or sidecar
+pub fn write_partitioned(
+    processing: &AccountProcessing,
+    directory: &Path,
+    partitions: usize,
+    integrity: Option<OutputIntegrity>,
+) -> std::io::Result<Vec<PathBuf>> {
+    let paths: Vec<PathBuf> = (0..partitions)
+        .map(|index| directory.join(partition_file_name(index, partitions)))
+        .collect();
+
+    let mut writers = paths
+        .iter()
+        .map(|path| File::create(path).map(BufWriter::new))
+        .collect::<std::io::Result<Vec<BufWriter<File>>>>()?;
+
+    for (index, writer) in writers.iter_mut().enumerate() {
+        let accounts = processing
+            .accounts_iter()
+            .filter(|account| partition_of(account.id, partitions) == index);
+        match integrity {
+            Some(integrity) => {
+                let accounts: Vec<_> = accounts.collect();
+                let digest = write_accounts_with(processing, writer, &accounts, integrity)?;
+                if integrity == OutputIntegrity::Sidecar {
+                    write_sidecar(&paths[index], &digest)?;
+                }
+            }
+            None => processing.write_accounts(writer, accounts)?,
+        }
+        writer.flush()?;
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::SignedMoney;
+    use crate::partition::{partition_file_name, partition_of, write_partitioned, Partition};
+    use crate::{AccountProcessing, ClientAccount, Config};
+    use std::fs;
+
+    #[test]
+    fn file_names() {
+        assert_eq!("accounts-00.csv", partition_file_name(0, 16));
+        assert_eq!("accounts-15.csv", partition_file_name(15, 16));
+        assert_eq!("accounts-007.csv", partition_file_name(7, 101));
+    }
+
+    #[test]
+    fn partitions_are_stable_and_in_range() {
+        for client_id in 0..1000 {
+            let partition = partition_of(client_id, 16);
+            assert!(partition < 16);
+            assert_eq!(partition, partition_of(client_id, 16));
+        }
+    }
+
+    #[test]
+    fn every_account_is_written_once() {
+        let directory = std::env::temp_dir().join("kraken_test_partitions");
+        fs::create_dir_all(&directory).unwrap();
+
+        let mut processing = AccountProcessing::new(Config::default());
+        for client_id in 0..100 {
+            processing
+                .insert_account(ClientAccount::new(
+                    client_id,
+                    SignedMoney::from_minor_units(10000),
+                ))
+                .unwrap();
+        }
+
+        let paths = write_partitioned(&processing, &directory, 4, None).unwrap();
+        let mut rows = 0;
+        for (index, path) in paths.iter().enumerate() {
+            let content = fs::read_to_string(path).unwrap();
+            let mut lines = content.lines();
+            assert_eq!(lines.next(), Some("client,available,held,total,locked"));
+            for line in lines {
+                let client_id: u16 = line.split(',').next().unwrap().parse().unwrap();
+                assert_eq!(partition_of(client_id, 4), index);
+                rows += 1;
+            }
+        }
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(rows, 100);
+    }
+
+    #[test]
+    fn every_client_is_in_exactly_one_partition() {
+        let partitions: Vec<Partition> =
+            (0..4).map(|index| Partition { index, count: 4 }).collect();
+        for client_id in 0..1000u16 {
+            let containing = partitions
+                .iter()
+                .filter(|partition| partition.contains(client_id))
+                .count();
+            assert_eq!(containing, 1);
+        }
+    }
+}
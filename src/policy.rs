@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::compliance::ComplianceRules;
+use crate::money::Money;
+use crate::risk::RiskScoring;
+use crate::Config;
+
+/// a policy file overrides the rule related parts of the configuration.
+/// we only need a tiny subset of toml so instead of another dependency this is a line based parser:
+///
+/// ```toml
+/// rounding = "half-even"
+/// high_risk_score = 70
+/// high_risk_withdrawal_limit = "500.0"
+/// embargoed_countries = ["KP", "IR"]
+///
+/// [country_ceilings]
+/// US = "10000"
+///
+/// [activity_score]
+/// dispute_weight = 40
+/// velocity_window = 100
+/// ```
+///
+/// amounts are strings so they are parsed exactly like the CLI values
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Policy {
+    // section.key -> raw value
+    values: BTreeMap<String, PolicyValue>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PolicyValue {
+    String(String),
+    Integer(i64),
+    Array(Vec<String>),
+}
+
+impl PolicyValue {
+    fn as_string(&self, key: &str) -> Result<String, String> {
+        match self {
+            PolicyValue::String(value) => Ok(value.to_string()),
+            PolicyValue::Integer(value) => Ok(value.to_string()),
+            PolicyValue::Array(_) => Err(format!("{} cannot be an array", key)),
+        }
+    }
+
+    fn as_integer(&self, key: &str) -> Result<u64, String> {
+        match self {
+            PolicyValue::Integer(value) => {
+                u64::try_from(*value).map_err(|_| format!("{} cannot be negative", key))
+            }
+            _ => Err(format!("{} has to be an integer", key)),
+        }
+    }
+}
+
+fn parse_string(raw: &str) -> Option<String> {
+    raw.strip_prefix('"')?
+        .strip_suffix('"')
+        .map(|value| value.to_string())
+}
+
+fn parse_value(raw: &str) -> Result<PolicyValue, String> {
+    if let Some(value) = parse_string(raw) {
+        return Ok(PolicyValue::String(value));
+    }
+
+    if let Some(inner) = raw.strip_prefix('[').and_then(|raw| raw.strip_suffix(']')) {
+        return inner
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| parse_string(item).ok_or_else(|| format!("invalid array item: {}", item)))
+            .collect::<Result<Vec<String>, String>>()
+            .map(PolicyValue::Array);
+    }
+
+    raw.parse()
+        .map(PolicyValue::Integer)
+        .map_err(|_| format!("invalid value: {}", raw))
+}
+
+impl Policy {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut values = BTreeMap::new();
+        let mut section = String::new();
+
+        for (number, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                section = format!("{}.", name.trim());
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected key = value", number + 1))?;
+            let value =
+                parse_value(value.trim()).map_err(|e| format!("line {}: {}", number + 1, e))?;
+            values.insert(format!("{}{}", section, key.trim()), value);
+        }
+
+        Ok(Policy { values })
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content =
+            fs::read_to_string(path).map_err(|e| format!("cannot read policy {}: {}", path, e))?;
+        Self::parse(&content)
+    }
+
+    /// everything that is not in the policy stays as it is
+    pub fn apply(
+        &self,
+        config: &mut Config,
+        compliance: &mut ComplianceRules,
+    ) -> Result<(), String> {
+        for (key, value) in &self.values {
+            match key.as_str() {
+                "rounding" => config.rounding = value.as_string(key)?.parse()?,
+                "dispute_policy" => config.dispute_policy = value.as_string(key)?.parse()?,
+                "account_creation" => config.account_creation = value.as_string(key)?.parse()?,
+                "disputable" => match value {
+                    PolicyValue::Array(actions) => config.disputable = actions.join(",").parse()?,
+                    _ => return Err(format!("{} has to be an array", key)),
+                },
+                "high_risk_score" => {
+                    let score = value.as_string(key)?;
+                    config.high_risk_score = Some(
+                        score
+                            .parse()
+                            .map_err(|_| format!("invalid risk score: {}", score))?,
+                    )
+                }
+                "high_risk_withdrawal_limit" => {
+                    config.high_risk_withdrawal_limit =
+                        Some(Money::parse(&value.as_string(key)?, config.rounding)?)
+                }
+                "embargoed_countries" => match value {
+                    PolicyValue::Array(countries) => {
+                        compliance.embargoed_countries.clear();
+                        compliance.add_embargoed(&countries.join(","));
+                    }
+                    _ => return Err(format!("{} has to be an array", key)),
+                },
+                // the formula of the score, see apply_activity_score
+                _ if key.starts_with("activity_score.") => {}
+                _ => match key.strip_prefix("country_ceilings.") {
+                    Some(country) => {
+                        let ceiling = format!("{}={}", country, value.as_string(key)?);
+                        compliance.add_ceiling(&ceiling, config.rounding)?
+                    }
+                    None => return Err(format!("unknown policy key: {}", key)),
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// only the \[activity_score\] section, everything else is left to apply
+    pub fn apply_activity_score(&self, scoring: &mut RiskScoring) -> Result<(), String> {
+        for (key, value) in &self.values {
+            let Some(name) = key.strip_prefix("activity_score.") else {
+                continue;
+            };
+            let value = value.as_integer(key)?;
+            match name {
+                "dispute_weight" => scoring.dispute_weight = value,
+                "chargeback_weight" => scoring.chargeback_weight = value,
+                "velocity_weight" => scoring.velocity_weight = value,
+                "velocity_window" => scoring.velocity_window = value,
+                "velocity_limit" => scoring.velocity_limit = value,
+                _ => return Err(format!("unknown policy key: {}", key)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compliance::ComplianceRules;
+    use crate::money::{Money, RoundingMode};
+    use crate::policy::{Policy, PolicyValue};
+    use crate::risk::RiskScoring;
+    use crate::Config;
+
+    const POLICY: &str = r#"
+# stricter rules
+rounding = "half-even"
+high_risk_score = 70
+high_risk_withdrawal_limit = "500.5"
+embargoed_countries = ["KP", "IR"]
+
+[country_ceilings]
+US = "10000" # per transaction
+
+[activity_score]
+velocity_weight = 0
+velocity_limit = 5
+"#;
+
+    #[test]
+    fn parse_policy() {
+        let policy = Policy::parse(POLICY).unwrap();
+
+        assert_eq!(
+            policy.values.get("high_risk_score"),
+            Some(&PolicyValue::Integer(70))
+        );
+        assert_eq!(
+            policy.values.get("embargoed_countries"),
+            Some(&PolicyValue::Array(vec![
+                "KP".to_string(),
+                "IR".to_string()
+            ]))
+        );
+        assert_eq!(
+            policy.values.get("country_ceilings.US"),
+            Some(&PolicyValue::String("10000".to_string()))
+        );
+    }
+
+    #[test]
+    fn apply_policy() {
+        let mut config = Config::default();
+        let mut compliance = ComplianceRules::default();
+        compliance.add_embargoed("CU");
+        Policy::parse(POLICY)
+            .unwrap()
+            .apply(&mut config, &mut compliance)
+            .unwrap();
+
+        assert_eq!(config.rounding, RoundingMode::HalfEven);
+        assert_eq!(config.high_risk_score, Some(70));
+        assert_eq!(
+            config.high_risk_withdrawal_limit,
+            Some(Money::from_minor_units(5005000))
+        );
+        assert!(!compliance.embargoed_countries.contains("CU"));
+        assert!(compliance.embargoed_countries.contains("IR"));
+        assert_eq!(
+            compliance.country_ceilings["US"],
+            Money::from_minor_units(100000000)
+        );
+
+        let mut scoring = RiskScoring::default();
+        Policy::parse(POLICY)
+            .unwrap()
+            .apply_activity_score(&mut scoring)
+            .unwrap();
+        assert_eq!(
+            (
+                scoring.dispute_weight,
+                scoring.velocity_weight,
+                scoring.velocity_limit
+            ),
+            (40, 0, 5)
+        );
+        let negative = Policy::parse("[activity_score]\nvelocity_limit = -1").unwrap();
+        assert!(negative.apply_activity_score(&mut scoring).is_err());
+    }
+
+    #[test]
+    fn invalid_policies() {
+        assert!(Policy::parse("rounding").is_err());
+        assert!(Policy::parse("rounding = half-even").is_err());
+        assert!(Policy::parse("countries = [KP]").is_err());
+
+        let unknown = Policy::parse("unknown = 1").unwrap();
+        let result = unknown.apply(&mut Config::default(), &mut ComplianceRules::default());
+        assert!(result.is_err());
+    }
+}
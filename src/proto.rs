@@ -0,0 +1,259 @@
+//! protobuf encoding of the events of proto/kraken.proto without a protobuf dependency, the
+//! messages are flat. a stream is a sequence of length delimited messages (varint length + message)
+//! like `writeDelimitedTo` of the official libraries.
+//!
+//! fields are written in field order and proto3 defaults are left out, so the same value
+//! always has the same bytes
+
+use std::io::{Read, Write};
+
+use crate::binary::{read_csv_events, write_csv_events};
+use crate::money::{Money, RoundingMode};
+use crate::snapshot::{action_code, action_from_code};
+use crate::{AccountEvent, Config};
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+const FIXED32: u64 = 5;
+
+pub fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+pub fn read_varint(bytes: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or("truncated varint")?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err("varint is too long".to_string())
+}
+
+pub fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+pub fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn write_field(buffer: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(buffer, field << 3 | VARINT);
+    write_varint(buffer, value);
+}
+
+/// field number -> varint value, fields of other wire types are skipped like unknown fields
+fn read_fields(mut bytes: &[u8]) -> Result<Vec<(u64, u64)>, String> {
+    let mut fields = vec![];
+    while !bytes.is_empty() {
+        let key = read_varint(&mut bytes)?;
+        let skip = match key & 7 {
+            VARINT => {
+                fields.push((key >> 3, read_varint(&mut bytes)?));
+                0
+            }
+            FIXED64 => 8,
+            FIXED32 => 4,
+            LENGTH_DELIMITED => read_varint(&mut bytes)? as usize,
+            wire_type => return Err(format!("unsupported wire type {}", wire_type)),
+        };
+        bytes = bytes.get(skip..).ok_or("truncated field")?;
+    }
+
+    Ok(fields)
+}
+
+fn int<T: TryFrom<u64>>(value: u64, name: &str) -> Result<T, String> {
+    T::try_from(value).map_err(|_| format!("{} out of range: {}", name, value))
+}
+
+pub fn encode_event(event: &AccountEvent) -> Result<Vec<u8>, String> {
+    let code = action_code(event.action_type).map_err(|e| e.to_string())?;
+    let mut buffer = vec![];
+    if code != 0 {
+        write_field(&mut buffer, 1, code as u64);
+    }
+    if event.client_id != 0 {
+        write_field(&mut buffer, 2, event.client_id as u64);
+    }
+    if event.transaction_id != 0 {
+        write_field(&mut buffer, 3, zigzag(event.transaction_id as i64));
+    }
+    // optional, so an amount of 0 is still written
+    if let Some(amount) = event.amount {
+        write_field(&mut buffer, 4, amount.minor_units());
+    }
+
+    Ok(buffer)
+}
+
+pub fn decode_event(bytes: &[u8]) -> Result<AccountEvent, String> {
+    let mut event = AccountEvent {
+        transaction_id: 0,
+        action_type: action_from_code(0).map_err(|e| e.to_string())?,
+        client_id: 0,
+        amount: None,
+    };
+    for (field, value) in read_fields(bytes)? {
+        match field {
+            1 => {
+                event.action_type =
+                    action_from_code(int(value, "type")?).map_err(|e| e.to_string())?
+            }
+            2 => event.client_id = int(value, "client")?,
+            3 => {
+                event.transaction_id = i32::try_from(unzigzag(value))
+                    .map_err(|_| format!("tx out of range: {}", unzigzag(value)))?
+            }
+            4 => event.amount = Some(Money::from_minor_units(value)),
+            _ => {}
+        }
+    }
+
+    Ok(event)
+}
+
+pub fn write_delimited<W: Write>(writer: &mut W, message: &[u8]) -> std::io::Result<()> {
+    let mut length = vec![];
+    write_varint(&mut length, message.len() as u64);
+    writer.write_all(&length)?;
+    writer.write_all(message)
+}
+
+/// None at the end of the stream
+pub fn read_delimited<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>, String> {
+    let io = |e: std::io::Error| format!("cannot read message: {}", e);
+    let mut length = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8; 1];
+        if reader.read(&mut byte).map_err(io)? == 0 {
+            return match shift {
+                0 => Ok(None),
+                _ => Err("truncated message length".to_string()),
+            };
+        }
+        length |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            let mut message = vec![0u8; length as usize];
+            reader.read_exact(&mut message).map_err(io)?;
+            return Ok(Some(message));
+        }
+    }
+
+    Err("message length is too long".to_string())
+}
+
+pub fn csv_to_proto<R: Read, W: Write>(
+    reader: R,
+    writer: &mut W,
+    config: &Config,
+) -> Result<u64, String> {
+    let io = |e: std::io::Error| format!("cannot write protobuf events: {}", e);
+    let events = read_csv_events(reader, config, |event| {
+        write_delimited(writer, &encode_event(event)?).map_err(io)
+    })?;
+    writer.flush().map_err(io)?;
+
+    Ok(events)
+}
+
+pub fn proto_to_csv<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    rounding: RoundingMode,
+) -> Result<u64, String> {
+    let events = std::iter::from_fn(|| match read_delimited(&mut reader) {
+        Ok(Some(message)) => Some(decode_event(&message)),
+        Ok(None) => None,
+        Err(e) => Some(Err(e)),
+    });
+
+    write_csv_events(writer, events, rounding)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::{Money, RoundingMode};
+    use crate::proto::{
+        csv_to_proto, decode_event, encode_event, proto_to_csv, read_delimited, unzigzag, zigzag,
+    };
+    use crate::{AccountActions, AccountEvent, Config};
+
+    #[test]
+    fn wire_format() {
+        let event = AccountEvent {
+            transaction_id: -2,
+            action_type: AccountActions::Withdrawal,
+            client_id: 300,
+            amount: Some(Money::from_minor_units(15000)),
+        };
+        // type=1, client=300, tx=zigzag(-2)=3, amount=15000
+        let encoded = encode_event(&event).unwrap();
+        assert_eq!(
+            encoded,
+            [0x08, 0x01, 0x10, 0xac, 0x02, 0x18, 0x03, 0x20, 0x98, 0x75]
+        );
+        assert_eq!(decode_event(&encoded), Ok(event));
+
+        // a deposit of client 0 and tx 0 without an amount has only defaults
+        let empty = AccountEvent {
+            transaction_id: 0,
+            action_type: AccountActions::Deposit,
+            client_id: 0,
+            amount: None,
+        };
+        assert!(encode_event(&empty).unwrap().is_empty());
+        assert_eq!(decode_event(&[]), Ok(empty));
+
+        // unknown fields of every wire type are skipped
+        let with_unknown = [
+            0x08, 0x02, 0x29, 1, 2, 3, 4, 5, 6, 7, 8, 0x32, 0x01, 0xff, 0x3d, 1, 2, 3, 4,
+        ];
+        assert_eq!(
+            decode_event(&with_unknown).unwrap().action_type,
+            AccountActions::Dispute
+        );
+        assert!(decode_event(&[0x10, 0x80, 0x80, 0x04]).is_err());
+        assert!(decode_event(&[0x08]).is_err());
+
+        for value in [0, 1, -1, i64::MAX, i64::MIN] {
+            assert_eq!(unzigzag(zigzag(value)), value);
+        }
+    }
+
+    #[test]
+    fn byte_exact_round_trips() {
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,1.2345\n\
+                   withdrawal,1,-2,0.0000\n\
+                   dispute,65535,1,\n\
+                   chargeback,1,2147483647,\n";
+        let mut proto = vec![];
+        assert_eq!(
+            csv_to_proto(csv.as_bytes(), &mut proto, &Config::default()),
+            Ok(4)
+        );
+        let mut back = vec![];
+        proto_to_csv(proto.as_slice(), &mut back, RoundingMode::default()).unwrap();
+        assert_eq!(String::from_utf8(back.clone()).unwrap(), csv);
+
+        let mut again = vec![];
+        csv_to_proto(back.as_slice(), &mut again, &Config::default()).unwrap();
+        assert_eq!(again, proto);
+
+        assert_eq!(
+            read_delimited(&mut [0x05u8, 0x08].as_slice()).map(|_| ()),
+            Err("cannot read message: failed to fill whole buffer".to_string())
+        );
+    }
+}
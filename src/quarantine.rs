@@ -0,0 +1,261 @@
+use std::any::Any;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::compliance::ComplianceRule;
+use crate::money::RoundingMode;
+use crate::redact::MASK;
+use crate::AccountEvent;
+
+/// why a parsed event was not applied, the display is the rule id in the report
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RejectReason {
+    // the dispute family references a tx we do not know
+    UnknownTransaction,
+    // only a deposit creates the account of a client we have not seen yet
+    UnknownClient,
+    Compliance(ComplianceRule),
+    HighRiskLimit,
+    NotDisputable,
+    AccountLocked,
+    InsufficientFunds,
+    // the dispute policy rejected a dispute of more than is available
+    DisputeExceedsAvailable,
+    // resolve or chargeback without an open dispute
+    NoOpenDispute,
+    // a dispute of a tx that is disputed already, it would hold the funds twice
+    AlreadyDisputed,
+    // a deposit or withdrawal with the tx id of an earlier one, only if they are rejected
+    DuplicateTransaction,
+    Overflow,
+    // a registered action decided against it
+    ActionRejected,
+    // applying it panicked, the payload of the panic
+    Panic(String),
+}
+
+impl Display for RejectReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RejectReason::UnknownTransaction => write!(f, "unknown_transaction"),
+            RejectReason::UnknownClient => write!(f, "unknown_client"),
+            RejectReason::Compliance(rule) => write!(f, "compliance_{}", rule),
+            RejectReason::HighRiskLimit => write!(f, "high_risk_limit"),
+            RejectReason::NotDisputable => write!(f, "not_disputable"),
+            RejectReason::AccountLocked => write!(f, "account_locked"),
+            RejectReason::InsufficientFunds => write!(f, "insufficient_funds"),
+            RejectReason::DisputeExceedsAvailable => write!(f, "dispute_exceeds_available"),
+            RejectReason::NoOpenDispute => write!(f, "no_open_dispute"),
+            RejectReason::AlreadyDisputed => write!(f, "already_disputed"),
+            RejectReason::DuplicateTransaction => write!(f, "duplicate_transaction"),
+            RejectReason::Overflow => write!(f, "overflow"),
+            RejectReason::ActionRejected => write!(f, "action_rejected"),
+            RejectReason::Panic(message) => write!(f, "panic: {}", message),
+        }
+    }
+}
+
+/// a row that never became an event, the row is written again as it was read
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseError {
+    pub line: u64,
+    pub row: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct PolicyReject {
+    pub event: AccountEvent,
+    pub reason: RejectReason,
+}
+
+/// the parse errors belong to whoever produces the input, the policy rejects to whoever owns
+/// the rules, so they end up in two files
+#[derive(Debug, Clone, Default)]
+pub struct Quarantine {
+    pub parse_errors: Vec<ParseError>,
+    pub policy_rejects: Vec<PolicyReject>,
+    // the amounts are masked in both files, a row that could not be parsed is masked as a whole
+    pub redact_amounts: bool,
+}
+
+pub const PARSE_ERRORS: &str = "parse-errors.csv";
+pub const POLICY_REJECTS: &str = "policy-rejects.csv";
+
+impl Quarantine {
+    pub fn parse_error(&mut self, line: u64, row: String, error: String) {
+        self.parse_errors.push(ParseError { line, row, error });
+    }
+
+    pub fn reject(&mut self, event: &AccountEvent, reason: RejectReason) {
+        self.policy_rejects.push(PolicyReject {
+            event: *event,
+            reason,
+        });
+    }
+
+    pub fn write_parse_errors<W: Write>(&self, writer: W) -> std::io::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["line", "row", "error"])?;
+        for parse_error in &self.parse_errors {
+            writer.write_record([
+                parse_error.line.to_string(),
+                match self.redact_amounts {
+                    true => MASK.to_string(),
+                    false => parse_error.row.clone(),
+                },
+                parse_error.error.clone(),
+            ])?;
+        }
+
+        writer.flush()
+    }
+
+    pub fn write_policy_rejects<W: Write>(
+        &self,
+        writer: W,
+        rounding: RoundingMode,
+    ) -> std::io::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["type", "client", "tx", "amount", "rule"])?;
+        for reject in &self.policy_rejects {
+            writer.write_record([
+                reject.event.action_type.to_string(),
+                reject.event.client_id.to_string(),
+                reject.event.transaction_id.to_string(),
+                reject
+                    .event
+                    .amount
+                    .map(|amount| match self.redact_amounts {
+                        true => MASK.to_string(),
+                        false => amount.format(rounding),
+                    })
+                    .unwrap_or_default(),
+                reject.reason.to_string(),
+            ])?;
+        }
+
+        writer.flush()
+    }
+
+    /// both files are always written so an empty one means nothing was rejected
+    pub fn export(&self, dir: &Path, rounding: RoundingMode) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        self.write_parse_errors(BufWriter::new(File::create(dir.join(PARSE_ERRORS))?))?;
+        self.write_policy_rejects(
+            BufWriter::new(File::create(dir.join(POLICY_REJECTS))?),
+            rounding,
+        )
+    }
+}
+
+/// the message of a caught panic, panic! with a format string has a String payload
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "unknown panic".to_string(),
+    }
+}
+
+/// the fields as one csv line without the line break
+pub fn row_text(row: &csv::StringRecord) -> String {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    if writer.write_record(row).is_err() {
+        return row.iter().collect::<Vec<_>>().join(",");
+    }
+
+    let bytes = writer.into_inner().unwrap_or_default();
+    String::from_utf8_lossy(&bytes).trim_end().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compliance::ComplianceRule;
+    use crate::money::{Money, RoundingMode};
+    use crate::quarantine::{row_text, Quarantine, RejectReason};
+    use crate::{AccountActions, AccountEvent};
+
+    #[test]
+    fn reports() {
+        let mut quarantine = Quarantine::default();
+        quarantine.parse_error(
+            3,
+            "deposit,x,1,\"1,0\"".to_string(),
+            "invalid client".to_string(),
+        );
+        let event = AccountEvent {
+            transaction_id: 7,
+            action_type: AccountActions::Withdrawal,
+            client_id: 2,
+            amount: Some(Money::from_minor_units(15000)),
+        };
+        quarantine.reject(&event, RejectReason::InsufficientFunds);
+        quarantine.reject(&event, RejectReason::Compliance(ComplianceRule::Embargo));
+
+        let mut parse_errors = vec![];
+        quarantine.write_parse_errors(&mut parse_errors).unwrap();
+        assert_eq!(
+            String::from_utf8(parse_errors).unwrap(),
+            "line,row,error\n3,\"deposit,x,1,\"\"1,0\"\"\",invalid client\n"
+        );
+
+        let mut policy_rejects = vec![];
+        quarantine
+            .write_policy_rejects(&mut policy_rejects, RoundingMode::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(policy_rejects).unwrap(),
+            "type,client,tx,amount,rule\n\
+             withdrawal,2,7,1.5000,insufficient_funds\n\
+             withdrawal,2,7,1.5000,compliance_embargo\n"
+        );
+    }
+
+    #[test]
+    fn redacted_reports() {
+        let mut quarantine = Quarantine {
+            redact_amounts: true,
+            ..Quarantine::default()
+        };
+        quarantine.parse_error(
+            3,
+            "deposit,1,1,1.0.0".to_string(),
+            "invalid amount".to_string(),
+        );
+        let event = AccountEvent {
+            transaction_id: 7,
+            action_type: AccountActions::Withdrawal,
+            client_id: 2,
+            amount: Some(Money::from_minor_units(15000)),
+        };
+        quarantine.reject(&event, RejectReason::InsufficientFunds);
+
+        let mut parse_errors = vec![];
+        quarantine.write_parse_errors(&mut parse_errors).unwrap();
+        assert_eq!(
+            String::from_utf8(parse_errors).unwrap(),
+            "line,row,error\n3,***,invalid amount\n"
+        );
+
+        let mut policy_rejects = vec![];
+        quarantine
+            .write_policy_rejects(&mut policy_rejects, RoundingMode::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(policy_rejects).unwrap(),
+            "type,client,tx,amount,rule\nwithdrawal,2,7,***,insufficient_funds\n"
+        );
+    }
+
+    #[test]
+    fn rows_are_written_like_they_were_read() {
+        let row = csv::StringRecord::from(vec!["deposit", "1", "a,b"]);
+        assert_eq!(row_text(&row), "deposit,1,\"a,b\"");
+    }
+}
@@ -0,0 +1,72 @@
+use crate::Transaction;
+
+/// most disputes reference one of the last transactions of the client
+pub const RECENT_TRANSACTIONS: usize = 8;
+
+/// the last transactions of one client, inline so a lookup is a scan over a few entries
+/// instead of a walk through the map of all transactions
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct RecentTransactions {
+    entries: [Option<(i32, Transaction)>; RECENT_TRANSACTIONS],
+    // the slot that is overwritten next
+    next: usize,
+}
+
+impl RecentTransactions {
+    pub fn push(&mut self, transaction_id: i32, transaction: Transaction) {
+        self.entries[self.next] = Some((transaction_id, transaction));
+        self.next = (self.next + 1) % RECENT_TRANSACTIONS;
+    }
+
+    /// the newest entry wins if a tx id was reused
+    pub fn get(&self, transaction_id: i32) -> Option<Transaction> {
+        (1..=RECENT_TRANSACTIONS)
+            .filter_map(|age| {
+                self.entries[(self.next + RECENT_TRANSACTIONS - age) % RECENT_TRANSACTIONS]
+            })
+            .find(|(id, _)| *id == transaction_id)
+            .map(|(_, transaction)| transaction)
+    }
+
+    /// the tx now belongs to another client
+    pub fn remove(&mut self, transaction_id: i32) {
+        for entry in self.entries.iter_mut() {
+            if matches!(entry, Some((id, _)) if *id == transaction_id) {
+                *entry = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::Money;
+    use crate::recent::{RecentTransactions, RECENT_TRANSACTIONS};
+    use crate::{AccountActions, Transaction};
+
+    fn deposit(amount: u64) -> Transaction {
+        Transaction {
+            client_id: 1,
+            action_type: AccountActions::Deposit,
+            amount: Money::from_minor_units(amount),
+        }
+    }
+
+    #[test]
+    fn only_the_last_transactions_are_kept() {
+        let mut recent = RecentTransactions::default();
+        for transaction_id in 0..RECENT_TRANSACTIONS as i32 + 2 {
+            recent.push(transaction_id, deposit(transaction_id as u64));
+        }
+
+        assert_eq!(recent.get(0), None);
+        assert_eq!(recent.get(1), None);
+        assert_eq!(recent.get(2), Some(deposit(2)));
+        assert_eq!(recent.get(9), Some(deposit(9)));
+
+        recent.push(9, deposit(90));
+        assert_eq!(recent.get(9), Some(deposit(90)));
+        recent.remove(9);
+        assert_eq!(recent.get(9), None);
+    }
+}
@@ -0,0 +1,31 @@
+use std::fmt::{Display, Formatter};
+
+/// what a masked amount looks like
+pub const MASK: &str = "***";
+
+/// an amount as it appears in a log line, ids and outcomes are never wrapped in it. the flag is
+/// the redact_amounts of the processing that logs it
+pub struct Logged<T>(pub T, pub bool);
+
+impl<T: Display> Display for Logged<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.1 {
+            true => write!(f, "{}", MASK),
+            false => self.0.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::Money;
+    use crate::redact::{Logged, MASK};
+
+    #[test]
+    fn logged_values_are_masked() {
+        let amount = Money::from_minor_units(15000);
+        assert_eq!(Logged(amount, true).to_string(), MASK);
+        assert_eq!(Logged(amount, false).to_string(), amount.to_string());
+        assert_eq!(Logged("1.5 held", true).to_string(), MASK);
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+
+use crate::AccountEvent;
+
+/// events that are cut out of a replay, e.g. a known bad upstream event.
+/// an excluded tx also excludes the disputes, resolves and chargebacks that reference it
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Exclusions {
+    pub transactions: BTreeSet<i32>,
+    pub clients: BTreeSet<u16>,
+}
+
+fn parse_list<T: std::str::FromStr>(list: &str, what: &str) -> Result<Vec<T>, String> {
+    list.split(',')
+        .map(str::trim)
+        .map(|item| {
+            item.parse()
+                .map_err(|_| format!("invalid {}: {}", what, item))
+        })
+        .collect()
+}
+
+impl Exclusions {
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty() && self.clients.is_empty()
+    }
+
+    /// 1234 or 1234,1235
+    pub fn add_transactions(&mut self, list: &str) -> Result<(), String> {
+        self.transactions.extend(parse_list::<i32>(list, "tx")?);
+        Ok(())
+    }
+
+    pub fn add_clients(&mut self, list: &str) -> Result<(), String> {
+        self.clients.extend(parse_list::<u16>(list, "client")?);
+        Ok(())
+    }
+
+    pub fn excludes(&self, event: &AccountEvent) -> bool {
+        self.transactions.contains(&event.transaction_id) || self.clients.contains(&event.client_id)
+    }
+}
+
+impl Display for Exclusions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let join = |ids: Vec<String>| ids.join(",");
+        write!(
+            f,
+            "tx: [{}] clients: [{}]",
+            join(self.transactions.iter().map(i32::to_string).collect()),
+            join(self.clients.iter().map(u16::to_string).collect())
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::replay::Exclusions;
+    use crate::{AccountActions, AccountEvent};
+
+    #[test]
+    fn excluded_events() {
+        let mut exclusions = Exclusions::default();
+        exclusions.add_transactions("1234, 1235").unwrap();
+        exclusions.add_clients("42").unwrap();
+        assert!(exclusions.add_clients("70000").is_err());
+        assert!(exclusions.add_transactions("x").is_err());
+
+        let event = |client_id, transaction_id| AccountEvent {
+            transaction_id,
+            action_type: AccountActions::Dispute,
+            client_id,
+            amount: None,
+        };
+        assert!(exclusions.excludes(&event(1, 1235)));
+        assert!(exclusions.excludes(&event(42, 1)));
+        assert!(!exclusions.excludes(&event(1, 1)));
+        assert_eq!(exclusions.to_string(), "tx: [1234,1235] clients: [42]");
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{AccountActions, AccountEvent};
+
+/// what the score of a client is computed from, only applied events count
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ClientActivity {
+    // deposits and withdrawals
+    pub transactions: u64,
+    pub disputes: u64,
+    pub chargebacks: u64,
+    // the most transactions within one window of the input
+    pub peak_velocity: u64,
+    // input positions of the transactions that are still in the window
+    recent: VecDeque<u64>,
+}
+
+/// a 0-100 score per client from its dispute frequency, chargeback ratio and velocity, each of
+/// them is scaled to 0-100 and weighted. we don't have timestamps so the velocity is the number
+/// of transactions of a client within the last `velocity_window` events of the input
+#[derive(Debug, Clone)]
+pub struct RiskScoring {
+    pub dispute_weight: u64,
+    pub chargeback_weight: u64,
+    pub velocity_weight: u64,
+    pub velocity_window: u64,
+    // this many transactions within the window is the full velocity score
+    pub velocity_limit: u64,
+    activity: BTreeMap<u16, ClientActivity>,
+}
+
+impl Default for RiskScoring {
+    fn default() -> Self {
+        RiskScoring {
+            dispute_weight: 40,
+            chargeback_weight: 40,
+            velocity_weight: 20,
+            velocity_window: 100,
+            velocity_limit: 10,
+            activity: Default::default(),
+        }
+    }
+}
+
+fn percent(count: u64, of: u64) -> u64 {
+    match of {
+        0 => 0,
+        _ => (count.saturating_mul(100) / of).min(100),
+    }
+}
+
+impl RiskScoring {
+    /// position is the number of the event in the input
+    pub fn observe(&mut self, event: &AccountEvent, position: u64) {
+        let window = self.velocity_window;
+        let activity = self.activity.entry(event.client_id).or_default();
+        match event.action_type {
+            AccountActions::Deposit | AccountActions::Withdrawal => {
+                activity.transactions += 1;
+                activity.recent.push_back(position);
+                while activity
+                    .recent
+                    .front()
+                    .is_some_and(|&first| first + window <= position)
+                {
+                    activity.recent.pop_front();
+                }
+                activity.peak_velocity = activity.peak_velocity.max(activity.recent.len() as u64);
+            }
+            AccountActions::Dispute => activity.disputes += 1,
+            AccountActions::ChargeBack => activity.chargebacks += 1,
+            AccountActions::Resolve | AccountActions::Custom(_) => {}
+        }
+    }
+
+    pub fn activity(&self, client_id: u16) -> Option<&ClientActivity> {
+        self.activity.get(&client_id)
+    }
+
+    /// 0 for a client without any applied event
+    pub fn score(&self, client_id: u16) -> u8 {
+        let Some(activity) = self.activity(client_id) else {
+            return 0;
+        };
+
+        let weights = self.dispute_weight + self.chargeback_weight + self.velocity_weight;
+        if weights == 0 {
+            return 0;
+        }
+        let weighted = self.dispute_weight * percent(activity.disputes, activity.transactions)
+            + self.chargeback_weight * percent(activity.chargebacks, activity.disputes)
+            + self.velocity_weight * percent(activity.peak_velocity, self.velocity_limit);
+
+        (weighted / weights) as u8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::Money;
+    use crate::risk::RiskScoring;
+    use crate::{AccountActions, AccountEvent};
+
+    fn event(action_type: AccountActions, client_id: u16) -> AccountEvent {
+        AccountEvent {
+            transaction_id: 1,
+            action_type,
+            client_id,
+            amount: Some(Money::from_minor_units(1)),
+        }
+    }
+
+    #[test]
+    fn scores() {
+        let mut scoring = RiskScoring {
+            velocity_window: 4,
+            velocity_limit: 4,
+            ..RiskScoring::default()
+        };
+        let events = [
+            event(AccountActions::Deposit, 1),
+            event(AccountActions::Deposit, 1),
+            event(AccountActions::Deposit, 2),
+            event(AccountActions::Dispute, 1),
+            event(AccountActions::ChargeBack, 1),
+            event(AccountActions::Deposit, 2),
+            event(AccountActions::Deposit, 2),
+            event(AccountActions::Withdrawal, 2),
+            event(AccountActions::Deposit, 2),
+        ];
+        for (position, event) in events.iter().enumerate() {
+            scoring.observe(event, position as u64);
+        }
+
+        // 50% disputed, all disputes charged back and 2 of 4 transactions in the window
+        let client = scoring.activity(1).unwrap();
+        assert_eq!(
+            (client.disputes, client.chargebacks, client.peak_velocity),
+            (1, 1, 2)
+        );
+        // (40 * 50 + 40 * 100 + 20 * 50) / 100
+        assert_eq!(scoring.score(1), 70);
+
+        // positions 5 to 8 are all client 2
+        assert_eq!(scoring.activity(2).unwrap().peak_velocity, 4);
+        assert_eq!(scoring.score(2), 20);
+        assert_eq!(scoring.score(3), 0);
+
+        scoring.velocity_weight = 0;
+        assert_eq!(scoring.score(2), 0);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::hashing::mix;
+use crate::money::{parse_amount, RoundingMode};
+
+/// parts per million, 1% -> 10_000
+const SAMPLE_SCALE: u64 = 1_000_000;
+
+/// deterministic sample of clients, every event of a sampled client is processed so the
+/// resulting accounts are exactly what the full run would produce for them
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Sample {
+    pub rate_ppm: u64,
+    pub seed: u64,
+}
+
+impl Sample {
+    /// "1%", "0.5%" or "1" (percent sign is optional)
+    pub fn parse(rate: &str, seed: u64) -> Result<Self, String> {
+        let percent = rate.trim().trim_end_matches('%');
+        // 4 decimal places of a percent are exactly parts per million
+        let rate_ppm = parse_amount(percent, RoundingMode::HalfUp)
+            .map_err(|_| format!("invalid sample rate: {}", rate))?;
+        if rate_ppm > SAMPLE_SCALE {
+            return Err(format!("sample rate cannot be above 100%: {}", rate));
+        }
+
+        Ok(Sample { rate_ppm, seed })
+    }
+
+    pub fn contains(&self, client_id: u16) -> bool {
+        mix(self.seed ^ mix(client_id as u64)) % SAMPLE_SCALE < self.rate_ppm
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sampling::Sample;
+
+    #[test]
+    fn parse_sample_rate() {
+        assert_eq!(Sample::parse("1%", 7).unwrap().rate_ppm, 10_000);
+        assert_eq!(Sample::parse("0.5", 7).unwrap().rate_ppm, 5_000);
+        assert_eq!(Sample::parse("100%", 7).unwrap().rate_ppm, 1_000_000);
+        assert!(Sample::parse("101%", 7).is_err());
+        assert!(Sample::parse("a%", 7).is_err());
+    }
+
+    #[test]
+    fn sample_is_reproducible() {
+        let first = Sample::parse("10%", 7).unwrap();
+        let second = Sample::parse("10%", 7).unwrap();
+        let other_seed = Sample::parse("10%", 8).unwrap();
+
+        let clients = |sample: &Sample| -> Vec<u16> {
+            (0..=u16::MAX).filter(|id| sample.contains(*id)).collect()
+        };
+        assert_eq!(clients(&first), clients(&second));
+        assert_ne!(clients(&first), clients(&other_seed));
+
+        // roughly 10% of all client ids
+        let sampled = clients(&first).len();
+        assert!(sampled > 6000 && sampled < 7100, "{} sampled", sampled);
+    }
+
+    #[test]
+    fn edge_rates() {
+        let none = Sample::parse("0%", 7).unwrap();
+        let all = Sample::parse("100%", 7).unwrap();
+
+        assert!((0..1000).all(|id| !none.contains(id)));
+        assert!((0..1000).all(|id| all.contains(id)));
+    }
+}
@@ -0,0 +1,443 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::audit::json_string;
+
+/// the types of our columns, amounts are decimals with 4 places that are written as strings
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FieldType {
+    Text,
+    Integer { bits: u8, signed: bool },
+    Decimal,
+    Bool,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+    pub field_type: FieldType,
+    // the column can be empty or, for the metadata of the accounts, missing
+    pub nullable: bool,
+    pub description: &'static str,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RecordSchema {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub fields: Vec<Field>,
+}
+
+const fn field(
+    name: &'static str,
+    field_type: FieldType,
+    nullable: bool,
+    description: &'static str,
+) -> Field {
+    Field {
+        name,
+        field_type,
+        nullable,
+        description,
+    }
+}
+
+const CLIENT: FieldType = FieldType::Integer {
+    bits: 16,
+    signed: false,
+};
+const TX: FieldType = FieldType::Integer {
+    bits: 32,
+    signed: true,
+};
+
+/// the input record, the accounts report and the audit log lines
+pub fn schemas() -> Vec<RecordSchema> {
+    vec![
+        RecordSchema {
+            name: "input",
+            description: "one event of the csv input or the json lines of the socket",
+            fields: vec![
+                field(
+                    "type",
+                    FieldType::Text,
+                    false,
+                    "deposit, withdrawal, dispute, resolve, chargeback or a registered action",
+                ),
+                field("client", CLIENT, false, "id of the client"),
+                field(
+                    "tx",
+                    TX,
+                    false,
+                    "id of the transaction, the referenced one for the dispute family",
+                ),
+                field(
+                    "amount",
+                    FieldType::Decimal,
+                    true,
+                    "only for deposits and withdrawals, more than 4 decimals are rounded",
+                ),
+            ],
+        },
+        RecordSchema {
+            name: "accounts",
+            description: "one row per client of the output",
+            fields: vec![
+                field("client", CLIENT, false, "id of the client"),
+                field(
+                    "available",
+                    FieldType::Decimal,
+                    false,
+                    "only negative if the dispute policy allows it",
+                ),
+                field("held", FieldType::Decimal, false, "amount of open disputes"),
+                field(
+                    "total",
+                    FieldType::Decimal,
+                    true,
+                    "available + held, empty if it overflows",
+                ),
+                field(
+                    "locked",
+                    FieldType::Bool,
+                    false,
+                    "locked after a chargeback",
+                ),
+                field(
+                    "name",
+                    FieldType::Text,
+                    true,
+                    "metadata, only with a clients file",
+                ),
+                field(
+                    "country",
+                    FieldType::Text,
+                    true,
+                    "metadata, only with a clients file",
+                ),
+                field(
+                    "risk_score",
+                    FieldType::Integer {
+                        bits: 8,
+                        signed: false,
+                    },
+                    true,
+                    "metadata, only with a clients file",
+                ),
+                field(
+                    "activity_score",
+                    FieldType::Integer {
+                        bits: 8,
+                        signed: false,
+                    },
+                    true,
+                    "0-100 from disputes, chargebacks and velocity, only with --activity-score",
+                ),
+                field(
+                    "version",
+                    FieldType::Integer {
+                        bits: 64,
+                        signed: false,
+                    },
+                    true,
+                    "applied events of the client, only with --account-versions",
+                ),
+                field(
+                    "last_tx",
+                    TX,
+                    true,
+                    "tx of the last applied event, only with --account-versions",
+                ),
+            ],
+        },
+        RecordSchema {
+            name: "audit",
+            description: "a line of the audit log, event names the decision. the first line of a file is the build header",
+            fields: vec![
+                field("event", FieldType::Text, false, "dispute_exceeds_available"),
+                field("client", CLIENT, false, "id of the client"),
+                field("tx", TX, false, "the disputed transaction"),
+                field(
+                    "policy",
+                    FieldType::Text,
+                    false,
+                    "allow-negative, partial-hold or reject-and-report",
+                ),
+                field(
+                    "requested",
+                    FieldType::Decimal,
+                    false,
+                    "amount of the dispute",
+                ),
+                field("held", FieldType::Decimal, false, "what was actually held"),
+            ],
+        },
+    ]
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum SchemaFormat {
+    #[default]
+    JsonSchema,
+    // the json representation of an arrow schema
+    Arrow,
+    // one avro schema per record, what the avro files are written with
+    Avro,
+}
+
+impl FromStr for SchemaFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json-schema" => Ok(SchemaFormat::JsonSchema),
+            "arrow" => Ok(SchemaFormat::Arrow),
+            "avro" => Ok(SchemaFormat::Avro),
+            _ => Err(format!(
+                "unknown schema format: {} (json-schema, arrow, avro)",
+                s
+            )),
+        }
+    }
+}
+
+fn json_schema_type(field_type: FieldType) -> String {
+    match field_type {
+        FieldType::Text => "{\"type\":\"string\"".to_string(),
+        FieldType::Integer { bits, signed } => {
+            let (minimum, maximum) = if signed {
+                (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+            } else {
+                (0, (1i128 << bits) - 1)
+            };
+            format!(
+                "{{\"type\":\"integer\",\"minimum\":{},\"maximum\":{}",
+                minimum, maximum
+            )
+        }
+        FieldType::Decimal => {
+            format!("{{\"type\":\"string\",\"pattern\":{}", json_string(DECIMAL))
+        }
+        FieldType::Bool => "{\"type\":\"boolean\"".to_string(),
+    }
+}
+
+/// what our parser accepts, the output always has exactly 4 decimals
+const DECIMAL: &str = "^-?[0-9]*(\\.[0-9]*)?$";
+
+fn arrow_type(field_type: FieldType) -> String {
+    match field_type {
+        FieldType::Text => "{\"name\":\"utf8\"}".to_string(),
+        FieldType::Integer { bits, signed } => format!(
+            "{{\"name\":\"int\",\"bitWidth\":{},\"isSigned\":{}}}",
+            bits, signed
+        ),
+        // u64 minor units have 20 digits
+        FieldType::Decimal => {
+            "{\"name\":\"decimal\",\"precision\":20,\"scale\":4,\"bitWidth\":128}".to_string()
+        }
+        FieldType::Bool => "{\"name\":\"bool\"}".to_string(),
+    }
+}
+
+/// avro has no unsigned types, a u32 would need a long
+fn avro_type(field_type: FieldType) -> &'static str {
+    match field_type {
+        FieldType::Text => "\"string\"",
+        FieldType::Integer { bits, signed } if bits < 32 || (bits == 32 && signed) => "\"int\"",
+        FieldType::Integer { .. } => "\"long\"",
+        // the unscaled value as big endian two's complement
+        FieldType::Decimal => {
+            "{\"type\":\"bytes\",\"logicalType\":\"decimal\",\"precision\":20,\"scale\":4}"
+        }
+        FieldType::Bool => "\"boolean\"",
+    }
+}
+
+fn json_schema(schema: &RecordSchema) -> String {
+    let properties: Vec<String> = schema
+        .fields
+        .iter()
+        .map(|field| {
+            format!(
+                "{}:{},\"description\":{}}}",
+                json_string(field.name),
+                json_schema_type(field.field_type),
+                json_string(field.description)
+            )
+        })
+        .collect();
+    let required: Vec<String> = schema
+        .fields
+        .iter()
+        .filter(|field| !field.nullable)
+        .map(|field| json_string(field.name))
+        .collect();
+
+    format!(
+        "{}:{{\"title\":{},\"description\":{},\"type\":\"object\",\"properties\":{{{}}},\"required\":[{}]}}",
+        json_string(schema.name),
+        json_string(schema.name),
+        json_string(schema.description),
+        properties.join(","),
+        required.join(",")
+    )
+}
+
+fn arrow_schema(schema: &RecordSchema) -> String {
+    let fields: Vec<String> = schema
+        .fields
+        .iter()
+        .map(|field| {
+            format!(
+                "{{\"name\":{},\"nullable\":{},\"type\":{},\"children\":[]}}",
+                json_string(field.name),
+                field.nullable,
+                arrow_type(field.field_type)
+            )
+        })
+        .collect();
+
+    format!(
+        "{}:{{\"fields\":[{}],\"metadata\":[{{\"key\":\"description\",\"value\":{}}}]}}",
+        json_string(schema.name),
+        fields.join(","),
+        json_string(schema.description)
+    )
+}
+
+/// the avro files embed it, so it has to be the same string for every run of a version
+pub fn avro_schema(schema: &RecordSchema) -> String {
+    let fields: Vec<String> = schema
+        .fields
+        .iter()
+        .map(|field| {
+            let field_type = avro_type(field.field_type);
+            let field_type = match field.nullable {
+                true => format!("[\"null\",{}],\"default\":null", field_type),
+                false => field_type.to_string(),
+            };
+            format!(
+                "{{\"name\":{},\"type\":{},\"doc\":{}}}",
+                json_string(field.name),
+                field_type,
+                json_string(field.description)
+            )
+        })
+        .collect();
+
+    format!(
+        "{{\"type\":\"record\",\"name\":{},\"namespace\":\"kraken\",\"doc\":{},\"fields\":[{}]}}",
+        json_string(schema.name),
+        json_string(schema.description),
+        fields.join(",")
+    )
+}
+
+/// one json document with all records, for json schema they are definitions
+pub fn write_schemas<W: Write>(writer: &mut W, format: SchemaFormat) -> std::io::Result<()> {
+    let records: Vec<String> = schemas()
+        .iter()
+        .map(|schema| match format {
+            SchemaFormat::JsonSchema => json_schema(schema),
+            SchemaFormat::Arrow => arrow_schema(schema),
+            SchemaFormat::Avro => format!("{}:{}", json_string(schema.name), avro_schema(schema)),
+        })
+        .collect();
+
+    match format {
+        SchemaFormat::JsonSchema => writeln!(
+            writer,
+            "{{\"$schema\":\"https://json-schema.org/draft/2020-12/schema\",\"$defs\":{{{}}}}}",
+            records.join(",")
+        ),
+        SchemaFormat::Arrow | SchemaFormat::Avro => writeln!(writer, "{{{}}}", records.join(",")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::audit::test::SharedBuffer;
+    use crate::audit::AuditLog;
+    use crate::metadata::ClientMetadata;
+    use crate::risk::RiskScoring;
+    use crate::schema::{schemas, write_schemas, RecordSchema, SchemaFormat};
+    use crate::{AccountProcessing, Config, COLUMNS};
+
+    fn names(schema: &RecordSchema) -> Vec<&str> {
+        schema.fields.iter().map(|field| field.name).collect()
+    }
+
+    #[test]
+    fn schemas_match_what_is_written() {
+        let schemas = schemas();
+        assert_eq!(names(&schemas[0]), COLUMNS);
+
+        let mut app = AccountProcessing::new(Config::default());
+        let metadata = ClientMetadata {
+            client_id: 1,
+            name: "Jane".to_string(),
+            country: "AT".to_string(),
+            risk_score: 1,
+        };
+        app.client_metadata.insert(1, metadata);
+        app.subsystems_mut().risk = Some(RiskScoring::default());
+        app.config.account_versions = true;
+        let buffer = SharedBuffer::default();
+        app.subsystems_mut().audit = Some(AuditLog::new(Box::new(buffer.clone())));
+        app.process_reader(
+            "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,0.5\ndispute,1,1,\n".as_bytes(),
+        );
+
+        let mut output = vec![];
+        app.write_accounts(&mut output, app.accounts.values())
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output.lines().next(),
+            Some(names(&schemas[1]).join(",").as_str())
+        );
+
+        let audit = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let keys: Vec<String> = names(&schemas[2])
+            .iter()
+            .map(|name| format!("\"{}\":", name))
+            .collect();
+        assert!(keys.iter().all(|key| audit.contains(key)), "{}", audit);
+        assert_eq!(audit.matches("\":").count(), keys.len());
+    }
+
+    #[test]
+    fn formats() {
+        let mut json_schema = vec![];
+        write_schemas(&mut json_schema, SchemaFormat::JsonSchema).unwrap();
+        let json_schema = String::from_utf8(json_schema).unwrap();
+        assert!(json_schema.starts_with("{\"$schema\":"));
+        assert!(json_schema.contains(
+            "\"client\":{\"type\":\"integer\",\"minimum\":0,\"maximum\":65535,\"description\""
+        ));
+        assert!(json_schema.contains("\"required\":[\"type\",\"client\",\"tx\"]"));
+
+        let mut arrow = vec![];
+        write_schemas(&mut arrow, SchemaFormat::Arrow).unwrap();
+        let arrow = String::from_utf8(arrow).unwrap();
+        assert!(arrow.contains(
+            "{\"name\":\"tx\",\"nullable\":false,\"type\":{\"name\":\"int\",\"bitWidth\":32,\"isSigned\":true},\"children\":[]}"
+        ));
+        assert_eq!(arrow.matches('{').count(), arrow.matches('}').count());
+
+        let mut avro = vec![];
+        write_schemas(&mut avro, SchemaFormat::Avro).unwrap();
+        let avro = String::from_utf8(avro).unwrap();
+        assert!(avro.starts_with(
+            "{\"input\":{\"type\":\"record\",\"name\":\"input\",\"namespace\":\"kraken\""
+        ));
+        assert!(avro.contains("{\"name\":\"client\",\"type\":\"int\","));
+        assert!(avro.contains(
+            "{\"name\":\"amount\",\"type\":[\"null\",{\"type\":\"bytes\",\"logicalType\":\"decimal\",\"precision\":20,\"scale\":4}],\"default\":null,"
+        ));
+        assert_eq!(avro.matches('{').count(), avro.matches('}').count());
+
+        assert!("protobuf".parse::<SchemaFormat>().is_err());
+    }
+}
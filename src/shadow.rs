@@ -0,0 +1,124 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::cli::{build_processing, process_input, process_partitioned, Args};
+use crate::state_diff::{diff, StateDiff};
+use crate::AccountProcessing;
+
+/// a second implementation the input is run through to compare its final state with the
+/// real run before it replaces it
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ShadowEngine {
+    // one processing over the whole input
+    Single,
+    // this many sub-engines in parallel, like --partition-by-client
+    Partitioned(usize),
+}
+
+impl FromStr for ShadowEngine {
+    type Err = String;
+
+    /// single or partitioned:4
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("unknown shadow engine: {} (single, partitioned:N)", s);
+        match s.split_once(':') {
+            None if s == "single" => Ok(ShadowEngine::Single),
+            Some(("partitioned", partitions)) => match partitions.parse() {
+                Ok(0) | Err(_) => Err(invalid()),
+                Ok(partitions) => Ok(ShadowEngine::Partitioned(partitions)),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Display for ShadowEngine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShadowEngine::Single => write!(f, "single"),
+            ShadowEngine::Partitioned(partitions) => write!(f, "partitioned:{}", partitions),
+        }
+    }
+}
+
+/// only the final state of the shadow is used, none of its reports or logs are written.
+/// the input has to be readable a second time so sockets, states and offsets are not supported
+pub(crate) fn run_shadow(args: &Args, engine: ShadowEngine) -> Result<AccountProcessing, String> {
+    if args.listen_uds
+        || args.state_path.is_some()
+        || args.since_offset.is_some()
+        || args.offset_file.is_some()
+    {
+        return Err(
+            "--shadow-engine cannot be combined with --listen-uds, --state or offsets".to_string(),
+        );
+    }
+
+    let mut shadow_args = args.clone();
+    shadow_args.audit_log = None;
+    shadow_args.structuring = None;
+    shadow_args.graph_out = None;
+    shadow_args.slow_event_threshold = None;
+    shadow_args.latency_report = None;
+    shadow_args.quarantine_dir = None;
+    shadow_args.metrics = None;
+
+    match engine {
+        ShadowEngine::Single => build_processing(&shadow_args)
+            .and_then(|mut app| process_input(&mut app, &shadow_args).map(|_| app)),
+        ShadowEngine::Partitioned(partitions) => process_partitioned(&shadow_args, partitions),
+    }
+}
+
+/// the clients whose final state differs, empty if both engines agree
+pub fn divergence(app: &AccountProcessing, shadow: &AccountProcessing) -> StateDiff {
+    if app.summary.processed != shadow.summary.processed
+        || app.summary.rejected != shadow.summary.rejected
+    {
+        warn!(
+            "shadow engine processed {} and rejected {} events instead of {} and {}",
+            shadow.summary.processed,
+            shadow.summary.rejected,
+            app.summary.processed,
+            app.summary.rejected
+        );
+    }
+
+    diff(&app.snapshot(), &shadow.snapshot())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::Money;
+    use crate::shadow::{divergence, ShadowEngine};
+    use crate::{AccountProcessing, Config};
+
+    #[test]
+    fn parse_engines() {
+        assert_eq!("single".parse(), Ok(ShadowEngine::Single));
+        assert_eq!("partitioned:4".parse(), Ok(ShadowEngine::Partitioned(4)));
+        for invalid in ["partitioned", "partitioned:0", "partitioned:x", "hashmap"] {
+            assert!(invalid.parse::<ShadowEngine>().is_err(), "{}", invalid);
+        }
+        assert_eq!(ShadowEngine::Partitioned(2).to_string(), "partitioned:2");
+    }
+
+    #[test]
+    fn divergence_is_reported_per_client() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,1.0\n";
+        let mut app = AccountProcessing::new(Config::default());
+        app.process_reader(input.as_bytes());
+        let mut shadow = AccountProcessing::new(Config::default());
+        shadow.process_reader(input.as_bytes());
+        assert!(divergence(&app, &shadow).changes.is_empty());
+
+        let account = shadow.accounts.get_mut(&2).unwrap();
+        account.available = account
+            .available
+            .checked_add(Money::from_minor_units(1))
+            .unwrap();
+        let diff = divergence(&app, &shadow);
+        assert_eq!(diff.changes.len(), 1);
+        assert_eq!(diff.changes[0].client_id, 2);
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeSet;
+use std::io::Write;
+
+use crate::money::{format_minor_units, RoundingMode, SignedMoney};
+use crate::AccountProcessing;
+
+/// per client difference between the baseline and the alternative run
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ClientDifference {
+    pub client_id: u16,
+    // None if the client does not exist in the run
+    pub baseline_total: Option<SignedMoney>,
+    pub alternative_total: Option<SignedMoney>,
+    pub baseline_locked: bool,
+    pub alternative_locked: bool,
+}
+
+impl ClientDifference {
+    /// alternative - baseline in minor units
+    pub fn delta(&self) -> i128 {
+        let minor_units =
+            |total: Option<SignedMoney>| total.unwrap_or_default().minor_units() as i128;
+        minor_units(self.alternative_total) - minor_units(self.baseline_total)
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SimulationReport {
+    // only the clients that differ
+    pub differences: Vec<ClientDifference>,
+    pub baseline_rejected: u64,
+    pub alternative_rejected: u64,
+    pub baseline_locked: usize,
+    pub alternative_locked: usize,
+}
+
+fn locked_accounts(processing: &AccountProcessing) -> usize {
+    processing
+        .accounts_iter()
+        .filter(|account| account.locked)
+        .count()
+}
+
+pub fn compare(baseline: &AccountProcessing, alternative: &AccountProcessing) -> SimulationReport {
+    let client_ids: BTreeSet<u16> = baseline
+        .accounts_iter()
+        .chain(alternative.accounts_iter())
+        .map(|account| account.id)
+        .collect();
+
+    let differences = client_ids
+        .into_iter()
+        .map(|client_id| {
+            let before = baseline.account(client_id);
+            let after = alternative.account(client_id);
+            ClientDifference {
+                client_id,
+                baseline_total: before.and_then(|account| account.total()),
+                alternative_total: after.and_then(|account| account.total()),
+                baseline_locked: before.map(|account| account.locked).unwrap_or(false),
+                alternative_locked: after.map(|account| account.locked).unwrap_or(false),
+            }
+        })
+        .filter(|difference| {
+            difference.baseline_total != difference.alternative_total
+                || difference.baseline_locked != difference.alternative_locked
+        })
+        .collect();
+
+    SimulationReport {
+        differences,
+        baseline_rejected: baseline.summary.rejected,
+        alternative_rejected: alternative.summary.rejected,
+        baseline_locked: locked_accounts(baseline),
+        alternative_locked: locked_accounts(alternative),
+    }
+}
+
+fn format_delta(delta: i128, rounding: RoundingMode) -> String {
+    let sign = if delta < 0 { "-" } else { "" };
+    format!(
+        "{}{}",
+        sign,
+        format_minor_units(delta.unsigned_abs() as u64, rounding)
+    )
+}
+
+fn format_total(total: Option<SignedMoney>, rounding: RoundingMode) -> String {
+    total
+        .map(|total| total.format(rounding))
+        .unwrap_or_default()
+}
+
+impl SimulationReport {
+    /// 2 csv blocks separated by an empty line, the changed clients and the counters
+    pub fn write<W: Write>(&self, writer: &mut W, rounding: RoundingMode) -> std::io::Result<()> {
+        // each block gets its own csv writer, the empty line between them is not a record
+        let mut clients = csv::Writer::from_writer(&mut *writer);
+        clients.write_record([
+            "client",
+            "baseline_total",
+            "alternative_total",
+            "difference",
+            "baseline_locked",
+            "alternative_locked",
+        ])?;
+        for difference in &self.differences {
+            clients.write_record([
+                difference.client_id.to_string(),
+                format_total(difference.baseline_total, rounding),
+                format_total(difference.alternative_total, rounding),
+                format_delta(difference.delta(), rounding),
+                difference.baseline_locked.to_string(),
+                difference.alternative_locked.to_string(),
+            ])?;
+        }
+        clients.flush()?;
+        drop(clients);
+
+        writeln!(writer)?;
+        let mut metrics = csv::Writer::from_writer(writer);
+        metrics.write_record(["metric", "baseline", "alternative", "difference"])?;
+        metrics.write_record([
+            "rejected".to_string(),
+            self.baseline_rejected.to_string(),
+            self.alternative_rejected.to_string(),
+            (self.alternative_rejected as i64 - self.baseline_rejected as i64).to_string(),
+        ])?;
+        metrics.write_record([
+            "locked".to_string(),
+            self.baseline_locked.to_string(),
+            self.alternative_locked.to_string(),
+            (self.alternative_locked as i64 - self.baseline_locked as i64).to_string(),
+        ])?;
+        metrics.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::{RoundingMode, SignedMoney};
+    use crate::simulation::compare;
+    use crate::{AccountProcessing, ClientAccount, Config};
+
+    #[test]
+    fn only_changed_clients_are_reported() {
+        let mut baseline = AccountProcessing::new(Config::default());
+        let mut alternative = AccountProcessing::new(Config::default());
+        let account = |client_id, available| {
+            ClientAccount::new(client_id, SignedMoney::from_minor_units(available))
+        };
+        baseline.insert_account(account(1, 100)).unwrap();
+        baseline.insert_account(account(2, 100)).unwrap();
+        alternative.insert_account(account(1, 100)).unwrap();
+        alternative.insert_account(account(2, 40)).unwrap();
+        let mut locked = ClientAccount::new(3, SignedMoney::ZERO);
+        locked.locked = true;
+        alternative.insert_account(locked).unwrap();
+        alternative.summary.rejected = 2;
+
+        let report = compare(&baseline, &alternative);
+        assert_eq!(report.differences.len(), 2);
+        assert_eq!(report.differences[0].client_id, 2);
+        assert_eq!(report.differences[0].delta(), -60);
+        assert_eq!(report.differences[1].baseline_total, None);
+        assert_eq!(report.alternative_locked, 1);
+
+        let mut output = vec![];
+        report.write(&mut output, RoundingMode::HalfUp).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("2,0.0100,0.0040,-0.0060,false,false\n"));
+        assert!(output.contains("3,,0.0000,0.0000,false,true\n"));
+        assert!(output.contains("rejected,0,2,2\n"));
+        assert!(output.contains("locked,0,1,1\n"));
+    }
+}
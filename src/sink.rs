@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io::{BufWriter, Stdout, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::ClientAccount;
+
+/// where the account rows of display go. the processing renders the fields of a row in its
+/// output format, a sink only decides where they end up
+pub trait OutputSink {
+    /// once, before the first row
+    fn header(&mut self, columns: &[&str]) -> std::io::Result<()>;
+    fn row(&mut self, account: &ClientAccount, fields: &[String]) -> std::io::Result<()>;
+    /// after the last row
+    fn flush(&mut self) -> std::io::Result<()>;
+}
+
+/// the csv of the accounts on any writer, the metadata comes from a file we don't control so
+/// everything goes through the csv writer
+pub struct CsvSink<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        CsvSink {
+            writer: csv::Writer::from_writer(writer),
+        }
+    }
+}
+
+impl CsvSink<Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(std::io::stdout())
+    }
+}
+
+impl CsvSink<BufWriter<File>> {
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> OutputSink for CsvSink<W> {
+    fn header(&mut self, columns: &[&str]) -> std::io::Result<()> {
+        self.writer.write_record(columns).map_err(Into::into)
+    }
+
+    fn row(&mut self, _account: &ClientAccount, fields: &[String]) -> std::io::Result<()> {
+        self.writer.write_record(fields).map_err(Into::into)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Captured {
+    header: Vec<String>,
+    accounts: Vec<ClientAccount>,
+    rows: Vec<Vec<String>>,
+}
+
+/// keeps the rows in memory, a clone shares them so they can be read after display
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    captured: Arc<Mutex<Captured>>,
+}
+
+impl MemorySink {
+    fn captured(&self) -> std::sync::MutexGuard<'_, Captured> {
+        self.captured.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn columns(&self) -> Vec<String> {
+        self.captured().header.clone()
+    }
+
+    pub fn accounts(&self) -> Vec<ClientAccount> {
+        self.captured().accounts.clone()
+    }
+
+    /// the fields of every row as they would be written to the csv
+    pub fn rows(&self) -> Vec<Vec<String>> {
+        self.captured().rows.clone()
+    }
+}
+
+impl OutputSink for MemorySink {
+    fn header(&mut self, columns: &[&str]) -> std::io::Result<()> {
+        self.captured().header = columns.iter().map(|column| column.to_string()).collect();
+        Ok(())
+    }
+
+    fn row(&mut self, account: &ClientAccount, fields: &[String]) -> std::io::Result<()> {
+        let mut captured = self.captured();
+        captured.accounts.push(*account);
+        captured.rows.push(fields.to_vec());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::SignedMoney;
+    use crate::sink::{CsvSink, MemorySink, OutputSink};
+    use crate::ClientAccount;
+
+    #[test]
+    fn sinks_get_the_same_rows() {
+        let account = ClientAccount::new(1, SignedMoney::from_minor_units(10000));
+        let fields = ["1".to_string(), "a,b".to_string()];
+
+        let mut csv = CsvSink::new(vec![]);
+        csv.header(&["client", "name"]).unwrap();
+        csv.row(&account, &fields).unwrap();
+        csv.flush().unwrap();
+        let written = csv.writer.into_inner().unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "client,name\n1,\"a,b\"\n"
+        );
+
+        let memory = MemorySink::default();
+        let mut sink = memory.clone();
+        sink.header(&["client", "name"]).unwrap();
+        sink.row(&account, &fields).unwrap();
+        assert_eq!(memory.columns(), ["client", "name"]);
+        assert_eq!(memory.accounts(), [account]);
+        assert_eq!(memory.rows(), [fields]);
+    }
+}
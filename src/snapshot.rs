@@ -0,0 +1,396 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use crate::analytics::ClientStatistics;
+use crate::money::{Money, SignedMoney};
+use crate::{AccountActions, ClientAccount, Note, Transaction};
+
+/// "KRST" kraken state
+const MAGIC: &[u8; 4] = b"KRST";
+const VERSION: u16 = 7;
+
+/// the ledger state that is needed to continue processing in another run.
+///
+/// binary layout, everything little endian:
+///
+/// ```text
+/// magic "KRST" | version u16 | accounts u32 | (id u16, available i64, held u64, locked u8)*
+///              | transactions u32 | (tx i32, client u16, action u8, amount u64)*
+///              | open disputes u32 | (tx i32, held u64)*
+///              | statistics u32 | (client u16, deposits u64, deposit volume u64, disputes u64,
+///                                  chargebacks u64, version u64, last tx i32)*
+///              | notes u32 | (client u16, at u64, author len u16, author utf-8,
+///                             text len u32, text utf-8)*
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Snapshot {
+    pub accounts: BTreeMap<u16, ClientAccount>,
+    pub transactions: BTreeMap<i32, Transaction>,
+    pub open_disputes: BTreeMap<i32, Money>,
+    pub statistics: BTreeMap<u16, ClientStatistics>,
+    pub notes: BTreeMap<u16, Vec<Note>>,
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_string<R: Read>(reader: &mut R, length: usize) -> std::io::Result<String> {
+    let mut buffer = vec![0u8; length];
+    reader.read_exact(&mut buffer)?;
+    String::from_utf8(buffer).map_err(|_| invalid("invalid utf-8 in a note"))
+}
+
+fn read_array<const N: usize, R: Read>(reader: &mut R) -> std::io::Result<[u8; N]> {
+    let mut buffer = [0u8; N];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// only deposits and withdrawals are stored as transactions
+pub fn action_code(action_type: AccountActions) -> std::io::Result<u8> {
+    match action_type {
+        AccountActions::Deposit => Ok(0),
+        AccountActions::Withdrawal => Ok(1),
+        AccountActions::Dispute => Ok(2),
+        AccountActions::Resolve => Ok(3),
+        AccountActions::ChargeBack => Ok(4),
+        // the registry is not part of the state, the id would mean nothing to another run
+        AccountActions::Custom(_) => Err(invalid("custom actions cannot be stored")),
+    }
+}
+
+pub fn action_from_code(code: u8) -> std::io::Result<AccountActions> {
+    match code {
+        0 => Ok(AccountActions::Deposit),
+        1 => Ok(AccountActions::Withdrawal),
+        2 => Ok(AccountActions::Dispute),
+        3 => Ok(AccountActions::Resolve),
+        4 => Ok(AccountActions::ChargeBack),
+        _ => Err(invalid("invalid action type")),
+    }
+}
+
+impl Snapshot {
+    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+
+        writer.write_all(&(self.accounts.len() as u32).to_le_bytes())?;
+        for account in self.accounts.values() {
+            writer.write_all(&account.id.to_le_bytes())?;
+            writer.write_all(&account.available.minor_units().to_le_bytes())?;
+            writer.write_all(&account.held.minor_units().to_le_bytes())?;
+            writer.write_all(&[account.locked as u8])?;
+        }
+
+        writer.write_all(&(self.transactions.len() as u32).to_le_bytes())?;
+        for (transaction_id, transaction) in &self.transactions {
+            writer.write_all(&transaction_id.to_le_bytes())?;
+            writer.write_all(&transaction.client_id.to_le_bytes())?;
+            writer.write_all(&[action_code(transaction.action_type)?])?;
+            writer.write_all(&transaction.amount.minor_units().to_le_bytes())?;
+        }
+
+        Self::write_amounts(writer, &self.open_disputes)?;
+
+        writer.write_all(&(self.statistics.len() as u32).to_le_bytes())?;
+        for (client_id, statistics) in &self.statistics {
+            writer.write_all(&client_id.to_le_bytes())?;
+            writer.write_all(&statistics.deposits.to_le_bytes())?;
+            writer.write_all(&statistics.deposit_volume.minor_units().to_le_bytes())?;
+            writer.write_all(&statistics.disputes.to_le_bytes())?;
+            writer.write_all(&statistics.chargebacks.to_le_bytes())?;
+            writer.write_all(&statistics.version.to_le_bytes())?;
+            // only read back if the version is not 0
+            writer.write_all(&statistics.last_tx.unwrap_or_default().to_le_bytes())?;
+        }
+
+        let notes = self.notes.values().map(Vec::len).sum::<usize>();
+        writer.write_all(&(notes as u32).to_le_bytes())?;
+        for (client_id, notes) in &self.notes {
+            for note in notes {
+                let author =
+                    u16::try_from(note.author.len()).map_err(|_| invalid("author is too long"))?;
+                let text =
+                    u32::try_from(note.text.len()).map_err(|_| invalid("note is too long"))?;
+                writer.write_all(&client_id.to_le_bytes())?;
+                writer.write_all(&note.at.to_le_bytes())?;
+                writer.write_all(&author.to_le_bytes())?;
+                writer.write_all(note.author.as_bytes())?;
+                writer.write_all(&text.to_le_bytes())?;
+                writer.write_all(note.text.as_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_amounts<W: Write>(
+        writer: &mut W,
+        amounts: &BTreeMap<i32, Money>,
+    ) -> std::io::Result<()> {
+        writer.write_all(&(amounts.len() as u32).to_le_bytes())?;
+        for (transaction_id, amount) in amounts {
+            writer.write_all(&transaction_id.to_le_bytes())?;
+            writer.write_all(&amount.minor_units().to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    fn read_amounts<R: Read>(reader: &mut R) -> std::io::Result<BTreeMap<i32, Money>> {
+        let mut amounts = BTreeMap::new();
+        let count = u32::from_le_bytes(read_array(reader)?);
+        for _ in 0..count {
+            let transaction_id = i32::from_le_bytes(read_array(reader)?);
+            let amount = Money::from_minor_units(u64::from_le_bytes(read_array(reader)?));
+            amounts.insert(transaction_id, amount);
+        }
+
+        Ok(amounts)
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        if &read_array::<4, R>(reader)? != MAGIC {
+            return Err(invalid("not a state snapshot"));
+        }
+        if u16::from_le_bytes(read_array(reader)?) != VERSION {
+            return Err(invalid("unsupported snapshot version"));
+        }
+
+        let mut snapshot = Snapshot::default();
+        let accounts = u32::from_le_bytes(read_array(reader)?);
+        for _ in 0..accounts {
+            let id = u16::from_le_bytes(read_array(reader)?);
+            let available = SignedMoney::from_minor_units(i64::from_le_bytes(read_array(reader)?));
+            let held = Money::from_minor_units(u64::from_le_bytes(read_array(reader)?));
+            let locked = match read_array::<1, R>(reader)?[0] {
+                0 => false,
+                1 => true,
+                _ => return Err(invalid("invalid locked flag")),
+            };
+
+            let account = ClientAccount {
+                id,
+                available,
+                held,
+                locked,
+            };
+            // the engine never stores a balance whose total overflows, such a file is corrupt
+            if account.total().is_none() {
+                return Err(invalid(&format!("the balance of client {} overflows", id)));
+            }
+            snapshot.accounts.insert(id, account);
+        }
+
+        let transactions = u32::from_le_bytes(read_array(reader)?);
+        for _ in 0..transactions {
+            let transaction_id = i32::from_le_bytes(read_array(reader)?);
+            let client_id = u16::from_le_bytes(read_array(reader)?);
+            let action_type = action_from_code(read_array::<1, R>(reader)?[0])?;
+            let amount = Money::from_minor_units(u64::from_le_bytes(read_array(reader)?));
+            let transaction = Transaction {
+                client_id,
+                action_type,
+                amount,
+            };
+            snapshot.transactions.insert(transaction_id, transaction);
+        }
+
+        snapshot.open_disputes = Self::read_amounts(reader)?;
+
+        let statistics = u32::from_le_bytes(read_array(reader)?);
+        for _ in 0..statistics {
+            let client_id = u16::from_le_bytes(read_array(reader)?);
+            let mut client = ClientStatistics {
+                deposits: u64::from_le_bytes(read_array(reader)?),
+                deposit_volume: Money::from_minor_units(u64::from_le_bytes(read_array(reader)?)),
+                disputes: u64::from_le_bytes(read_array(reader)?),
+                chargebacks: u64::from_le_bytes(read_array(reader)?),
+                version: u64::from_le_bytes(read_array(reader)?),
+                last_tx: None,
+            };
+            let last_tx = i32::from_le_bytes(read_array(reader)?);
+            client.last_tx = (client.version > 0).then_some(last_tx);
+            snapshot.statistics.insert(client_id, client);
+        }
+
+        let notes = u32::from_le_bytes(read_array(reader)?);
+        for _ in 0..notes {
+            let client_id = u16::from_le_bytes(read_array(reader)?);
+            let at = u64::from_le_bytes(read_array(reader)?);
+            let author = u16::from_le_bytes(read_array(reader)?) as usize;
+            let author = read_string(reader, author)?;
+            let text = u32::from_le_bytes(read_array(reader)?) as usize;
+            let text = read_string(reader, text)?;
+            let note = Note { author, at, text };
+            snapshot.notes.entry(client_id).or_default().push(note);
+        }
+
+        Ok(snapshot)
+    }
+
+    /// combines the states of runs over disjoint clients, a client or tx that is in both
+    /// means the inputs were not partitioned and merging them would lose money
+    pub fn merge(mut self, other: Snapshot) -> Result<Snapshot, String> {
+        if let Some(id) = other
+            .accounts
+            .keys()
+            .find(|id| self.accounts.contains_key(id))
+        {
+            return Err(format!("client {} is in more than one state", id));
+        }
+        if let Some(tx) = other
+            .transactions
+            .keys()
+            .find(|tx| self.transactions.contains_key(tx))
+        {
+            return Err(format!("tx {} is in more than one state", tx));
+        }
+        if let Some(tx) = other
+            .open_disputes
+            .keys()
+            .find(|tx| self.open_disputes.contains_key(tx))
+        {
+            return Err(format!("dispute of tx {} is in more than one state", tx));
+        }
+
+        self.accounts.extend(other.accounts);
+        self.transactions.extend(other.transactions);
+        self.open_disputes.extend(other.open_disputes);
+        self.statistics.extend(other.statistics);
+        // a closed account has no balance left but still its notes
+        for (client_id, notes) in other.notes {
+            self.notes.entry(client_id).or_default().extend(notes);
+        }
+        Ok(self)
+    }
+
+    /// written next to the path and renamed over it, a crash while writing leaves the previous
+    /// state that the offset file points into
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let partial = format!("{}.partial", path);
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        self.write(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        std::fs::rename(&partial, path)
+    }
+
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analytics::ClientStatistics;
+    use crate::money::{Money, SignedMoney};
+    use crate::snapshot::Snapshot;
+    use crate::{AccountActions, ClientAccount, Note, Transaction};
+
+    #[test]
+    fn round_trip() {
+        let mut snapshot = Snapshot::default();
+        let mut locked = ClientAccount::new(2, SignedMoney::MIN);
+        locked.locked = true;
+        locked.held = Money::from_minor_units(7);
+        snapshot
+            .accounts
+            .insert(1, ClientAccount::new(1, SignedMoney::from_minor_units(10)));
+        snapshot.accounts.insert(2, locked);
+        let transaction = |action_type, amount| Transaction {
+            client_id: 1,
+            action_type,
+            amount: Money::from_minor_units(amount),
+        };
+        snapshot
+            .transactions
+            .insert(-1, transaction(AccountActions::Deposit, 10));
+        snapshot
+            .transactions
+            .insert(i32::MAX, transaction(AccountActions::Withdrawal, u64::MAX));
+        snapshot
+            .open_disputes
+            .insert(-1, Money::from_minor_units(5));
+        let statistics = ClientStatistics {
+            deposits: 1,
+            deposit_volume: Money::from_minor_units(10),
+            disputes: 1,
+            chargebacks: 0,
+            version: 3,
+            last_tx: Some(-1),
+        };
+        snapshot.statistics.insert(1, statistics);
+        let note = Note {
+            author: "ops".to_string(),
+            at: 1_700_000_000,
+            text: "chargeback reversed".to_string(),
+        };
+        snapshot.notes.insert(2, vec![note]);
+
+        let mut buffer = vec![];
+        snapshot.write(&mut buffer).unwrap();
+        // header + 2 accounts + 2 transactions + 1 dispute + 1 client statistics + 1 note
+        assert_eq!(
+            buffer.len(),
+            6 + 4 + 2 * 19 + 4 + 2 * 15 + 4 + 12 + 4 + 46 + 4 + 38
+        );
+
+        let restored = Snapshot::read(&mut buffer.as_slice()).unwrap();
+        assert_eq!(restored.accounts.len(), 2);
+        assert_eq!(restored.accounts[&2].held, Money::from_minor_units(7));
+        assert!(restored.accounts[&2].locked);
+        assert_eq!(restored, snapshot);
+    }
+
+    #[test]
+    fn invalid_snapshots() {
+        assert!(Snapshot::read(&mut b"KRSX".as_slice()).is_err());
+        assert!(Snapshot::read(&mut b"KRST\x01\x00".as_slice()).is_err());
+        // truncated
+        assert!(Snapshot::read(&mut b"KRST\x07\x00\x01\x00\x00\x00".as_slice()).is_err());
+
+        let mut overflowing = Snapshot::default();
+        let mut account = ClientAccount::new(1, SignedMoney::MAX);
+        account.held = Money::from_minor_units(1);
+        overflowing.accounts.insert(1, account);
+        let mut buffer = vec![];
+        overflowing.write(&mut buffer).unwrap();
+        let error = Snapshot::read(&mut buffer.as_slice()).unwrap_err();
+        assert_eq!(error.to_string(), "the balance of client 1 overflows");
+    }
+
+    #[test]
+    fn merge_disjoint_states() {
+        let mut a = Snapshot::default();
+        a.accounts
+            .insert(1, ClientAccount::new(1, SignedMoney::from_minor_units(10)));
+        a.transactions.insert(
+            1,
+            Transaction {
+                client_id: 1,
+                action_type: AccountActions::Deposit,
+                amount: Money::from_minor_units(10),
+            },
+        );
+        let mut b = Snapshot::default();
+        b.accounts
+            .insert(2, ClientAccount::new(2, SignedMoney::from_minor_units(20)));
+        b.open_disputes.insert(2, Money::from_minor_units(20));
+
+        let merged = a.clone().merge(b.clone()).unwrap();
+        assert_eq!(merged.accounts.len(), 2);
+        assert_eq!(merged.transactions.len(), 1);
+        assert_eq!(merged.open_disputes.len(), 1);
+
+        assert!(a.clone().merge(a.clone()).is_err(), "same client");
+        let mut same_tx = b.clone();
+        same_tx.transactions = a.transactions.clone();
+        assert!(a.merge(same_tx).is_err(), "same tx");
+    }
+}
@@ -0,0 +1,409 @@
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+
+use crate::encoding::decode;
+use crate::engine::{Config, EngineError};
+use crate::io::{CsvRecord, SchemaVersion, SCHEMA_VERSION};
+use crate::model::{AccountActions, AccountEvent};
+use crate::money::RoundingMode;
+use crate::quarantine::row_text;
+
+/// where the events of a run come from. the engine only pulls events out of it, so another
+/// input (stdin, json, a queue) is one more implementation and not a change of the engine
+pub trait EventSource {
+    /// None once the source is exhausted
+    fn next_event(&mut self) -> Option<Result<AccountEvent, SourceError>>;
+
+    /// the correlation id of the event that was returned last, if the source has them
+    fn correlation(&self) -> Option<&str> {
+        None
+    }
+
+    /// where the event that was returned last came from, only a fan-in knows more than one
+    fn source_name(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl<S: EventSource + ?Sized> EventSource for Box<S> {
+    fn next_event(&mut self) -> Option<Result<AccountEvent, SourceError>> {
+        (**self).next_event()
+    }
+
+    fn correlation(&self) -> Option<&str> {
+        (**self).correlation()
+    }
+
+    fn source_name(&self) -> Option<&str> {
+        (**self).source_name()
+    }
+}
+
+#[derive(Debug)]
+pub enum SourceError {
+    // the row is counted as a parse error and the source continues with the next one
+    Row {
+        line: u64,
+        row: String,
+        message: String,
+    },
+    // the source cannot continue
+    Io(std::io::Error),
+}
+
+impl Display for SourceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceError::Row { line, message, .. } => write!(f, "line {}: {}", line, message),
+            SourceError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// any csv with a header row, the type column can name the custom actions of the processing
+pub struct CsvSource<'r> {
+    reader: csv::Reader<Box<dyn Read + 'r>>,
+    headers: csv::StringRecord,
+    has_amount: bool,
+    // of the rows that do not name their own version
+    version: SchemaVersion,
+    // positions of the schema_version, timestamp and currency columns
+    version_column: Option<usize>,
+    timestamp_column: Option<usize>,
+    currency_column: Option<usize>,
+    skip_embedded_headers: bool,
+    rounding: RoundingMode,
+    // the names of the registered custom actions, the position is the id
+    custom_actions: Vec<String>,
+    correlation: Option<String>,
+    pub embedded_headers: u64,
+    // rows without the amount column or with fewer columns than the header
+    pub missing_columns: u64,
+}
+
+impl<'r> CsvSource<'r> {
+    /// the header is read and checked against the schema of the config right away
+    pub fn new<R: Read + 'r>(
+        reader: R,
+        config: &Config,
+        custom_actions: Vec<String>,
+    ) -> Result<Self, EngineError> {
+        let reader = decode(reader, config.input_encoding)?;
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(config.tolerate_missing_columns)
+            .from_reader(reader);
+        let headers = match reader.headers() {
+            Ok(headers) => headers.clone(),
+            Err(e) => {
+                return Err(EngineError::Parse {
+                    line: 1,
+                    message: format!("cannot read the csv header: {}", e),
+                })
+            }
+        };
+        if let Err(e) = config.schema.check(&headers, config.schema_version) {
+            return Err(EngineError::Parse {
+                line: 1,
+                message: format!("the input does not match the schema: {}", e),
+            });
+        }
+
+        let position = |name: &str| headers.iter().position(|header| header == name);
+        Ok(CsvSource {
+            has_amount: headers.iter().any(|header| header == "amount"),
+            version: config
+                .schema_version
+                .unwrap_or_else(|| SchemaVersion::detect(&headers)),
+            version_column: position(SCHEMA_VERSION),
+            timestamp_column: position("timestamp"),
+            currency_column: position("currency"),
+            reader,
+            headers,
+            skip_embedded_headers: config.skip_embedded_headers,
+            rounding: config.rounding,
+            custom_actions,
+            correlation: None,
+            embedded_headers: 0,
+            missing_columns: 0,
+        })
+    }
+
+    fn action_type(&self, name: &str) -> Option<AccountActions> {
+        name.parse().ok().or_else(|| {
+            self.custom_actions
+                .iter()
+                .position(|custom| custom == name)
+                .map(|id| AccountActions::Custom(id as u8))
+        })
+    }
+}
+
+impl EventSource for CsvSource<'_> {
+    fn next_event(&mut self) -> Option<Result<AccountEvent, SourceError>> {
+        let mut row = csv::StringRecord::new();
+        loop {
+            match self.reader.read_record(&mut row) {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) if e.is_io_error() => return Some(Err(SourceError::Io(e.into()))),
+                Err(e) => {
+                    debug!("unreadable row: {}", e);
+                    let line = e.position().map_or(0, |position| position.line());
+                    return Some(Err(SourceError::Row {
+                        line,
+                        row: String::new(),
+                        message: e.to_string(),
+                    }));
+                }
+            }
+            let line = row.position().map_or(0, |position| position.line());
+            if self.skip_embedded_headers && row == self.headers {
+                debug!("embedded header row skipped: {:?}", row.position());
+                self.embedded_headers += 1;
+                continue;
+            }
+            let invalid = |message: String| {
+                Some(Err(SourceError::Row {
+                    line,
+                    row: row_text(&row),
+                    message,
+                }))
+            };
+
+            let record = match row.deserialize::<CsvRecord<String>>(Some(&self.headers)) {
+                Ok(record) => record,
+                Err(e) => {
+                    debug!("invalid row: {}", e);
+                    return invalid(e.to_string());
+                }
+            };
+            // only amount is optional, the other columns still have to be there
+            if !self.has_amount || row.len() < self.headers.len() {
+                self.missing_columns += 1;
+            }
+
+            let field = |column: Option<usize>| column.and_then(|i| row.get(i)).unwrap_or("");
+            let version = match field(self.version_column) {
+                "" => self.version,
+                version => match version.parse::<SchemaVersion>() {
+                    Ok(version) => version,
+                    Err(e) => return invalid(e),
+                },
+            };
+            if let Err(e) =
+                version.check_fields(field(self.timestamp_column), field(self.currency_column))
+            {
+                debug!("invalid v{} row: {}", version, e);
+                return invalid(e);
+            }
+
+            let Some(action_type) = self.action_type(&record.r#type) else {
+                debug!("unknown action type: {}", record.r#type);
+                return invalid(format!("unknown action type: {}", record.r#type));
+            };
+            self.correlation = record.correlation;
+            let record = CsvRecord {
+                r#type: action_type,
+                client: record.client,
+                tx: record.tx,
+                amount: record.amount,
+                correlation: None,
+            };
+            return match AccountEvent::from_record(record, self.rounding) {
+                Ok(event) => Some(Ok(event)),
+                Err(e) => {
+                    debug!("invalid amount: {}", e);
+                    invalid(e)
+                }
+            };
+        }
+    }
+
+    fn correlation(&self) -> Option<&str> {
+        self.correlation.as_deref()
+    }
+}
+
+/// several named sources merged into one, e.g. the files of two acquirers. they take turns
+/// event by event, so the events of one source keep their order but nothing is ordered across
+/// the sources. an exhausted source is left out, the fan-in ends with the last one
+pub struct FanIn<S: EventSource> {
+    sources: Vec<(String, S)>,
+    exhausted: Vec<bool>,
+    // the source of the next and of the last returned event
+    next: usize,
+    last: Option<usize>,
+}
+
+impl<S: EventSource> FanIn<S> {
+    pub fn new(sources: Vec<(String, S)>) -> Self {
+        FanIn {
+            exhausted: vec![false; sources.len()],
+            sources,
+            next: 0,
+            last: None,
+        }
+    }
+
+    /// e.g. for the counters of the csv sources after the run
+    pub fn sources(&self) -> impl Iterator<Item = (&str, &S)> {
+        self.sources
+            .iter()
+            .map(|(name, source)| (name.as_str(), source))
+    }
+}
+
+impl<S: EventSource> EventSource for FanIn<S> {
+    fn next_event(&mut self) -> Option<Result<AccountEvent, SourceError>> {
+        for _ in 0..self.sources.len() {
+            let current = self.next;
+            self.next = (current + 1) % self.sources.len();
+            if self.exhausted[current] {
+                continue;
+            }
+            match self.sources[current].1.next_event() {
+                Some(next) => {
+                    self.last = Some(current);
+                    return Some(next);
+                }
+                None => self.exhausted[current] = true,
+            }
+        }
+
+        self.last = None;
+        None
+    }
+
+    fn correlation(&self) -> Option<&str> {
+        self.sources[self.last?].1.correlation()
+    }
+
+    fn source_name(&self) -> Option<&str> {
+        self.last.map(|last| self.sources[last].0.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::source::{CsvSource, EventSource, FanIn, SourceError};
+    use crate::{AccountActions, Config, SchemaMode, SchemaVersion};
+
+    #[test]
+    fn csv_rows_become_events() {
+        let config = Config {
+            skip_embedded_headers: true,
+            ..Default::default()
+        };
+        let input = "type,client,tx,amount,correlation\n\
+                     deposit,1,1,1.0,abc\n\
+                     type,client,tx,amount,correlation\n\
+                     refund,1,2,1.0,\n\
+                     fee,1,3,0.5,\n";
+        let mut source =
+            CsvSource::new(input.as_bytes(), &config, vec!["fee".to_string()]).unwrap();
+
+        let deposit = source.next_event().unwrap().unwrap();
+        assert_eq!(deposit.action_type, AccountActions::Deposit);
+        assert_eq!(source.correlation(), Some("abc"));
+        assert!(matches!(
+            source.next_event(),
+            Some(Err(SourceError::Row { line: 4, .. }))
+        ));
+        let fee = source.next_event().unwrap().unwrap();
+        assert_eq!(fee.action_type, AccountActions::Custom(0));
+        assert_eq!(source.correlation(), None);
+        assert!(source.next_event().is_none());
+        assert_eq!(source.embedded_headers, 1);
+    }
+
+    #[test]
+    fn vintages_can_be_mixed() {
+        let input = "type,client,tx,amount,timestamp,currency,schema_version\n\
+                     deposit,1,1,1.0,1700000000,EUR,\n\
+                     deposit,1,2,1.0,,,1\n\
+                     deposit,1,3,1.0,,,\n\
+                     deposit,1,4,1.0,1700000000,euro,2\n\
+                     deposit,1,5,1.0,,,3\n";
+        let config = Config::default();
+        let mut source = CsvSource::new(input.as_bytes(), &config, vec![]).unwrap();
+        let lines: Vec<Option<u64>> = std::iter::from_fn(|| source.next_event())
+            .map(|next| match next {
+                Ok(_) => None,
+                Err(SourceError::Row { line, .. }) => Some(line),
+                Err(SourceError::Io(e)) => panic!("{}", e),
+            })
+            .collect();
+        // the header makes v2 the default, row 2 is v1 and 3 is missing the v2 fields
+        assert_eq!(lines, [None, None, Some(4), Some(5), Some(6)]);
+
+        let v1 = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+        let config = Config {
+            schema_version: Some(SchemaVersion::V2),
+            ..Default::default()
+        };
+        let mut source = CsvSource::new(v1.as_bytes(), &config, vec![]).unwrap();
+        assert!(source.next_event().unwrap().is_err());
+
+        let strict = Config {
+            schema: SchemaMode::Strict,
+            ..Default::default()
+        };
+        for header in [
+            "type,client,tx,amount,timestamp,currency",
+            "type,client,tx,amount,schema_version,correlation",
+        ] {
+            assert!(CsvSource::new(header.as_bytes(), &strict, vec![]).is_ok());
+        }
+        let strict_v1 = Config {
+            schema_version: Some(SchemaVersion::V1),
+            ..strict
+        };
+        let v2 = "type,client,tx,amount,timestamp,currency";
+        assert!(CsvSource::new(v2.as_bytes(), &strict_v1, vec![]).is_err());
+    }
+
+    #[test]
+    fn sources_take_turns() {
+        let config = Config::default();
+        let a = "type,client,tx,amount,correlation\n\
+                 deposit,1,1,1.0,a-1\n\
+                 deposit,1,2,1.0,a-2\n\
+                 deposit,1,3,1.0,a-3\n";
+        let b = "type,client,tx,amount\ndeposit,2,10,1.0\n";
+        let mut fan_in = FanIn::new(vec![
+            (
+                "a".to_string(),
+                CsvSource::new(a.as_bytes(), &config, vec![]).unwrap(),
+            ),
+            (
+                "b".to_string(),
+                CsvSource::new(b.as_bytes(), &config, vec![]).unwrap(),
+            ),
+        ]);
+
+        let mut seen = vec![];
+        while let Some(next) = fan_in.next_event() {
+            seen.push((
+                next.unwrap().transaction_id,
+                fan_in.source_name().unwrap().to_string(),
+                fan_in.correlation().map(str::to_string),
+            ));
+        }
+        let expected = [
+            (1, "a", Some("a-1")),
+            (10, "b", None),
+            (2, "a", Some("a-2")),
+            (3, "a", Some("a-3")),
+        ];
+        assert_eq!(
+            seen,
+            expected.map(|(tx, name, correlation)| (
+                tx,
+                name.to_string(),
+                correlation.map(str::to_string)
+            ))
+        );
+        assert_eq!(fan_in.source_name(), None);
+        assert_eq!(fan_in.sources().count(), 2);
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::BTreeSet;
+use std::io::Write;
+
+use crate::money::{format_minor_units, RoundingMode};
+use crate::snapshot::Snapshot;
+use crate::ClientAccount;
+
+/// a client whose account is not the same in both states, None if it does not exist in one
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ClientChange {
+    pub client_id: u16,
+    pub old: Option<ClientAccount>,
+    pub new: Option<ClientAccount>,
+}
+
+impl ClientChange {
+    /// new - old in minor units, a missing account counts as 0
+    pub fn available_delta(&self) -> i128 {
+        let available = |account: Option<ClientAccount>| {
+            account.map_or(0, |a| a.available.minor_units() as i128)
+        };
+        available(self.new) - available(self.old)
+    }
+
+    pub fn held_delta(&self) -> i128 {
+        let held =
+            |account: Option<ClientAccount>| account.map_or(0, |a| a.held.minor_units() as i128);
+        held(self.new) - held(self.old)
+    }
+
+    pub fn total_delta(&self) -> i128 {
+        self.available_delta() + self.held_delta()
+    }
+
+    pub fn newly_locked(&self) -> bool {
+        let locked = |account: Option<ClientAccount>| account.is_some_and(|a| a.locked);
+        locked(self.new) && !locked(self.old)
+    }
+}
+
+/// what changed between two states, e.g. before and after a replay or a migration
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct StateDiff {
+    // only the clients that changed
+    pub changes: Vec<ClientChange>,
+    pub old_accounts: usize,
+    pub new_accounts: usize,
+    pub old_transactions: usize,
+    pub new_transactions: usize,
+    pub old_disputes: usize,
+    pub new_disputes: usize,
+}
+
+pub fn diff(old: &Snapshot, new: &Snapshot) -> StateDiff {
+    let client_ids: BTreeSet<u16> = old
+        .accounts
+        .keys()
+        .chain(new.accounts.keys())
+        .copied()
+        .collect();
+
+    let changes = client_ids
+        .into_iter()
+        .map(|client_id| ClientChange {
+            client_id,
+            old: old.accounts.get(&client_id).copied(),
+            new: new.accounts.get(&client_id).copied(),
+        })
+        .filter(|change| change.old != change.new)
+        .collect();
+
+    StateDiff {
+        changes,
+        old_accounts: old.accounts.len(),
+        new_accounts: new.accounts.len(),
+        old_transactions: old.transactions.len(),
+        new_transactions: new.transactions.len(),
+        old_disputes: old.open_disputes.len(),
+        new_disputes: new.open_disputes.len(),
+    }
+}
+
+fn format_delta(delta: i128, rounding: RoundingMode) -> String {
+    let sign = if delta < 0 { "-" } else { "" };
+    format!(
+        "{}{}",
+        sign,
+        format_minor_units(delta.unsigned_abs() as u64, rounding)
+    )
+}
+
+fn status(change: &ClientChange) -> &'static str {
+    match (change.old, change.new) {
+        (None, _) => "added",
+        (_, None) => "removed",
+        _ => "changed",
+    }
+}
+
+impl StateDiff {
+    pub fn newly_locked(&self) -> impl Iterator<Item = u16> + '_ {
+        self.changes
+            .iter()
+            .filter(|change| change.newly_locked())
+            .map(|change| change.client_id)
+    }
+
+    /// 2 csv blocks separated by an empty line, the changed clients and the counters
+    pub fn write<W: Write>(&self, writer: &mut W, rounding: RoundingMode) -> std::io::Result<()> {
+        let mut clients = csv::Writer::from_writer(&mut *writer);
+        clients.write_record([
+            "client",
+            "status",
+            "available_delta",
+            "held_delta",
+            "total_delta",
+            "newly_locked",
+        ])?;
+        for change in &self.changes {
+            clients.write_record([
+                change.client_id.to_string(),
+                status(change).to_string(),
+                format_delta(change.available_delta(), rounding),
+                format_delta(change.held_delta(), rounding),
+                format_delta(change.total_delta(), rounding),
+                change.newly_locked().to_string(),
+            ])?;
+        }
+        clients.flush()?;
+        drop(clients);
+
+        writeln!(writer)?;
+        let mut metrics = csv::Writer::from_writer(writer);
+        metrics.write_record(["metric", "old", "new", "growth"])?;
+        for (metric, old, new) in [
+            ("accounts", self.old_accounts, self.new_accounts),
+            ("transactions", self.old_transactions, self.new_transactions),
+            ("open_disputes", self.old_disputes, self.new_disputes),
+        ] {
+            metrics.write_record([
+                metric.to_string(),
+                old.to_string(),
+                new.to_string(),
+                (new as i64 - old as i64).to_string(),
+            ])?;
+        }
+        metrics.write_record([
+            "newly_locked".to_string(),
+            String::new(),
+            self.newly_locked().count().to_string(),
+            String::new(),
+        ])?;
+        metrics.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::{Money, RoundingMode, SignedMoney};
+    use crate::snapshot::Snapshot;
+    use crate::state_diff::diff;
+    use crate::{AccountActions, ClientAccount, Transaction};
+
+    #[test]
+    fn only_changed_clients_are_reported() {
+        let mut old = Snapshot::default();
+        old.accounts
+            .insert(1, ClientAccount::new(1, SignedMoney::from_minor_units(100)));
+        old.accounts
+            .insert(2, ClientAccount::new(2, SignedMoney::from_minor_units(100)));
+        old.accounts
+            .insert(3, ClientAccount::new(3, SignedMoney::from_minor_units(100)));
+
+        let mut new = old.clone();
+        let locked = new.accounts.get_mut(&2).unwrap();
+        locked.available = SignedMoney::from_minor_units(40);
+        locked.held = Money::from_minor_units(10);
+        locked.locked = true;
+        new.accounts.remove(&3);
+        new.accounts
+            .insert(4, ClientAccount::new(4, SignedMoney::from_minor_units(5)));
+        new.transactions.insert(
+            1,
+            Transaction {
+                client_id: 1,
+                action_type: AccountActions::Deposit,
+                amount: Money::from_minor_units(5),
+            },
+        );
+
+        let diff = diff(&old, &new);
+        let changed: Vec<u16> = diff.changes.iter().map(|change| change.client_id).collect();
+        assert_eq!(changed, vec![2, 3, 4]);
+        assert_eq!(diff.changes[0].available_delta(), -60);
+        assert_eq!(diff.changes[0].held_delta(), 10);
+        assert_eq!(diff.changes[0].total_delta(), -50);
+        assert_eq!(diff.changes[1].total_delta(), -100);
+        assert_eq!(diff.newly_locked().collect::<Vec<_>>(), vec![2]);
+        assert_eq!((diff.old_transactions, diff.new_transactions), (0, 1));
+
+        let mut output = vec![];
+        diff.write(&mut output, RoundingMode::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,status,available_delta,held_delta,total_delta,newly_locked\n\
+             2,changed,-0.0060,0.0010,-0.0050,true\n\
+             3,removed,-0.0100,0.0000,-0.0100,false\n\
+             4,added,0.0005,0.0000,0.0005,false\n\
+             \n\
+             metric,old,new,growth\n\
+             accounts,3,3,0\n\
+             transactions,0,1,1\n\
+             open_disputes,0,0,0\n\
+             newly_locked,,1,\n"
+        );
+    }
+
+    #[test]
+    fn identical_states() {
+        let mut state = Snapshot::default();
+        state
+            .accounts
+            .insert(1, ClientAccount::new(1, SignedMoney::from_minor_units(100)));
+
+        assert!(diff(&state, &state).changes.is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+//! where a processing keeps its accounts and transactions. the engine only goes through these
+//! traits, the btree maps are the default in memory implementation. a store for datasets that
+//! don't fit into memory can keep the hot entries in a cache and hand out references into it
+
+use std::collections::BTreeMap;
+
+use crate::{ClientAccount, Transaction};
+
+/// the accounts keyed by client. the output and the snapshot follow iter, it has to be ordered
+/// by client id
+pub trait AccountStore {
+    fn get(&self, client_id: u16) -> Option<&ClientAccount>;
+    fn get_mut(&mut self, client_id: u16) -> Option<&mut ClientAccount>;
+    /// the previous account of the client
+    fn insert(&mut self, account: ClientAccount) -> Option<ClientAccount>;
+    fn remove(&mut self, client_id: u16) -> Option<ClientAccount>;
+    fn len(&self) -> usize;
+    fn iter(&self) -> Box<dyn Iterator<Item = &ClientAccount> + '_>;
+    /// before a restore
+    fn clear(&mut self);
+
+    fn contains(&self, client_id: u16) -> bool {
+        self.get(client_id).is_some()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// the deposits and withdrawals that can be disputed, ordered by tx
+pub trait TransactionStore {
+    fn get(&self, transaction_id: i32) -> Option<&Transaction>;
+    /// the previous transaction with the id
+    fn insert(&mut self, transaction_id: i32, transaction: Transaction) -> Option<Transaction>;
+    fn len(&self) -> usize;
+    fn iter(&self) -> Box<dyn Iterator<Item = (i32, &Transaction)> + '_>;
+    fn clear(&mut self);
+
+    fn contains(&self, transaction_id: i32) -> bool {
+        self.get(transaction_id).is_some()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl AccountStore for BTreeMap<u16, ClientAccount> {
+    fn get(&self, client_id: u16) -> Option<&ClientAccount> {
+        BTreeMap::get(self, &client_id)
+    }
+
+    fn get_mut(&mut self, client_id: u16) -> Option<&mut ClientAccount> {
+        BTreeMap::get_mut(self, &client_id)
+    }
+
+    fn insert(&mut self, account: ClientAccount) -> Option<ClientAccount> {
+        BTreeMap::insert(self, account.id, account)
+    }
+
+    fn remove(&mut self, client_id: u16) -> Option<ClientAccount> {
+        BTreeMap::remove(self, &client_id)
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &ClientAccount> + '_> {
+        Box::new(self.values())
+    }
+
+    fn clear(&mut self) {
+        BTreeMap::clear(self)
+    }
+}
+
+impl TransactionStore for BTreeMap<i32, Transaction> {
+    fn get(&self, transaction_id: i32) -> Option<&Transaction> {
+        BTreeMap::get(self, &transaction_id)
+    }
+
+    fn insert(&mut self, transaction_id: i32, transaction: Transaction) -> Option<Transaction> {
+        BTreeMap::insert(self, transaction_id, transaction)
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (i32, &Transaction)> + '_> {
+        Box::new(
+            BTreeMap::iter(self)
+                .map(|(transaction_id, transaction)| (*transaction_id, transaction)),
+        )
+    }
+
+    fn clear(&mut self) {
+        BTreeMap::clear(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::store::AccountStore;
+    use crate::{AccountProcessing, ClientAccount, Config, Transaction};
+    use std::collections::BTreeMap;
+
+    // the default store that counts how often the engine writes an account
+    #[derive(Default)]
+    struct CountingStore {
+        accounts: BTreeMap<u16, ClientAccount>,
+        inserts: usize,
+    }
+
+    impl AccountStore for CountingStore {
+        fn get(&self, client_id: u16) -> Option<&ClientAccount> {
+            self.accounts.get(&client_id)
+        }
+
+        fn get_mut(&mut self, client_id: u16) -> Option<&mut ClientAccount> {
+            self.accounts.get_mut(&client_id)
+        }
+
+        fn insert(&mut self, account: ClientAccount) -> Option<ClientAccount> {
+            self.inserts += 1;
+            self.accounts.insert(account.id, account)
+        }
+
+        fn remove(&mut self, client_id: u16) -> Option<ClientAccount> {
+            self.accounts.remove(&client_id)
+        }
+
+        fn len(&self) -> usize {
+            self.accounts.len()
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = &ClientAccount> + '_> {
+            Box::new(self.accounts.values())
+        }
+
+        fn clear(&mut self) {
+            self.accounts.clear()
+        }
+    }
+
+    #[test]
+    fn processing_with_another_store() {
+        let transactions: BTreeMap<i32, Transaction> = BTreeMap::new();
+        let mut app = AccountProcessing::with_stores(
+            Config::default(),
+            CountingStore::default(),
+            transactions,
+        );
+        app.process_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,2.0\n\
+             deposit,2,2,1.0\n\
+             dispute,1,1,\n\
+             withdrawal,2,3,5.0\n"
+                .as_bytes(),
+        );
+
+        assert_eq!(app.account(1).unwrap().held.minor_units(), 20000);
+        assert_eq!(app.account_count(), 2);
+        assert_eq!(
+            app.accounts.inserts, 3,
+            "the rejected withdrawal is not written"
+        );
+
+        let mut restored = AccountProcessing::with_stores(
+            Config::default(),
+            CountingStore::default(),
+            BTreeMap::new(),
+        );
+        restored.restore(app.snapshot());
+        assert_eq!(restored.snapshot(), app.snapshot());
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::money::{Money, RoundingMode};
+use crate::{AccountActions, AccountEvent};
+
+/// classic structuring: instead of one deposit above the reporting threshold a client makes many
+/// deposits just below it. We don't have timestamps so the window is the last n deposits of a client.
+///
+/// per client we only keep the rolling window, so memory is bounded by clients * window
+#[derive(Debug, Clone)]
+pub struct StructuringDetector {
+    // the reporting threshold the client tries to stay below
+    pub threshold: Money,
+    // deposits in [threshold - margin, threshold) count as "just below"
+    pub margin: Money,
+    // how many of the last deposits of a client are looked at
+    pub window: usize,
+    // how many just below deposits within the window are suspicious
+    pub min_count: usize,
+    // (transaction_id, amount) of the last deposits per client
+    recent: BTreeMap<u16, VecDeque<(i32, Money)>>,
+    // a client is only reported once
+    flagged: BTreeSet<u16>,
+    pub reports: Vec<SuspiciousActivity>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SuspiciousActivity {
+    pub client_id: u16,
+    // the just below threshold deposits that triggered the report
+    pub transactions: Vec<(i32, Money)>,
+}
+
+impl SuspiciousActivity {
+    /// None if the deposits cannot be summed, they come from the input
+    pub fn total(&self) -> Option<Money> {
+        self.transactions
+            .iter()
+            .try_fold(Money::ZERO, |sum, (_, amount)| sum.checked_add(*amount))
+    }
+}
+
+impl StructuringDetector {
+    /// margin defaults to 10% of the threshold, looking at the last 10 deposits with 3 being suspicious
+    pub fn new(threshold: Money) -> Self {
+        StructuringDetector {
+            threshold,
+            margin: threshold / 10,
+            window: 10,
+            min_count: 3,
+            recent: Default::default(),
+            flagged: Default::default(),
+            reports: vec![],
+        }
+    }
+
+    pub fn is_just_below(&self, amount: Money) -> bool {
+        amount < self.threshold && amount >= self.threshold.saturating_sub(self.margin)
+    }
+
+    pub fn observe(&mut self, event: &AccountEvent) {
+        if event.action_type != AccountActions::Deposit || self.flagged.contains(&event.client_id) {
+            return;
+        }
+
+        let amount = event.amount.unwrap_or_default();
+        let recent = self.recent.entry(event.client_id).or_default();
+        recent.push_back((event.transaction_id, amount));
+        if recent.len() > self.window {
+            recent.pop_front();
+        }
+
+        let window: Vec<(i32, Money)> = recent.iter().copied().collect();
+        let suspicious: Vec<(i32, Money)> = window
+            .into_iter()
+            .filter(|(_, amount)| self.is_just_below(*amount))
+            .collect();
+
+        if suspicious.len() >= self.min_count {
+            warn!(
+                "client_id: {} possible structuring with {} deposits",
+                event.client_id,
+                suspicious.len()
+            );
+            self.flagged.insert(event.client_id);
+            self.recent.remove(&event.client_id);
+            self.reports.push(SuspiciousActivity {
+                client_id: event.client_id,
+                transactions: suspicious,
+            });
+        }
+    }
+}
+
+/// one row per supporting transaction so the report can be filtered by client
+pub fn write_suspicious_activity_report(
+    path: &str,
+    reports: &[SuspiciousActivity],
+    rounding: RoundingMode,
+) -> std::io::Result<()> {
+    let mut writer = csv::Writer::from_writer(BufWriter::new(File::create(path)?));
+    writer.write_record(["client", "deposits", "total", "tx", "amount"])?;
+    for report in reports {
+        for (transaction_id, amount) in &report.transactions {
+            writer.write_record([
+                report.client_id.to_string(),
+                report.transactions.len().to_string(),
+                report
+                    .total()
+                    .map(|total| total.format(rounding))
+                    .unwrap_or_default(),
+                transaction_id.to_string(),
+                amount.format(rounding),
+            ])?;
+        }
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::Money;
+    use crate::structuring::StructuringDetector;
+    use crate::{AccountActions, AccountEvent};
+
+    fn event(action_type: AccountActions, transaction_id: i32, amount: u64) -> AccountEvent {
+        AccountEvent {
+            transaction_id,
+            action_type,
+            client_id: 1,
+            amount: Some(Money::from_minor_units(amount)),
+        }
+    }
+
+    #[test]
+    fn just_below_threshold() {
+        let detector = StructuringDetector::new(Money::from_minor_units(10000));
+        let is_just_below = |amount| detector.is_just_below(Money::from_minor_units(amount));
+
+        assert!(is_just_below(9000));
+        assert!(is_just_below(9999));
+        assert!(!is_just_below(10000));
+        assert!(!is_just_below(8999));
+    }
+
+    #[test]
+    fn flags_repeated_deposits_below_threshold() {
+        let mut detector = StructuringDetector::new(Money::from_minor_units(10000));
+        detector.observe(&event(AccountActions::Deposit, 1, 9500));
+        detector.observe(&event(AccountActions::Deposit, 2, 100));
+        detector.observe(&event(AccountActions::Withdrawal, 3, 9500));
+        detector.observe(&event(AccountActions::Deposit, 4, 9900));
+        assert!(detector.reports.is_empty());
+
+        detector.observe(&event(AccountActions::Deposit, 5, 9100));
+        assert_eq!(detector.reports.len(), 1);
+        assert_eq!(
+            detector.reports[0].transactions,
+            [(1, 9500), (4, 9900), (5, 9100)]
+                .map(|(tx, amount)| (tx, Money::from_minor_units(amount)))
+        );
+        assert_eq!(
+            detector.reports[0].total(),
+            Some(Money::from_minor_units(28500))
+        );
+
+        // only reported once
+        detector.observe(&event(AccountActions::Deposit, 6, 9100));
+        assert_eq!(detector.reports.len(), 1);
+    }
+
+    #[test]
+    fn old_deposits_leave_the_window() {
+        let mut detector = StructuringDetector::new(Money::from_minor_units(10000));
+        detector.window = 3;
+        detector.observe(&event(AccountActions::Deposit, 1, 9500));
+        detector.observe(&event(AccountActions::Deposit, 2, 9500));
+        detector.observe(&event(AccountActions::Deposit, 3, 100));
+        detector.observe(&event(AccountActions::Deposit, 4, 9500));
+
+        assert!(detector.reports.is_empty());
+    }
+}
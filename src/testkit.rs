@@ -0,0 +1,176 @@
+//! scenario builders and assertions for behaviour tests, enabled with the `testkit` feature
+//! so code embedding the processing can use the same helpers as our own tests
+#![allow(dead_code)]
+
+use crate::money::{Money, RoundingMode, SignedMoney};
+use crate::{AccountProcessing, Config};
+
+/// the rows of an input csv in the order they are added, amounts are given exactly like in a file
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    rows: Vec<String>,
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Scenario::default()
+    }
+
+    pub fn deposit(self, client: u16, tx: i32, amount: &str) -> Self {
+        self.row(&format!("deposit,{},{},{}", client, tx, amount))
+    }
+
+    pub fn withdrawal(self, client: u16, tx: i32, amount: &str) -> Self {
+        self.row(&format!("withdrawal,{},{},{}", client, tx, amount))
+    }
+
+    pub fn dispute(self, client: u16, tx: i32) -> Self {
+        self.row(&format!("dispute,{},{},", client, tx))
+    }
+
+    pub fn resolve(self, client: u16, tx: i32) -> Self {
+        self.row(&format!("resolve,{},{},", client, tx))
+    }
+
+    pub fn chargeback(self, client: u16, tx: i32) -> Self {
+        self.row(&format!("chargeback,{},{},", client, tx))
+    }
+
+    /// deposit -> dispute -> chargeback of the same tx, the client ends up locked
+    pub fn chargeback_chain(self, client: u16, tx: i32, amount: &str) -> Self {
+        self.deposit(client, tx, amount)
+            .dispute(client, tx)
+            .chargeback(client, tx)
+    }
+
+    /// a locked client that still tries to move money, both attempts have to be rejected.
+    /// uses the tx ids tx, tx + 1 and tx + 2
+    pub fn locked_account(self, client: u16, tx: i32, amount: &str) -> Self {
+        self.deposit(client, tx, amount)
+            .chargeback_chain(client, tx + 1, amount)
+            .deposit(client, tx + 2, amount)
+            .withdrawal(client, tx + 2, amount)
+    }
+
+    /// rows the reader has to drop without touching any account
+    pub fn malformed_rows(self) -> Self {
+        self.row("deposit,not-a-client,1,1.0")
+            .row("refund,1,1,1.0")
+            .row("deposit,1")
+            .row("withdrawal,1,1,-")
+    }
+
+    /// anything else, written as is
+    pub fn row(mut self, row: &str) -> Self {
+        self.rows.push(row.to_string());
+        self
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = "type,client,tx,amount\n".to_string();
+        for row in &self.rows {
+            csv.push_str(row);
+            csv.push('\n');
+        }
+        csv
+    }
+
+    pub fn run(&self) -> AccountProcessing {
+        self.run_with(Config::default())
+    }
+
+    pub fn run_with(&self, config: Config) -> AccountProcessing {
+        let mut processing = AccountProcessing::new(config);
+        processing.process_reader(self.to_csv().as_bytes());
+        processing
+    }
+}
+
+/// "-1.5" -> -15000, amounts in assertions are written like in the output
+fn signed_amount(amount: &str) -> SignedMoney {
+    SignedMoney::parse(amount, RoundingMode::default())
+        .unwrap_or_else(|e| panic!("invalid amount in assertion: {}", e))
+}
+
+pub fn assert_account(
+    processing: &AccountProcessing,
+    client: u16,
+    available: &str,
+    held: &str,
+    locked: bool,
+) {
+    let account = processing
+        .account(client)
+        .unwrap_or_else(|| panic!("client {} has no account", client));
+    let rounding = processing.config.rounding;
+
+    assert_eq!(
+        account.available.format(rounding),
+        signed_amount(available).format(rounding),
+        "available of client {}",
+        client
+    );
+    assert_eq!(
+        account.held.format(rounding),
+        Money::parse(held, rounding).unwrap().format(rounding),
+        "held of client {}",
+        client
+    );
+    assert_eq!(account.locked, locked, "locked of client {}", client);
+}
+
+pub fn assert_no_account(processing: &AccountProcessing, client: u16) {
+    assert!(
+        processing.account(client).is_none(),
+        "client {} should not have an account",
+        client
+    );
+}
+
+/// processed and rejected events of the summary
+pub fn assert_summary(processing: &AccountProcessing, processed: u64, rejected: u64) {
+    assert_eq!(processing.summary.processed, processed, "processed events");
+    assert_eq!(processing.summary.rejected, rejected, "rejected events");
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testkit::{assert_account, assert_no_account, assert_summary, Scenario};
+
+    #[test]
+    fn chargeback_chain_locks() {
+        let processing = Scenario::new()
+            .deposit(1, 1, "5.0")
+            .chargeback_chain(1, 2, "2.5")
+            .run();
+
+        assert_account(&processing, 1, "5", "0", true);
+        assert_summary(&processing, 4, 0);
+    }
+
+    #[test]
+    fn locked_account_rejects_everything() {
+        let processing = Scenario::new().locked_account(2, 10, "1.0").run();
+
+        assert_account(&processing, 2, "1.0", "0.0", true);
+        assert_summary(&processing, 6, 2);
+    }
+
+    #[test]
+    fn malformed_rows_are_dropped() {
+        let processing = Scenario::new().malformed_rows().deposit(3, 1, "1.0").run();
+
+        assert_account(&processing, 3, "1.0", "0", false);
+        assert_no_account(&processing, 1);
+        assert_summary(&processing, 1, 0);
+    }
+
+    #[test]
+    fn resolve_releases_the_hold() {
+        let scenario = Scenario::new().deposit(1, 1, "2.0").dispute(1, 1);
+        assert_account(&scenario.run(), 1, "0", "2.0", false);
+
+        let processing = scenario.resolve(1, 1).withdrawal(1, 2, "0.5").run();
+        assert_account(&processing, 1, "1.5", "0", false);
+    }
+}
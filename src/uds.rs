@@ -0,0 +1,334 @@
+//! producers on the same host write newline-delimited json events to a unix socket, one flat
+//! object per line with the same fields as the csv:
+//!
+//! ```json
+//! {"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+//! ```
+//!
+//! connections are handled one after another until a producer sends `{"shutdown": true}`,
+//! after that the output and the reports are written like for a file.
+//!
+//! with `--input-format binary` every connection is a stream of the frames of src/binary.rs
+//! instead, an end frame takes the place of the shutdown message.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::iter::Peekable;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::str::Chars;
+
+use crate::binary::{process_binary, InputFormat};
+use crate::io::CORRELATION;
+use crate::memory;
+use crate::{AccountProcessing, CsvRecord};
+
+/// like the audit log we only need flat objects so there is no json dependency for it
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    String(String),
+    // kept as written so amounts are parsed exactly like the csv values
+    Number(String),
+    Bool(bool),
+    Null,
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), String> {
+    skip_whitespace(chars);
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        Some(c) => Err(format!("expected {} but got {}", expected, c)),
+        None => Err(format!("expected {} but the line ended", expected)),
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    expect(chars, '"')?;
+    let mut value = String::new();
+    loop {
+        match chars.next().ok_or("unterminated string")? {
+            '"' => return Ok(value),
+            '\\' => match chars.next().ok_or("unterminated string")? {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("invalid escape \\u{}", code))?;
+                    value.push(c);
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> Result<JsonValue, String> {
+    skip_whitespace(chars);
+    match chars.peek() {
+        Some('"') => return parse_string(chars).map(JsonValue::String),
+        Some('{') | Some('[') => return Err("nested values are not supported".to_string()),
+        _ => {}
+    }
+
+    let mut raw = String::new();
+    while let Some(c) = chars.next_if(|c| !matches!(c, ',' | '}') && !c.is_whitespace()) {
+        raw.push(c);
+    }
+    match raw.as_str() {
+        "true" => Ok(JsonValue::Bool(true)),
+        "false" => Ok(JsonValue::Bool(false)),
+        "null" => Ok(JsonValue::Null),
+        _ if raw.parse::<f64>().is_ok() => Ok(JsonValue::Number(raw)),
+        _ => Err(format!("invalid value: {}", raw)),
+    }
+}
+
+pub fn parse_object(line: &str) -> Result<BTreeMap<String, JsonValue>, String> {
+    let mut chars = line.chars().peekable();
+    let mut object = BTreeMap::new();
+    expect(&mut chars, '{')?;
+
+    skip_whitespace(&mut chars);
+    if chars.next_if_eq(&'}').is_none() {
+        loop {
+            let key = parse_string(&mut chars)?;
+            expect(&mut chars, ':')?;
+            object.insert(key, parse_value(&mut chars)?);
+
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err("expected , or }".to_string()),
+            }
+        }
+    }
+
+    skip_whitespace(&mut chars);
+    match chars.next() {
+        None => Ok(object),
+        Some(c) => Err(format!("unexpected {} after the object", c)),
+    }
+}
+
+/// unknown fields are ignored like unknown csv columns
+pub fn parse_event(object: &BTreeMap<String, JsonValue>) -> Result<CsvRecord<String>, String> {
+    let raw = |field: &str| match object.get(field) {
+        Some(JsonValue::String(value)) | Some(JsonValue::Number(value)) => Ok(Some(value)),
+        Some(JsonValue::Null) | None => Ok(None),
+        Some(JsonValue::Bool(_)) => Err(format!("{} cannot be a bool", field)),
+    };
+    let required = |field: &str| raw(field)?.ok_or_else(|| format!("{} is missing", field));
+
+    Ok(CsvRecord {
+        r#type: required("type")?.to_string(),
+        client: required("client")?
+            .parse()
+            .map_err(|_| "invalid client".to_string())?,
+        tx: required("tx")?
+            .parse()
+            .map_err(|_| "invalid tx".to_string())?,
+        amount: raw("amount")?.cloned(),
+        correlation: raw(CORRELATION)?.cloned(),
+    })
+}
+
+/// true if the producer asked to shut down the listener
+pub fn process_lines<R: BufRead>(app: &mut AccountProcessing, reader: R) -> bool {
+    for (number, line) in (1..).zip(reader.lines()) {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("connection closed: {}", e);
+                return false;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let object = match parse_object(&line) {
+            Ok(object) => object,
+            Err(e) => {
+                debug!("invalid line: {}", e);
+                app.parse_error(number, line, e);
+                continue;
+            }
+        };
+        if object.get("shutdown") == Some(&JsonValue::Bool(true)) {
+            return true;
+        }
+
+        if let Err(e) = parse_event(&object).and_then(|record| app.ingest_record(record)) {
+            debug!("invalid event: {}", e);
+            app.parse_error(number, line, e);
+        }
+    }
+
+    false
+}
+
+/// a socket file left behind by a previous listener is replaced
+pub fn serve(app: &mut AccountProcessing, path: &str, format: InputFormat) -> Result<(), String> {
+    if Path::new(path).exists() {
+        std::fs::remove_file(path).map_err(|e| format!("cannot remove socket {}: {}", path, e))?;
+    }
+    let listener =
+        UnixListener::bind(path).map_err(|e| format!("cannot listen on {}: {}", path, e))?;
+    info!("listening on {}", path);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("cannot accept connection: {}", e);
+                continue;
+            }
+        };
+        let shutdown = match format {
+            InputFormat::Csv => process_lines(app, BufReader::new(stream)),
+            // every connection starts with its own header
+            InputFormat::Binary => process_binary(app, BufReader::new(stream)),
+        };
+        if shutdown {
+            info!(
+                "shutdown requested, {} events processed",
+                app.summary.processed
+            );
+            break;
+        }
+
+        // nothing comes in until the next connection, a good time to give back a burst
+        memory::shrink(app);
+        memory::record(app);
+        let gauges: Vec<String> = memory::gauges(app).iter().map(|g| g.to_string()).collect();
+        debug!("idle, len/capacity: {}", gauges.join(" "));
+    }
+
+    std::fs::remove_file(path).map_err(|e| format!("cannot remove socket {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::binary::InputFormat;
+    use crate::uds::{parse_event, parse_object, process_lines, serve, JsonValue};
+    use crate::{AccountProcessing, Config};
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn flat_objects() {
+        let object =
+            parse_object(r#" {"type": "deposit", "client":1 , "note": "a \"b\" é", "x": null} "#)
+                .unwrap();
+        assert_eq!(object["type"], JsonValue::String("deposit".to_string()));
+        assert_eq!(object["client"], JsonValue::Number("1".to_string()));
+        assert_eq!(object["note"], JsonValue::String("a \"b\" é".to_string()));
+        assert_eq!(object["x"], JsonValue::Null);
+        assert!(parse_object("{}").unwrap().is_empty());
+
+        for invalid in [
+            "",
+            "{",
+            r#"{"a": 1,}"#,
+            r#"{"a": {"b": 1}}"#,
+            r#"{"a": 1} x"#,
+            r#"{"a": one}"#,
+        ] {
+            assert!(parse_object(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn events_are_validated_like_rows() {
+        let event = |line: &str| parse_event(&parse_object(line).unwrap());
+
+        let record =
+            event(r#"{"type": "deposit", "client": 2, "tx": 3, "amount": "1.5"}"#).unwrap();
+        assert_eq!(
+            (record.r#type.as_str(), record.client, record.tx),
+            ("deposit", 2, 3)
+        );
+        assert_eq!(record.amount.as_deref(), Some("1.5"));
+        assert_eq!(record.correlation, None);
+        assert_eq!(
+            event(r#"{"type": "deposit", "client": 2, "tx": 3, "correlation": "req-7"}"#)
+                .unwrap()
+                .correlation
+                .as_deref(),
+            Some("req-7")
+        );
+        assert_eq!(
+            event(r#"{"type": "dispute", "client": 2, "tx": 3}"#)
+                .unwrap()
+                .amount,
+            None
+        );
+
+        assert!(event(r#"{"type": "deposit", "client": 70000, "tx": 1}"#).is_err());
+        assert!(event(r#"{"type": "deposit", "tx": 1}"#).is_err());
+        assert!(event(r#"{"type": true, "client": 1, "tx": 1}"#).is_err());
+    }
+
+    #[test]
+    fn lines_until_shutdown() {
+        let input = concat!(
+            "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 2.0}\n",
+            "not json\n",
+            "\n",
+            "{\"type\": \"refund\", \"client\": 1, \"tx\": 2, \"amount\": 1.0}\n",
+            "{\"type\": \"withdrawal\", \"client\": 1, \"tx\": 3, \"amount\": \"0.5\"}\n",
+            "{\"shutdown\": true}\n",
+            "{\"type\": \"deposit\", \"client\": 1, \"tx\": 4, \"amount\": 2.0}\n",
+        );
+        let mut app = AccountProcessing::new(Config::default());
+
+        assert!(process_lines(&mut app, input.as_bytes()));
+        assert_eq!(app.accounts[&1].available.minor_units(), 15000);
+        assert_eq!(app.summary.processed, 2);
+    }
+
+    #[test]
+    fn socket_connections() {
+        let path = std::env::temp_dir().join(format!("uds-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let mut app = AccountProcessing::new(Config::default());
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let connect = || loop {
+                    match UnixStream::connect(&path) {
+                        Ok(stream) => return stream,
+                        Err(_) => thread::sleep(Duration::from_millis(5)),
+                    }
+                };
+                let mut producer = connect();
+                writeln!(
+                    producer,
+                    r#"{{"type": "deposit", "client": 4, "tx": 1, "amount": 1}}"#
+                )
+                .unwrap();
+                drop(producer);
+
+                let mut producer = connect();
+                writeln!(producer, r#"{{"shutdown": true}}"#).unwrap();
+            });
+
+            serve(&mut app, &path, InputFormat::Csv).unwrap();
+        });
+
+        assert_eq!(app.accounts[&4].available.minor_units(), 10000);
+        assert!(!std::path::Path::new(&path).exists());
+    }
+}